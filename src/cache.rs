@@ -0,0 +1,234 @@
+// # cache.rs
+//
+// The conversation list, and the messages loaded in each conversation, saved to `cache.json`
+// (next to `config.toml`) when quitting. At startup they go on screen straight away, before
+// keybase has answered, and are swapped for the live list once it has. Only kept when
+// `cache_ttl_secs` is set, and a cache older than that is ignored.
+
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use dirs::config_dir;
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Conversation, KeybaseConversation, Message};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CachedConversation {
+    pub data: KeybaseConversation,
+    // empty if it was never opened
+    pub messages: Vec<Message>,
+    pub history_next: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cache {
+    // unix time (seconds) it was saved
+    pub saved_at: i64,
+    pub username: Option<String>,
+    // most recently active first, like keybase lists them
+    pub conversations: Vec<CachedConversation>,
+}
+
+pub fn cache_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("keybase-chat-tui/cache.json"))
+}
+
+impl Cache {
+    pub fn new<'a, I>(conversations: I, username: Option<&str>, saved_at: i64) -> Self
+    where
+        I: IntoIterator<Item = &'a Conversation>,
+    {
+        let mut conversations: Vec<CachedConversation> = conversations
+            .into_iter()
+            .map(|c| CachedConversation {
+                data: c.data.clone(),
+                // the ones still being sent might never make it
+                messages: c.messages.iter().filter(|m| !m.is_pending()).cloned().collect(),
+                history_next: c.history_next.clone(),
+            })
+            .collect();
+        conversations.sort_by_key(|c| std::cmp::Reverse(c.data.active_at));
+        Cache {
+            saved_at,
+            username: username.map(String::from),
+            conversations,
+        }
+    }
+
+    // None if there's no cache, it can't be read (e.g. after an upgrade changed the format), or
+    // it's more than `ttl_secs` old
+    pub fn load(path: &Path, ttl_secs: u64, now: i64) -> Option<Self> {
+        let contents = std::fs::read_to_string(path).ok()?;
+        let cache: Cache = match serde_json::from_str(&contents) {
+            Ok(cache) => cache,
+            Err(e) => {
+                warn!("Ignoring unreadable cache {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        let age = now.saturating_sub(cache.saved_at);
+        if age < 0 || age as u64 > ttl_secs {
+            info!("Ignoring cache from {} seconds ago", age);
+            return None;
+        }
+        Some(cache)
+    }
+
+    // Written next to it first, so quitting halfway through doesn't leave half a cache. Only you
+    // can read it, since it's your messages in plain text.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let part = path.with_extension("json.part");
+        // left over from a save that didn't finish, maybe with other permissions
+        let _ = std::fs::remove_file(&part);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options.open(&part)?.write_all(&serde_json::to_vec(self)?)?;
+        std::fs::rename(&part, path)?;
+        Ok(())
+    }
+
+    pub fn into_conversations(self) -> Vec<Conversation> {
+        self.conversations
+            .into_iter()
+            .map(|cached| {
+                let mut convo = Conversation::from(cached.data);
+                convo.fetched = !cached.messages.is_empty();
+                convo.insert_history(cached.messages, cached.history_next);
+                convo
+            })
+            .collect()
+    }
+}
+
+// A conversation from keybase, keeping the cached messages if nothing's been sent to it since.
+// Otherwise they could be missing the newest ones, so it's loaded again when it's opened.
+pub fn reconcile(live: KeybaseConversation, cached: Option<&Conversation>) -> Conversation {
+    let mut convo = Conversation::from(live);
+    if let Some(cached) = cached.filter(|c| c.fetched && c.data.active_at == convo.data.active_at) {
+        convo.fetched = true;
+        convo.insert_history(cached.messages.clone(), cached.history_next.clone());
+    }
+    convo
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{conversation, message};
+    use crate::types::{
+        Channel, DeliveryState, MemberType, MessageBody, MessageType, DEFAULT_MAX_MESSAGES,
+    };
+
+    fn cache_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("keybase-chat-tui-cache-{}-{}.json", name, std::process::id()))
+    }
+
+    fn conversations() -> Vec<Conversation> {
+        let mut quiet = conversation!("test1");
+        quiet.active_at = 100;
+        let mut busy: Conversation = KeybaseConversation { active_at: 200, ..conversation!("test2") }.into();
+        busy.fetched = true;
+        busy.history_next = Some("older".to_string());
+        let mut pending = message!("test2", "on its way", 3);
+        pending.delivery = Some(DeliveryState::Sending);
+        busy.insert_messages(vec![message!("test2", "two", 2), message!("test2", "one", 1), pending]);
+        vec![quiet.into(), busy]
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = cache_file("round-trip");
+        let cache = Cache::new(&conversations(), Some("me"), 1000);
+        cache.save(&path).unwrap();
+        let loaded = Cache::load(&path, 60, 1030).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, cache);
+
+        let restored = loaded.into_conversations();
+        // most recently active first, without the message that hadn't been sent
+        assert_eq!(restored[0].id, "test2");
+        assert!(restored[0].fetched);
+        assert_eq!(restored[0].messages.iter().map(|m| m.id).collect::<Vec<u64>>(), [2, 1]);
+        assert_eq!(restored[0].history_next.as_deref(), Some("older"));
+        assert_eq!(restored[1].id, "test1");
+        assert!(!restored[1].fetched);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_readable_by_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = cache_file("permissions");
+        // e.g. from a save that was interrupted
+        std::fs::write(path.with_extension("json.part"), "").unwrap();
+        Cache::new(&conversations(), Some("me"), 1000).save(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn keeps_to_the_limit() {
+        let mut cache = Cache::new(&conversations(), None, 1000);
+        // e.g. saved before `max_messages` was lowered
+        cache.conversations[0].messages =
+            (0..DEFAULT_MAX_MESSAGES as u64 + 10).map(|i| message!("test2", "hi", i)).collect();
+
+        let restored = cache.into_conversations();
+        assert_eq!(restored[0].messages.len(), DEFAULT_MAX_MESSAGES);
+        // newest first, whatever order they were saved in
+        assert_eq!(restored[0].messages[0].id, DEFAULT_MAX_MESSAGES as u64 + 9);
+        assert_eq!(restored[0].history_next, None);
+
+        let mut lower = restored[0].clone();
+        lower.set_max_messages(5);
+        assert_eq!(lower.messages.len(), 5);
+    }
+
+    #[test]
+    fn expires() {
+        let path = cache_file("ttl");
+        Cache::new(&conversations(), None, 1000).save(&path).unwrap();
+        assert!(Cache::load(&path, 60, 1060).is_some());
+        assert!(Cache::load(&path, 60, 1061).is_none());
+        // saved in the future, the clock must have changed
+        assert!(Cache::load(&path, 60, 999).is_none());
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(Cache::load(&path, 60, 1000).is_none());
+        std::fs::remove_file(&path).unwrap();
+        assert!(Cache::load(&path, 60, 1000).is_none());
+    }
+
+    #[test]
+    fn reconciles_with_keybase() {
+        let cached = conversations();
+        let busy = &cached[1];
+
+        // nothing new, so the cached messages are still good
+        let same = reconcile(busy.data.clone(), Some(busy));
+        assert!(same.fetched);
+        assert_eq!(same.messages, busy.messages);
+
+        let mut newer = busy.data.clone();
+        newer.active_at = 300;
+        newer.unread = true;
+        let newer = reconcile(newer, Some(busy));
+        assert!(!newer.fetched);
+        assert!(newer.messages.is_empty());
+        assert!(newer.data.unread);
+
+        assert!(!reconcile(conversation!("test3"), None).fetched);
+    }
+}
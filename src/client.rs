@@ -3,47 +3,151 @@
 // A client struct which talks to the Keybase API, handles serialization and deserialization of the
 // messages and writing to the proper channels.
 
+use std::collections::HashMap;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
 use std::process::{Stdio};
 use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use tokio::process::{Child, Command};
-use tokio::io::{BufReader, AsyncWriteExt, AsyncBufReadExt};
+use tokio::process::{Child, ChildStdout, Command};
+use tokio::io::{AsyncBufRead, BufReader, AsyncWriteExt, AsyncBufReadExt};
 use tokio::sync::mpsc::{self, Sender, Receiver};
 use serde_json::{from_str, from_value, json, to_string_pretty, Value};
 use async_trait::async_trait;
+use futures::stream::{self, Stream};
 #[cfg(test)]
 use mockall::*;
 
+use crate::config::KeybaseConfig;
+use crate::error::KeybaseError;
+use crate::latency::Latency;
+use crate::support::SupportBundle;
 use crate::types::{
     Message, ApiResponseWrapper, ApiResponse, Channel, KeybaseConversation, ListenerEvent,
+    ConversationPage, MemberType, MessagePage, MessageWrapper,
 };
 
 #[cfg_attr(test, automock)]
 #[async_trait]
 pub trait KeybaseClient {
-    fn get_receiver(&mut self) -> Receiver<ListenerEvent>;
+    fn get_receiver(&self) -> Receiver<ListenerEvent>;
+    fn restart_listener(&self) -> Result<(), Box<dyn Error>>;
     async fn fetch_conversations(&self) -> Result<Vec<KeybaseConversation>, Box<dyn Error>>;
-    async fn fetch_messages(&self, conversation: &KeybaseConversation, count: u32) -> Result<Vec<Message>, Box<dyn Error>>;
-    async fn send_message<T: Into<String> + Send + 'static>(&self, channel: &Channel, message: T) -> Result<(), Box<dyn Error>>;
+    async fn fetch_conversation_page(&self, count: u32, next: Option<String>) -> Result<ConversationPage, Box<dyn Error>>;
+    async fn fetch_message_page(&self, conversation: &KeybaseConversation, count: u32, next: Option<String>) -> Result<MessagePage, Box<dyn Error>>;
+    // the new message's id, if keybase said what it was
+    async fn send_message<T>(&self, channel: &Channel, message: T) -> Result<Option<u64>, Box<dyn Error>>
+    where
+        T: Into<String> + Send + 'static;
+    async fn pin_message(&self, channel: &Channel, message_id: u64) -> Result<(), Box<dyn Error>>;
+    async fn unpin_message(&self, channel: &Channel, message_id: u64) -> Result<(), Box<dyn Error>>;
+    async fn whoami(&self) -> Result<String, Box<dyn Error>>;
+    async fn rename_channel(&self, channel: &Channel, new_topic: &str) -> Result<(), Box<dyn Error>>;
+    async fn mark_as_read(&self, conversation_id: &str, message_id: u64) -> Result<(), Box<dyn Error>>;
+    async fn react(&self, channel: &Channel, message_id: u64, reaction: &str) -> Result<(), Box<dyn Error>>;
+    async fn send_reply(&self, channel: &Channel, message_id: u64, message: String) -> Result<(), Box<dyn Error>>;
+    async fn send_exploding(&self, channel: &Channel, message: String, lifetime_secs: u64) -> Result<(), Box<dyn Error>>;
+    async fn edit_message(&self, channel: &Channel, message_id: u64, message: String) -> Result<(), Box<dyn Error>>;
+    async fn delete_message(&self, channel: &Channel, message_id: u64) -> Result<(), Box<dyn Error>>;
+    async fn join_channel(&self, channel: &Channel) -> Result<(), Box<dyn Error>>;
+    // makes keybase create the conversation with some users if there isn't one yet
+    async fn new_conversation(&self, channel: &Channel) -> Result<(), Box<dyn Error>>;
+    // save the attachment in a message to `output`
+    async fn download_attachment(&self, conversation_id: &str, message_id: u64, output: &Path) -> Result<(), Box<dyn Error>>;
+    // the topic names of a team's channels, including ones we haven't joined
+    async fn list_channels(&self, team: &str) -> Result<Vec<String>, Box<dyn Error>>;
+    // cached per team, unless `refresh` is set (e.g. someone joined or left)
+    async fn fetch_member_count(&self, team: &str, refresh: bool) -> Result<usize, Box<dyn Error>>;
+    // the usernames of everyone in a team, cached the same way
+    async fn fetch_members(&self, team: &str, refresh: bool) -> Result<Vec<String>, Box<dyn Error>>;
+}
+
+// `count` messages, newest first, fetched `chunk` at a time. Each page comes out as soon as it's
+// in, so the newest messages can go on screen while older ones are still on their way. Stops at
+// the start of the conversation, or after an error. `client` can be a reference, or something
+// like an `Rc` for a stream that outlives the caller.
+pub fn message_pages<C: KeybaseClient, R: Deref<Target = C>>(
+    client: R,
+    conversation: KeybaseConversation,
+    count: u32,
+    chunk: u32,
+) -> impl Stream<Item = Result<MessagePage, Box<dyn Error>>> {
+    // how many are still wanted, and where the next page starts (None once there isn't one)
+    let start = (client, conversation, count, None);
+    stream::unfold(Some(start), move |fetch| async move {
+        let (client, conversation, remaining, next) = fetch.filter(|f| f.2 > 0)?;
+        let num = remaining.min(chunk.max(1));
+        match client.fetch_message_page(&conversation, num, next).await {
+            Ok(page) => {
+                let after = page
+                    .next
+                    .clone()
+                    .map(|next| (client, conversation, remaining - num, Some(next)));
+                Some((Ok(page), after))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+}
+
+// wait this long before restarting a listener that died, doubling each time it fails again
+const LISTENER_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_LISTENER_RESTART_DELAY: Duration = Duration::from_secs(30);
+
+// starts the `keybase chat api-listen` process, swappable so tests don't need keybase
+pub type ListenerSpawner = Arc<dyn Fn() -> io::Result<Child> + Send + Sync>;
+
+fn spawn_keybase_listener(keybase: &KeybaseConfig) -> io::Result<Child> {
+    Command::from(keybase.command(&["chat", "api-listen"]))
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+}
+
+// the running listener process, shared between the client and the task reading from it
+#[derive(Default)]
+struct ListenerHandle {
+    child: Mutex<Option<Child>>,
+    // the client is done with it, so the process going away isn't a reason to start another
+    stopped: AtomicBool,
+}
+
+impl ListenerHandle {
+    fn kill(&self) {
+        if let Some(child) = self.child.lock().unwrap().as_mut() {
+            // the listener may well have died already, which is nothing to panic about
+            if let Err(e) = child.kill() {
+                warn!("Failed to stop the listener: {}", e);
+            }
+        }
+    }
 }
 
 pub struct Client<Executor: KeybaseExecutor> {
-    receiver: Option<Receiver<ListenerEvent>>,
+    receiver: Mutex<Option<Receiver<ListenerEvent>>>,
     subscriber: Option<Sender<ListenerEvent>>,
-    listener: Option<Child>, 
+    listener: Mutex<Option<Arc<ListenerHandle>>>,
+    spawner: ListenerSpawner,
     executor: Executor,
+    // team name -> usernames of its members
+    members: Mutex<HashMap<String, Vec<String>>>,
 }
 
 impl Default for Client<ClientExecutor> {
     fn default() -> Self {
-        Client::new(ClientExecutor)
+        Client::new(ClientExecutor::default())
     }
 }
 
 impl<Executor: KeybaseExecutor> Drop for Client<Executor> {
     fn drop(&mut self) {
-        if let Some(mut c) = self.listener.take() {
-            c.kill().unwrap()
+        if let Ok(Some(listener)) = self.listener.get_mut().map(Option::take) {
+            listener.stopped.store(true, Ordering::SeqCst);
+            listener.kill();
         }
     }
 }
@@ -51,8 +155,19 @@ impl<Executor: KeybaseExecutor> Drop for Client<Executor> {
 #[async_trait]
 impl<Executor: KeybaseExecutor + Send + Sync + 'static> KeybaseClient for Client<Executor> {
 
-    fn get_receiver(&mut self) -> Receiver<ListenerEvent>{
-        self.receiver.take().unwrap()
+    fn get_receiver(&self) -> Receiver<ListenerEvent>{
+        self.receiver.lock().unwrap().take().unwrap()
+    }
+
+    // the task reading from the listener starts a new one once the old one is gone, and events
+    // keep going to the same receiver
+    fn restart_listener(&self) -> Result<(), Box<dyn Error>> {
+        let listener = self.listener.lock().unwrap().clone();
+        match listener {
+            Some(listener) => listener.kill(),
+            None => self.start_listener()?,
+        }
+        Ok(())
     }
 
     async fn fetch_conversations(&self) -> Result<Vec<KeybaseConversation>, Box<dyn Error>> {
@@ -61,36 +176,84 @@ impl<Executor: KeybaseExecutor + Send + Sync + 'static> KeybaseClient for Client
                 "method": "list"
             }),
         ).await?;
+        check_api_error(&value)?;
         let parsed = from_value::<ApiResponseWrapper>(value)?.result;
-        if let ApiResponse::ConversationList { conversations: convos } = parsed {
+        if let ApiResponse::ConversationList { conversations: convos, .. } = parsed {
             return Ok(convos);
         }
-        // should be an Err
-        Ok(vec![])
+        Err(KeybaseError::UnexpectedResponse.into())
+    }
+
+    async fn fetch_conversation_page(&self, count: u32, next: Option<String>) -> Result<ConversationPage, Box<dyn Error>> {
+        let mut pagination = json!({"num": count});
+        if let Some(next) = next {
+            pagination["next"] = json!(next);
+        }
+        let value = self.executor.run_api_command(
+            json!({
+                "method": "list",
+                "params": {
+                    "options": {
+                        "pagination": pagination
+                    }
+                }
+            }),
+        ).await?;
+        check_api_error(&value)?;
+        let parsed = from_value::<ApiResponseWrapper>(value)?.result;
+        if let ApiResponse::ConversationList { conversations, pagination } = parsed {
+            let next = match pagination {
+                Some(p) if !p.last && !p.next.is_empty() => Some(p.next),
+                _ => None,
+            };
+            return Ok(ConversationPage { conversations, next });
+        }
+        Err(KeybaseError::UnexpectedResponse.into())
     }
 
-    async fn fetch_messages(&self, conversation: &KeybaseConversation, count: u32) -> Result<Vec<Message>, Box<dyn Error>>{
+    async fn fetch_message_page(&self, conversation: &KeybaseConversation, count: u32, next: Option<String>) -> Result<MessagePage, Box<dyn Error>> {
+        let mut pagination = json!({"num": count});
+        if let Some(next) = next {
+            pagination["next"] = json!(next);
+        }
         let value = self.executor.run_api_command(
             json!({
                 "method": "read",
                 "params": {
                     "options": {
                         "channel": &conversation.channel,
-                        "pagination": {"num": count}
+                        "pagination": pagination
                     }
                 }
             }),
         ).await?;
+        check_api_error(&value)?;
         let parsed = from_value::<ApiResponseWrapper>(value)?.result;
-        if let ApiResponse::MessageList { messages: wrapper } = parsed {
-            return Ok(wrapper.into_iter().map(|m| m.msg).collect::<Vec<Message>>());
+        if let ApiResponse::MessageList { messages, pagination } = parsed {
+            let messages = messages
+                .into_iter()
+                .filter_map(|m| match from_value::<MessageWrapper>(m) {
+                    Ok(wrapper) => Some(wrapper.msg),
+                    Err(e) => {
+                        warn!("Skipping message that failed to parse: {}", e);
+                        None
+                    }
+                })
+                .collect::<Vec<Message>>();
+            let next = match pagination {
+                Some(p) if !p.last && !p.next.is_empty() => Some(p.next),
+                _ => None,
+            };
+            return Ok(MessagePage { messages, next });
         }
-        // should be an Err
-        Ok(vec![])
+        Err(KeybaseError::UnexpectedResponse.into())
     }
 
-    async fn send_message<T: Into<String> + Send>(&self, channel: &Channel, message: T) -> Result<(), Box<dyn Error>> {
-        self.executor.run_api_command(
+    async fn send_message<T>(&self, channel: &Channel, message: T) -> Result<Option<u64>, Box<dyn Error>>
+    where
+        T: Into<String> + Send,
+    {
+        let value = self.executor.run_api_command(
             json!({
                 "method": "send",
                 "params": {
@@ -101,99 +264,676 @@ impl<Executor: KeybaseExecutor + Send + Sync + 'static> KeybaseClient for Client
                 }
             }),
         ).await?;
+        check_api_error(&value)?;
+        let parsed = from_value::<ApiResponseWrapper>(value)?.result;
+        if let ApiResponse::MessageSent { id, .. } = parsed {
+            // it went either way, the listener will say what it became
+            return Ok(id);
+        }
+        Err(KeybaseError::UnexpectedResponse.into())
+    }
+
+    async fn pin_message(&self, channel: &Channel, message_id: u64) -> Result<(), Box<dyn Error>> {
+        let value = self.executor.run_api_command(
+            json!({
+                "method": "pin",
+                "params": {
+                    "options": {
+                        "channel": channel,
+                        "message_id": message_id
+                    }
+                }
+            }),
+        ).await?;
+        check_api_error(&value)
+    }
+
+    async fn unpin_message(&self, channel: &Channel, message_id: u64) -> Result<(), Box<dyn Error>> {
+        let value = self.executor.run_api_command(
+            json!({
+                "method": "unpin",
+                "params": {
+                    "options": {
+                        "channel": channel,
+                        "message_id": message_id
+                    }
+                }
+            }),
+        ).await?;
+        check_api_error(&value)
+    }
+
+    async fn whoami(&self) -> Result<String, Box<dyn Error>> {
+        let value = self.executor.run_command(vec!["status".to_string(), "-j".to_string()]).await?;
+        // the last user is still there after logging out
+        if value.get("LoggedIn").and_then(|l| l.as_bool()) == Some(false) {
+            return Err(KeybaseError::NotLoggedIn.into());
+        }
+        match value.get("Username").and_then(|u| u.as_str()) {
+            Some(username) => Ok(username.to_string()),
+            None => Err("No username in the output of `keybase status`".into()),
+        }
+    }
+
+    async fn rename_channel(&self, channel: &Channel, new_topic: &str) -> Result<(), Box<dyn Error>> {
+        // only team channels have a topic name to change
+        if channel.members_type != MemberType::Team {
+            return Err("Only team channels can be renamed".into());
+        }
+        self.executor.run_command(vec![
+            "chat".to_string(),
+            "rename-channel".to_string(),
+            channel.name.clone(),
+            channel.topic_name.clone(),
+            new_topic.to_string(),
+        ]).await?;
         Ok(())
     }
 
+    async fn mark_as_read(&self, conversation_id: &str, message_id: u64) -> Result<(), Box<dyn Error>> {
+        let value = self.executor.run_api_command(
+            json!({
+                "method": "mark",
+                "params": {
+                    "options": {
+                        "conversation_id": conversation_id,
+                        "message_id": message_id
+                    }
+                }
+            }),
+        ).await?;
+        check_api_error(&value)
+    }
+
+    async fn react(&self, channel: &Channel, message_id: u64, reaction: &str) -> Result<(), Box<dyn Error>> {
+        let value = self.executor.run_api_command(
+            json!({
+                "method": "reaction",
+                "params": {
+                    "options": {
+                        "channel": channel,
+                        "message_id": message_id,
+                        "message": {"body": reaction}
+                    }
+                }
+            }),
+        ).await?;
+        check_api_error(&value)
+    }
+
+    async fn send_reply(&self, channel: &Channel, message_id: u64, message: String) -> Result<(), Box<dyn Error>> {
+        let value = self.executor.run_api_command(
+            json!({
+                "method": "send",
+                "params": {
+                    "options": {
+                        "channel": channel,
+                        "message": {"body": message},
+                        "reply_to": message_id
+                    }
+                }
+            }),
+        ).await?;
+        check_api_error(&value)
+    }
+
+    async fn send_exploding(&self, channel: &Channel, message: String, lifetime_secs: u64) -> Result<(), Box<dyn Error>> {
+        let value = self.executor.run_api_command(
+            json!({
+                "method": "send",
+                "params": {
+                    "options": {
+                        "channel": channel,
+                        "message": {"body": message},
+                        // a Go duration
+                        "exploding_lifetime": format!("{}s", lifetime_secs)
+                    }
+                }
+            }),
+        ).await?;
+        check_api_error(&value)
+    }
+
+    async fn edit_message(&self, channel: &Channel, message_id: u64, message: String) -> Result<(), Box<dyn Error>> {
+        let value = self.executor.run_api_command(
+            json!({
+                "method": "edit",
+                "params": {
+                    "options": {
+                        "channel": channel,
+                        "message_id": message_id,
+                        "message": {"body": message}
+                    }
+                }
+            }),
+        ).await?;
+        check_api_error(&value)
+    }
+
+    async fn delete_message(&self, channel: &Channel, message_id: u64) -> Result<(), Box<dyn Error>> {
+        let value = self.executor.run_api_command(
+            json!({
+                "method": "delete",
+                "params": {
+                    "options": {
+                        "channel": channel,
+                        "message_id": message_id
+                    }
+                }
+            }),
+        ).await?;
+        check_api_error(&value)
+    }
+
+    async fn join_channel(&self, channel: &Channel) -> Result<(), Box<dyn Error>> {
+        if channel.members_type != MemberType::Team {
+            return Err("Only team channels can be joined".into());
+        }
+        let value = self.executor.run_api_command(
+            json!({
+                "method": "join",
+                "params": {
+                    "options": {
+                        "channel": channel
+                    }
+                }
+            }),
+        ).await?;
+        check_api_error(&value)
+    }
+
+    async fn new_conversation(&self, channel: &Channel) -> Result<(), Box<dyn Error>> {
+        // team channels are joined instead
+        if channel.members_type != MemberType::User {
+            return Err("Only conversations between users can be started".into());
+        }
+        let value = self.executor.run_api_command(
+            json!({
+                "method": "newconv",
+                "params": {
+                    "options": {
+                        "channel": channel
+                    }
+                }
+            }),
+        ).await?;
+        check_api_error(&value)
+    }
+
+    async fn download_attachment(&self, conversation_id: &str, message_id: u64, output: &Path) -> Result<(), Box<dyn Error>> {
+        let value = self.executor.run_api_command(
+            json!({
+                "method": "download",
+                "params": {
+                    "options": {
+                        "conversation_id": conversation_id,
+                        "message_id": message_id,
+                        "output": output
+                    }
+                }
+            }),
+        ).await?;
+        check_api_error(&value)
+    }
+
+    async fn list_channels(&self, team: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let value = self.executor.run_api_command(
+            json!({
+                "method": "listconvsonname",
+                "params": {
+                    "options": {
+                        "topic_type": "CHAT",
+                        "members_type": "team",
+                        "name": team
+                    }
+                }
+            }),
+        ).await?;
+        check_api_error(&value)?;
+        let parsed = from_value::<ApiResponseWrapper>(value)?.result;
+        if let ApiResponse::ConversationList { conversations, .. } = parsed {
+            let mut topics: Vec<String> = conversations
+                .into_iter()
+                .map(|c| c.channel.topic_name)
+                .collect();
+            topics.sort();
+            topics.dedup();
+            return Ok(topics);
+        }
+        Err(KeybaseError::UnexpectedResponse.into())
+    }
+
+    async fn fetch_member_count(&self, team: &str, refresh: bool) -> Result<usize, Box<dyn Error>> {
+        Ok(self.fetch_members(team, refresh).await?.len())
+    }
+
+    async fn fetch_members(&self, team: &str, refresh: bool) -> Result<Vec<String>, Box<dyn Error>> {
+        if !refresh {
+            if let Some(members) = self.members.lock().unwrap().get(team) {
+                return Ok(members.clone());
+            }
+        }
+        let request = json!({
+            "method": "list-team-memberships",
+            "params": {"options": {"team": team}}
+        });
+        let value = self.executor.run_command(vec![
+            "team".to_string(),
+            "api".to_string(),
+            "-m".to_string(),
+            request.to_string(),
+        ]).await?;
+        check_api_error(&value)?;
+        // members are grouped by role (owners, admins, writers, ...)
+        let members = value
+            .get("result")
+            .and_then(|r| r.get("members"))
+            .and_then(|m| m.as_object())
+            .ok_or("No members in the team api response")?;
+        let members: Vec<String> = members
+            .values()
+            .filter_map(|role| role.as_array())
+            .flatten()
+            .filter_map(|member| member.get("username")?.as_str())
+            .map(String::from)
+            .collect();
+        self.members.lock().unwrap().insert(team.to_string(), members.clone());
+        Ok(members)
+    }
+
+}
+
+// Some commands (like pinning in a team channel without the right role) come back with an error
+// object instead of a result, so turn that into an Err.
+fn check_api_error(value: &Value) -> Result<(), Box<dyn Error>> {
+    match KeybaseError::from_response(value) {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
 }
 
 impl<Executor: KeybaseExecutor> Client<Executor> {
     pub fn new(executor: Executor) -> Self {
+        let c = Client::without_listener(executor);
+        c.start_listener().unwrap();
+        c
+    }
+
+    // for one-off jobs (like `export-all`) that don't need to hear about new messages
+    pub fn without_listener(executor: Executor) -> Self {
         let (s, r) = mpsc::channel(32);
-        let mut c = Client {
-            receiver: Some(r), 
+        Client {
+            receiver: Mutex::new(Some(r)),
             subscriber: Some(s),
-            listener: None, 
-            executor
-        };
-        c.listener = Some(c.start_listener().unwrap());
-        c
+            listener: Mutex::new(None),
+            spawner: Arc::new(|| spawn_keybase_listener(&KeybaseConfig::default())),
+            executor,
+            members: Mutex::new(HashMap::new()),
+        }
     }
 
-    pub fn start_listener(&self) -> Result<Child, Box<dyn Error>> {
-        let mut child = Command::new("keybase")
-            .arg("chat")
-            .arg("api-listen")
-            .stdout(Stdio::piped())
-            .spawn()?;
+    // run the listener with this keybase instead of the one on the PATH
+    pub fn with_keybase(mut self, keybase: KeybaseConfig) -> Self {
+        self.spawner = Arc::new(move || spawn_keybase_listener(&keybase));
+        self
+    }
 
-        debug!("Started listener process: {}", child.id());
+    // Start the listener, failing straight away if it can't be. After that it's restarted
+    // whenever it exits, until the client is dropped.
+    pub fn start_listener(&self) -> Result<(), Box<dyn Error>> {
+        let mut child = (self.spawner)()?;
+        let stdout = take_stdout(&mut child)?;
+        let listener = Arc::new(ListenerHandle::default());
+        *listener.child.lock().unwrap() = Some(child);
 
-        let stdout = child.stdout.take().unwrap();
-        let mut subscriber = self.subscriber.clone().unwrap();
+        tokio::spawn(supervise_listener(
+            stdout,
+            self.spawner.clone(),
+            listener.clone(),
+            self.subscriber.clone().unwrap(),
+            LISTENER_RESTART_DELAY,
+        ));
+        *self.listener.lock().unwrap() = Some(listener);
+        Ok(())
+    }
+}
 
-        tokio::spawn(async move {
-            let mut lines = BufReader::new(stdout).lines();
-            while let Some(line) = lines.next_line().await.unwrap() {
-                let parsed: Value = from_str(&line).unwrap();
-                debug!("Listener Event: {}", to_string_pretty(&parsed).unwrap());
-                let event = from_value::<ListenerEvent>(parsed).unwrap();
-                subscriber.send(event).await.unwrap();
-            }
-        });
+fn take_stdout(child: &mut Child) -> io::Result<ChildStdout> {
+    debug!("Started listener process: {}", child.id());
+    child
+        .stdout
+        .take()
+        .ok_or_else(|| io::Error::other("listener has no stdout"))
+}
+
+// how long to wait before restart number `attempt` (counting from 0)
+fn restart_delay(initial: Duration, attempt: u32) -> Duration {
+    initial
+        .checked_mul(2u32.saturating_pow(attempt))
+        .unwrap_or(MAX_LISTENER_RESTART_DELAY)
+        .min(MAX_LISTENER_RESTART_DELAY)
+}
+
+// Read events from the listener, and when it exits (e.g. the keybase service restarted) start a
+// new one, backing off while that keeps failing. `ListenerDisconnected` says it's gone, and once
+// it's back a `ListenerReconnected` lets the controller catch up on anything it missed.
+async fn supervise_listener(
+    mut stdout: ChildStdout,
+    spawner: ListenerSpawner,
+    listener: Arc<ListenerHandle>,
+    mut subscriber: Sender<ListenerEvent>,
+    initial_delay: Duration,
+) {
+    let mut attempt = 0;
+    loop {
+        let events = match forward_events(BufReader::new(stdout), subscriber.clone()).await {
+            Some(events) => events,
+            None => return,
+        };
+        if listener.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        // it was working, so this isn't the same failure over again
+        if events > 0 {
+            attempt = 0;
+        }
+        warn!("Chat listener exited, restarting it");
+        if subscriber.send(ListenerEvent::ListenerDisconnected).await.is_err() {
+            return;
+        }
 
-        Ok(child)
+        stdout = loop {
+            tokio::time::delay_for(restart_delay(initial_delay, attempt)).await;
+            attempt = attempt.saturating_add(1);
+            if listener.stopped.load(Ordering::SeqCst) {
+                return;
+            }
+            match spawner().and_then(|mut child| Ok((take_stdout(&mut child)?, child))) {
+                Ok((stdout, child)) => {
+                    *listener.child.lock().unwrap() = Some(child);
+                    break stdout;
+                }
+                Err(e) => error!("Failed to restart the chat listener: {}", e),
+            }
+        };
+        if subscriber.send(ListenerEvent::ListenerReconnected).await.is_err() {
+            return;
+        }
     }
 }
 
-pub struct ClientExecutor;
+fn parse_event(line: &str) -> Result<ListenerEvent, Box<dyn Error>> {
+    let parsed: Value = from_str(line)?;
+    debug!("Listener Event: {}", to_string_pretty(&parsed)?);
+    Ok(from_value::<ListenerEvent>(parsed)?)
+}
 
-#[cfg_attr(test, automock)]
-#[async_trait]
-pub trait KeybaseExecutor {
-    // helper to start the oneoff keybase process that will run our command
-    async fn run_api_command(&self, command: Value) -> Result<Value, Box<dyn Error>>;
+// Pass on each line the listener prints as an event, until it exits or nobody's listening any
+// more. A line that doesn't make sense is skipped rather than ending the whole stream. Returns how
+// many events went through, or None once nothing is receiving them.
+async fn forward_events<R: AsyncBufRead + Unpin>(reader: R, mut subscriber: Sender<ListenerEvent>) -> Option<usize> {
+    let mut lines = reader.lines();
+    let mut events = 0;
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                error!("Failed to read from the listener: {}", e);
+                break;
+            }
+        };
+        let event = match parse_event(&line) {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("Skipping listener event ({}): {}", e, line);
+                continue;
+            }
+        };
+        if subscriber.send(event).await.is_err() {
+            debug!("Nothing receiving listener events, stopping");
+            return None;
+        }
+        events += 1;
+    }
+    Some(events)
 }
 
-#[async_trait]
-impl KeybaseExecutor for ClientExecutor {
-    async fn run_api_command(&self, command: Value) -> Result<Value, Box<dyn Error>> {
-        let mut child = Command::new("keybase")
-            .arg("chat")
-            .arg("api")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("Failed to start keybase api process");
+// starts a `keybase chat api` process, swappable so tests don't need keybase
+pub type ApiSpawner = Arc<dyn Fn() -> io::Result<Child> + Send + Sync>;
 
-        {
-            // scoped so that the pipe is dropped
-            let mut stdin = child.stdin.take().unwrap();
+fn spawn_keybase_api(keybase: &KeybaseConfig) -> io::Result<Child> {
+    Command::from(keybase.command(&["chat", "api"]))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+}
 
-            info!("Sending Keybase Command");
-            debug!("Keybase Command: {}", to_string_pretty(&command)?);
-            stdin.write_all(serde_json::to_vec(&command)?.as_slice()).await.unwrap();
+// How often to try an api call that doesn't get an answer (keybase wouldn't start, or exited
+// without printing anything). An answer with an error in it is final, and so is no answer to one of
+// `ONCE_ONLY_METHODS`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    // before the first retry, doubling each time after that
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    fn from_config(keybase: &KeybaseConfig) -> Self {
+        RetryPolicy {
+            max_attempts: keybase.api_attempts.max(1),
+            base_delay: Duration::from_millis(keybase.api_retry_delay_ms),
         }
+    }
 
+    // how long to wait after attempt number `attempt` (counting from 0) failed
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(Duration::MAX)
+    }
+}
 
-        let output = child.wait_with_output().await?;
+// Calls that would be done twice if keybase did them and then exited without saying so (a second
+// reaction even takes the first away). They're only tried again if keybase didn't start at all.
+const ONCE_ONLY_METHODS: &[&str] = &["send", "delete", "reaction"];
 
-        let parsed: Value = serde_json::from_slice(&output.stdout)?;
-        info!("Got Keybase Response");
-        debug!("Keybase Response: {}", to_string_pretty(&parsed)?);
-        Ok(parsed)
+// why an api call didn't get an answer
+enum NoAnswer {
+    // keybase didn't start, so nothing was done
+    NotStarted(io::Error),
+    // it started, and may or may not have done it before going quiet
+    Unanswered(io::Error),
+}
+
+impl NoAnswer {
+    fn into_error(self) -> io::Error {
+        match self {
+            NoAnswer::NotStarted(e) | NoAnswer::Unanswered(e) => e,
+        }
     }
 }
 
+pub struct ClientExecutor {
+    // optional trace of every command and response, for attaching to bug reports
+    support_bundle: Option<SupportBundle>,
+    // how long each api call takes, shown in the status bar
+    latency: Option<Arc<Latency>>,
+    keybase: KeybaseConfig,
+    spawner: ApiSpawner,
+    retry: RetryPolicy,
+}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::{message, conversation};
-    use crate::types::*;
+impl Default for ClientExecutor {
+    fn default() -> Self {
+        let keybase = KeybaseConfig::default();
+        ClientExecutor {
+            support_bundle: None,
+            latency: None,
+            spawner: Arc::new(|| spawn_keybase_api(&KeybaseConfig::default())),
+            retry: RetryPolicy::from_config(&keybase),
+            keybase,
+        }
+    }
+}
 
-    #[tokio::test]
-    async fn fetch_list() {
-        let convos = vec![conversation!("test1"), conversation!("test2")];
+impl ClientExecutor {
+    pub fn with_support_bundle(support_bundle: SupportBundle) -> Self {
+        ClientExecutor {
+            support_bundle: Some(support_bundle),
+            ..ClientExecutor::default()
+        }
+    }
+
+    pub fn with_latency(mut self, latency: Arc<Latency>) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    pub fn with_keybase(mut self, keybase: KeybaseConfig) -> Self {
+        self.retry = RetryPolicy::from_config(&keybase);
+        let api_keybase = keybase.clone();
+        self.spawner = Arc::new(move || spawn_keybase_api(&api_keybase));
+        self.keybase = keybase;
+        self
+    }
+
+    // One go at an api call, returning what keybase printed. Only fails when there's no answer
+    // at all, which is usually worth trying again.
+    async fn call_api(&self, input: &[u8]) -> Result<Vec<u8>, NoAnswer> {
+        let started = Instant::now();
+        let mut child = (self.spawner)().map_err(NoAnswer::NotStarted)?;
+
+        {
+            // scoped so that the pipe is dropped
+            let mut stdin = child
+                .stdin
+                .take()
+                .ok_or_else(|| NoAnswer::NotStarted(io::Error::other("no stdin")))?;
+            // if keybase exits without reading the command, its output below says why
+            if let Err(e) = stdin.write_all(input).await {
+                warn!("Failed to send keybase command: {}", e);
+            }
+        }
+
+        let output = child.wait_with_output().await.map_err(NoAnswer::Unanswered)?;
+        if let Some(latency) = &self.latency {
+            latency.record(started.elapsed());
+        }
+        if output.stdout.iter().all(u8::is_ascii_whitespace) {
+            return Err(NoAnswer::Unanswered(io::Error::other(format!(
+                "keybase exited ({}) without answering",
+                output.status
+            ))));
+        }
+        Ok(output.stdout)
+    }
+}
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait KeybaseExecutor {
+    // helper to start the oneoff keybase process that will run our command
+    async fn run_api_command(&self, command: Value) -> Result<Value, Box<dyn Error>>;
+    // same, for the few things that aren't part of `keybase chat api` (e.g. `keybase status -j`)
+    async fn run_command(&self, args: Vec<String>) -> Result<Value, Box<dyn Error>>;
+}
+
+#[async_trait]
+impl KeybaseExecutor for ClientExecutor {
+    async fn run_api_command(&self, command: Value) -> Result<Value, Box<dyn Error>> {
+        info!("Sending Keybase Command");
+        debug!("Keybase Command: {}", to_string_pretty(&command)?);
+        let input = serde_json::to_vec(&command)?;
+        let once_only = command["method"]
+            .as_str()
+            .is_some_and(|method| ONCE_ONLY_METHODS.contains(&method));
+        let mut attempt = 0;
+        let stdout = loop {
+            // every attempt, so a bug report shows the ones that didn't get an answer too
+            if let Some(bundle) = &self.support_bundle {
+                bundle.record_request(&command);
+            }
+            let no_answer = match self.call_api(&input).await {
+                Ok(stdout) => break stdout,
+                Err(e) => e,
+            };
+            let retry = attempt + 1 < self.retry.max_attempts
+                && !(once_only && matches!(no_answer, NoAnswer::Unanswered(_)));
+            let e = no_answer.into_error();
+            if let Some(bundle) = &self.support_bundle {
+                bundle.record_error(&e.to_string());
+            }
+            if !retry {
+                return Err(e.into());
+            }
+            warn!("Keybase command failed, trying again: {}", e);
+            tokio::time::delay_for(self.retry.delay(attempt)).await;
+            attempt += 1;
+        };
+
+        let parsed: Value = match serde_json::from_slice(&stdout) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                if let Some(bundle) = &self.support_bundle {
+                    bundle.record_error(&format!("{}: {}", e, String::from_utf8_lossy(&stdout)));
+                }
+                return Err(e.into());
+            }
+        };
+        info!("Got Keybase Response");
+        debug!("Keybase Response: {}", to_string_pretty(&parsed)?);
+        if let Some(bundle) = &self.support_bundle {
+            bundle.record_response(&parsed);
+        }
+        if let Some(e) = KeybaseError::from_response(&parsed) {
+            return Err(e.into());
+        }
+        Ok(parsed)
+    }
+
+    async fn run_command(&self, args: Vec<String>) -> Result<Value, Box<dyn Error>> {
+        debug!("Keybase Command: keybase {}", args.join(" "));
+        let output = Command::from(self.keybase.command(&[]))
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .await?;
+
+        // these report failures (like missing permissions) on stderr rather than as json
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(stderr.trim().to_string().into());
+        }
+
+        // ...and some of them don't print anything when they succeed
+        if output.stdout.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Value::Null);
+        }
+
+        let parsed: Value = serde_json::from_slice(&output.stdout)?;
+        debug!("Keybase Response: {}", to_string_pretty(&parsed)?);
+        Ok(parsed)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{message, conversation};
+    use crate::types::*;
+    use std::sync::atomic::AtomicUsize;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn fetch_list() {
+        let convos: Vec<KeybaseConversation> = vec![conversation!("test1"), conversation!("test2")]
+            .into_iter()
+            .map(|c| KeybaseConversation { active_at: 1, ..c })
+            .collect();
         let mut executor = MockKeybaseExecutor::new();
         executor.expect_run_api_command()
             .times(1)
@@ -258,7 +998,7 @@ mod test {
                         "messages": [
                         {
                             "msg": {
-                                "id": "msg_id",
+                                "id": 1,
                                 "conversation_id": "test1", 
                                 "channel": {
                                     "members_type": "impteamnative",
@@ -293,9 +1033,236 @@ mod test {
         let client = Client::new(executor);
 
         let convo = conversation!("test1");
-        let messages = vec![message!("test1", "hi")];
+        let messages = vec![message!("test1", "hi", 1)];
+
+        assert_eq!(messages, client.fetch_message_page(&convo, 10, None).await.unwrap().messages);
+    }
+
+    #[tokio::test]
+    async fn listener_skips_bad_lines() {
+        let (s, mut r) = mpsc::channel(32);
+        let input = concat!(
+            "not json\n",
+            "{\"type\": \"chat\", \"msg\": \"not a message\"}\n",
+            "{\"type\": \"typing\", \"conversation_id\": \"test1\", \"username\": \"alice\"}\n",
+        );
+        assert_eq!(forward_events(BufReader::new(input.as_bytes()), s).await, Some(1));
+
+        match r.recv().await {
+            Some(ListenerEvent::Typing { username, .. }) => assert_eq!(username, "alice"),
+            _ => panic!("Expected a typing event"),
+        }
+        assert!(r.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn listener_stops_without_receiver() {
+        let (s, r) = mpsc::channel(32);
+        drop(r);
+        let input = "{\"type\": \"wallet\"}\n{\"type\": \"wallet\"}\n";
+        // returns rather than panicking
+        assert_eq!(forward_events(BufReader::new(input.as_bytes()), s).await, None);
+    }
+
+    #[test]
+    fn restart_backoff() {
+        let initial = Duration::from_secs(1);
+        let delays: Vec<u64> = (0..7).map(|a| restart_delay(initial, a).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(restart_delay(initial, u32::MAX), MAX_LISTENER_RESTART_DELAY);
+    }
+
+    #[tokio::test]
+    async fn listener_restarts_after_dying() {
+        let spawns = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = spawns.clone();
+        // prints one event and exits straight away, like keybase restarting
+        let spawner: ListenerSpawner = Arc::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Command::new("sh")
+                .arg("-c")
+                .arg(r#"echo '{"type": "typing", "conversation_id": "test1", "username": "alice"}'"#)
+                .stdout(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+        });
+        let (s, mut r) = mpsc::channel(32);
+        let listener = Arc::new(ListenerHandle::default());
+        let mut child = spawner().unwrap();
+        let stdout = take_stdout(&mut child).unwrap();
+        *listener.child.lock().unwrap() = Some(child);
+        tokio::spawn(supervise_listener(
+            stdout,
+            spawner,
+            listener.clone(),
+            s,
+            Duration::from_millis(1),
+        ));
+
+        assert!(matches!(r.recv().await, Some(ListenerEvent::Typing { .. })));
+        assert!(matches!(r.recv().await, Some(ListenerEvent::ListenerDisconnected)));
+        assert!(matches!(r.recv().await, Some(ListenerEvent::ListenerReconnected)));
+        assert!(matches!(r.recv().await, Some(ListenerEvent::Typing { .. })));
+        assert!(spawns.load(Ordering::SeqCst) >= 2);
+        listener.stopped.store(true, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn drop_without_listener() {
+        let client = Client::without_listener(MockKeybaseExecutor::new());
+        drop(client);
+    }
+
+    #[tokio::test]
+    async fn unexpected_responses() {
+        let mut executor = MockKeybaseExecutor::new();
+        // a send result where a list or read result should be
+        executor.expect_run_api_command()
+            .times(2)
+            .returning(|_| Ok(json!({"result": {"message": "message sent"}})));
+        let client = Client::without_listener(executor);
+
+        let err = client.fetch_conversations().await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<KeybaseError>(),
+            Some(&KeybaseError::UnexpectedResponse)
+        );
+        let err = client.fetch_message_page(&conversation!("test1"), 10, None).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<KeybaseError>(),
+            Some(&KeybaseError::UnexpectedResponse)
+        );
+    }
+
+    #[tokio::test]
+    async fn api_errors() {
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .times(3)
+            .returning(|_| Ok(json!({
+                "error": {"code": 2623, "message": "no conversations matched"}
+            })));
+        let client = Client::without_listener(executor);
+        let expected = KeybaseError::Api(ApiError {
+            code: 2623,
+            message: "no conversations matched".to_string(),
+        });
+
+        let err = client.fetch_conversations().await.unwrap_err();
+        assert_eq!(err.downcast_ref::<KeybaseError>(), Some(&expected));
+        let err = client.fetch_message_page(&conversation!("test1"), 10, None).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<KeybaseError>(), Some(&expected));
+        let err = client.send_message(&conversation!("test1").channel, "hi").await.unwrap_err();
+        assert_eq!(err.downcast_ref::<KeybaseError>(), Some(&expected));
+    }
+
+    #[tokio::test]
+    async fn fetch_messages_skips_unparseable() {
+        let text = |id: u64| json!({
+            "msg": {
+                "id": id,
+                "conversation_id": "test1",
+                "channel": {
+                    "members_type": "impteamnative",
+                    "name": "channel",
+                    "topic_type": "chat"
+                },
+                "content": {
+                    "text": {
+                        "body": "hi"
+                    },
+                    "type": "text"
+                },
+                "sender": {
+                    "device_id": "1",
+                    "device_name": "My Device",
+                    "uid": "1",
+                    "username": "Some Guy"
+                },
+                "unread": false
+            }
+        });
+        let mut broken = text(2);
+        broken["msg"]["content"] = json!({"type": "some-future-type"});
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .times(1)
+            .return_once(move |_| Ok(json!({
+                "result": {
+                    "messages": [text(1), broken, text(3)]
+                }
+            })));
+        let client = Client::new(executor);
+
+        let convo = conversation!("test1");
+        let messages = vec![message!("test1", "hi", 1), message!("test1", "hi", 3)];
+
+        assert_eq!(messages, client.fetch_message_page(&convo, 10, None).await.unwrap().messages);
+    }
+
+    #[tokio::test]
+    async fn fetch_message_page() {
+        let convo = conversation!("test1");
+        let my_value = json!({
+            "method": "read",
+            "params": {
+                "options": {
+                    "channel": convo.channel,
+                    "pagination": {"num": 100, "next": "abc"}
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(|_| Ok(json!({
+                "result": {
+                    "messages": [],
+                    "pagination": {"next": "def", "num": 0, "last": true}
+                }
+            })));
+        let client = Client::without_listener(executor);
 
-        assert_eq!(messages, client.fetch_messages(&convo, 10).await.unwrap());
+        let page = client.fetch_message_page(&convo, 100, Some("abc".to_string())).await.unwrap();
+        assert_eq!(page, MessagePage::default());
+    }
+
+    #[tokio::test]
+    async fn fetch_conversation_page() {
+        let my_value = json!({
+            "method": "list",
+            "params": {
+                "options": {
+                    "pagination": {"num": 1}
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(|_| Ok(json!({
+                "result": {
+                    "conversations": [{
+                        "id": "test1",
+                        "unread": false,
+                        "channel": {
+                            "members_type": "impteamnative",
+                            "name": "channel",
+                            "topic_name": ""
+                        }
+                    }],
+                    "pagination": {"next": "abc", "num": 1, "last": false}
+                }
+            })));
+        let client = Client::without_listener(executor);
+
+        let page = client.fetch_conversation_page(1, None).await.unwrap();
+        assert_eq!(page, ConversationPage {
+            conversations: vec![conversation!("test1")],
+            next: Some("abc".to_string()),
+        });
     }
 
     #[tokio::test]
@@ -311,13 +1278,658 @@ mod test {
             }
         });
         let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(move |_| Ok(json!({
+                "result": {"message": "message sent", "id": 42, "ratchets": null}
+            })));
+        let client = Client::new(executor);
+
+        assert_eq!(client.send_message(&convo.channel, "hi").await.unwrap(), Some(42));
+    }
+
+    #[tokio::test]
+    async fn send_message_without_id() {
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .times(1)
+            .return_once(|_| Ok(json!({"result": {"message": "message sent"}})));
+        let client = Client::new(executor);
+
+        let sent = client.send_message(&conversation!("test1").channel, "hi").await;
+        assert_eq!(sent.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn send_message_as_typed() {
+        let convo = conversation!("test1");
+        let body = "  *not bold*  \n```\ncode  \n```\n";
+        let my_value = json!({
+            "method": "send",
+            "params": {
+                "options": {
+                    "channel": convo.channel,
+                    "message": {"body": body}
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(move |_| Ok(json!({"result": {"message": "message sent", "id": 7}})));
+        let client = Client::new(executor);
+
+        client.send_message(&convo.channel, body).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pin_message() {
+        let convo = conversation!("test1");
+        let my_value = json!({
+            "method": "pin",
+            "params": {
+                "options": {
+                    "channel": convo.channel,
+                    "message_id": 5
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
         executor.expect_run_api_command()
             .withf(move |value: &Value| *value == my_value)
             .times(1)
             .return_once(move |_| Ok(Value::Null));
         let client = Client::new(executor);
 
-        client.send_message(&convo.channel, "hi").await.unwrap();
+        client.pin_message(&convo.channel, 5).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn mark_as_read() {
+        let my_value = json!({
+            "method": "mark",
+            "params": {
+                "options": {
+                    "conversation_id": "test1",
+                    "message_id": 7
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(move |_| Ok(Value::Null));
+        let client = Client::new(executor);
+
+        client.mark_as_read("test1", 7).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn react() {
+        let convo = conversation!("test1");
+        let my_value = json!({
+            "method": "reaction",
+            "params": {
+                "options": {
+                    "channel": convo.channel,
+                    "message_id": 3,
+                    "message": {"body": ":+1:"}
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(move |_| Ok(Value::Null));
+        let client = Client::new(executor);
+
+        client.react(&convo.channel, 3, ":+1:").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn send_reply() {
+        let convo = conversation!("test1");
+        let my_value = json!({
+            "method": "send",
+            "params": {
+                "options": {
+                    "channel": convo.channel,
+                    "message": {"body": "sure"},
+                    "reply_to": 3
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(move |_| Ok(Value::Null));
+        let client = Client::new(executor);
+
+        client.send_reply(&convo.channel, 3, "sure".to_string()).await.unwrap();
     }
-}
 
+    #[tokio::test]
+    async fn send_exploding() {
+        let convo = conversation!("test1");
+        let my_value = json!({
+            "method": "send",
+            "params": {
+                "options": {
+                    "channel": convo.channel,
+                    "message": {"body": "shh"},
+                    "exploding_lifetime": "3600s"
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(move |_| Ok(Value::Null));
+        let client = Client::new(executor);
+
+        client.send_exploding(&convo.channel, "shh".to_string(), 3600).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn edit_message() {
+        let convo = conversation!("test1");
+        let my_value = json!({
+            "method": "edit",
+            "params": {
+                "options": {
+                    "channel": convo.channel,
+                    "message_id": 3,
+                    "message": {"body": "fixed"}
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(move |_| Ok(Value::Null));
+        let client = Client::new(executor);
+
+        client.edit_message(&convo.channel, 3, "fixed".to_string()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_message() {
+        let convo = conversation!("test1");
+        let my_value = json!({
+            "method": "delete",
+            "params": {
+                "options": {
+                    "channel": convo.channel,
+                    "message_id": 3
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(move |_| Ok(Value::Null));
+        let client = Client::new(executor);
+
+        client.delete_message(&convo.channel, 3).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn join_channel() {
+        let mut convo = conversation!("test1");
+        convo.channel.name = "myteam".to_string();
+        convo.channel.topic_name = "random".to_string();
+        convo.channel.members_type = MemberType::Team;
+        let my_value = json!({
+            "method": "join",
+            "params": {
+                "options": {
+                    "channel": convo.channel
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(move |_| Ok(Value::Null));
+        let client = Client::new(executor);
+
+        client.join_channel(&convo.channel).await.unwrap();
+        // there's nothing to join in a conversation between users
+        assert!(client.join_channel(&conversation!("test2").channel).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn download_attachment() {
+        let my_value = json!({
+            "method": "download",
+            "params": {
+                "options": {
+                    "conversation_id": "test1",
+                    "message_id": 7,
+                    "output": "/home/me/Downloads/photo.jpg"
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(move |_| Ok(json!({"result": {}})));
+        let client = Client::new(executor);
+
+        client
+            .download_attachment("test1", 7, Path::new("/home/me/Downloads/photo.jpg"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn new_conversation() {
+        let mut convo = conversation!("test1");
+        convo.channel.name = "alice,me".to_string();
+        let my_value = json!({
+            "method": "newconv",
+            "params": {
+                "options": {
+                    "channel": convo.channel
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(move |_| Ok(Value::Null));
+        let client = Client::new(executor);
+
+        client.new_conversation(&convo.channel).await.unwrap();
+        convo.channel.members_type = MemberType::Team;
+        assert!(client.new_conversation(&convo.channel).await.is_err());
+    }
+
+    // an executor whose api calls fail to start `failures` times, then answer with `response`
+    fn flaky_executor(failures: usize, response: &str, attempts: Arc<AtomicUsize>) -> ClientExecutor {
+        let response = response.to_string();
+        let spawner: ApiSpawner = Arc::new(move || {
+            if attempts.fetch_add(1, Ordering::SeqCst) < failures {
+                return Err(io::Error::other("keybase service isn't running"));
+            }
+            Command::new("sh")
+                .arg("-c")
+                .arg(format!("cat > /dev/null; echo '{}'", response))
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+        });
+        ClientExecutor {
+            spawner,
+            retry: RetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(1),
+            },
+            ..ClientExecutor::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn support_bundle_records_every_attempt() {
+        let path = std::env::temp_dir().join(format!("keybase-chat-tui-attempts-{}.jsonl", std::process::id()));
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let executor = ClientExecutor {
+            support_bundle: Some(SupportBundle::create(&path, false, 1024 * 1024).unwrap()),
+            ..flaky_executor(1, r#"{"result": {"ok": true}}"#, attempts)
+        };
+
+        executor.run_api_command(json!({"method": "list"})).await.unwrap();
+        let lines: Vec<Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        let kinds: Vec<&str> = lines.iter().map(|l| l["kind"].as_str().unwrap()).collect();
+        assert_eq!(kinds, ["request", "error", "request", "response"]);
+        assert_eq!(lines[1]["data"], "keybase service isn't running");
+    }
+
+    #[tokio::test]
+    async fn retries_api_calls() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let executor = flaky_executor(2, r#"{"result": {"ok": true}}"#, attempts.clone());
+
+        let value = executor.run_api_command(json!({"method": "list"})).await.unwrap();
+        assert_eq!(value, json!({"result": {"ok": true}}));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // out of attempts
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let executor = flaky_executor(3, r#"{"result": {}}"#, attempts.clone());
+        let err = executor.run_api_command(json!({"method": "list"})).await.unwrap_err();
+        assert_eq!(err.to_string(), "keybase service isn't running");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn sends_arent_retried_without_answer() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let spawner: ApiSpawner = Arc::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            // reads the command, maybe does it, and exits without a word
+            Command::new("sh")
+                .arg("-c")
+                .arg("cat > /dev/null")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+        });
+        let executor = ClientExecutor {
+            spawner,
+            retry: RetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(1),
+            },
+            ..ClientExecutor::default()
+        };
+
+        let err = executor.run_api_command(json!({"method": "send"})).await.unwrap_err();
+        assert!(err.to_string().ends_with("without answering"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        // reading can be done again
+        assert!(executor.run_api_command(json!({"method": "read"})).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+
+        // keybase not starting means it wasn't sent
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let executor = flaky_executor(2, r#"{"result": {"id": 5}}"#, attempts.clone());
+        executor.run_api_command(json!({"method": "send"})).await.unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn api_errors_arent_retried() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let executor = flaky_executor(
+            0,
+            r#"{"error": {"code": 2623, "message": "team not found"}}"#,
+            attempts.clone(),
+        );
+
+        let err = executor.run_api_command(json!({"method": "list"})).await.unwrap_err();
+        assert_eq!(err.to_string(), "team not found");
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn message_pages_come_in_chunks() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let mut client = MockKeybaseClient::new();
+        client.expect_fetch_message_page()
+            .returning(move |_, count, next| {
+                let call = counter.fetch_add(1, Ordering::SeqCst) as u64;
+                // each page starts where the one before it left off
+                let expected = if call == 0 { None } else { Some(format!("page{}", call)) };
+                assert_eq!(next, expected);
+                Ok(MessagePage {
+                    messages: (0..count as u64).map(|i| message!("test1", "hi", call * 100 + i)).collect(),
+                    next: Some(format!("page{}", call + 1)),
+                })
+            });
+        let convo = conversation!("test1");
+
+        let pages = message_pages(&client, convo.clone(), 25, 10);
+        futures::pin_mut!(pages);
+        // nothing's fetched until it's asked for, and then only the next page
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        let first = pages.next().await.unwrap().unwrap();
+        assert_eq!(first.messages.len(), 10);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let rest: Vec<usize> = pages
+            .map(|page| page.unwrap().messages.len())
+            .collect()
+            .await;
+        assert_eq!(rest, [10, 5]);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn message_pages_stop_early() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_fetch_message_page()
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage { messages: vec![], next: None }));
+        let convo = conversation!("test1");
+        // already at the start of the conversation
+        assert_eq!(message_pages(&client, convo.clone(), 100, 10).count().await, 1);
+
+        let mut client = MockKeybaseClient::new();
+        client.expect_fetch_message_page()
+            .times(1)
+            .return_once(|_, _, _| Err(KeybaseError::UnexpectedResponse.into()));
+        let pages: Vec<_> = message_pages(&client, convo.clone(), 100, 10).collect().await;
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0].is_err());
+    }
+
+    #[test]
+    fn retry_delays() {
+        let policy = RetryPolicy::from_config(&KeybaseConfig::default());
+        assert_eq!(policy.max_attempts, 3);
+        assert_eq!(policy.delay(0), Duration::from_millis(250));
+        assert_eq!(policy.delay(2), Duration::from_millis(1000));
+    }
+
+    #[tokio::test]
+    async fn list_channels() {
+        let my_value = json!({
+            "method": "listconvsonname",
+            "params": {
+                "options": {
+                    "topic_type": "CHAT",
+                    "members_type": "team",
+                    "name": "keybase"
+                }
+            }
+        });
+        let channel = |id: &str, topic: &str| json!({
+            "id": id,
+            "unread": false,
+            "member_status": "active",
+            "channel": {
+                "members_type": "team",
+                "name": "keybase",
+                "topic_name": topic,
+                "topic_type": "chat"
+            }
+        });
+        let conversations = json!([
+            channel("test1", "random"),
+            channel("test2", "general"),
+            channel("test3", "dev"),
+        ]);
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(move |_| Ok(json!({
+                "result": {"conversations": conversations, "offline": false}
+            })));
+        let client = Client::new(executor);
+
+        assert_eq!(client.list_channels("keybase").await.unwrap(), ["dev", "general", "random"]);
+    }
+
+    #[tokio::test]
+    async fn list_channels_error() {
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .times(1)
+            .return_once(|_| Ok(json!({"error": {"code": 2623, "message": "team not found"}})));
+        let client = Client::new(executor);
+
+        let err = client.list_channels("nope").await.unwrap_err();
+        assert_eq!(err.to_string(), "team not found");
+    }
+
+    #[tokio::test]
+    async fn fetch_member_count() {
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_command()
+            .withf(|args: &Vec<String>| args[..3] == ["team", "api", "-m"] && args[3].contains("\"keybase\""))
+            .times(2)
+            .returning(|_| Ok(json!({
+                "result": {
+                    "members": {
+                        "owners": [{"username": "alice"}],
+                        "admins": null,
+                        "writers": [{"username": "bob"}, {"username": "carol"}],
+                        "readers": []
+                    }
+                }
+            })));
+        let client = Client::new(executor);
+
+        assert_eq!(client.fetch_member_count("keybase", false).await.unwrap(), 3);
+        // cached
+        assert_eq!(client.fetch_member_count("keybase", false).await.unwrap(), 3);
+        assert_eq!(client.fetch_member_count("keybase", true).await.unwrap(), 3);
+        // from the same cache
+        assert_eq!(client.fetch_members("keybase", false).await.unwrap(), ["alice", "bob", "carol"]);
+    }
+
+    #[tokio::test]
+    async fn fetch_member_count_error() {
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_command()
+            .times(1)
+            .return_once(|_| Ok(json!({"error": {"code": 2623, "message": "team not found"}})));
+        let client = Client::new(executor);
+
+        let err = client.fetch_member_count("nope", false).await.unwrap_err();
+        assert_eq!(err.to_string(), "team not found");
+    }
+
+    #[tokio::test]
+    async fn unpin_message() {
+        let convo = conversation!("test1");
+        let my_value = json!({
+            "method": "unpin",
+            "params": {
+                "options": {
+                    "channel": convo.channel,
+                    "message_id": 5
+                }
+            }
+        });
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .withf(move |value: &Value| *value == my_value)
+            .times(1)
+            .return_once(move |_| Ok(Value::Null));
+        let client = Client::new(executor);
+
+        client.unpin_message(&convo.channel, 5).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pin_message_permission_error() {
+        let convo = conversation!("test1");
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_api_command()
+            .times(1)
+            .return_once(move |_| Ok(json!({
+                "error": {
+                    "code": 2624,
+                    "message": "must be at least a writer to pin"
+                }
+            })));
+        let client = Client::new(executor);
+
+        let err = client.pin_message(&convo.channel, 5).await.unwrap_err();
+        assert_eq!(err.to_string(), "must be at least a writer to pin");
+    }
+
+    #[tokio::test]
+    async fn whoami() {
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_command()
+            .withf(|args: &Vec<String>| args == &["status", "-j"])
+            .times(1)
+            .return_once(|_| Ok(json!({
+                "Username": "me",
+                "LoggedIn": true,
+                "Device": {"name": "My Device"}
+            })));
+        let client = Client::new(executor);
+
+        assert_eq!(client.whoami().await.unwrap(), "me");
+    }
+
+    #[tokio::test]
+    async fn whoami_logged_out() {
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_command()
+            .times(1)
+            .return_once(|_| Ok(json!({
+                "Username": "me",
+                "LoggedIn": false
+            })));
+        let client = Client::new(executor);
+
+        let err = client.whoami().await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<KeybaseError>(),
+            Some(&KeybaseError::NotLoggedIn)
+        );
+    }
+
+    #[tokio::test]
+    async fn rename_channel() {
+        let mut convo = conversation!("test1");
+        convo.channel.name = "myteam".to_string();
+        convo.channel.topic_name = "general".to_string();
+        convo.channel.members_type = MemberType::Team;
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_command()
+            .withf(|args: &Vec<String>| args == &["chat", "rename-channel", "myteam", "general", "random"])
+            .times(1)
+            .return_once(|_| Ok(Value::Null));
+        let client = Client::new(executor);
+
+        client.rename_channel(&convo.channel, "random").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rename_non_team_channel() {
+        let convo = conversation!("test1");
+        let mut executor = MockKeybaseExecutor::new();
+        executor.expect_run_command().times(0);
+        let client = Client::new(executor);
+
+        assert!(client.rename_channel(&convo.channel, "random").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn records_latency() {
+        let latency = Arc::new(Latency::default());
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let executor = flaky_executor(0, r#"{"result": {"ok": true}}"#, attempts)
+            .with_latency(latency.clone());
+        assert_eq!(latency.last(), None);
+
+        executor.run_api_command(json!({"method": "list"})).await.unwrap();
+
+        assert!(latency.last().is_some());
+        assert_eq!(latency.average(), latency.last());
+    }
+}
@@ -0,0 +1,370 @@
+// # commands.rs
+//
+// Parsing for the text typed into the chat box. Anything starting with a `/` is a command for the
+// app rather than a message, everything else gets sent to the current conversation. A message
+// that really does start with a `/` is typed with two, `//shrug` sends `/shrug`.
+
+use std::path::PathBuf;
+
+use crate::goto::{parse_message_link, MessageLink};
+use crate::types::UiEvent;
+
+pub enum Command {
+    // handled by the controller
+    Event(UiEvent),
+    // handled by the UI itself: pick a hidden conversation to show again
+    PickHiddenConversation,
+    // mute or unmute the current conversation, which the UI turns into a conversation id
+    ToggleMute,
+    // only show messages from this user in the current conversation, or everyone again if None
+    FilterSender(Option<String>),
+    // react to / reply to a recent message by the number shown next to it (see Alt+n), which
+    // the UI turns into a message id
+    React(usize, String),
+    Reply(usize, String),
+    // new text for the selected message (or your newest), which the UI turns into a message id
+    Edit(String),
+    // delete the selected message, once the UI has asked if you're sure
+    Delete,
+    // jump to a message, which the UI resolves to a conversation
+    Goto(MessageLink),
+    // open a conversation by name, which the UI resolves to an id
+    Switch(String),
+    // react to the selected message
+    ReactToSelected(String),
+    // save the selected attachment, which the UI checks is one
+    DownloadSelected,
+    Quit,
+}
+
+// Names with spaces or other odd characters can be quoted, `/switch "team#big news"`. Only for
+// commands that take a single name, message text is left alone.
+fn unquote(args: &str) -> &str {
+    for quote in &['"', '\''] {
+        if args.len() >= 2 && args.starts_with(*quote) && args.ends_with(*quote) {
+            return &args[1..args.len() - 1];
+        }
+    }
+    args
+}
+
+// keybase only lets messages explode after 30 seconds to a week
+const MIN_EXPLODING_SECS: u64 = 30;
+const MAX_EXPLODING_SECS: u64 = 7 * 24 * 60 * 60;
+
+// `90s`, `5m`, `1h` or `7d` in seconds
+fn parse_lifetime(lifetime: &str) -> Result<u64, String> {
+    let unit_at = lifetime.find(|c: char| !c.is_ascii_digit()).unwrap_or(lifetime.len());
+    let (number, unit) = lifetime.split_at(unit_at);
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Not a lifetime: {} (try e.g. 30s, 5m, 1h or 7d)", lifetime)),
+    };
+    let secs = number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .ok_or_else(|| format!("Not a lifetime: {}", lifetime))?;
+    if !(MIN_EXPLODING_SECS..=MAX_EXPLODING_SECS).contains(&secs) {
+        return Err("Messages can explode after 30s at the soonest and 7d at the latest".to_string());
+    }
+    Ok(secs)
+}
+
+// the `3` in `/r3` or `/reply3`
+fn message_reference(command: &str, prefix: &str) -> Option<usize> {
+    command.strip_prefix(prefix)?.parse().ok()
+}
+
+pub fn parse_input(input: &str) -> Result<Command, String> {
+    if !input.starts_with('/') {
+        return Ok(Command::Event(UiEvent::SendMessage(input.to_owned())));
+    }
+    if let Some(message) = input.strip_prefix("//") {
+        return Ok(Command::Event(UiEvent::SendMessage(format!("/{}", message))));
+    }
+
+    let mut parts = input[1..].splitn(2, ' ');
+    let command = parts.next().unwrap_or("");
+    let args = parts.next().unwrap_or("").trim();
+
+    if let Some(reference) = message_reference(command, "reply") {
+        if args.is_empty() {
+            return Err(format!("Usage: /reply{} <message>", reference));
+        }
+        return Ok(Command::Reply(reference, args.to_string()));
+    }
+    if let Some(reference) = message_reference(command, "r") {
+        if args.is_empty() {
+            return Err(format!("Usage: /r{} <emoji>", reference));
+        }
+        return Ok(Command::React(reference, args.to_string()));
+    }
+
+    match command {
+        "rename" => {
+            let topic = unquote(args);
+            if topic.is_empty() {
+                return Err("Usage: /rename <newtopic>".to_string());
+            }
+            Ok(Command::Event(UiEvent::RenameChannel(topic.to_string())))
+        }
+        "switch" | "s" => {
+            let name = unquote(args);
+            if name.is_empty() {
+                return Err("Usage: /switch <conversation>".to_string());
+            }
+            Ok(Command::Switch(name.to_string()))
+        }
+        "react" => {
+            let reaction = unquote(args);
+            if reaction.is_empty() {
+                return Err("Usage: /react <emoji>".to_string());
+            }
+            Ok(Command::ReactToSelected(reaction.to_string()))
+        }
+        "quit" | "q" => Ok(Command::Quit),
+        "edit" => {
+            if args.is_empty() {
+                return Err("Usage: /edit <message>".to_string());
+            }
+            Ok(Command::Edit(args.to_string()))
+        }
+        "delete" => Ok(Command::Delete),
+        "explode" => {
+            let mut parts = args.splitn(2, ' ');
+            let lifetime = parts.next().unwrap_or("");
+            let body = parts.next().unwrap_or("").trim();
+            if body.is_empty() {
+                return Err("Usage: /explode <lifetime> <message>".to_string());
+            }
+            Ok(Command::Event(UiEvent::SendExploding {
+                body: body.to_string(),
+                lifetime_secs: parse_lifetime(lifetime)?,
+            }))
+        }
+        "download" => Ok(Command::DownloadSelected),
+        "export" => {
+            let path = unquote(args);
+            if path.is_empty() {
+                return Err("Usage: /export <path>".to_string());
+            }
+            Ok(Command::Event(UiEvent::ExportConversation { path: PathBuf::from(path) }))
+        }
+        "hide" => Ok(Command::Event(UiEvent::HideConversation)),
+        "unhide" => Ok(Command::PickHiddenConversation),
+        "mute" => Ok(Command::ToggleMute),
+        "goto" => {
+            if args.is_empty() {
+                return Err("Usage: /goto <message-link-or-id>".to_string());
+            }
+            Ok(Command::Goto(parse_message_link(args)?))
+        }
+        "only" if unquote(args).is_empty() => Ok(Command::FilterSender(None)),
+        "only" => Ok(Command::FilterSender(Some(
+            unquote(args).trim_start_matches('@').to_string(),
+        ))),
+        _ => Err(format!("Unknown command: /{}", command)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plain_message() {
+        match parse_input("hello /rename") {
+            Ok(Command::Event(UiEvent::SendMessage(msg))) => assert_eq!(msg, "hello /rename"),
+            _ => panic!("Expected a SendMessage event"),
+        }
+    }
+
+    #[test]
+    fn escaped_slash() {
+        match parse_input("//rename is a command") {
+            Ok(Command::Event(UiEvent::SendMessage(msg))) => assert_eq!(msg, "/rename is a command"),
+            _ => panic!("Expected a SendMessage event"),
+        }
+        match parse_input("///") {
+            Ok(Command::Event(UiEvent::SendMessage(msg))) => assert_eq!(msg, "//"),
+            _ => panic!("Expected a SendMessage event"),
+        }
+    }
+
+    #[test]
+    fn rename() {
+        match parse_input("/rename  random ") {
+            Ok(Command::Event(UiEvent::RenameChannel(topic))) => assert_eq!(topic, "random"),
+            _ => panic!("Expected a RenameChannel event"),
+        }
+        assert!(parse_input("/rename").is_err());
+        assert!(parse_input("/rename   ").is_err());
+    }
+
+    #[test]
+    fn edit() {
+        match parse_input("/edit  fixed it ") {
+            Ok(Command::Edit(msg)) => assert_eq!(msg, "fixed it"),
+            _ => panic!("Expected an Edit command"),
+        }
+        assert!(parse_input("/edit").is_err());
+        assert!(matches!(parse_input("/delete"), Ok(Command::Delete)));
+        assert!(matches!(parse_input("/download"), Ok(Command::DownloadSelected)));
+    }
+
+    #[test]
+    fn explode() {
+        match parse_input("/explode 1h  see you ") {
+            Ok(Command::Event(UiEvent::SendExploding { body, lifetime_secs })) => {
+                assert_eq!(body, "see you");
+                assert_eq!(lifetime_secs, 3600);
+            }
+            _ => panic!("Expected a SendExploding event"),
+        }
+        assert_eq!(parse_lifetime("30s"), Ok(30));
+        assert_eq!(parse_lifetime("5m"), Ok(300));
+        assert_eq!(parse_lifetime("7d"), Ok(604800));
+        assert!(parse_lifetime("10s").is_err());
+        assert!(parse_lifetime("8d").is_err());
+        assert!(parse_lifetime("1w").is_err());
+        assert!(parse_lifetime("h").is_err());
+        assert!(parse_input("/explode 1h").is_err());
+        assert!(parse_input("/explode soon hi").is_err());
+    }
+
+    #[test]
+    fn export() {
+        match parse_input("/export \"my chat.jsonl\"") {
+            Ok(Command::Event(UiEvent::ExportConversation { path })) => {
+                assert_eq!(path, PathBuf::from("my chat.jsonl"))
+            }
+            _ => panic!("Expected an ExportConversation event"),
+        }
+        assert!(parse_input("/export").is_err());
+    }
+
+    #[test]
+    fn hide_and_unhide() {
+        assert!(matches!(
+            parse_input("/hide"),
+            Ok(Command::Event(UiEvent::HideConversation))
+        ));
+        assert!(matches!(
+            parse_input("/unhide"),
+            Ok(Command::PickHiddenConversation)
+        ));
+        assert!(matches!(parse_input("/mute"), Ok(Command::ToggleMute)));
+    }
+
+    #[test]
+    fn only() {
+        match parse_input("/only @alice") {
+            Ok(Command::FilterSender(Some(user))) => assert_eq!(user, "alice"),
+            _ => panic!("Expected a FilterSender command"),
+        }
+        match parse_input("/only bob") {
+            Ok(Command::FilterSender(Some(user))) => assert_eq!(user, "bob"),
+            _ => panic!("Expected a FilterSender command"),
+        }
+        assert!(matches!(parse_input("/only"), Ok(Command::FilterSender(None))));
+    }
+
+    #[test]
+    fn message_references() {
+        match parse_input("/r3 :+1:") {
+            Ok(Command::React(3, reaction)) => assert_eq!(reaction, ":+1:"),
+            _ => panic!("Expected a React command"),
+        }
+        match parse_input("/reply12 sounds good") {
+            Ok(Command::Reply(12, msg)) => assert_eq!(msg, "sounds good"),
+            _ => panic!("Expected a Reply command"),
+        }
+        assert_eq!(parse_input("/r3").err().unwrap(), "Usage: /r3 <emoji>");
+        assert_eq!(parse_input("/reply1").err().unwrap(), "Usage: /reply1 <message>");
+        assert!(parse_input("/r :+1:").is_err());
+        assert!(parse_input("/rx :+1:").is_err());
+    }
+
+    #[test]
+    fn goto() {
+        match parse_input("/goto keybase://chat/alice,bob/12") {
+            Ok(Command::Goto(link)) => {
+                assert_eq!(link.conversation.as_deref(), Some("alice,bob"));
+                assert_eq!(link.message_id, 12);
+            }
+            _ => panic!("Expected a Goto command"),
+        }
+        match parse_input("/goto 5") {
+            Ok(Command::Goto(link)) => assert_eq!(link.conversation, None),
+            _ => panic!("Expected a Goto command"),
+        }
+        assert_eq!(parse_input("/goto").err().unwrap(), "Usage: /goto <message-link-or-id>");
+        assert!(parse_input("/goto nope").is_err());
+    }
+
+    #[test]
+    fn switch() {
+        match parse_input("/switch alice,bob") {
+            Ok(Command::Switch(name)) => assert_eq!(name, "alice,bob"),
+            _ => panic!("Expected a Switch command"),
+        }
+        match parse_input("/s  keybase#general ") {
+            Ok(Command::Switch(name)) => assert_eq!(name, "keybase#general"),
+            _ => panic!("Expected a Switch command"),
+        }
+        assert_eq!(parse_input("/switch").err().unwrap(), "Usage: /switch <conversation>");
+        assert_eq!(parse_input("/switch \"\"").err().unwrap(), "Usage: /switch <conversation>");
+    }
+
+    #[test]
+    fn react_and_quit() {
+        match parse_input("/react :tada:") {
+            Ok(Command::ReactToSelected(reaction)) => assert_eq!(reaction, ":tada:"),
+            _ => panic!("Expected a ReactToSelected command"),
+        }
+        assert_eq!(parse_input("/react ").err().unwrap(), "Usage: /react <emoji>");
+        assert!(matches!(parse_input("/quit"), Ok(Command::Quit)));
+        assert!(matches!(parse_input("/q"), Ok(Command::Quit)));
+    }
+
+    #[test]
+    fn quoting() {
+        match parse_input("/switch \"team#big news\"") {
+            Ok(Command::Switch(name)) => assert_eq!(name, "team#big news"),
+            _ => panic!("Expected a Switch command"),
+        }
+        match parse_input("/rename 'new topic'") {
+            Ok(Command::Event(UiEvent::RenameChannel(topic))) => assert_eq!(topic, "new topic"),
+            _ => panic!("Expected a RenameChannel event"),
+        }
+        match parse_input("/only \"@alice\"") {
+            Ok(Command::FilterSender(Some(user))) => assert_eq!(user, "alice"),
+            _ => panic!("Expected a FilterSender command"),
+        }
+        // a lone quote isn't quoting anything
+        match parse_input("/switch \"") {
+            Ok(Command::Switch(name)) => assert_eq!(name, "\""),
+            _ => panic!("Expected a Switch command"),
+        }
+        // message text keeps its quotes
+        match parse_input("/edit \"fixed\"") {
+            Ok(Command::Edit(msg)) => assert_eq!(msg, "\"fixed\""),
+            _ => panic!("Expected an Edit command"),
+        }
+    }
+
+    #[test]
+    fn unknown_command() {
+        assert_eq!(
+            parse_input("/frobnicate now").err().unwrap(),
+            "Unknown command: /frobnicate"
+        );
+        assert_eq!(parse_input("/").err().unwrap(), "Unknown command: /");
+        // commands are lowercase
+        assert!(parse_input("/QUIT").is_err());
+    }
+}
@@ -0,0 +1,107 @@
+// # completion.rs
+//
+// Completing `@name` in the message box: after an `@`, the people in the conversation whose name
+// fits what's been typed are listed above the box, and Tab or Enter puts the picked one in.
+
+use crate::mentions::is_name_char;
+
+// no more than this many names are listed at once
+pub const MAX_CANDIDATES: usize = 5;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Completion {
+    // where the `@` is in the message box
+    pub start: usize,
+    // the cursor, at the end of what's been typed after it
+    pub end: usize,
+    pub candidates: Vec<String>,
+    // index into `candidates`
+    pub selected: usize,
+}
+
+impl Completion {
+    // `content` with the selected name in place of what was typed, and where the cursor goes
+    pub fn apply(&self, content: &str) -> (String, usize) {
+        let name = &self.candidates[self.selected];
+        let completed = format!("{}@{} {}", &content[..self.start], name, &content[self.end..]);
+        (completed, self.start + name.len() + 2)
+    }
+
+    // step the selection up or down the list, going round at the ends
+    pub fn select(&mut self, up: bool) {
+        let len = self.candidates.len();
+        self.selected = if up { (self.selected + len - 1) % len } else { (self.selected + 1) % len };
+    }
+}
+
+// The `@name` being typed just before `cursor` in `content`: where its `@` is, and what's been
+// typed after it (which can be nothing yet). The `@` has to start a word, so not in `a@b.com`.
+pub fn mention_at(content: &str, cursor: usize) -> Option<(usize, &str)> {
+    let before = content.get(..cursor)?;
+    let start = before.rfind(|c| !is_name_char(c)).map_or(0, |i| i + 1);
+    let at = before[..start].strip_suffix('@')?;
+    if at.chars().next_back().is_some_and(is_name_char) {
+        return None;
+    }
+    Some((at.len(), &before[start..]))
+}
+
+// The `names` that `prefix` could be short for, ignoring case: the ones starting with it, then
+// the ones with it anywhere else. Otherwise they stay in the order they were in.
+pub fn rank_candidates<'a>(names: &'a [String], prefix: &str) -> Vec<&'a str> {
+    let prefix = prefix.to_lowercase();
+    let (mut starting, mut containing) = (vec![], vec![]);
+    for name in names {
+        let lower = name.to_lowercase();
+        if lower.starts_with(&prefix) {
+            starting.push(name.as_str());
+        } else if lower.contains(&prefix) {
+            containing.push(name.as_str());
+        }
+    }
+    starting.append(&mut containing);
+    starting
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_mention_being_typed() {
+        assert_eq!(mention_at("hi @al", 6), Some((3, "al")));
+        assert_eq!(mention_at("@", 1), Some((0, "")));
+        // the cursor's further back
+        assert_eq!(mention_at("hi @alice there", 9), Some((3, "alice")));
+        assert_eq!(mention_at("hi @alice there", 15), None);
+        assert_eq!(mention_at("mail a@b", 8), None);
+        assert_eq!(mention_at("no mention", 10), None);
+    }
+
+    #[test]
+    fn prefix_matches_first() {
+        let names: Vec<String> = ["malice", "bob", "Alicia", "alice", "carol"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        assert_eq!(rank_candidates(&names, "ali"), ["Alicia", "alice", "malice"]);
+        assert_eq!(rank_candidates(&names, "o"), ["bob", "carol"]);
+        assert_eq!(rank_candidates(&names, ""), ["malice", "bob", "Alicia", "alice", "carol"]);
+        assert!(rank_candidates(&names, "dave").is_empty());
+    }
+
+    #[test]
+    fn completes_selected_name() {
+        let mut completion = Completion {
+            start: 3,
+            end: 6,
+            candidates: vec!["alice".to_string(), "alicia".to_string()],
+            selected: 0,
+        };
+        completion.select(true);
+        assert_eq!(completion.selected, 1);
+        assert_eq!(completion.apply("hi @al, lunch?"), ("hi @alicia , lunch?".to_string(), 11));
+        completion.select(false);
+        assert_eq!(completion.apply("hi @al"), ("hi @alice ".to_string(), 10));
+    }
+}
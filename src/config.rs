@@ -0,0 +1,656 @@
+// # config.rs
+//
+// User configuration, read from `$HOME/.config/keybase-chat-tui/config.toml` (on linux). Every
+// key is optional, so an empty or missing file gives the defaults below.
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use dirs::{config_dir, download_dir};
+use serde::{Deserialize, Serialize};
+
+use crate::notify::NotificationPrivacy;
+use crate::sort::SortMode;
+use crate::status::{validate_template, DEFAULT_TEMPLATE};
+use crate::support::DEFAULT_MAX_BYTES;
+use crate::types::DEFAULT_MAX_MESSAGES;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // record every api command and response to this file (see `support.rs`)
+    pub support_bundle: Option<PathBuf>,
+    // replace message bodies in the support bundle so it's safe to share
+    pub support_bundle_redact: bool,
+    // stop recording once the support bundle reaches this many bytes
+    pub support_bundle_max_bytes: u64,
+    // per-conversation settings, keyed by the name shown in the sidebar (`team#channel` or
+    // `alice,bob`)
+    pub conversations: HashMap<String, ConversationConfig>,
+    // hard-wrap outgoing messages at this many columns
+    pub send_wrap_columns: Option<usize>,
+    // send messages as plain text, with anything keybase would take as markdown escaped
+    pub escape_markdown: bool,
+    pub layout: LayoutConfig,
+    pub keybase: KeybaseConfig,
+    // command that copied messages are piped into, e.g. `xclip -selection clipboard`
+    pub copy_command: Option<String>,
+    // where `/download` saves attachments, your downloads folder if unset
+    pub download_dir: Option<PathBuf>,
+    // how many messages to load when opening a conversation
+    pub initial_message_count: u32,
+    // the most messages kept per conversation, the oldest ones are dropped past this (but there's
+    // always room for `initial_message_count`)
+    pub max_messages: usize,
+    // load messages for all unread conversations at startup
+    pub prefetch_unread: bool,
+    // show what was on screen last time straight away at startup, unless it was longer ago than
+    // this (in seconds). 0 (the default) doesn't keep a cache at all.
+    pub cache_ttl_secs: u64,
+    // only load this many of the most active conversations at startup, with the rest a page at a
+    // time from the bottom of the sidebar
+    pub startup_conversation_limit: Option<u32>,
+    // how often (in seconds) to check that the chat listener is still alive, 0 to never check
+    pub listener_heartbeat_secs: u64,
+    // show a hidden conversation in the sidebar again when someone sends a message to it
+    pub unhide_on_message: bool,
+    // command to run for every incoming message, with the message as json on stdin
+    pub on_message_hook: Option<String>,
+    // command to show a desktop notification, run with a title and body as extra arguments
+    pub notify_command: Option<String>,
+    // how much of a message goes in its notification
+    pub notification_privacy: NotificationPrivacy,
+    // sound file played with each notification (when built with the `sound` feature), unless
+    // the conversation has its own
+    pub notification_sound: Option<PathBuf>,
+    // command that plays a sound file, run with the file as an extra argument
+    pub sound_command: String,
+    // what to show in the status bar (see `status.rs` for the tokens), empty to hide it
+    pub status_bar: String,
+    // how to order the conversation list (cycle through the modes with Alt+s)
+    pub sort_mode: SortMode,
+    // conversation names, top to bottom, for the "manual" sort mode
+    pub conversation_order: Vec<String>,
+    // conversations hidden from the sidebar with `/hide`
+    #[serde(skip)]
+    pub hidden: ConversationSet,
+    // conversations muted with `/mute`
+    #[serde(skip)]
+    pub muted: ConversationSet,
+}
+
+// Some conversations, by name (e.g. the hidden ones). The app writes these itself, so they live
+// in their own files (`hidden.toml` and `muted.toml`, next to `config.toml`) instead of the
+// user's config.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConversationSet {
+    pub conversations: BTreeSet<String>,
+    // where to save changes, or nowhere if unset
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl ConversationSet {
+    // `file_name` in the config directory
+    pub fn load(file_name: &str) -> Self {
+        if let Some(dir) = config_dir() {
+            let path = PathBuf::new().join(dir).join("keybase-chat-tui").join(file_name);
+            return match read_file(&path, ConversationSet::parse) {
+                Ok(set) => ConversationSet {
+                    path: Some(path),
+                    ..set.unwrap_or_default()
+                },
+                // not saved either, so the broken file is still there to fix
+                Err(e) => {
+                    warn!("Starting with no {}: {}", file_name, e);
+                    ConversationSet::default()
+                }
+            };
+        }
+        ConversationSet::default()
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(path, toml::to_string(self)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LayoutConfig {
+    // percentage of the terminal width used by the conversation list, or sized to fit the
+    // conversation names if unset
+    pub sidebar_percent: Option<usize>,
+    // columns of space between the conversation list and the chat area
+    pub gutter: usize,
+    // draw a vertical line in the gutter
+    pub separator: bool,
+    // leave out the sender on consecutive messages from the same person (toggle with Alt+d)
+    pub compact: bool,
+    // draw replies indented under the message they reply to, instead of in the order they were
+    // sent
+    pub threaded: bool,
+    // show how many members a team has next to its channels, e.g. `team#general (42)`
+    pub member_counts: bool,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        LayoutConfig {
+            sidebar_percent: None,
+            gutter: 1,
+            separator: false,
+            compact: false,
+            threaded: false,
+            member_counts: false,
+        }
+    }
+}
+
+// how to run keybase, for installs that aren't on the PATH or need a wrapper
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct KeybaseConfig {
+    pub binary_path: PathBuf,
+    // go before the arguments of every keybase command, e.g. `["--home", "/other/home"]`
+    pub extra_args: Vec<String>,
+    // how many times to try a `keybase chat api` call that doesn't get an answer at all, e.g.
+    // while the keybase service restarts
+    pub api_attempts: u32,
+    // wait this long before the first retry, then twice as long before each one after that
+    pub api_retry_delay_ms: u64,
+}
+
+impl Default for KeybaseConfig {
+    fn default() -> Self {
+        KeybaseConfig {
+            binary_path: PathBuf::from("keybase"),
+            extra_args: vec![],
+            api_attempts: 3,
+            api_retry_delay_ms: 250,
+        }
+    }
+}
+
+impl KeybaseConfig {
+    // `keybase <extra args> <args>`
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(&self.binary_path);
+        command.args(&self.extra_args).args(args);
+        command
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ConversationConfig {
+    // added to the start of every message sent to the conversation
+    pub send_prefix: String,
+    // added to the end of every message sent to the conversation
+    pub send_suffix: String,
+    // played instead of the default `notification_sound` for this conversation
+    pub notification_sound: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            support_bundle: None,
+            support_bundle_redact: true,
+            support_bundle_max_bytes: DEFAULT_MAX_BYTES,
+            conversations: HashMap::new(),
+            send_wrap_columns: None,
+            escape_markdown: false,
+            layout: LayoutConfig::default(),
+            keybase: KeybaseConfig::default(),
+            copy_command: None,
+            download_dir: None,
+            initial_message_count: 20,
+            max_messages: DEFAULT_MAX_MESSAGES,
+            prefetch_unread: false,
+            cache_ttl_secs: 0,
+            startup_conversation_limit: None,
+            listener_heartbeat_secs: 60,
+            unhide_on_message: false,
+            on_message_hook: None,
+            notify_command: None,
+            notification_privacy: NotificationPrivacy::default(),
+            notification_sound: None,
+            sound_command: "paplay".to_string(),
+            status_bar: DEFAULT_TEMPLATE.to_string(),
+            sort_mode: SortMode::default(),
+            conversation_order: vec![],
+            hidden: ConversationSet::default(),
+            muted: ConversationSet::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Self {
+        if let Some(dir) = config_dir() {
+            let config_path = PathBuf::new().join(dir).join("keybase-chat-tui/config.toml");
+            match read_file(&config_path, Config::parse) {
+                Ok(Some(mut config)) => {
+                    config.hidden = ConversationSet::load("hidden.toml");
+                    config.muted = ConversationSet::load("muted.toml");
+                    return config;
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Using the default config: {}", e),
+            }
+        }
+        Config {
+            hidden: ConversationSet::load("hidden.toml"),
+            muted: ConversationSet::load("muted.toml"),
+            ..Config::default()
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, Box<dyn Error>> {
+        let config: Config = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(percent) = self.layout.sidebar_percent {
+            if percent == 0 || percent >= 100 {
+                return Err(format!(
+                    "layout.sidebar_percent must be between 1 and 99, got {}",
+                    percent
+                )
+                .into());
+            }
+        }
+        if !(1..=1000).contains(&self.initial_message_count) {
+            return Err(format!(
+                "initial_message_count must be between 1 and 1000, got {}",
+                self.initial_message_count
+            )
+            .into());
+        }
+        if self.max_messages == 0 {
+            return Err("max_messages must be more than 0".into());
+        }
+        if self.keybase.api_attempts == 0 {
+            return Err("keybase.api_attempts must be more than 0".into());
+        }
+        if self.startup_conversation_limit == Some(0) {
+            return Err("startup_conversation_limit must be more than 0".into());
+        }
+        if self.send_wrap_columns == Some(0) {
+            return Err("send_wrap_columns must be more than 0".into());
+        }
+        validate_template(&self.status_bar)?;
+        Ok(())
+    }
+
+    pub fn download_dir(&self) -> PathBuf {
+        self.download_dir
+            .clone()
+            .or_else(download_dir)
+            .unwrap_or_else(|| PathBuf::from("."))
+    }
+
+    // how many messages each conversation keeps, enough for a freshly opened one at least
+    pub fn messages_kept(&self) -> usize {
+        self.max_messages.max(self.initial_message_count as usize)
+    }
+
+    // the message body that actually gets sent, after wrapping (and escaping) it and applying any
+    // prefix/suffix configured for the conversation
+    pub fn outgoing_message(&self, conversation_name: &str, body: String) -> String {
+        let body = match self.send_wrap_columns {
+            Some(columns) => hard_wrap(&body, columns),
+            None => body,
+        };
+        let body = if self.escape_markdown { escape_markdown(&body) } else { body };
+        match self.conversations.get(conversation_name) {
+            Some(c) => format!("{}{}{}", c.send_prefix, body, c.send_suffix),
+            None => body,
+        }
+    }
+}
+
+// Break lines longer than `columns` at the last space that fits. Existing newlines are kept, and
+// words longer than a whole line (like urls) get a line to themselves rather than being split.
+fn hard_wrap(text: &str, columns: usize) -> String {
+    text.split('\n')
+        .map(|line| {
+            let mut wrapped: Vec<String> = vec![];
+            let mut current = String::new();
+            // splitting on single spaces keeps indentation and repeated spaces intact
+            for (i, word) in line.split(' ').enumerate() {
+                let width = current.chars().count() + 1 + word.chars().count();
+                if width > columns && !current.trim().is_empty() {
+                    wrapped.push(std::mem::take(&mut current));
+                } else if i > 0 {
+                    current.push(' ');
+                }
+                current.push_str(word);
+            }
+            wrapped.push(current);
+            wrapped.join("\n")
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// A backslash before everything keybase formats (`*bold*`, `_italics_`, `~strike~`, `` `code` ``
+// and `> quotes`), so it shows up as typed.
+fn escape_markdown(text: &str) -> String {
+    text.split('\n')
+        .map(|line| {
+            let rest = line.trim_start();
+            let mut escaped = line[..line.len() - rest.len()].to_string();
+            if rest.starts_with('>') {
+                escaped.push('\\');
+            }
+            for c in rest.chars() {
+                if matches!(c, '\\' | '*' | '_' | '~' | '`') {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            escaped
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Read and parse `path`, or None if it isn't there. The error says which file it was.
+fn read_file<T>(
+    path: &Path,
+    parse: fn(&str) -> Result<T, Box<dyn Error>>,
+) -> Result<Option<T>, Box<dyn Error>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e).into()),
+    };
+    match parse(&contents) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) => Err(format!("Failed to parse {}: {}", path.display(), e).into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty_config() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn support_bundle() {
+        let config = Config::parse(
+            r#"
+            support_bundle = "/tmp/bundle.jsonl"
+            support_bundle_redact = false
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.support_bundle, Some(PathBuf::from("/tmp/bundle.jsonl")));
+        assert!(!config.support_bundle_redact);
+        assert_eq!(config.support_bundle_max_bytes, DEFAULT_MAX_BYTES);
+    }
+
+    #[test]
+    fn keybase_command() {
+        let config = Config::parse(
+            r#"
+            [keybase]
+            binary_path = "/opt/keybase/bin/keybase"
+            extra_args = ["--home", "/tmp/kb"]
+            "#,
+        )
+        .unwrap();
+
+        let command = config.keybase.command(&["chat", "api"]);
+        assert_eq!(command.get_program(), "/opt/keybase/bin/keybase");
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(args, ["--home", "/tmp/kb", "chat", "api"]);
+
+        // on the PATH by default
+        let command = KeybaseConfig::default().command(&["status"]);
+        assert_eq!(command.get_program(), "keybase");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["status"]);
+    }
+
+    #[test]
+    fn api_retries() {
+        let config = Config::parse("[keybase]\napi_attempts = 5\napi_retry_delay_ms = 100").unwrap();
+        assert_eq!(config.keybase.api_attempts, 5);
+        assert_eq!(config.keybase.api_retry_delay_ms, 100);
+        assert_eq!(Config::default().keybase.api_attempts, 3);
+        assert!(Config::parse("[keybase]\napi_attempts = 0").is_err());
+    }
+
+    #[test]
+    fn layout() {
+        let config = Config::parse(
+            r#"
+            [layout]
+            sidebar_percent = 25
+            separator = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.layout.sidebar_percent, Some(25));
+        assert_eq!(config.layout.gutter, 1);
+        assert!(config.layout.separator);
+
+        assert!(Config::parse("[layout]\nsidebar_percent = 100").is_err());
+        assert!(Config::parse("[layout]\nsidebar_percent = 0").is_err());
+    }
+
+    #[test]
+    fn send_prefix_and_suffix() {
+        let config = Config::parse(
+            r#"
+            [conversations."team#general"]
+            send_prefix = "[nik] "
+            send_suffix = " #tag"
+
+            [conversations."alice,bob"]
+            send_suffix = "\n-- bob"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.outgoing_message("team#general", "hi".to_string()),
+            "[nik] hi #tag"
+        );
+        assert_eq!(
+            config.outgoing_message("alice,bob", "hi".to_string()),
+            "hi\n-- bob"
+        );
+        assert_eq!(config.outgoing_message("team#random", "hi".to_string()), "hi");
+    }
+
+    #[test]
+    fn send_wrap_columns() {
+        let config = Config::parse(
+            r#"
+            send_wrap_columns = 10
+
+            [conversations."team#general"]
+            send_prefix = "> "
+            "#,
+        )
+        .unwrap();
+
+        // the prefix doesn't count towards the width
+        assert_eq!(
+            config.outgoing_message("team#general", "one two three four".to_string()),
+            "> one two\nthree four"
+        );
+        assert!(Config::parse("send_wrap_columns = 0").is_err());
+    }
+
+    #[test]
+    fn escape_markdown() {
+        let config = Config::parse(
+            r#"
+            escape_markdown = true
+
+            [conversations."team#general"]
+            send_prefix = "> "
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.outgoing_message("alice,bob", "*not* _bold_ ~or~ `code`".to_string()),
+            "\\*not\\* \\_bold\\_ \\~or\\~ \\`code\\`"
+        );
+        assert_eq!(
+            config.outgoing_message("alice,bob", "a \\ b\n  > not a quote".to_string()),
+            "a \\\\ b\n  \\> not a quote"
+        );
+        // the prefix is still markdown
+        assert_eq!(config.outgoing_message("team#general", "2 * 3".to_string()), "> 2 \\* 3");
+        assert_eq!(Config::default().outgoing_message("alice,bob", "*hi*".to_string()), "*hi*");
+    }
+
+    #[test]
+    fn initial_message_count() {
+        assert_eq!(Config::default().initial_message_count, 20);
+        let config = Config::parse("initial_message_count = 100").unwrap();
+        assert_eq!(config.initial_message_count, 100);
+        assert!(Config::parse("initial_message_count = 1000").is_ok());
+        assert!(Config::parse("initial_message_count = 0").is_err());
+        assert!(Config::parse("initial_message_count = 1001").is_err());
+    }
+
+    #[test]
+    fn max_messages() {
+        assert_eq!(Config::default().max_messages, 500);
+        let config = Config::parse("max_messages = 2000").unwrap();
+        assert_eq!(config.max_messages, 2000);
+        assert!(Config::parse("max_messages = 0").is_err());
+        assert_eq!(Config::parse("max_messages = 10").unwrap().messages_kept(), 20);
+        assert_eq!(config.messages_kept(), 2000);
+    }
+
+    #[test]
+    fn startup_conversation_limit() {
+        let config = Config::parse("startup_conversation_limit = 50").unwrap();
+        assert_eq!(config.startup_conversation_limit, Some(50));
+        assert!(Config::parse("startup_conversation_limit = 0").is_err());
+    }
+
+    #[test]
+    fn wrap_at_boundary() {
+        // exactly fits
+        assert_eq!(hard_wrap("aaaa bbbbb", 10), "aaaa bbbbb");
+        assert_eq!(hard_wrap("aaaa bbbbbb", 10), "aaaa\nbbbbbb");
+        assert_eq!(hard_wrap("", 10), "");
+    }
+
+    #[test]
+    fn wrap_long_words() {
+        assert_eq!(
+            hard_wrap("see https://example.com/a/very/long/path ok", 10),
+            "see\nhttps://example.com/a/very/long/path\nok"
+        );
+    }
+
+    #[test]
+    fn wrap_keeps_newlines_and_indentation() {
+        assert_eq!(
+            hard_wrap("first line here\n\n  indented code", 10),
+            "first line\nhere\n\n  indented\ncode"
+        );
+    }
+
+    #[test]
+    fn status_bar() {
+        let config = Config::parse(r#"status_bar = "{time} {user}""#).unwrap();
+        assert_eq!(config.status_bar, "{time} {user}");
+        assert!(Config::parse(r#"status_bar = "{weather}""#).is_err());
+    }
+
+    #[test]
+    fn sort_mode() {
+        assert_eq!(Config::parse("").unwrap().sort_mode, SortMode::Activity);
+        let config = Config::parse(
+            r#"
+            sort_mode = "manual"
+            conversation_order = ["team#general", "alice,bob"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.sort_mode, SortMode::Manual);
+        assert_eq!(config.conversation_order, ["team#general", "alice,bob"]);
+        assert_eq!(
+            Config::parse(r#"sort_mode = "unread-first""#).unwrap().sort_mode,
+            SortMode::UnreadFirst
+        );
+        assert!(Config::parse(r#"sort_mode = "random""#).is_err());
+    }
+
+    #[test]
+    fn notifications() {
+        let config = Config::parse(
+            r#"
+            notify_command = "notify-send -a keybase"
+            notification_privacy = "sender_only"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.notify_command.as_deref(), Some("notify-send -a keybase"));
+        assert_eq!(config.notification_privacy, NotificationPrivacy::SenderOnly);
+        assert_eq!(Config::parse("").unwrap().notification_privacy, NotificationPrivacy::Full);
+        assert!(Config::parse(r#"notification_privacy = "none""#).is_err());
+    }
+
+    #[test]
+    fn conversation_sets() {
+        let mut hidden =
+            ConversationSet::parse(r#"conversations = ["team#random", "alice,bob"]"#).unwrap();
+        assert!(hidden.conversations.contains("team#random"));
+        assert!(hidden.conversations.contains("alice,bob"));
+
+        hidden.conversations.remove("alice,bob");
+        let saved = toml::to_string(&hidden).unwrap();
+        assert_eq!(ConversationSet::parse(&saved).unwrap(), hidden);
+        assert_eq!(ConversationSet::parse("").unwrap(), ConversationSet::default());
+    }
+
+    #[test]
+    fn unreadable_files() {
+        let dir = std::env::temp_dir().join(format!("keybase-chat-tui-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hidden.toml");
+
+        assert_eq!(read_file(&path, ConversationSet::parse).unwrap(), None);
+        std::fs::write(&path, "conversations = [").unwrap();
+        let e = read_file(&path, ConversationSet::parse).unwrap_err();
+        assert!(e.to_string().starts_with(&format!("Failed to parse {}", path.display())));
+        std::fs::write(&path, r#"conversations = ["team#random"]"#).unwrap();
+        let set = read_file(&path, ConversationSet::parse).unwrap().unwrap();
+        assert!(set.conversations.contains("team#random"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+// # connection.rs
+//
+// Whether keybase can be reached, going by how the api calls made through a client went. The
+// controller talks to keybase through a `TrackedClient` and shows whatever the latest call said
+// in the status bar.
+
+use std::error::Error;
+use std::path::Path;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::Receiver;
+
+use crate::client::KeybaseClient;
+use crate::error::KeybaseError;
+use crate::status::ConnectionStatus;
+use crate::types::{
+    Channel, ConversationPage, KeybaseConversation, ListenerEvent, MessagePage,
+};
+
+type ApiResult<T> = Result<T, Box<dyn Error>>;
+
+// What an api call says about the connection to keybase. It answering with an error (e.g. not
+// being allowed to post somewhere) still means it's there.
+fn connection_status<T>(result: &ApiResult<T>) -> ConnectionStatus {
+    match result {
+        Err(e) if e.downcast_ref::<KeybaseError>().is_none() => ConnectionStatus::Error,
+        _ => ConnectionStatus::Connected,
+    }
+}
+
+pub struct TrackedClient<C> {
+    inner: C,
+    // what the last call said, until it's taken
+    status: Mutex<Option<ConnectionStatus>>,
+}
+
+impl<C> TrackedClient<C> {
+    pub fn new(inner: C) -> Self {
+        TrackedClient {
+            inner,
+            status: Mutex::new(None),
+        }
+    }
+
+    // how the connection looked after the last call, if there's been one since last time
+    pub fn take_status(&self) -> Option<ConnectionStatus> {
+        self.status.lock().unwrap().take()
+    }
+
+    fn track<T>(&self, result: ApiResult<T>) -> ApiResult<T> {
+        *self.status.lock().unwrap() = Some(connection_status(&result));
+        result
+    }
+}
+
+#[async_trait]
+impl<C: KeybaseClient + Send + Sync> KeybaseClient for TrackedClient<C> {
+    fn get_receiver(&self) -> Receiver<ListenerEvent> {
+        self.inner.get_receiver()
+    }
+
+    // the listener says for itself how it's going
+    fn restart_listener(&self) -> ApiResult<()> {
+        self.inner.restart_listener()
+    }
+
+    async fn fetch_conversations(&self) -> ApiResult<Vec<KeybaseConversation>> {
+        self.track(self.inner.fetch_conversations().await)
+    }
+
+    async fn fetch_conversation_page(&self, count: u32, next: Option<String>) -> ApiResult<ConversationPage> {
+        self.track(self.inner.fetch_conversation_page(count, next).await)
+    }
+
+    async fn fetch_message_page(&self, conversation: &KeybaseConversation, count: u32, next: Option<String>) -> ApiResult<MessagePage> {
+        self.track(self.inner.fetch_message_page(conversation, count, next).await)
+    }
+
+    async fn send_message<T>(&self, channel: &Channel, message: T) -> ApiResult<Option<u64>>
+    where
+        T: Into<String> + Send + 'static,
+    {
+        self.track(self.inner.send_message(channel, message).await)
+    }
+
+    async fn pin_message(&self, channel: &Channel, message_id: u64) -> ApiResult<()> {
+        self.track(self.inner.pin_message(channel, message_id).await)
+    }
+
+    async fn unpin_message(&self, channel: &Channel, message_id: u64) -> ApiResult<()> {
+        self.track(self.inner.unpin_message(channel, message_id).await)
+    }
+
+    async fn whoami(&self) -> ApiResult<String> {
+        self.track(self.inner.whoami().await)
+    }
+
+    async fn rename_channel(&self, channel: &Channel, new_topic: &str) -> ApiResult<()> {
+        self.track(self.inner.rename_channel(channel, new_topic).await)
+    }
+
+    async fn mark_as_read(&self, conversation_id: &str, message_id: u64) -> ApiResult<()> {
+        self.track(self.inner.mark_as_read(conversation_id, message_id).await)
+    }
+
+    async fn react(&self, channel: &Channel, message_id: u64, reaction: &str) -> ApiResult<()> {
+        self.track(self.inner.react(channel, message_id, reaction).await)
+    }
+
+    async fn send_reply(&self, channel: &Channel, message_id: u64, message: String) -> ApiResult<()> {
+        self.track(self.inner.send_reply(channel, message_id, message).await)
+    }
+
+    async fn send_exploding(&self, channel: &Channel, message: String, lifetime_secs: u64) -> ApiResult<()> {
+        self.track(self.inner.send_exploding(channel, message, lifetime_secs).await)
+    }
+
+    async fn edit_message(&self, channel: &Channel, message_id: u64, message: String) -> ApiResult<()> {
+        self.track(self.inner.edit_message(channel, message_id, message).await)
+    }
+
+    async fn delete_message(&self, channel: &Channel, message_id: u64) -> ApiResult<()> {
+        self.track(self.inner.delete_message(channel, message_id).await)
+    }
+
+    async fn join_channel(&self, channel: &Channel) -> ApiResult<()> {
+        self.track(self.inner.join_channel(channel).await)
+    }
+
+    async fn new_conversation(&self, channel: &Channel) -> ApiResult<()> {
+        self.track(self.inner.new_conversation(channel).await)
+    }
+
+    async fn download_attachment(&self, conversation_id: &str, message_id: u64, output: &Path) -> ApiResult<()> {
+        self.track(self.inner.download_attachment(conversation_id, message_id, output).await)
+    }
+
+    async fn list_channels(&self, team: &str) -> ApiResult<Vec<String>> {
+        self.track(self.inner.list_channels(team).await)
+    }
+
+    async fn fetch_member_count(&self, team: &str, refresh: bool) -> ApiResult<usize> {
+        self.track(self.inner.fetch_member_count(team, refresh).await)
+    }
+
+    async fn fetch_members(&self, team: &str, refresh: bool) -> ApiResult<Vec<String>> {
+        self.track(self.inner.fetch_members(team, refresh).await)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::MockKeybaseClient;
+
+    #[tokio::test]
+    async fn tracks_every_call() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_mark_as_read()
+            .times(1)
+            .returning(|_, _| Err("keybase isn't running".into()));
+        client.expect_list_channels()
+            .times(1)
+            .returning(|_| Err(KeybaseError::UnexpectedResponse.into()));
+        client.expect_whoami()
+            .times(1)
+            .returning(|| Ok("me".to_string()));
+        let client = TrackedClient::new(client);
+        assert_eq!(client.take_status(), None);
+
+        client.mark_as_read("test1", 1).await.unwrap_err();
+        assert_eq!(client.take_status(), Some(ConnectionStatus::Error));
+        assert_eq!(client.take_status(), None);
+        // it answered, just not with what was wanted
+        client.list_channels("team").await.unwrap_err();
+        assert_eq!(client.take_status(), Some(ConnectionStatus::Connected));
+        client.whoami().await.unwrap();
+        assert_eq!(client.take_status(), Some(ConnectionStatus::Connected));
+    }
+}
@@ -1,45 +1,672 @@
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+use futures::stream::{self, LocalBoxStream, StreamExt};
 use tokio::sync::mpsc::{Receiver};
+use tokio::time::Instant;
 
-use crate::client::{KeybaseClient};
+use crate::cache::{reconcile, Cache};
+use crate::client::{message_pages, KeybaseClient};
+use crate::config::Config;
+use crate::connection::TrackedClient;
+use crate::export::json_lines;
 use crate::state::ApplicationState;
-use crate::types::{ListenerEvent, UiEvent};
+use crate::status::ConnectionStatus;
+use crate::types::{Channel, Conversation, DeliveryState, KeybaseConversation, ListenerEvent, MemberType, Message, MessageBody, MessagePage, MessageType, Sender, UiEvent};
+use crate::watchdog::ListenerWatchdog;
+
+// how many older messages to load at a time when scrolling back
+const HISTORY_PAGE_SIZE: u32 = 20;
+
+// how far back `/goto` looks for a message before giving up
+const GOTO_PAGE_SIZE: u32 = 100;
+const GOTO_MAX_PAGES: usize = 10;
+
+// when switching, messages are fetched this many at a time so the first ones show up quickly
+const FETCH_CHUNK_SIZE: u32 = 10;
+
+// how many conversations to prefetch at the same time
+const PREFETCH_CONCURRENCY: usize = 4;
+
+// a conversation that hasn't been fetched yet is switched to straight away, but only fetched once
+// it's been on screen this long, so skipping past a few with next/prev doesn't fetch every one on
+// the way
+const SWITCH_DEBOUNCE: Duration = Duration::from_millis(150);
+
+type FetchResult = Result<MessagePage, Box<dyn std::error::Error>>;
+// the rest of a conversation's messages after the first page, a page at a time (see
+// `switch_conversation`)
+type OlderPages = LocalBoxStream<'static, (String, FetchResult)>;
 
 pub struct Controller<S, C> {
-    client: C,
+    // every call through it says whether keybase can be reached (see `connection.rs`)
+    client: Rc<TrackedClient<C>>,
     state: S,
     ui_receiver: Receiver<UiEvent>,
+    config: Config,
+    // where the next page of conversations starts, if there is one
+    conversations_next: Option<String>,
+    // something outside the UI (e.g. a SIGINT) asking `process_events` to stop, which it also
+    // does once this is closed
+    shutdown_receiver: Receiver<()>,
+    // where the conversations are cached between runs (see `cache.rs`)
+    cache_path: Option<PathBuf>,
+    // unread conversations' messages on their way in (see `prefetch_unread`)
+    prefetching: Option<LocalBoxStream<'static, (String, FetchResult)>>,
+    // older messages of the conversation last switched to, on their way in after the newest
+    older_pages: Option<OlderPages>,
+    // the conversation last switched to while its newest messages are still to come (see
+    // `switch_later`), and when fetching them starts if it hasn't yet
+    awaiting_first_page: Option<String>,
+    fetch_at: Option<Instant>,
 }
 
-impl<S: ApplicationState, C: KeybaseClient> Controller<S, C>{
-    pub fn new(client: C, state: S, receiver: Receiver<UiEvent>) -> Self {
+impl<S: ApplicationState, C: KeybaseClient + Send + Sync + 'static> Controller<S, C>{
+    pub fn new(client: C, state: S, receiver: Receiver<UiEvent>, shutdown_receiver: Receiver<()>) -> Self {
         Controller {
-            client,
+            client: Rc::new(TrackedClient::new(client)),
             state,
-            ui_receiver: receiver
+            ui_receiver: receiver,
+            config: Config::default(),
+            conversations_next: None,
+            shutdown_receiver,
+            cache_path: None,
+            prefetching: None,
+            older_pages: None,
+            awaiting_first_page: None,
+            fetch_at: None,
+        }
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_cache(mut self, path: PathBuf) -> Self {
+        self.cache_path = Some(path);
+        self
+    }
+
+    // last time's conversations, on screen until keybase answers
+    fn load_cache(&mut self) -> bool {
+        let path = match &self.cache_path {
+            Some(path) if self.config.cache_ttl_secs > 0 => path,
+            _ => return false,
+        };
+        let cache = match Cache::load(path, self.config.cache_ttl_secs, unix_time()) {
+            Some(cache) => cache,
+            None => return false,
+        };
+        if let Some(username) = &cache.username {
+            self.state.set_my_username(username);
+        }
+        let conversations = cache.into_conversations();
+        let first_id = match conversations.first() {
+            Some(convo) => convo.id.clone(),
+            None => return false,
+        };
+        self.state.set_conversations(conversations);
+        if let Err(e) = self.state.set_current_conversation(&first_id) {
+            error!("{}", e);
+        }
+        true
+    }
+
+    // for next time, once keybase has answered (so there's something worth keeping)
+    pub fn save_cache(&self) {
+        let path = match &self.cache_path {
+            Some(path) if self.config.cache_ttl_secs > 0 => path,
+            _ => return,
+        };
+        let cache = Cache::new(self.state.get_conversations(), self.state.get_my_username(), unix_time());
+        match cache.save(path) {
+            Ok(()) => info!("Saved {} conversations to {}", cache.conversations.len(), path.display()),
+            Err(e) => error!("Failed to save the cache to {}: {}", path.display(), e),
         }
     }
 
     pub async fn init(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let conversations = self.client.fetch_conversations().await?;
-        if !conversations.is_empty() {
-            let first_id = conversations[0].id.clone();
-            self.state.set_conversations(conversations.into_iter().map(|c| c.into()).collect());
-            self.state.set_current_conversation(&first_id);
+        let cached = self.load_cache();
+        // without it there's just no telling which messages (or which conversation) are yours
+        match self.client.whoami().await {
+            Ok(username) => self.state.set_my_username(&username),
+            Err(e) => error!("Failed to find out who's logged in: {}", e),
+        }
+
+        let conversations = match self.config.startup_conversation_limit {
+            Some(limit) => {
+                let page = self.client.fetch_conversation_page(limit, None).await?;
+                self.conversations_next = page.next;
+                page.conversations
+            }
+            None => self.client.fetch_conversations().await?,
+        };
+        let ids: Vec<String> = conversations.iter().map(|c| c.id.clone()).collect();
+        // stay on the conversation picked while the cached list was up, if it's still there
+        let current_id = self
+            .state
+            .get_current_conversation()
+            .map(|c| c.id.clone())
+            .filter(|id| ids.contains(id))
+            .or_else(|| ids.first().cloned());
+        // anything that hasn't changed since it was cached keeps its messages
+        let conversations: Vec<Conversation> = conversations
+            .into_iter()
+            .map(|c| {
+                let cached = self.state.get_conversation(&c.id);
+                reconcile(c, cached)
+            })
+            .collect();
+        if cached && (conversations.is_empty() || self.config.startup_conversation_limit.is_some()) {
+            // out with the cached list
+            self.state.replace_conversations(vec![]);
+        }
+        if let Some(current_id) = current_id {
+            if self.config.startup_conversation_limit.is_some() {
+                let more = self.conversations_next.is_some();
+                self.state.append_conversations(conversations, more);
+            } else {
+                self.state.replace_conversations(conversations);
+            }
+            self.state.set_current_conversation(&current_id)?;
+            self.setup_conversations(&ids).await;
         }
+        self.update_connection_status();
         Ok(())
     }
 
+    // the next page of conversations after `startup_conversation_limit`, which go at the bottom
+    // of the sidebar since they're less active than the ones already loaded
+    async fn load_more_conversations(&mut self) {
+        let limit = self.config.startup_conversation_limit.unwrap_or(u32::MAX);
+        // taken straight away, so asking twice doesn't load the same page twice
+        let next = match self.conversations_next.take() {
+            Some(next) => next,
+            None => return,
+        };
+        let page = match self.client.fetch_conversation_page(limit, Some(next.clone())).await {
+            Ok(page) => page,
+            Err(e) => {
+                error!("Failed to load more conversations: {}", e);
+                // try again next time
+                self.conversations_next = Some(next);
+                return;
+            }
+        };
+        self.conversations_next = page.next;
+        let ids: Vec<String> = page.conversations.iter().map(|c| c.id.clone()).collect();
+        let more = self.conversations_next.is_some();
+        self.state
+            .append_conversations(page.conversations.into_iter().map(|c| c.into()).collect(), more);
+        self.setup_conversations(&ids).await;
+    }
+
+    // Send whatever the UI asked to send that hasn't gone yet, e.g. a message typed just before
+    // quitting. Nothing else it asked for matters any more, apart from which conversation those
+    // were typed in.
+    pub async fn shutdown(&mut self) {
+        self.ui_receiver.close();
+        let mut current = self.state.get_current_conversation().map(|c| c.id.clone());
+        while let Some(event) = self.ui_receiver.recv().await {
+            if let UiEvent::SwitchConversation(conversation_id) = &event {
+                current = Some(conversation_id.clone());
+                continue;
+            }
+            let convo = match current.as_deref().and_then(|id| self.state.get_conversation(id)) {
+                Some(convo) => convo,
+                None => continue,
+            };
+            let channel = &convo.data.channel;
+            let result = match event {
+                UiEvent::SendMessage(msg) => {
+                    let msg = self.config.outgoing_message(&convo.get_name(), msg);
+                    self.client.send_message(channel, msg).await.map(|_| ())
+                }
+                UiEvent::Reply(message_id, msg) => {
+                    let msg = self.config.outgoing_message(&convo.get_name(), msg);
+                    self.client.send_reply(channel, message_id, msg).await
+                }
+                UiEvent::SendExploding { body, lifetime_secs } => {
+                    let msg = self.config.outgoing_message(&convo.get_name(), body);
+                    self.client.send_exploding(channel, msg, lifetime_secs).await
+                }
+                _ => continue,
+            };
+            if let Err(e) = result {
+                error!("Failed to send message: {}", e);
+            }
+        }
+    }
+
+    // a message from the listener
+    async fn receive_message(&mut self, message: Message) {
+        let conversation_id = message.conversation_id.clone();
+        let hidden = matches!(
+            self.state.get_conversation(&conversation_id),
+            Some(c) if c.hidden
+        );
+        if hidden && self.config.unhide_on_message {
+            self.set_hidden(&conversation_id, false);
+        }
+        // e.g. a brand new DM, or one past `startup_conversation_limit`
+        if self.state.get_conversation(&conversation_id).is_none() {
+            self.add_conversation_for(&message).await;
+        }
+
+        // someone joined or left, so the cached count is out of date
+        let channel = message.channel.clone();
+        let membership_changed = matches!(
+            message.content,
+            MessageType::Join | MessageType::Leave
+        );
+        let id = message.id;
+        if let Err(e) = self.state.insert_message(&conversation_id, message) {
+            error!("Dropped message {}: {}", id, e);
+        }
+        if membership_changed && channel.members_type == MemberType::Team {
+            if self.config.layout.member_counts {
+                update_member_count(&*self.client, &mut self.state, &channel.name, true).await;
+            }
+            let known = matches!(
+                self.state.get_conversation(&conversation_id),
+                Some(c) if !c.participants.is_empty()
+            );
+            if known {
+                update_participants(&*self.client, &mut self.state, &channel.name, true).await;
+            }
+        }
+    }
+
+    // Start a conversation in the sidebar for a message we had nowhere to put. The message's
+    // channel is all keybase needs to know which conversation it is, so there's nothing to fetch.
+    async fn add_conversation_for(&mut self, message: &Message) {
+        let conversation = KeybaseConversation {
+            id: message.conversation_id.clone(),
+            channel: message.channel.clone(),
+            unread: true,
+            active_at: message.sent_at,
+        };
+        let more = self.conversations_next.is_some();
+        self.state.append_conversations(vec![conversation.into()], more);
+        self.setup_conversations(std::slice::from_ref(&message.conversation_id)).await;
+    }
+
+    // apply the config to newly loaded conversations
+    async fn setup_conversations(&mut self, ids: &[String]) {
+        let hidden: Vec<String> = ids
+            .iter()
+            .filter_map(|id| self.state.get_conversation(id))
+            .filter(|c| self.config.hidden.conversations.contains(&c.get_name()))
+            .map(|c| c.id.clone())
+            .collect();
+        for id in hidden {
+            self.state.set_hidden(&id, true);
+        }
+        let muted: Vec<String> = ids
+            .iter()
+            .filter_map(|id| self.state.get_conversation(id))
+            .filter(|c| self.config.muted.conversations.contains(&c.get_name()))
+            .map(|c| c.id.clone())
+            .collect();
+        for id in muted {
+            self.state.set_muted(&id, true);
+        }
+
+        if self.config.layout.member_counts {
+            let mut teams: Vec<String> = ids
+                .iter()
+                .filter_map(|id| self.state.get_conversation(id))
+                .filter(|c| c.data.channel.members_type == MemberType::Team)
+                .map(|c| c.data.channel.name.clone())
+                .collect();
+            teams.sort();
+            teams.dedup();
+            for team in teams {
+                update_member_count(&*self.client, &mut self.state, &team, false).await;
+            }
+        }
+    }
+
+    // Send a message to the current conversation, exploding after `lifetime_secs` if it's set.
+    // Whether it went.
+    async fn send_message(&mut self, msg: String, lifetime_secs: Option<u64>) -> bool {
+        let convo = match self.state.get_current_conversation() {
+            Some(convo) => convo,
+            None => return false,
+        };
+        let convo_id = convo.id.clone();
+        let channel = convo.data.channel.clone();
+        // what was typed, to put back if it doesn't go
+        let body = msg.clone();
+        let msg = self.config.outgoing_message(&convo.get_name(), msg);
+        // shown straight away rather than once the listener sends it back
+        let pending = self.state.get_my_username().map(|me| Message {
+            is_ephemeral: lifetime_secs.is_some(),
+            ..pending_message(convo, me, &msg)
+        });
+        let pending_id = pending.as_ref().map(|m| m.id);
+        if let Some(pending) = pending {
+            self.state.insert_message(&convo_id, pending).ok();
+        }
+        let result = match lifetime_secs {
+            Some(lifetime_secs) => self.client.send_exploding(&channel, msg, lifetime_secs).await.map(|()| None),
+            None => self.client.send_message(&channel, msg).await,
+        };
+        match result {
+            Ok(message_id) => {
+                // no need to wait for the listener to send it back
+                if let (Some(pending_id), Some(message_id)) = (pending_id, message_id) {
+                    self.state.confirm_message(&convo_id, pending_id, message_id);
+                }
+                true
+            }
+            // e.g. not allowed to post there, which is no reason to quit
+            Err(e) => {
+                error!("Failed to send message: {}", e);
+                if let Some(id) = pending_id {
+                    self.state.remove_message(&convo_id, id);
+                }
+                self.state.error(format!("Couldn't send your message: {}", e));
+                self.state.send_failed(&body);
+                false
+            }
+        }
+    }
+
+    // mute or unmute a conversation, and remember it for next time
+    fn toggle_mute(&mut self, conversation_id: &str) {
+        let (name, muted) = match self.state.get_conversation(conversation_id) {
+            Some(convo) => (convo.get_name(), !convo.muted),
+            None => return,
+        };
+        self.state.set_muted(conversation_id, muted);
+
+        if muted {
+            self.config.muted.conversations.insert(name);
+        } else {
+            self.config.muted.conversations.remove(&name);
+        }
+        if let Err(e) = self.config.muted.save() {
+            error!("Failed to save muted conversations: {}", e);
+        }
+    }
+
+    // hide or unhide a conversation, and remember it for next time
+    fn set_hidden(&mut self, conversation_id: &str, hidden: bool) {
+        let name = match self.state.get_conversation(conversation_id) {
+            Some(convo) => convo.get_name(),
+            None => return,
+        };
+        self.state.set_hidden(conversation_id, hidden);
+
+        if hidden {
+            self.config.hidden.conversations.insert(name);
+        } else {
+            self.config.hidden.conversations.remove(&name);
+        }
+        if let Err(e) = self.config.hidden.save() {
+            error!("Failed to save hidden conversations: {}", e);
+        }
+    }
+
+    // Start loading messages for every unread conversation so switching to them is instant.
+    // `process_events` puts each one in as it comes, and carries on handling events meanwhile.
+    fn prefetch_unread(&mut self) {
+        let unread: Vec<KeybaseConversation> = self
+            .state
+            .get_conversations()
+            .filter(|c| c.data.unread && !c.fetched)
+            .map(|c| c.data.clone())
+            .collect();
+
+        let client = self.client.clone();
+        let count = self.config.initial_message_count;
+        let fetches = stream::iter(unread)
+            .map(move |convo| {
+                let client = client.clone();
+                async move {
+                    let result = client.fetch_message_page(&convo, count, None).await;
+                    (convo.id, result)
+                }
+            })
+            .buffer_unordered(PREFETCH_CONCURRENCY);
+        self.prefetching = Some(fetches.boxed_local());
+    }
+
+    // one of the unread conversations is in
+    fn prefetched(&mut self, convo_id: String, result: FetchResult) {
+        match result {
+            Ok(page) => {
+                // unless it was opened in the meantime
+                if let Some(convo) = self.state.get_conversation_mut(&convo_id) {
+                    if !convo.fetched {
+                        convo.fetched = true;
+                        convo.reset_history(page.messages, page.next);
+                    }
+                }
+            }
+            Err(e) => error!("Failed to prefetch conversation {}: {}", convo_id, e),
+        }
+    }
+
+    // Switch conversations, leaving any older messages to come in while other events are handled.
+    // They're only wanted for the conversation switched to last.
+    async fn switch_to(&mut self, conversation_id: String) {
+        let count = self.config.initial_message_count;
+        match switch_conversation(&self.client, &mut self.state, conversation_id, count).await {
+            Ok(Some(older)) => self.older_pages = Some(older),
+            Ok(None) => {}
+            Err(e) => error!("Failed to switch conversation: {}", e),
+        }
+    }
+
+    // Switch to a conversation that hasn't been fetched yet straight away, so anything sent from
+    // now on goes there, but only start fetching it once it's been on screen for SWITCH_DEBOUNCE.
+    fn switch_later(&mut self, conversation_id: String) {
+        self.cancel_fetch();
+        if let Err(e) = self.state.set_current_conversation(&conversation_id) {
+            error!("Failed to switch conversation: {}", e);
+            return;
+        }
+        self.state.set_loading(&conversation_id, true);
+        self.awaiting_first_page = Some(conversation_id);
+        self.fetch_at = Some(Instant::now() + SWITCH_DEBOUNCE);
+    }
+
+    // the messages of the conversation switched to last, now that it's stayed on screen
+    async fn start_fetch(&mut self) {
+        let id = match self.awaiting_first_page.clone() {
+            Some(id) => id,
+            None => return,
+        };
+        let convo = match self.state.get_conversation_mut(&id) {
+            Some(convo) => convo,
+            None => return self.cancel_fetch(),
+        };
+        if convo.fetched {
+            // prefetched in the meantime
+            self.awaiting_first_page = None;
+            self.state.set_loading(&id, false);
+            self.switch_to(id).await;
+            return;
+        }
+        convo.fetched = true;
+        let pages = message_pages(self.client.clone(), convo.data.clone(), self.config.initial_message_count, FETCH_CHUNK_SIZE);
+        self.older_pages = Some(pages.map(move |page| (id.clone(), page)).boxed_local());
+    }
+
+    // Whatever is still on its way for the conversation being switched away from is dropped,
+    // which stops it being fetched. If its newest messages never came, it's fetched again next
+    // time it's opened.
+    fn cancel_fetch(&mut self) {
+        self.older_pages = None;
+        self.fetch_at = None;
+        if let Some(id) = self.awaiting_first_page.take() {
+            if let Some(convo) = self.state.get_conversation_mut(&id) {
+                convo.fetched = false;
+            }
+            self.state.set_loading(&id, false);
+        }
+    }
+
+    // switch to a channel, which might have to be joined first, the same way
+    async fn open(&mut self, channel: &Channel) {
+        self.cancel_fetch();
+        let count = self.config.initial_message_count;
+        match open_channel(&self.client, &mut self.state, channel, count).await {
+            Ok(Some(older)) => self.older_pages = Some(older),
+            Ok(None) => {}
+            Err(e) => error!("Failed to open {}: {}", channel.name, e),
+        }
+    }
+
+    // the next page of the conversation last switched to is in
+    async fn older_page(&mut self, convo_id: String, result: FetchResult) {
+        if self.awaiting_first_page.as_ref() == Some(&convo_id) {
+            self.awaiting_first_page = None;
+            return self.first_page(convo_id, result).await;
+        }
+        match result {
+            Ok(page) => self.state.insert_history(&convo_id, page.messages, page.next),
+            // what's there is enough to be going on with, scrolling up gets the rest
+            Err(e) => error!("Failed to fetch older messages for {}: {}", convo_id, e),
+        }
+    }
+
+    // the newest messages of a conversation switched to with `switch_later`
+    async fn first_page(&mut self, convo_id: String, result: FetchResult) {
+        self.state.set_loading(&convo_id, false);
+        let page = match result {
+            Ok(page) => page,
+            Err(e) => {
+                // try again next time
+                if let Some(convo) = self.state.get_conversation_mut(&convo_id) {
+                    convo.fetched = false;
+                }
+                error!("Failed to switch conversation: {}", e);
+                return;
+            }
+        };
+        if let Some(convo) = self.state.get_conversation_mut(&convo_id) {
+            // it may have messages from before a reconnect, with a gap after them
+            convo.reset_history(page.messages, page.next);
+        }
+        // re-render it
+        if let Err(e) = self.state.set_current_conversation(&convo_id) {
+            error!("{}", e);
+        }
+        mark_newest_as_read(&*self.client, &mut self.state, &convo_id).await;
+        let team = self
+            .state
+            .get_conversation(&convo_id)
+            .map(|c| &c.data.channel)
+            .filter(|channel| channel.members_type == MemberType::Team)
+            .map(|channel| channel.name.clone());
+        if let Some(team) = team {
+            update_participants(&*self.client, &mut self.state, &team, false).await;
+        }
+    }
+
+    // The listener stopped delivering events (probably because the machine went to sleep), so
+    // start a new one. It says when it's back, and `catch_up` fetches whatever it missed.
+    fn restart_listener(&mut self) {
+        info!("Chat listener looks stale, restarting it");
+        self.state.set_connection_status(ConnectionStatus::Reconnecting);
+        if let Err(e) = self.client.restart_listener() {
+            error!("Failed to restart the chat listener: {}", e);
+        }
+    }
+
+    // show whatever the last api call said about keybase being there
+    fn update_connection_status(&mut self) {
+        if let Some(status) = self.client.take_status() {
+            self.state.set_connection_status(status);
+        }
+    }
+
+    // after the listener's been gone for a while, messages it would have told us about
+    async fn catch_up(&mut self) {
+        let current_id = self.state.get_current_conversation().map(|c| c.id.clone());
+        let ids: Vec<String> = self.state.get_conversations().map(|c| c.id.clone()).collect();
+        for id in ids.iter().filter(|id| Some(*id) != current_id.as_ref()) {
+            // load them again next time they're opened, starting over from the newest page then
+            // (see `Conversation::reset_history`)
+            if let Some(convo) = self.state.get_conversation_mut(id) {
+                convo.fetched = false;
+            }
+        }
+
+        if let Some(id) = current_id {
+            let data = self.state.get_conversation(&id).unwrap().data.clone();
+            let result = self.client.fetch_message_page(&data, self.config.initial_message_count, None).await;
+            match result {
+                Ok(page) => {
+                    // there could be more missed than fit in the page, so it starts over from it
+                    let convo = self.state.get_conversation_mut(&id).unwrap();
+                    convo.reset_history(page.messages, page.next);
+                    // re-render it
+                    if let Err(e) = self.state.set_current_conversation(&id) {
+                        error!("{}", e);
+                    }
+                }
+                Err(e) => error!("Failed to fetch missed messages for {}: {}", id, e),
+            }
+        }
+    }
+
     pub async fn process_events(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.config.prefetch_unread {
+            self.prefetch_unread();
+        }
+
+        let heartbeat_secs = self.config.listener_heartbeat_secs;
+        let interval = Duration::from_secs(heartbeat_secs.max(1));
+        let mut heartbeat = tokio::time::interval(interval);
+        let mut watchdog = ListenerWatchdog::new(interval, SystemTime::now());
+
         let mut client_receiver = self.client.get_receiver();
         loop {
             tokio::select! {
+                _ = self.shutdown_receiver.recv() => {
+                    info!("Shutting down");
+                    return Ok(());
+                },
+                (convo_id, result) = next_from(&mut self.prefetching) => {
+                    self.prefetched(convo_id, result);
+                },
+                (convo_id, result) = next_from(&mut self.older_pages) => {
+                    self.older_page(convo_id, result).await;
+                },
+                _ = fetch_due(self.fetch_at) => {
+                    self.fetch_at = None;
+                    self.start_fetch().await;
+                },
+                _ = heartbeat.tick(), if heartbeat_secs > 0 => {
+                    if watchdog.on_tick(SystemTime::now()) {
+                        self.restart_listener();
+                    }
+                },
                 msg = client_receiver.recv() => {
                     if let Some(value) = msg {
+                        watchdog.on_event();
                         match value {
                             ListenerEvent::ChatMessage(msg) => {
-                                let conversation_id = &msg.msg.conversation_id;
-                                self.state.insert_message(conversation_id, msg.msg.clone());
+                                self.receive_message(msg.msg).await;
+                            }
+                            ListenerEvent::Typing { conversation_id, username } => {
+                                self.state.set_typing(&conversation_id, &username);
+                            }
+                            ListenerEvent::ListenerDisconnected => {
+                                self.state.set_connection_status(ConnectionStatus::Reconnecting);
                             }
+                            ListenerEvent::ListenerReconnected => {
+                                self.state.set_connection_status(ConnectionStatus::Connected);
+                                self.catch_up().await;
+                            }
+                            // still shows the listener is alive, for the watchdog
+                            ListenerEvent::Unknown => {}
                         }
                     }
                 },
@@ -47,23 +674,183 @@ impl<S: ApplicationState, C: KeybaseClient> Controller<S, C>{
                     if let Some(value) = msg {
                         match value {
                             UiEvent::SendMessage(msg) => {
+                                if self.send_message(msg, None).await {
+                                    watchdog.on_sent(SystemTime::now());
+                                }
+                            },
+                            UiEvent::SendExploding { body, lifetime_secs } => {
+                                if self.send_message(body, Some(lifetime_secs)).await {
+                                    watchdog.on_sent(SystemTime::now());
+                                }
+                            },
+                            UiEvent::SwitchConversation(conversation_id) => {
+                                let current = self.state.get_current_conversation().map(|c| c.id.clone());
+                                let fetched = self.state.get_conversation(&conversation_id).is_none_or(|convo| convo.fetched);
+                                if self.awaiting_first_page.as_ref() == Some(&conversation_id) {
+                                    // already on its way
+                                } else if !fetched {
+                                    self.switch_later(conversation_id);
+                                } else {
+                                    if current.as_ref() != Some(&conversation_id) {
+                                        self.cancel_fetch();
+                                    }
+                                    self.switch_to(conversation_id).await;
+                                }
+                            },
+                            UiEvent::PinMessage(message_id) => {
+                                set_pinned(&*self.client, &mut self.state, message_id, true).await;
+                            },
+                            UiEvent::UnpinMessage(message_id) => {
+                                set_pinned(&*self.client, &mut self.state, message_id, false).await;
+                            },
+                            UiEvent::RenameChannel(topic_name) => {
+                                rename_channel(&*self.client, &mut self.state, &topic_name).await;
+                            },
+                            UiEvent::HideConversation => {
+                                if let Some(convo) = self.state.get_current_conversation() {
+                                    let id = convo.id.clone();
+                                    self.set_hidden(&id, true);
+                                }
+                            },
+                            UiEvent::UnhideConversation(conversation_id) => {
+                                self.set_hidden(&conversation_id, false);
+                            },
+                            UiEvent::ToggleMute { conversation_id } => {
+                                self.toggle_mute(&conversation_id);
+                            },
+                            UiEvent::MarkRead(conversation_id, message_id) => {
+                                mark_as_read(&*self.client, &conversation_id, message_id).await;
+                            },
+                            UiEvent::React(message_id, reaction) => {
+                                if let Some(convo) = self.state.get_current_conversation() {
+                                    let channel = convo.data.channel.clone();
+                                    if let Err(e) = self.client.react(&channel, message_id, &reaction).await {
+                                        error!("Failed to react to message {}: {}", message_id, e);
+                                        self.state.error(format!("Couldn't react to message {}: {}", message_id, e));
+                                    }
+                                }
+                            },
+                            UiEvent::Reply(message_id, msg) => {
                                 if let Some(convo) = self.state.get_current_conversation() {
                                     let channel = &convo.data.channel;
-                                    self.client.send_message(channel, msg).await?;
+                                    let body = msg.clone();
+                                    let msg = self.config.outgoing_message(&convo.get_name(), msg);
+                                    let result = self.client.send_reply(channel, message_id, msg).await;
+                                    match result {
+                                        Ok(()) => watchdog.on_sent(SystemTime::now()),
+                                        Err(e) => {
+                                            error!("Failed to reply to message {}: {}", message_id, e);
+                                            self.state.error(format!("Couldn't send your reply: {}", e));
+                                            self.state.send_failed(&body);
+                                        }
+                                    }
                                 }
                             },
-                            UiEvent::SwitchConversation(conversation_id) => {
-                                switch_conversation(&mut self.client, &mut self.state, conversation_id).await?;
+                            UiEvent::EditMessage(message_id, msg) => {
+                                if let Some(convo) = self.state.get_current_conversation() {
+                                    let channel = convo.data.channel.clone();
+                                    if let Err(e) = self.client.edit_message(&channel, message_id, msg).await {
+                                        error!("Failed to edit message {}: {}", message_id, e);
+                                        self.state.error(format!("Couldn't edit message {}: {}", message_id, e));
+                                    }
+                                }
+                            },
+                            UiEvent::DeleteMessage(message_id) => {
+                                if let Some(convo) = self.state.get_current_conversation() {
+                                    let channel = convo.data.channel.clone();
+                                    if let Err(e) = self.client.delete_message(&channel, message_id).await {
+                                        error!("Failed to delete message {}: {}", message_id, e);
+                                        self.state.error(format!("Couldn't delete message {}: {}", message_id, e));
+                                    }
+                                }
+                            },
+                            UiEvent::LoadMore(conversation_id) => {
+                                load_more_messages(&*self.client, &mut self.state, &conversation_id).await;
+                            },
+                            UiEvent::GotoMessage(conversation_id, message_id) => {
+                                goto_message(&*self.client, &mut self.state, &conversation_id, message_id).await;
+                            },
+                            UiEvent::OpenChannel(channel) => {
+                                self.open(&channel).await;
+                            },
+                            UiEvent::LoadMoreConversations => {
+                                self.load_more_conversations().await;
+                            }
+                            UiEvent::DownloadAttachment { message_id } => {
+                                let dir = self.config.download_dir();
+                                download_attachment(&*self.client, &mut self.state, &dir, message_id).await;
+                            }
+                            UiEvent::ListChannels(team) => {
+                                list_channels(&*self.client, &mut self.state, &team).await;
+                            }
+                            UiEvent::ExportConversation { path } => {
+                                export_conversation(&mut self.state, &path);
+                            }
+                            UiEvent::NewConversation { name, members_type } => {
+                                let channel = new_channel(&name, members_type, self.state.get_my_username());
+                                self.open(&channel).await;
                             }
                         }
                     }
                 },
             }
+            self.update_connection_status();
+        }
+    }
+}
+
+// the next thing out of `stream`, or never once it's finished (and gone)
+async fn next_from<T>(stream: &mut Option<LocalBoxStream<'static, T>>) -> T {
+    if let Some(s) = stream {
+        if let Some(item) = s.next().await {
+            return item;
         }
+        *stream = None;
+    }
+    futures::future::pending().await
+}
+
+// when it's time to fetch the conversation switched to, never without one waiting
+async fn fetch_due(at: Option<Instant>) {
+    match at {
+        Some(at) => tokio::time::delay_until(at).await,
+        None => futures::future::pending().await,
+    }
+}
+
+// in seconds, like keybase's timestamps
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+// What's shown for a message from `my_username` until keybase sends it back.
+fn pending_message(convo: &Conversation, my_username: &str, body: &str) -> Message {
+    let sent_at = unix_time();
+    Message {
+        id: convo.next_pending_id(),
+        channel: convo.data.channel.clone(),
+        content: MessageType::Text {
+            text: MessageBody {
+                body: body.to_string(),
+                reply_to: None,
+            },
+        },
+        sender: Sender {
+            username: my_username.to_string(),
+            device_name: String::new(),
+        },
+        conversation_id: convo.id.clone(),
+        sent_at,
+        delivery: Some(DeliveryState::Sending),
+        is_ephemeral: false,
     }
 }
 
-async fn switch_conversation<S: ApplicationState, C: KeybaseClient>(client: &mut C, state: &mut S, conversation_id: String) -> Result<(), Box<dyn std::error::Error>>{
+// Open a conversation, fetching its newest messages first if they haven't been yet. The rest of
+// `count` come after, from what's returned.
+async fn switch_conversation<S: ApplicationState, C: KeybaseClient + 'static>(client: &Rc<C>, state: &mut S, conversation_id: String, count: u32) -> Result<Option<OlderPages>, Box<dyn std::error::Error>>{
     let (convo_id, should_fetch) = {
         if let Some(mut convo) = state.get_conversation_mut(&conversation_id){
             if !convo.fetched {
@@ -80,74 +867,1680 @@ async fn switch_conversation<S: ApplicationState, C: KeybaseClient>(client: &mut
     if should_fetch {
         let id = &convo_id.unwrap();
         let convo = state.get_conversation(id).unwrap();
-        let messages = client.fetch_messages(&convo.data, 20).await?;
-                
-        state.get_conversation_mut(id).unwrap().insert_messages(messages);
+        let data = convo.data.clone();
+        // this can take a while, the UI shows that something's happening
+        state.set_loading(id, true);
+        let mut pages = message_pages(client.clone(), data.clone(), count, FETCH_CHUNK_SIZE).boxed_local();
+        let page = pages.next().await.unwrap_or_else(|| Ok(MessagePage::default()));
+        state.set_loading(id, false);
+
+        let convo = state.get_conversation_mut(id).unwrap();
+        let page = match page {
+            Ok(page) => page,
+            Err(e) => {
+                // try again next time
+                convo.fetched = false;
+                return Err(e);
+            }
+        };
+        // it may have messages from before a reconnect, with a gap after them
+        convo.reset_history(page.messages, page.next);
+
+        // the newest ones go on screen now, older ones as they come in
+        mark_newest_as_read(&**client, state, &conversation_id).await;
+        state.set_current_conversation(&conversation_id)?;
+        if data.channel.members_type == MemberType::Team {
+            update_participants(&**client, state, &data.channel.name, false).await;
+        }
+        let id = id.clone();
+        return Ok(Some(pages.map(move |page| (id.clone(), page)).boxed_local()));
     }
 
-    state.set_current_conversation(&conversation_id);
-    Ok(())
+    mark_newest_as_read(&**client, state, &conversation_id).await;
+    state.set_current_conversation(&conversation_id)?;
+    Ok(None)
 }
 
-#[cfg(test)]
-mod test {
+async fn mark_newest_as_read<S: ApplicationState, C: KeybaseClient>(client: &C, state: &mut S, conversation_id: &str) {
 
-    use super::*;
-    use crate::client::MockKeybaseClient;
-    use crate::state::ApplicationStateInner;
-    use crate::conversation;
-    use crate::types::*;
+    // so it stops showing up as unread in the other keybase clients too
+    let newest_unread = state
+        .get_conversation(conversation_id)
+        .filter(|c| c.data.unread || c.unread_count > 0)
+        .and_then(|c| c.messages.iter().find(|m| !m.is_pending()))
+        .map(|m| m.id);
+    if let Some(message_id) = newest_unread {
+        if mark_as_read(client, conversation_id, message_id).await {
+            if let Some(convo) = state.get_conversation_mut(conversation_id) {
+                convo.data.unread = false;
+            }
+        }
+    }
+}
 
-    #[tokio::test]
-    async fn init() {
-        let (_, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
-        let mut client = MockKeybaseClient::new();
-        client.expect_fetch_conversations()
-            .times(1)
-            .return_once(|| Ok(vec![]));
+async fn set_pinned<S: ApplicationState, C: KeybaseClient>(client: &C, state: &mut S, message_id: u64, pinned: bool) {
+    let (convo_id, channel) = match state.get_current_conversation() {
+        Some(convo) => (convo.id.clone(), convo.data.channel.clone()),
+        None => return,
+    };
 
-        let state = ApplicationStateInner::default();
+    let result = if pinned {
+        client.pin_message(&channel, message_id).await
+    } else {
+        client.unpin_message(&channel, message_id).await
+    };
 
-        let mut controller = Controller::new(client, state, r);
-        controller.init().await.unwrap();
+    // usually a permission error in a team channel, which shouldn't take down the whole app
+    match result {
+        Ok(()) => state.set_pinned(&convo_id, message_id, pinned),
+        Err(e) => {
+            error!("Failed to update pin for message {}: {}", message_id, e);
+            let action = if pinned { "pin" } else { "unpin" };
+            state.error(format!("Couldn't {} message {}: {}", action, message_id, e));
+        }
     }
+}
 
-    #[tokio::test]
-    async fn switch_conversation() {
-        let (mut s, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
-        let (_, c_recv) = tokio::sync::mpsc::channel::<ListenerEvent>(32);
-        let mut client = MockKeybaseClient::new();
-        let convo = conversation!("test1");
-        let convo2 = conversation!("test2");
-        let c1 = convo.clone();
-        let c2 = convo2.clone();
+async fn rename_channel<S: ApplicationState, C: KeybaseClient>(client: &C, state: &mut S, topic_name: &str) {
+    let (convo_id, channel) = match state.get_current_conversation() {
+        Some(convo) => (convo.id.clone(), convo.data.channel.clone()),
+        None => return,
+    };
 
-        client.expect_get_receiver()
-            .times(1)
-            .return_once(move || c_recv);
+    // the client refuses non-team conversations, and only admins can rename team channels
+    match client.rename_channel(&channel, topic_name).await {
+        Ok(()) => state.set_topic_name(&convo_id, topic_name),
+        Err(e) => {
+            error!("Failed to rename {}#{}: {}", channel.name, channel.topic_name, e);
+            state.error(format!("Couldn't rename the channel: {}", e));
+        }
+    }
+}
 
-        client.expect_fetch_conversations()
-            .times(1)
-            .return_once(move || Ok(vec![c1, c2]));
+// returns whether it worked
+async fn mark_as_read<C: KeybaseClient>(client: &C, conversation_id: &str, message_id: u64) -> bool {
+    // the worst case is the conversation shows up as unread again next time
+    match client.mark_as_read(conversation_id, message_id).await {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Failed to mark message {} as read: {}", message_id, e);
+            false
+        }
+    }
+}
 
-        client.expect_fetch_messages()
-            .withf(move |c: &KeybaseConversation, _| c.id == "test1")
-            .times(1)
-            .return_once(|_, _| Ok(vec![]));
+// Open a conversation with enough history loaded to include `message_id`, if it can be found
+// within a few pages. The UI scrolls to it, or says it couldn't be found, once it's shown.
+async fn goto_message<S: ApplicationState, C: KeybaseClient>(client: &C, state: &mut S, conversation_id: &str, message_id: u64) {
+    let data = match state.get_conversation(conversation_id) {
+        Some(convo) if convo.messages.iter().any(|m| m.id == message_id) => {
+            if let Err(e) = state.set_current_conversation(conversation_id) {
+                error!("{}", e);
+            }
+            return;
+        }
+        Some(convo) => convo.data.clone(),
+        None => return,
+    };
 
-        let state = ApplicationStateInner::default();
+    let mut messages = vec![];
+    let mut next = None;
+    for _ in 0..GOTO_MAX_PAGES {
+        match client.fetch_message_page(&data, GOTO_PAGE_SIZE, next.clone()).await {
+            Ok(page) => {
+                messages.extend(page.messages);
+                next = page.next;
+            }
+            Err(e) => {
+                error!("Failed to fetch messages for {}: {}", conversation_id, e);
+                break;
+            }
+        }
+        if next.is_none() || messages.iter().any(|m| m.id == message_id) {
+            break;
+        }
+    }
 
-        let mut controller = Controller::new(client, state, r);
+    // it goes back further than what we had, which it overlaps from the newest message on
+    let convo = state.get_conversation_mut(conversation_id).unwrap();
+    if messages.len() > convo.messages.len() {
+        convo.insert_history(messages, next);
+    }
+    convo.fetched = true;
+    if let Err(e) = state.set_current_conversation(conversation_id) {
+        error!("{}", e);
+    }
+}
 
-        controller.init().await.unwrap();
+async fn list_channels<S: ApplicationState, C: KeybaseClient>(client: &C, state: &mut S, team: &str) {
+    match client.list_channels(team).await {
+        Ok(topic_names) => state.show_channels(team, &topic_names),
+        Err(e) => {
+            error!("Failed to list the channels in {}: {}", team, e);
+            state.error(format!("Couldn't list the channels in {}: {}", team, e));
+        }
+    }
+}
 
-        tokio::spawn(async move {
-            s.send(UiEvent::SwitchConversation("test1".to_string())).await.ok();
-        });
+// Fetch the page of messages before the oldest one we have, for scrolling back through history.
+async fn load_more_messages<S: ApplicationState, C: KeybaseClient>(client: &C, state: &mut S, conversation_id: &str) {
+    let (data, next) = match state.get_conversation(conversation_id) {
+        // older ones would only push out what's there already
+        Some(convo) if convo.messages.len() >= convo.max_messages => {
+            let text = format!(
+                "Only the newest {} messages are kept (see max_messages)",
+                convo.max_messages
+            );
+            state.notice(&text);
+            return;
+        }
+        Some(convo) => match &convo.history_next {
+            Some(next) => (convo.data.clone(), next.clone()),
+            // nothing older to load
+            None => return,
+        },
+        None => return,
+    };
+    match client.fetch_message_page(&data, HISTORY_PAGE_SIZE, Some(next)).await {
+        Ok(page) => state.insert_history(conversation_id, page.messages, page.next),
+        Err(e) => {
+            error!("Failed to fetch older messages for {}: {}", conversation_id, e);
+            // done trying, so the UI can ask again
+            state.set_loading(conversation_id, false);
+            state.error(format!("Couldn't load older messages: {}", e));
+        }
+    }
+}
 
-        tokio::select! {
-            _ = controller.process_events() => {},
-            _ = tokio::time::delay_for(tokio::time::Duration::from_millis(10)) => {}
+// The channel for a conversation asked for by name (`team#channel` or `alice,bob`). Keybase names
+// conversations between users by everyone in them, you included, in alphabetical order.
+fn new_channel(name: &str, members_type: MemberType, my_username: Option<&str>) -> Channel {
+    match members_type {
+        MemberType::Team => {
+            let (team, topic) = name.split_once('#').unwrap_or((name, "general"));
+            Channel {
+                name: team.to_string(),
+                topic_name: topic.to_string(),
+                members_type,
+            }
+        }
+        MemberType::User => {
+            let mut users: Vec<&str> = name.split(',').collect();
+            users.extend(my_username);
+            users.sort_unstable();
+            users.dedup();
+            Channel {
+                name: users.join(","),
+                topic_name: String::new(),
+                members_type,
+            }
         }
     }
 }
+
+// Join a team channel that was mentioned somewhere (or start a conversation with some users),
+// then switch to it once keybase has a conversation for it. Conversations we already had keep
+// their messages.
+async fn open_channel<S: ApplicationState, C: KeybaseClient + 'static>(client: &Rc<C>, state: &mut S, channel: &Channel, count: u32) -> Result<Option<OlderPages>, Box<dyn std::error::Error>> {
+    let same_channel = |c: &KeybaseConversation| {
+        c.channel.members_type == channel.members_type
+            && c.channel.name.eq_ignore_ascii_case(&channel.name)
+            && c.channel.topic_name.eq_ignore_ascii_case(&channel.topic_name)
+    };
+    if let Some(convo) = state.get_conversations().find(|c| same_channel(&c.data)) {
+        let id = convo.id.clone();
+        return switch_conversation(client, state, id, count).await;
+    }
+
+    let name = match channel.members_type {
+        MemberType::Team => format!("{}#{}", channel.name, channel.topic_name),
+        MemberType::User => channel.name.clone(),
+    };
+    // usually the channel doesn't exist, or is in a team we aren't in
+    let opened = match channel.members_type {
+        MemberType::Team => client.join_channel(channel).await,
+        MemberType::User => client.new_conversation(channel).await,
+    };
+    if let Err(e) = opened {
+        error!("Failed to open {}: {}", name, e);
+        return Ok(None);
+    }
+    let conversations = client.fetch_conversations().await?;
+    let id = match conversations.iter().find(|c| same_channel(c)) {
+        Some(convo) => convo.id.clone(),
+        None => {
+            error!("Opened {}, but it isn't in the conversation list", name);
+            return Ok(None);
+        }
+    };
+    let conversations: Vec<Conversation> = conversations
+        .into_iter()
+        .map(|c| match state.get_conversation(&c.id) {
+            Some(existing) => existing.clone(),
+            None => c.into(),
+        })
+        .collect();
+    state.set_conversations(conversations);
+    switch_conversation(client, state, id, count).await
+}
+
+// Where to save a file called `filename` in `dir`, without overwriting anything: `photo.jpg`,
+// then `photo (1).jpg` and so on.
+fn download_path(dir: &Path, filename: &str) -> PathBuf {
+    // only the name, a filename with slashes in it doesn't get to pick the directory
+    let name = Path::new(filename)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "attachment".to_string());
+    let path = dir.join(&name);
+    if !path.exists() {
+        return path;
+    }
+    let name = Path::new(&name);
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = name
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| dir.join(format!("{} ({}){}", stem, n, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+// save an attachment in the current conversation to `dir`, telling the UI how it went
+async fn download_attachment<S: ApplicationState, C: KeybaseClient>(client: &C, state: &mut S, dir: &Path, message_id: u64) {
+    let convo = match state.get_current_conversation() {
+        Some(convo) => convo,
+        None => return,
+    };
+    let object = convo.messages.iter().find(|m| m.id == message_id).and_then(|m| match &m.content {
+        MessageType::Attachment { attachment } => Some(attachment.object.clone()),
+        _ => None,
+    });
+    let (filename, mime_type) = match object {
+        Some(object) => (object.filename, object.mime_type),
+        None => {
+            state.error(format!("Message {} isn't an attachment", message_id));
+            return;
+        }
+    };
+    let conversation_id = convo.id.clone();
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        state.error(format!("Couldn't create {}: {}", dir.display(), e));
+        return;
+    }
+    let path = download_path(dir, &filename);
+    match client.download_attachment(&conversation_id, message_id, &path).await {
+        Ok(()) => {
+            state.notice(&format!("Saved {}", path.display()));
+            state.downloaded(&path, &mime_type);
+        }
+        Err(e) => state.error(format!("Failed to download {}: {}", filename, e)),
+    }
+}
+
+// the current conversation's messages (the ones loaded so far), newest first like `export-all`
+fn export_conversation<S: ApplicationState>(state: &mut S, path: &Path) {
+    let messages: Vec<Message> = match state.get_current_conversation() {
+        Some(convo) => convo.messages.iter().filter(|m| !m.is_pending()).cloned().collect(),
+        None => return,
+    };
+    let written = json_lines(&messages)
+        .map_err(|e| e.to_string())
+        .and_then(|lines| std::fs::write(path, lines).map_err(|e| e.to_string()));
+    match written {
+        Ok(()) => {
+            let text = format!("Exported {} messages to {}", messages.len(), path.display());
+            state.notice(&text);
+        }
+        Err(e) => state.error(format!("Couldn't export to {}: {}", path.display(), e)),
+    }
+}
+
+// who's in a team, for completing `@name`
+async fn update_participants<S: ApplicationState, C: KeybaseClient>(client: &C, state: &mut S, team: &str, refresh: bool) {
+    match client.fetch_members(team, refresh).await {
+        Ok(members) => state.set_participants(team, &members),
+        // only the people who've spoken get suggested
+        Err(e) => error!("Failed to fetch the members of {}: {}", team, e),
+    }
+}
+
+async fn update_member_count<S: ApplicationState, C: KeybaseClient>(client: &C, state: &mut S, team: &str, refresh: bool) {
+    // without a count the channel is just shown by name
+    let count = match client.fetch_member_count(team, refresh).await {
+        Ok(count) => Some(count),
+        Err(e) => {
+            error!("Failed to fetch the member count for {}: {}", team, e);
+            None
+        }
+    };
+    state.set_member_count(team, count);
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::client::MockKeybaseClient;
+    use crate::state::{ApplicationStateInner, MockStateObserver};
+    use mockall::predicate::eq;
+    use mockall::Sequence;
+    use crate::{conversation, message};
+    use crate::types::*;
+
+    #[tokio::test]
+    async fn init() {
+        let (_, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_whoami()
+            .times(1)
+            .return_once(|| Ok("me".to_string()));
+        client.expect_fetch_conversations()
+            .times(1)
+            .return_once(|| Ok(vec![]));
+
+        let state = ApplicationStateInner::default();
+
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+        controller.init().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn init_without_username() {
+        let (_, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_whoami()
+            .times(1)
+            .return_once(|| Err("keybase isn't logged in".into()));
+        client.expect_fetch_conversations()
+            .times(1)
+            .return_once(|| Ok(vec![conversation!("test1")]));
+
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, ApplicationStateInner::default(), r, shutdown_r);
+        controller.init().await.unwrap();
+        assert_eq!(controller.state.get_my_username(), None);
+        assert_eq!(controller.state.get_current_conversation().unwrap().id, "test1");
+    }
+
+    #[test]
+    fn no_cache_by_default() {
+        let path = std::env::temp_dir().join(format!("keybase-chat-tui-no-cache-{}.json", std::process::id()));
+        let (_, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let mut state = ApplicationStateInner::default();
+        state.set_conversations(vec![conversation!("test1").into()]);
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let controller = Controller::new(MockKeybaseClient::new(), state, r, shutdown_r).with_cache(path.clone());
+        controller.save_cache();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn init_from_cache() {
+        let path = std::env::temp_dir().join(format!("keybase-chat-tui-init-cache-{}.json", std::process::id()));
+        let mut unchanged: Conversation = KeybaseConversation { active_at: 100, ..conversation!("test1") }.into();
+        unchanged.fetched = true;
+        unchanged.insert_messages(vec![message!("test1", "cached", 1)]);
+        let mut changed: Conversation = KeybaseConversation { active_at: 100, ..conversation!("test2") }.into();
+        changed.fetched = true;
+        changed.insert_messages(vec![message!("test2", "cached", 1)]);
+        let gone: Conversation = conversation!("test3").into();
+        Cache::new(&[unchanged, changed, gone], Some("me"), unix_time()).save(&path).unwrap();
+
+        let (_, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_whoami()
+            .times(1)
+            .return_once(|| Ok("me".to_string()));
+        client.expect_fetch_conversations()
+            .times(1)
+            .return_once(|| Ok(vec![
+                KeybaseConversation { active_at: 100, ..conversation!("test1") },
+                KeybaseConversation { active_at: 200, ..conversation!("test2") },
+            ]));
+        let mut obs = MockStateObserver::new();
+        let mut seq = mockall::Sequence::new();
+        let ids = |conversations: &[Conversation]| -> Vec<String> {
+            conversations.iter().map(|c| c.id.clone()).collect()
+        };
+        // the cached list first, then keybase's
+        obs.expect_on_conversations_added()
+            .withf(move |c: &[Conversation]| ids(c) == ["test1", "test2", "test3"])
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        obs.expect_on_conversations_added()
+            .withf(move |c: &[Conversation]| ids(c) == ["test1", "test2"])
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        obs.expect_on_username_changed().return_const(());
+        obs.expect_on_conversation_change().return_const(());
+        let mut state = ApplicationStateInner::default();
+        state.register_observer(Box::new(obs));
+
+        let config = Config { cache_ttl_secs: 3600, ..Config::default() };
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r)
+            .with_config(config)
+            .with_cache(path.clone());
+        controller.init().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let convo = |id: &str| controller.state.get_conversation(id).cloned();
+        // nothing new in it, so what was cached is still good
+        assert!(convo("test1").unwrap().fetched);
+        assert_eq!(convo("test1").unwrap().messages.len(), 1);
+        assert!(!convo("test2").unwrap().fetched);
+        assert!(convo("test2").unwrap().messages.is_empty());
+        assert!(convo("test3").is_none());
+    }
+
+    #[tokio::test]
+    async fn startup_conversation_limit() {
+        let (_, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_whoami()
+            .times(1)
+            .return_once(|| Ok("me".to_string()));
+        client.expect_fetch_conversations().times(0);
+        client.expect_fetch_conversation_page()
+            .withf(|count, next| *count == 2 && next.is_none())
+            .times(1)
+            .return_once(|_, _| Ok(ConversationPage {
+                conversations: vec![conversation!("test1"), conversation!("test2")],
+                next: Some("page2".to_string()),
+            }));
+        client.expect_fetch_conversation_page()
+            .withf(|count, next| *count == 2 && next.as_deref() == Some("page2"))
+            .times(1)
+            .return_once(|_, _| Ok(ConversationPage {
+                conversations: vec![conversation!("test3")],
+                next: None,
+            }));
+
+        let mut obs = MockStateObserver::new();
+        let mut seq = mockall::Sequence::new();
+        obs.expect_on_username_changed().return_const(());
+        obs.expect_on_conversation_change().return_const(());
+        obs.expect_on_conversations_appended()
+            .withf(|convos: &[Conversation], more: &bool| convos.len() == 2 && *more)
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        obs.expect_on_conversations_appended()
+            .withf(|convos: &[Conversation], more: &bool| convos[0].id == "test3" && !*more)
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        let mut state = ApplicationStateInner::default();
+        state.register_observer(Box::new(obs));
+        let config = Config {
+            startup_conversation_limit: Some(2),
+            ..Config::default()
+        };
+
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r).with_config(config);
+        controller.init().await.unwrap();
+        assert_eq!(controller.state.get_conversations().count(), 2);
+        assert_eq!(controller.state.get_current_conversation().unwrap().id, "test1");
+
+        controller.load_more_conversations().await;
+        assert_eq!(controller.state.get_conversations().count(), 3);
+        // nothing left, so this doesn't fetch anything
+        controller.load_more_conversations().await;
+    }
+
+    #[tokio::test]
+    async fn init_member_counts() {
+        let (_, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        let team = |id: &str, name: &str| {
+            let mut convo = conversation!(id);
+            convo.channel.name = name.to_string();
+            convo.channel.members_type = MemberType::Team;
+            convo
+        };
+        let convos = vec![
+            team("test1", "keybase"),
+            team("test2", "keybase"),
+            team("test3", "gone"),
+            conversation!("test4"),
+        ];
+        client.expect_whoami()
+            .times(1)
+            .return_once(|| Ok("me".to_string()));
+        client.expect_fetch_conversations()
+            .times(1)
+            .return_once(move || Ok(convos));
+        client.expect_fetch_member_count()
+            .withf(|team, refresh| team == "keybase" && !refresh)
+            .times(1)
+            .return_once(|_, _| Ok(42));
+        client.expect_fetch_member_count()
+            .withf(|team, _| team == "gone")
+            .times(1)
+            .return_once(|_, _| Err("team not found".into()));
+
+        let mut config = Config::default();
+        config.layout.member_counts = true;
+        let state = ApplicationStateInner::default();
+
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r).with_config(config);
+        controller.init().await.unwrap();
+
+        let count = |id: &str| controller.state.get_conversation(id).unwrap().member_count;
+        assert_eq!(count("test1"), Some(42));
+        assert_eq!(count("test2"), Some(42));
+        assert_eq!(count("test3"), None);
+        assert_eq!(count("test4"), None);
+    }
+
+    #[tokio::test]
+    async fn goto_message_fetches_history() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_fetch_message_page()
+            .withf(|_, _, next| next.is_none())
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test1", "three", 3), message!("test1", "two", 2)],
+                next: Some("older".to_string()),
+            }));
+        client.expect_fetch_message_page()
+            .withf(|_, _, next| next.as_deref() == Some("older"))
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test1", "one", 1)],
+                next: Some("oldest".to_string()),
+            }));
+
+        let mut state = ApplicationStateInner::default();
+        let mut convo: Conversation = conversation!("test1").into();
+        let mut pending = message!("test1", "on its way", FIRST_PENDING_ID);
+        pending.delivery = Some(DeliveryState::Sending);
+        convo.insert_messages(vec![pending]);
+        state.insert_conversation(convo);
+
+        goto_message(&client, &mut state, "test1", 1).await;
+
+        let convo = state.get_current_conversation().unwrap();
+        assert_eq!(convo.id, "test1");
+        let ids: Vec<u64> = convo.messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [FIRST_PENDING_ID, 3, 2, 1]);
+        assert_eq!(convo.history_next.as_deref(), Some("oldest"));
+    }
+
+    #[tokio::test]
+    async fn load_more_pages_back() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_fetch_message_page()
+            .withf(|_, _, next| next.as_deref() == Some("page2"))
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test1", "two", 2)],
+                next: Some("page3".to_string()),
+            }));
+        client.expect_fetch_message_page()
+            .withf(|_, _, next| next.as_deref() == Some("page3"))
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test1", "one", 1)],
+                next: None,
+            }));
+
+        let mut state = ApplicationStateInner::default();
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.fetched = true;
+        convo.insert_messages(vec![message!("test1", "three", 3)]);
+        convo.history_next = Some("page2".to_string());
+        state.insert_conversation(convo);
+
+        load_more_messages(&client, &mut state, "test1").await;
+        load_more_messages(&client, &mut state, "test1").await;
+        // that was the oldest page, so this doesn't fetch anything
+        load_more_messages(&client, &mut state, "test1").await;
+
+        let convo = state.get_conversation("test1").unwrap();
+        let ids: Vec<u64> = convo.messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [3, 2, 1]);
+        assert_eq!(convo.history_next, None);
+    }
+
+    #[tokio::test]
+    async fn load_more_failure() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_fetch_message_page()
+            .times(1)
+            .return_once(|_, _, _| Err("keybase service isn't running".into()));
+
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_loading()
+            .withf(|id: &str, loading: &bool| id == "test1" && !*loading)
+            .times(1)
+            .return_const(());
+        obs.expect_on_error()
+            .withf(|message: &String| message == "Couldn't load older messages: keybase service isn't running")
+            .times(1)
+            .return_const(());
+        let mut state = ApplicationStateInner::default();
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.history_next = Some("older".to_string());
+        state.insert_conversation(convo);
+        state.register_observer(Box::new(obs));
+
+        load_more_messages(&client, &mut state, "test1").await;
+    }
+
+    #[tokio::test]
+    async fn goto_message_gives_up() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_fetch_message_page()
+            .times(GOTO_MAX_PAGES)
+            .returning(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test1", "hi", 5)],
+                next: Some("older".to_string()),
+            }));
+
+        let mut state = ApplicationStateInner::default();
+        state.insert_conversation(conversation!("test1").into());
+
+        goto_message(&client, &mut state, "test1", 1).await;
+
+        let convo = state.get_current_conversation().unwrap();
+        assert!(convo.messages.iter().all(|m| m.id != 1));
+    }
+
+    #[tokio::test]
+    async fn open_channel_joins() {
+        let mut client = MockKeybaseClient::new();
+        let mut random = conversation!("test2");
+        random.channel.name = "keybase".to_string();
+        random.channel.topic_name = "random".to_string();
+        random.channel.members_type = MemberType::Team;
+        let channel = random.channel.clone();
+        let convos = vec![random, conversation!("test1")];
+
+        client.expect_join_channel()
+            .withf(|c: &Channel| c.topic_name == "random")
+            .times(1)
+            .return_once(|_| Ok(()));
+        client.expect_fetch_conversations()
+            .times(1)
+            .return_once(move || Ok(convos));
+        client.expect_fetch_message_page()
+            .withf(|c: &KeybaseConversation, _, _| c.id == "test2")
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test2", "hello", 1)],
+                next: None,
+            }));
+        client.expect_fetch_members()
+            .withf(|team: &str, refresh: &bool| team == "keybase" && !refresh)
+            .times(1)
+            .return_once(|_, _| Ok(vec!["alice".to_string(), "me".to_string()]));
+
+        let mut state = ApplicationStateInner::default();
+        let mut existing: Conversation = conversation!("test1").into();
+        existing.fetched = true;
+        existing.insert_messages(vec![message!("test1", "kept", 3)]);
+        state.insert_conversation(existing);
+
+        let client = Rc::new(client);
+        open_channel(&client, &mut state, &channel, 20).await.unwrap();
+
+        assert_eq!(state.get_current_conversation().unwrap().id, "test2");
+        assert_eq!(state.get_conversation("test2").unwrap().messages.len(), 1);
+        assert_eq!(state.get_conversation("test2").unwrap().participants, ["alice", "me"]);
+        assert_eq!(state.get_conversation("test1").unwrap().messages.len(), 1);
+
+        // already there this time, so no joining
+        state.set_current_conversation("test1").unwrap();
+        open_channel(&client, &mut state, &channel, 20).await.unwrap();
+        assert_eq!(state.get_current_conversation().unwrap().id, "test2");
+    }
+
+    #[tokio::test]
+    async fn new_conversation() {
+        let mut client = MockKeybaseClient::new();
+        let mut dm = conversation!("test2");
+        dm.channel.name = "alice,bob,me".to_string();
+        let convos = vec![dm, conversation!("test1")];
+
+        client.expect_new_conversation()
+            .withf(|c: &Channel| c.name == "alice,bob,me" && c.members_type == MemberType::User)
+            .times(1)
+            .return_once(|_| Ok(()));
+        client.expect_fetch_conversations()
+            .times(1)
+            .return_once(move || Ok(convos));
+        client.expect_fetch_message_page()
+            .withf(|c: &KeybaseConversation, _, _| c.id == "test2")
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage { messages: vec![], next: None }));
+
+        let mut state = ApplicationStateInner::default();
+        state.set_my_username("me");
+        state.insert_conversation(conversation!("test1").into());
+
+        let channel = new_channel("bob,alice", MemberType::User, state.get_my_username());
+        open_channel(&Rc::new(client), &mut state, &channel, 20).await.unwrap();
+        assert_eq!(state.get_current_conversation().unwrap().id, "test2");
+        assert_eq!(state.get_current_conversation().unwrap().display_name(), "alice,bob");
+    }
+
+    fn attachment(id: u64, filename: &str) -> Message {
+        let mut message = message!("test1", "", id);
+        message.content = MessageType::Attachment {
+            attachment: MessageAttachment {
+                object: AttachmentObject {
+                    filename: filename.to_string(),
+                    mime_type: "image/jpeg".to_string(),
+                    ..AttachmentObject::default()
+                },
+                uploaded: true,
+            },
+        };
+        message
+    }
+
+    #[test]
+    fn export_current_conversation() {
+        let path = std::env::temp_dir().join(format!("keybase-chat-tui-export-{}.jsonl", std::process::id()));
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_conversation_change().return_const(());
+        obs.expect_on_notice()
+            .withf(|text: &str| text.starts_with("Exported 2 messages to "))
+            .times(1)
+            .return_const(());
+        obs.expect_on_error()
+            .withf(|message: &String| message.starts_with("Couldn't export to "))
+            .times(1)
+            .return_const(());
+        let mut state = ApplicationStateInner::default();
+        state.register_observer(Box::new(obs));
+        let mut convo: Conversation = conversation!("test1").into();
+        let mut pending = message!("test1", "on its way", FIRST_PENDING_ID);
+        pending.delivery = Some(DeliveryState::Sending);
+        convo.insert_messages(vec![message!("test1", "two", 2), message!("test1", "one", 1), pending]);
+        state.insert_conversation(convo);
+        state.set_current_conversation("test1").unwrap();
+
+        export_conversation(&mut state, &path);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let ids: Vec<u64> = contents
+            .lines()
+            .map(|line| serde_json::from_str::<Message>(line).unwrap().id)
+            .collect();
+        assert_eq!(ids, [2, 1]);
+
+        export_conversation(&mut state, &path.join("not/a/dir"));
+    }
+
+    #[tokio::test]
+    async fn download_attachments() {
+        let dir = std::env::temp_dir().join(format!("keybase-chat-tui-download-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut client = MockKeybaseClient::new();
+        let expected = dir.join("photo.jpg");
+        client.expect_download_attachment()
+            .withf(move |id: &str, message_id: &u64, output: &Path| {
+                id == "test1" && *message_id == 1 && output == expected
+            })
+            .times(1)
+            .return_once(|_, _, _| Ok(()));
+        client.expect_download_attachment()
+            .withf(|_: &str, message_id: &u64, _: &Path| *message_id == 3)
+            .times(1)
+            .return_once(|_, _, _| Err("no such file".into()));
+
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_conversation_change().return_const(());
+        let saved = format!("Saved {}", dir.join("photo.jpg").display());
+        obs.expect_on_notice()
+            .withf(move |text: &str| text == saved)
+            .times(1)
+            .return_const(());
+        obs.expect_on_error()
+            .withf(|message: &String| message == "Message 2 isn't an attachment")
+            .times(1)
+            .return_const(());
+        obs.expect_on_error()
+            .withf(|message: &String| message == "Failed to download ../x.png: no such file")
+            .times(1)
+            .return_const(());
+        // only for the one that was saved
+        let expected = dir.join("photo.jpg");
+        obs.expect_on_download()
+            .withf(move |path: &Path, mime_type: &str| path == expected && mime_type == "image/jpeg")
+            .times(1)
+            .return_const(());
+
+        let mut state = ApplicationStateInner::default();
+        state.register_observer(Box::new(obs));
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_messages(vec![
+            attachment(1, "photo.jpg"),
+            message!("test1", "hi", 2),
+            attachment(3, "../x.png"),
+        ]);
+        state.insert_conversation(convo);
+        state.set_current_conversation("test1").unwrap();
+
+        download_attachment(&client, &mut state, &dir, 1).await;
+        download_attachment(&client, &mut state, &dir, 2).await;
+        download_attachment(&client, &mut state, &dir, 3).await;
+        assert!(dir.is_dir());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn download_paths() {
+        let dir = std::env::temp_dir().join(format!("keybase-chat-tui-paths-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(download_path(&dir, "photo.jpg"), dir.join("photo.jpg"));
+        std::fs::write(dir.join("photo.jpg"), "").unwrap();
+        std::fs::write(dir.join("photo (1).jpg"), "").unwrap();
+        assert_eq!(download_path(&dir, "photo.jpg"), dir.join("photo (2).jpg"));
+        assert_eq!(download_path(&dir, "../../photo.jpg"), dir.join("photo (2).jpg"));
+        assert_eq!(download_path(&dir, "notes"), dir.join("notes"));
+        assert_eq!(download_path(&dir, ""), dir.join("attachment"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn new_channels() {
+        let channel = new_channel("keybase#random", MemberType::Team, Some("me"));
+        assert_eq!((channel.name.as_str(), channel.topic_name.as_str()), ("keybase", "random"));
+        assert_eq!(new_channel("keybase", MemberType::Team, None).topic_name, "general");
+        assert_eq!(new_channel("zed", MemberType::User, Some("me")).name, "me,zed");
+        assert_eq!(new_channel("me,alice", MemberType::User, Some("me")).name, "alice,me");
+        assert_eq!(new_channel("alice", MemberType::User, None).name, "alice");
+    }
+
+    // loading, then the fetch, then not loading any more, whether it worked or not
+    async fn fetch_with_loading(result: Result<MessagePage, String>) -> ApplicationStateInner {
+        let mut seq = mockall::Sequence::new();
+        let mut client = MockKeybaseClient::new();
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_loading()
+            .withf(|id: &str, loading: &bool| id == "test1" && *loading)
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        client.expect_fetch_message_page()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once(|_, _, _| result.map_err(|e| e.into()));
+        obs.expect_on_loading()
+            .withf(|id: &str, loading: &bool| id == "test1" && !*loading)
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        obs.expect_on_conversation_change().return_const(());
+
+        let mut state = ApplicationStateInner::default();
+        state.register_observer(Box::new(obs));
+        state.insert_conversation(conversation!("test1").into());
+        super::switch_conversation(&Rc::new(client), &mut state, "test1".to_string(), 20).await.ok();
+        state
+    }
+
+    #[tokio::test]
+    async fn loading_brackets_the_fetch() {
+        let page = MessagePage { messages: vec![message!("test1", "hi", 1)], next: None };
+        let state = fetch_with_loading(Ok(page)).await;
+        assert_eq!(state.get_current_conversation().unwrap().messages.len(), 1);
+
+        let state = fetch_with_loading(Err("timed out".to_string())).await;
+        assert!(state.get_current_conversation().is_none());
+        // gets another go next time
+        assert!(!state.get_conversation("test1").unwrap().fetched);
+    }
+
+    #[tokio::test]
+    async fn catch_up_keeps_pending_messages() {
+        let (_, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_fetch_message_page()
+            .returning(|c, _, _| Ok(MessagePage {
+                messages: vec![message!(c.id, "missed", 10)],
+                next: None,
+            }));
+
+        let mut other: Conversation = conversation!("test2").into();
+        other.fetched = true;
+        let mut pending = message!("test2", "on its way", FIRST_PENDING_ID);
+        pending.delivery = Some(DeliveryState::Sending);
+        other.insert_messages(vec![message!("test2", "old", 1), pending]);
+        let mut state = ApplicationStateInner::default();
+        state.set_conversations(vec![conversation!("test1").into(), other]);
+        state.set_current_conversation("test1").unwrap();
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+
+        controller.catch_up().await;
+        let other = controller.state.get_conversation("test2").unwrap();
+        assert!(!other.fetched);
+        assert_eq!(other.messages.len(), 2);
+
+        // opening it again starts over from the newest page, with the pending one still there
+        controller.switch_to("test2".to_string()).await;
+        let ids: Vec<u64> = controller.state.get_conversation("test2").unwrap().messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [FIRST_PENDING_ID, 10]);
+    }
+
+    #[tokio::test]
+    async fn switch_conversation() {
+        let (mut s, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let (_, c_recv) = tokio::sync::mpsc::channel::<ListenerEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        let convo = conversation!("test1");
+        let convo2 = conversation!("test2");
+        let c1 = convo.clone();
+        let c2 = convo2.clone();
+
+        client.expect_get_receiver()
+            .times(1)
+            .return_once(move || c_recv);
+
+        client.expect_whoami()
+            .times(1)
+            .return_once(|| Ok("me".to_string()));
+
+        client.expect_fetch_conversations()
+            .times(1)
+            .return_once(move || Ok(vec![c1, c2]));
+
+        client.expect_fetch_message_page()
+            .withf(move |c: &KeybaseConversation, _, _| c.id == "test1")
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage::default()));
+
+        let state = ApplicationStateInner::default();
+
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+
+        controller.init().await.unwrap();
+
+        tokio::spawn(async move {
+            s.send(UiEvent::SwitchConversation("test1".to_string())).await.ok();
+        });
+
+        tokio::select! {
+            _ = controller.process_events() => {},
+            _ = tokio::time::delay_for(SWITCH_DEBOUNCE * 2) => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn quick_switches_fetch_once() {
+        let (mut s, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_get_receiver()
+            .times(1)
+            .return_once(|| tokio::sync::mpsc::channel::<ListenerEvent>(32).1);
+        // only the one it ended up on
+        client.expect_fetch_message_page()
+            .withf(|c: &KeybaseConversation, _, _| c.id == "test2")
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage::default()));
+
+        let mut state = ApplicationStateInner::default();
+        state.set_conversations(vec![conversation!("test1").into(), conversation!("test2").into()]);
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+
+        tokio::spawn(async move {
+            s.send(UiEvent::SwitchConversation("test1".to_string())).await.ok();
+            s.send(UiEvent::SwitchConversation("test2".to_string())).await.ok();
+        });
+
+        tokio::select! {
+            _ = controller.process_events() => {},
+            _ = tokio::time::delay_for(SWITCH_DEBOUNCE * 2) => {}
+        }
+        assert_eq!(controller.state.get_current_conversation().unwrap().id, "test2");
+        assert!(!controller.state.get_conversation("test1").unwrap().fetched);
+    }
+
+    #[tokio::test]
+    async fn sends_while_waiting_go_to_the_new_conversation() {
+        let (mut s, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_get_receiver()
+            .times(1)
+            .return_once(|| tokio::sync::mpsc::channel::<ListenerEvent>(32).1);
+        // well before it's fetched
+        let mut seq = Sequence::new();
+        client.expect_send_message()
+            .withf(|channel: &Channel, msg: &String| channel.name == "test2" && msg == "hi")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(None));
+        client.expect_fetch_message_page()
+            .withf(|c: &KeybaseConversation, _, _| c.id == "test2")
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once(|_, _, _| Ok(MessagePage::default()));
+
+        let mut test1 = conversation!("test1");
+        test1.channel.name = "test1".to_string();
+        let mut test2 = conversation!("test2");
+        test2.channel.name = "test2".to_string();
+        let mut state = ApplicationStateInner::default();
+        state.set_conversations(vec![test1.into(), test2.into()]);
+        state.set_current_conversation("test1").unwrap();
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+
+        tokio::spawn(async move {
+            s.send(UiEvent::SwitchConversation("test2".to_string())).await.ok();
+            s.send(UiEvent::SendMessage("hi".to_string())).await.ok();
+        });
+
+        tokio::select! {
+            _ = controller.process_events() => {},
+            _ = tokio::time::delay_for(SWITCH_DEBOUNCE * 2) => {}
+        }
+        assert_eq!(controller.state.get_current_conversation().unwrap().id, "test2");
+        assert!(controller.state.get_conversation("test2").unwrap().fetched);
+    }
+
+    #[tokio::test]
+    async fn switching_away_cancels_the_fetch() {
+        let (_, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_fetch_message_page().times(0);
+
+        let mut state = ApplicationStateInner::default();
+        state.set_conversations(vec![conversation!("test1").into(), conversation!("test2").into()]);
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+
+        controller.switch_later("test1".to_string());
+        controller.start_fetch().await;
+        assert!(controller.older_pages.is_some());
+        // before any of it has come in
+        controller.switch_later("test2".to_string());
+        assert!(controller.older_pages.is_none());
+        assert!(!controller.state.get_conversation("test1").unwrap().fetched);
+        assert_eq!(controller.awaiting_first_page.as_deref(), Some("test2"));
+    }
+
+    #[tokio::test]
+    async fn send_failure_keeps_going() {
+        let (mut s, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let (_c_send, c_recv) = tokio::sync::mpsc::channel::<ListenerEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_get_receiver()
+            .times(1)
+            .return_once(move || c_recv);
+        client.expect_send_message()
+            .withf(|_, msg: &String| msg == "first")
+            .times(1)
+            .returning(|_, _| Err("network is down".into()));
+        // still listening after that
+        client.expect_send_message()
+            .withf(|_, msg: &String| msg == "second")
+            .times(1)
+            .returning(|_, _| Ok(Some(2)));
+
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_error()
+            .withf(|message: &String| message == "Couldn't send your message: network is down")
+            .times(1)
+            .return_const(());
+        obs.expect_on_send_failed()
+            .withf(|body: &str| body == "first")
+            .times(1)
+            .return_const(());
+        // keybase couldn't be reached, then it could again
+        let mut seq = Sequence::new();
+        obs.expect_on_status()
+            .with(eq(ConnectionStatus::Error))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        obs.expect_on_status()
+            .with(eq(ConnectionStatus::Connected))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        let mut state = ApplicationStateInner::default();
+        state.set_conversations(vec![conversation!("test1").into()]);
+        state.set_current_conversation("test1").unwrap();
+        state.register_observer(Box::new(obs));
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+
+        tokio::spawn(async move {
+            s.send(UiEvent::SendMessage("first".to_string())).await.ok();
+            s.send(UiEvent::SendMessage("second".to_string())).await.ok();
+        });
+
+        tokio::select! {
+            _ = controller.process_events() => panic!("stopped processing events"),
+            _ = tokio::time::delay_for(tokio::time::Duration::from_millis(10)) => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn sent_without_an_id() {
+        let (mut s, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let (_c_send, c_recv) = tokio::sync::mpsc::channel::<ListenerEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_get_receiver()
+            .times(1)
+            .return_once(move || c_recv);
+        client.expect_send_message()
+            .withf(|_, msg: &String| msg == "hi")
+            .times(1)
+            .returning(|_, _| Ok(None));
+
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_message().return_const(());
+        obs.expect_on_conversation_updated().return_const(());
+        obs.expect_on_status().with(eq(ConnectionStatus::Connected)).return_const(());
+        obs.expect_on_error().times(0);
+        obs.expect_on_send_failed().times(0);
+        let mut state = ApplicationStateInner::default();
+        state.set_my_username("me");
+        state.set_conversations(vec![conversation!("test1").into()]);
+        state.set_current_conversation("test1").unwrap();
+        state.register_observer(Box::new(obs));
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+
+        tokio::spawn(async move {
+            s.send(UiEvent::SendMessage("hi".to_string())).await.ok();
+        });
+
+        tokio::select! {
+            _ = controller.process_events() => panic!("stopped processing events"),
+            _ = tokio::time::delay_for(tokio::time::Duration::from_millis(10)) => {}
+        }
+        // still there until the listener says what it became
+        let convo = controller.state.get_conversation("test1").unwrap();
+        assert_eq!(convo.messages.len(), 1);
+        assert!(convo.messages[0].is_pending());
+    }
+
+    #[tokio::test]
+    async fn any_call_updates_connection_status() {
+        let (mut s, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let (_c_send, c_recv) = tokio::sync::mpsc::channel::<ListenerEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_get_receiver()
+            .times(1)
+            .return_once(move || c_recv);
+        client.expect_mark_as_read()
+            .times(1)
+            .returning(|_, _| Err("keybase isn't running".into()));
+        client.expect_react()
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut obs = MockStateObserver::new();
+        let mut seq = Sequence::new();
+        obs.expect_on_status()
+            .with(eq(ConnectionStatus::Error))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        obs.expect_on_status()
+            .with(eq(ConnectionStatus::Connected))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        let mut state = ApplicationStateInner::default();
+        state.set_conversations(vec![conversation!("test1").into()]);
+        state.set_current_conversation("test1").unwrap();
+        state.register_observer(Box::new(obs));
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+
+        tokio::spawn(async move {
+            s.send(UiEvent::MarkRead("test1".to_string(), 1)).await.ok();
+            s.send(UiEvent::React(1, ":+1:".to_string())).await.ok();
+        });
+
+        tokio::select! {
+            _ = controller.process_events() => panic!("stopped processing events"),
+            _ = tokio::time::delay_for(tokio::time::Duration::from_millis(10)) => {}
+        }
+    }
+
+    // `event` is handled while `client` fails, and `notice` is the error the user sees
+    async fn shows_failure(event: UiEvent, mut client: MockKeybaseClient, notice: &'static str) {
+        let (mut s, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let (_c_send, c_recv) = tokio::sync::mpsc::channel::<ListenerEvent>(32);
+        client.expect_get_receiver()
+            .times(1)
+            .return_once(move || c_recv);
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_status().return_const(());
+        obs.expect_on_error()
+            .withf(move |message: &String| *message == notice)
+            .times(1)
+            .return_const(());
+        let mut state = ApplicationStateInner::default();
+        state.set_conversations(vec![conversation!("test1").into()]);
+        state.set_current_conversation("test1").unwrap();
+        state.register_observer(Box::new(obs));
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+
+        tokio::spawn(async move {
+            s.send(event).await.ok();
+        });
+
+        tokio::select! {
+            _ = controller.process_events() => panic!("stopped processing events"),
+            _ = tokio::time::delay_for(tokio::time::Duration::from_millis(10)) => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn react_failure_is_shown() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_react()
+            .times(1)
+            .returning(|_, _, _| Err("message not found".into()));
+        let event = UiEvent::React(3, ":+1:".to_string());
+        shows_failure(event, client, "Couldn't react to message 3: message not found").await;
+    }
+
+    #[tokio::test]
+    async fn edit_failure_is_shown() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_edit_message()
+            .times(1)
+            .returning(|_, _, _| Err("you can only edit your own messages".into()));
+        let event = UiEvent::EditMessage(3, "fixed".to_string());
+        shows_failure(event, client, "Couldn't edit message 3: you can only edit your own messages").await;
+    }
+
+    #[tokio::test]
+    async fn delete_failure_is_shown() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_delete_message()
+            .times(1)
+            .returning(|_, _| Err("you can only delete your own messages".into()));
+        let event = UiEvent::DeleteMessage(3);
+        shows_failure(event, client, "Couldn't delete message 3: you can only delete your own messages").await;
+    }
+
+    #[tokio::test]
+    async fn pin_failure_is_shown() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_pin_message()
+            .times(1)
+            .returning(|_, _| Err("you must be an admin to pin messages".into()));
+
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_conversation_change().return_const(());
+        obs.expect_on_conversations_added().return_const(());
+        obs.expect_on_error()
+            .withf(|message: &String| message == "Couldn't pin message 3: you must be an admin to pin messages")
+            .times(1)
+            .return_const(());
+        let mut state = ApplicationStateInner::default();
+        state.register_observer(Box::new(obs));
+        state.set_conversations(vec![conversation!("test1").into()]);
+        state.set_current_conversation("test1").unwrap();
+
+        set_pinned(&client, &mut state, 3, true).await;
+        assert!(state.get_conversation("test1").unwrap().pinned.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rename_failure_is_shown() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_rename_channel()
+            .times(1)
+            .returning(|_, _| Err("only admins can rename channels".into()));
+
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_conversation_change().return_const(());
+        obs.expect_on_conversations_added().return_const(());
+        obs.expect_on_error()
+            .withf(|message: &String| message == "Couldn't rename the channel: only admins can rename channels")
+            .times(1)
+            .return_const(());
+        let mut state = ApplicationStateInner::default();
+        state.register_observer(Box::new(obs));
+        state.set_conversations(vec![conversation!("test1").into()]);
+        state.set_current_conversation("test1").unwrap();
+
+        rename_channel(&client, &mut state, "renamed").await;
+        assert_eq!(state.get_conversation("test1").unwrap().data.channel.topic_name, "");
+    }
+
+    #[tokio::test]
+    async fn switching_marks_as_read() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_fetch_message_page()
+            .returning(|c, _, _| Ok(MessagePage {
+                messages: match c.id.as_str() {
+                    "empty" => vec![],
+                    id => vec![message!(id, "new", 9), message!(id, "old", 8)],
+                },
+                next: None,
+            }));
+        // only the unread one with messages in it, up to the newest
+        client.expect_mark_as_read()
+            .withf(|id: &str, message_id: &u64| id == "unread" && *message_id == 9)
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let mut unread = conversation!("unread");
+        unread.unread = true;
+        let mut empty = conversation!("empty");
+        empty.unread = true;
+        let mut state = ApplicationStateInner::default();
+        state.set_conversations(vec![unread.into(), empty.into(), conversation!("read").into()]);
+
+        let client = Rc::new(client);
+        for id in &["unread", "empty", "read", "unread"] {
+            super::switch_conversation(&client, &mut state, id.to_string(), 20).await.unwrap();
+        }
+        assert!(!state.get_conversation("unread").unwrap().data.unread);
+        assert!(state.get_conversation("empty").unwrap().data.unread);
+    }
+
+    #[tokio::test]
+    async fn list_team_channels() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_list_channels()
+            .withf(|team: &str| team == "keybase")
+            .times(1)
+            .returning(|_| Ok(vec!["general".to_string(), "random".to_string()]));
+        client.expect_list_channels()
+            .withf(|team: &str| team == "nope")
+            .times(1)
+            .returning(|_| Err("team not found".into()));
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_channels()
+            .withf(|team: &str, topics: &[String]| team == "keybase" && topics == ["general", "random"])
+            .times(1)
+            .return_const(());
+        obs.expect_on_error()
+            .withf(|message: &String| message == "Couldn't list the channels in nope: team not found")
+            .times(1)
+            .return_const(());
+        let mut state = ApplicationStateInner::default();
+        state.register_observer(Box::new(obs));
+
+        list_channels(&client, &mut state, "keybase").await;
+        list_channels(&client, &mut state, "nope").await;
+    }
+
+    #[tokio::test]
+    async fn configured_message_count() {
+        let (mut s, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let (_c_send, c_recv) = tokio::sync::mpsc::channel::<ListenerEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_get_receiver()
+            .times(1)
+            .return_once(move || c_recv);
+        client.expect_fetch_message_page()
+            .withf(|c: &KeybaseConversation, count: &u32, next: &Option<String>| {
+                c.id == "test1" && *count == 5 && next.is_none()
+            })
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage::default()));
+
+        let mut state = ApplicationStateInner::default();
+        state.set_conversations(vec![conversation!("test1").into()]);
+        // fewer than a chunk, so they're all fetched at once
+        let config = Config {
+            initial_message_count: 5,
+            ..Config::default()
+        };
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r).with_config(config);
+
+        tokio::spawn(async move {
+            s.send(UiEvent::SwitchConversation("test1".to_string())).await.ok();
+        });
+
+        tokio::select! {
+            _ = controller.process_events() => {},
+            _ = tokio::time::delay_for(SWITCH_DEBOUNCE * 2) => {}
+        }
+        assert_eq!(controller.state.get_current_conversation().unwrap().id, "test1");
+    }
+
+    #[tokio::test]
+    async fn older_pages_come_in_while_running() {
+        let (mut s, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let (_c_send, c_recv) = tokio::sync::mpsc::channel::<ListenerEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_get_receiver()
+            .times(1)
+            .return_once(move || c_recv);
+        client.expect_fetch_message_page()
+            .withf(|_, count: &u32, next: &Option<String>| *count == FETCH_CHUNK_SIZE && next.is_none())
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test1", "new", 2)],
+                next: Some("older".to_string()),
+            }));
+        client.expect_fetch_message_page()
+            .withf(|_, count: &u32, next: &Option<String>| *count == FETCH_CHUNK_SIZE && next.as_deref() == Some("older"))
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test1", "old", 1)],
+                next: None,
+            }));
+
+        let mut state = ApplicationStateInner::default();
+        state.set_conversations(vec![conversation!("test1").into()]);
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+
+        tokio::spawn(async move {
+            s.send(UiEvent::SwitchConversation("test1".to_string())).await.ok();
+        });
+
+        tokio::select! {
+            _ = controller.process_events() => {},
+            _ = tokio::time::delay_for(SWITCH_DEBOUNCE * 2) => {}
+        }
+        let convo = controller.state.get_current_conversation().unwrap();
+        assert_eq!(convo.messages.iter().map(|m| m.id).collect::<Vec<u64>>(), [2, 1]);
+    }
+
+    #[tokio::test]
+    async fn switching_fetches_in_chunks() {
+        let mut client = MockKeybaseClient::new();
+        client.expect_fetch_message_page()
+            .withf(|_, count: &u32, next: &Option<String>| *count == FETCH_CHUNK_SIZE && next.is_none())
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test1", "new", 2)],
+                next: Some("older".to_string()),
+            }));
+        client.expect_fetch_message_page()
+            .withf(|_, count: &u32, next: &Option<String>| *count == 10 && next.as_deref() == Some("older"))
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test1", "old", 1)],
+                next: Some("oldest".to_string()),
+            }));
+
+        let mut state = ApplicationStateInner::default();
+        state.set_conversations(vec![conversation!("test1").into()]);
+        let older = super::switch_conversation(&Rc::new(client), &mut state, "test1".to_string(), FETCH_CHUNK_SIZE + 10)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // the newest ones are on screen before the rest are even asked for
+        let ids = |state: &ApplicationStateInner| -> Vec<u64> {
+            state.get_current_conversation().unwrap().messages.iter().map(|m| m.id).collect()
+        };
+        assert_eq!(state.get_current_conversation().unwrap().id, "test1");
+        assert_eq!(ids(&state), [2]);
+        let pages: Vec<(String, FetchResult)> = older.collect().await;
+        for (id, page) in pages {
+            let page = page.unwrap();
+            state.insert_history(&id, page.messages, page.next);
+        }
+
+        let convo = state.get_current_conversation().unwrap();
+        assert_eq!(ids(&state), [2, 1]);
+        // scrolling up carries on from the last page
+        assert_eq!(convo.history_next.as_deref(), Some("oldest"));
+    }
+
+    #[tokio::test]
+    async fn prefetch_unread() {
+        let (_, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        let mut unread1 = conversation!("unread1");
+        unread1.unread = true;
+        let mut unread2 = conversation!("unread2");
+        unread2.unread = true;
+        let read = conversation!("read");
+
+        client.expect_whoami()
+            .times(1)
+            .return_once(|| Ok("me".to_string()));
+
+        client.expect_fetch_conversations()
+            .times(1)
+            .return_once(move || Ok(vec![read, unread1, unread2]));
+
+        client.expect_fetch_message_page()
+            .withf(|c: &KeybaseConversation, _, _| c.id.starts_with("unread"))
+            .times(2)
+            .returning(|c, _, _| Ok(MessagePage {
+                messages: vec![message!(c.id, "hi")],
+                next: Some("older".to_string()),
+            }));
+        client.expect_get_receiver()
+            .times(1)
+            .return_once(|| tokio::sync::mpsc::channel::<ListenerEvent>(32).1);
+
+        let state = ApplicationStateInner::default();
+        let config = Config {
+            prefetch_unread: true,
+            ..Config::default()
+        };
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r).with_config(config);
+
+        controller.init().await.unwrap();
+        // they come in while events are being handled
+        tokio::select! {
+            _ = controller.process_events() => panic!("stopped processing events"),
+            _ = tokio::time::delay_for(tokio::time::Duration::from_millis(10)) => {}
+        }
+
+        for id in &["unread1", "unread2"] {
+            let convo = controller.state.get_conversation(id).unwrap();
+            assert!(convo.fetched);
+            assert_eq!(convo.messages.len(), 1);
+            assert_eq!(convo.history_next.as_deref(), Some("older"));
+        }
+        assert!(!controller.state.get_conversation("read").unwrap().fetched);
+    }
+
+    #[tokio::test]
+    async fn stops_on_shutdown_signal() {
+        let (_s, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let (_c_send, c_recv) = tokio::sync::mpsc::channel::<ListenerEvent>(32);
+        let (mut shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut client = MockKeybaseClient::new();
+        client.expect_get_receiver()
+            .times(1)
+            .return_once(move || c_recv);
+        let state = ApplicationStateInner::default();
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+
+        tokio::spawn(async move {
+            shutdown.send(()).await.ok();
+        });
+
+        let stopped = tokio::time::timeout(Duration::from_secs(1), controller.process_events()).await;
+        assert!(matches!(stopped, Ok(Ok(()))));
+        controller.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn shutdown_flushes_sends() {
+        let (mut s, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let mut client = MockKeybaseClient::new();
+        client.expect_send_message()
+            .withf(|channel: &Channel, msg: &String| channel.name == "test1" && msg == "bye")
+            .times(1)
+            .returning(|_, _| Ok(Some(4)));
+        client.expect_send_reply()
+            .withf(|channel: &Channel, id: &u64, msg: &String| {
+                channel.name == "test2" && *id == 3 && msg == "later"
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        let mut state = ApplicationStateInner::default();
+        let convos: Vec<Conversation> = ["test1", "test2"]
+            .iter()
+            .map(|id| {
+                let mut convo = conversation!(*id);
+                convo.channel.name = id.to_string();
+                convo.into()
+            })
+            .collect();
+        state.set_conversations(convos);
+        state.set_current_conversation("test1").unwrap();
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+
+        s.send(UiEvent::SendMessage("bye".to_string())).await.ok();
+        s.send(UiEvent::HideConversation).await.ok();
+        // typed after switching, so it goes there
+        s.send(UiEvent::SwitchConversation("test2".to_string())).await.ok();
+        s.send(UiEvent::Reply(3, "later".to_string())).await.ok();
+        controller.shutdown().await;
+
+        // nothing goes through after that
+        assert!(s.send(UiEvent::SendMessage("too late".to_string())).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn message_for_unknown_conversation() {
+        let (_, r) = tokio::sync::mpsc::channel::<UiEvent>(32);
+        let client = MockKeybaseClient::new();
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_conversations_added().return_const(());
+        obs.expect_on_conversations_appended()
+            .withf(|convos: &[Conversation], _: &bool| convos.len() == 1 && convos[0].id == "new")
+            .times(1)
+            .return_const(());
+        obs.expect_on_message().times(1).return_const(());
+        obs.expect_on_conversation_updated().return_const(());
+        let mut state = ApplicationStateInner::default();
+        state.register_observer(Box::new(obs));
+        state.set_conversations(vec![conversation!("test1").into()]);
+        let (_shutdown, shutdown_r) = tokio::sync::mpsc::channel::<()>(1);
+        let mut controller = Controller::new(client, state, r, shutdown_r);
+
+        let mut msg = message!("new", "hi there", 1);
+        msg.channel.name = "alice,me".to_string();
+        controller.receive_message(msg).await;
+
+        let convo = controller.state.get_conversation("new").unwrap();
+        assert_eq!(convo.data.channel.name, "alice,me");
+        assert_eq!(convo.messages.len(), 1);
+        assert_eq!(convo.unread_count, 1);
+        assert!(controller.state.get_conversation("test1").is_some());
+    }
+}
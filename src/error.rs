@@ -0,0 +1,94 @@
+// # error.rs
+//
+// Errors from talking to keybase that aren't just passed along from somewhere else (io, json), and
+// from asking the application state about things it doesn't have.
+
+use std::error::Error;
+use std::fmt;
+
+use serde_json::{from_value, Value};
+
+use crate::types::{ApiError, ApiErrorWrapper};
+
+#[derive(Debug, PartialEq)]
+pub enum KeybaseError {
+    // the api answered, but not with the kind of result that was asked for
+    UnexpectedResponse,
+    // the api said the command failed
+    Api(ApiError),
+    // keybase is running, but nobody is logged in to it
+    NotLoggedIn,
+}
+
+impl KeybaseError {
+    // the error in an api response, if it has one
+    pub fn from_response(value: &Value) -> Option<Self> {
+        value.get("error")?;
+        match from_value::<ApiErrorWrapper>(value.clone()) {
+            Ok(wrapper) => Some(KeybaseError::Api(wrapper.error)),
+            // an error we can't make sense of is still an error
+            Err(_) => Some(KeybaseError::UnexpectedResponse),
+        }
+    }
+}
+
+impl fmt::Display for KeybaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeybaseError::UnexpectedResponse => write!(f, "Unexpected response from the keybase api"),
+            KeybaseError::Api(e) => write!(f, "{}", e.message),
+            KeybaseError::NotLoggedIn => {
+                write!(f, "Not logged in to keybase, run `keybase login` first")
+            }
+        }
+    }
+}
+
+impl Error for KeybaseError {}
+
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    // no conversation with this id has been loaded (yet)
+    UnknownConversation(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::UnknownConversation(id) => write!(f, "Unknown conversation {}", id),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn api_error_envelope() {
+        let value = json!({
+            "error": {
+                "code": 2623,
+                "message": "no conversations matched \"keybase#nope\""
+            }
+        });
+        let error = KeybaseError::from_response(&value).unwrap();
+        assert_eq!(
+            error,
+            KeybaseError::Api(ApiError {
+                code: 2623,
+                message: "no conversations matched \"keybase#nope\"".to_string(),
+            })
+        );
+        assert_eq!(error.to_string(), "no conversations matched \"keybase#nope\"");
+
+        assert_eq!(
+            KeybaseError::from_response(&json!({"error": "???"})),
+            Some(KeybaseError::UnexpectedResponse)
+        );
+        assert_eq!(KeybaseError::from_response(&json!({"result": {}})), None);
+    }
+}
@@ -0,0 +1,314 @@
+// # export.rs
+//
+// `keybase-chat-tui export-all <dir>` writes the full history of every conversation to its own
+// file in `<dir>`, one JSON message per line, newest first. Pages are written as they arrive so
+// long histories never have to fit in memory.
+//
+// `/export <path>` writes the messages loaded for the current conversation the same way.
+//
+// Each conversation goes to `<name>.jsonl.part` first and is renamed once it's complete, so
+// running the export again skips everything that finished and redoes anything that didn't. If a
+// page of history can't be fetched, what came before it is left in the `.part` file and the
+// export carries on with the next conversation.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use futures::stream::{self, StreamExt};
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+
+use crate::client::KeybaseClient;
+use crate::types::{KeybaseConversation, MemberType, Message};
+
+// messages fetched per api call
+const PAGE_SIZE: u32 = 100;
+// conversations exported at the same time
+const EXPORT_CONCURRENCY: usize = 4;
+
+// `team#channel.jsonl` or `alice,bob.jsonl`, with anything that doesn't belong in a file name
+// replaced
+pub fn file_name(conversation: &KeybaseConversation) -> String {
+    let channel = &conversation.channel;
+    let name = match channel.members_type {
+        MemberType::Team => format!("{}#{}", channel.name, channel.topic_name),
+        MemberType::User => channel.name.clone(),
+    };
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "#,._-".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.jsonl", name)
+}
+
+// one JSON message per line
+pub fn json_lines(messages: &[Message]) -> serde_json::Result<Vec<u8>> {
+    let mut lines = vec![];
+    for message in messages {
+        serde_json::to_writer(&mut lines, message)?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+// only finished exports count, a leftover `.part` file gets started over
+fn is_exported(dir: &Path, conversation: &KeybaseConversation) -> bool {
+    dir.join(file_name(conversation)).exists()
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+// how far one conversation's export got
+enum Exported {
+    // every message, this many
+    All(usize),
+    // only the newest ones, up to a page that couldn't be fetched
+    Partial(usize, Box<dyn Error>),
+}
+
+// write one conversation's history
+async fn export_conversation<C: KeybaseClient>(
+    client: &C,
+    dir: &Path,
+    conversation: &KeybaseConversation,
+) -> Result<Exported, Box<dyn Error>> {
+    let path = dir.join(file_name(conversation));
+    let part = part_path(&path);
+    let mut file = File::create(&part).await?;
+    let mut count = 0;
+    let mut next = None;
+    loop {
+        // there's no finding the page after one that failed, so that's as far as it goes
+        let page = match client.fetch_message_page(conversation, PAGE_SIZE, next).await {
+            Ok(page) => page,
+            Err(e) => {
+                file.flush().await?;
+                return Ok(Exported::Partial(count, e));
+            }
+        };
+        file.write_all(&json_lines(&page.messages)?).await?;
+        count += page.messages.len();
+        next = page.next;
+        if next.is_none() || page.messages.is_empty() {
+            break;
+        }
+    }
+    file.flush().await?;
+    fs::rename(&part, &path).await?;
+    Ok(Exported::All(count))
+}
+
+// Export every conversation into `dir`, printing progress as each one finishes. A conversation
+// that fails doesn't stop the others, it's reported and can be retried by running it again.
+pub async fn export_all<C: KeybaseClient>(client: &C, dir: &Path) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir).await?;
+    let conversations = client.fetch_conversations().await?;
+    let total = conversations.len();
+    let (done, todo): (Vec<_>, Vec<_>) =
+        conversations.into_iter().partition(|c| is_exported(dir, c));
+    if !done.is_empty() {
+        println!("Skipping {} already exported conversations", done.len());
+    }
+
+    let mut finished = done.len();
+    let mut failed = 0;
+    let mut results = stream::iter(todo.iter())
+        .map(|c| async move { (c, export_conversation(client, dir, c).await) })
+        .buffer_unordered(EXPORT_CONCURRENCY);
+    while let Some((conversation, result)) = results.next().await {
+        finished += 1;
+        let name = file_name(conversation);
+        match result {
+            Ok(Exported::All(count)) => {
+                println!("[{}/{}] {}: {} messages", finished, total, name, count)
+            }
+            Ok(Exported::Partial(count, e)) => {
+                failed += 1;
+                println!(
+                    "[{}/{}] {}: stopped after {} messages, kept in {}.part: {}",
+                    finished, total, name, count, name, e
+                );
+            }
+            Err(e) => {
+                failed += 1;
+                println!("[{}/{}] {}: failed: {}", finished, total, name, e);
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("{} conversations failed to export", failed).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::MockKeybaseClient;
+    use crate::types::{Channel, Message, MessageBody, MessagePage, MessageType};
+    use crate::{conversation, message};
+
+    fn export_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("keybase-chat-tui-export-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn file_names() {
+        let mut team = conversation!("test1");
+        team.channel.name = "keybase".to_string();
+        team.channel.topic_name = "general".to_string();
+        team.channel.members_type = MemberType::Team;
+        assert_eq!(file_name(&team), "keybase#general.jsonl");
+
+        let mut user = conversation!("test2");
+        user.channel.name = "alice,bob".to_string();
+        assert_eq!(file_name(&user), "alice,bob.jsonl");
+
+        user.channel.name = "../etc/passwd".to_string();
+        assert_eq!(file_name(&user), ".._etc_passwd.jsonl");
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut attachment = message!("test1", "", 2);
+        attachment.content = serde_json::from_value(serde_json::json!({
+            "type": "attachment",
+            "attachment": {"object": {"filename": "photo.jpg", "title": "look"}}
+        }))
+        .unwrap();
+        let mut reply = message!("test1", "hi\nthere", 1);
+        if let MessageType::Text { text } = &mut reply.content {
+            text.reply_to = Some(3);
+        }
+        let messages = vec![message!("test1", "three", 3), attachment, reply];
+
+        let lines = json_lines(&messages).unwrap();
+        let parsed: Vec<Message> = String::from_utf8(lines)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(parsed, messages);
+    }
+
+    #[tokio::test]
+    async fn exports_every_page() {
+        let dir = export_dir("pages");
+        let convo = conversation!("test1");
+        let mut client = MockKeybaseClient::new();
+        let c = convo.clone();
+        client.expect_fetch_conversations()
+            .times(1)
+            .return_once(move || Ok(vec![c]));
+        client.expect_fetch_message_page()
+            .withf(|_, _, next| next.is_none())
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test1", "three", 3), message!("test1", "two", 2)],
+                next: Some("older".to_string()),
+            }));
+        client.expect_fetch_message_page()
+            .withf(|_, _, next| next.as_deref() == Some("older"))
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test1", "one", 1)],
+                next: None,
+            }));
+
+        export_all(&client, &dir).await.unwrap();
+
+        let contents = std::fs::read_to_string(dir.join(file_name(&convo))).unwrap();
+        let ids: Vec<u64> = contents
+            .lines()
+            .map(|line| serde_json::from_str::<Message>(line).unwrap().id)
+            .collect();
+        assert_eq!(ids, [3, 2, 1]);
+        assert!(!part_path(&dir.join(file_name(&convo))).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn skips_exported_conversations() {
+        let dir = export_dir("skip");
+        std::fs::create_dir_all(&dir).unwrap();
+        let done = conversation!("test1");
+        let mut todo = conversation!("test2");
+        todo.channel.name = "alice,bob".to_string();
+        std::fs::write(dir.join(file_name(&done)), "").unwrap();
+        // an unfinished export is started over
+        std::fs::write(part_path(&dir.join(file_name(&todo))), "stale\n").unwrap();
+
+        assert!(is_exported(&dir, &done));
+        assert!(!is_exported(&dir, &todo));
+
+        let mut client = MockKeybaseClient::new();
+        let convos = vec![done.clone(), todo.clone()];
+        client.expect_fetch_conversations()
+            .times(1)
+            .return_once(move || Ok(convos));
+        client.expect_fetch_message_page()
+            .withf(|c, _, _| c.id == "test2")
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage::default()));
+
+        export_all(&client, &dir).await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(dir.join(file_name(&todo))).unwrap(), "");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_pages_before_a_bad_one() {
+        let dir = export_dir("partial");
+        let broken = conversation!("test1");
+        let mut fine = conversation!("test2");
+        fine.channel.name = "alice,bob".to_string();
+        let mut client = MockKeybaseClient::new();
+        let convos = vec![broken.clone(), fine.clone()];
+        client.expect_fetch_conversations()
+            .times(1)
+            .return_once(move || Ok(convos));
+        client.expect_fetch_message_page()
+            .withf(|c, _, next| c.id == "test1" && next.is_none())
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test1", "two", 2)],
+                next: Some("older".to_string()),
+            }));
+        client.expect_fetch_message_page()
+            .withf(|c, _, next| c.id == "test1" && next.is_some())
+            .times(1)
+            .return_once(|_, _, _| Err("unexpected response".into()));
+        client.expect_fetch_message_page()
+            .withf(|c, _, _| c.id == "test2")
+            .times(1)
+            .return_once(|_, _, _| Ok(MessagePage {
+                messages: vec![message!("test2", "hi", 1)],
+                next: None,
+            }));
+
+        let e = export_all(&client, &dir).await.unwrap_err();
+        assert_eq!(e.to_string(), "1 conversations failed to export");
+
+        // the other conversation still made it, and the newest page of the broken one is kept
+        assert!(is_exported(&dir, &fine));
+        assert!(!is_exported(&dir, &broken));
+        let part = std::fs::read_to_string(part_path(&dir.join(file_name(&broken)))).unwrap();
+        assert_eq!(part.lines().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
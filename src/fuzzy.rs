@@ -0,0 +1,93 @@
+// # fuzzy.rs
+//
+// Fuzzy matching for the quick switcher (Ctrl+k): what's typed matches a conversation name if
+// its letters turn up in the name in the same order, ignoring case, e.g. `kbgen` for
+// `keybase#general`. Letters next to each other, or starting a word, make for a better match.
+
+// how much each matched letter is worth, and the bonuses on top
+const MATCH: i32 = 1;
+const CONSECUTIVE_BONUS: i32 = 5;
+const WORD_START_BONUS: i32 = 8;
+
+// `#` in team channels, `,` between usernames and so on start a new word
+fn starts_word(previous: Option<char>) -> bool {
+    previous.is_none_or(|c| !c.is_alphanumeric())
+}
+
+// How well `query` matches `candidate`, higher is better, or None if it doesn't. Letters skipped
+// between matches cost a point each, so tighter matches come first.
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<i32> {
+    let candidate: Vec<char> = candidate.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut last_match: Option<usize> = None;
+    for q in query.chars().flat_map(char::to_lowercase) {
+        let index = next + candidate[next..].iter().position(|&c| c == q)?;
+        score += MATCH;
+        if last_match.is_some_and(|last| last + 1 == index) {
+            score += CONSECUTIVE_BONUS;
+        } else {
+            score -= (index - next) as i32;
+        }
+        if starts_word(index.checked_sub(1).map(|i| candidate[i])) {
+            score += WORD_START_BONUS;
+        }
+        last_match = Some(index);
+        next = index + 1;
+    }
+    Some(score)
+}
+
+// The indices of the `candidates` that `query` matches, best first. Equally good ones keep the
+// order they were in, and an empty query matches everything.
+pub fn rank<'a, I: IntoIterator<Item = &'a str>>(query: &str, candidates: I) -> Vec<usize> {
+    let mut matches: Vec<(usize, i32)> = candidates
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, candidate)| Some((i, fuzzy_match(query, candidate)?)))
+        .collect();
+    matches.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+    matches.into_iter().map(|(i, _)| i).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn subsequences_match() {
+        assert!(fuzzy_match("kbgen", "keybase#general").is_some());
+        assert!(fuzzy_match("general", "keybase#general").is_some());
+        assert!(fuzzy_match("", "keybase#general").is_some());
+        // out of order, or not there at all
+        assert!(fuzzy_match("genkb", "keybase#general").is_none());
+        assert!(fuzzy_match("random", "keybase#general").is_none());
+        assert!(fuzzy_match("generals", "keybase#general").is_none());
+    }
+
+    #[test]
+    fn ignores_case() {
+        assert_eq!(fuzzy_match("KB", "keybase"), fuzzy_match("kb", "keybase"));
+        assert!(fuzzy_match("alice", "Alice,Bob").is_some());
+        assert!(fuzzy_match("ÜN", "ünïcödé").is_some());
+    }
+
+    #[test]
+    fn better_matches_score_higher() {
+        // next to each other beats spread out
+        assert!(fuzzy_match("gen", "team#general") > fuzzy_match("gen", "team#green-ops"));
+        // starting a word beats the middle of one
+        assert!(fuzzy_match("bob", "alice,bob") > fuzzy_match("bob", "alice,jimbob"));
+        // sooner beats later
+        assert!(fuzzy_match("a", "alice") > fuzzy_match("a", "bob,carla"));
+    }
+
+    #[test]
+    fn ranks_best_first() {
+        let names = ["team#random", "team#general", "alice,bob", "generic#chat"];
+        assert_eq!(rank("gen", names.iter().copied()), [3, 1]);
+        assert_eq!(rank("team", names.iter().copied()), [0, 1]);
+        assert_eq!(rank("", names.iter().copied()), [0, 1, 2, 3]);
+        assert!(rank("zzz", names.iter().copied()).is_empty());
+    }
+}
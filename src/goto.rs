@@ -0,0 +1,155 @@
+// # goto.rs
+//
+// `/goto <message-link-or-id>` jumps to a message: either a raw id in the current conversation,
+// or a keybase deep link like `keybase://chat/team#general/123` or `keybase://chat/alice,bob/123`
+// that names the conversation too.
+
+use crate::types::{Conversation, MemberType};
+
+const LINK_PREFIX: &str = "keybase://chat/";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageLink {
+    // conversation name from a deep link, or None for the current conversation
+    pub conversation: Option<String>,
+    pub message_id: u64,
+}
+
+fn parse_id(id: &str) -> Result<u64, String> {
+    id.parse().map_err(|_| format!("Not a message id: {}", id))
+}
+
+pub fn parse_message_link(input: &str) -> Result<MessageLink, String> {
+    let input = input.trim();
+    let path = match input.strip_prefix(LINK_PREFIX) {
+        Some(path) => path,
+        None => {
+            return Ok(MessageLink {
+                conversation: None,
+                message_id: parse_id(input)?,
+            })
+        }
+    };
+
+    // the `#` in a team link is sometimes escaped
+    let path = path.replace("%23", "#").replace("%2C", ",").replace("%2c", ",");
+    let mut parts = path.trim_end_matches('/').rsplitn(2, '/');
+    let id = parts.next().unwrap_or("");
+    match parts.next() {
+        Some(name) if !name.is_empty() => Ok(MessageLink {
+            conversation: Some(name.to_string()),
+            message_id: parse_id(id)?,
+        }),
+        _ => Err(format!("Not a link to a message: {}", input)),
+    }
+}
+
+// usernames in any order, `alice,bob` and `bob,alice` are the same conversation
+fn same_users(a: &str, b: &str) -> bool {
+    let users = |names: &str| {
+        let mut users: Vec<String> = names.split(',').map(|u| u.trim().to_lowercase()).collect();
+        users.sort();
+        users
+    };
+    users(a) == users(b)
+}
+
+fn matches_name(conversation: &Conversation, name: &str) -> bool {
+    let channel = &conversation.data.channel;
+    match channel.members_type {
+        MemberType::Team => {
+            // a team on its own means its general channel
+            let (team, topic) = match name.find('#') {
+                Some(i) => (&name[..i], &name[i + 1..]),
+                None => (name, "general"),
+            };
+            channel.name.eq_ignore_ascii_case(team) && channel.topic_name.eq_ignore_ascii_case(topic)
+        }
+        MemberType::User => same_users(&channel.name, name) || conversation.get_name() == name,
+    }
+}
+
+// the id of the conversation a link points at
+pub fn resolve_conversation<'a, I>(conversations: I, name: &str) -> Result<String, String>
+where
+    I: IntoIterator<Item = &'a Conversation>,
+{
+    conversations
+        .into_iter()
+        .find(|c| matches_name(c, name))
+        .map(|c| c.id.clone())
+        .ok_or_else(|| format!("No conversation named {}", name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::conversation;
+    use crate::types::{Channel, KeybaseConversation};
+
+    fn link(conversation: Option<&str>, message_id: u64) -> MessageLink {
+        MessageLink {
+            conversation: conversation.map(String::from),
+            message_id,
+        }
+    }
+
+    #[test]
+    fn raw_ids() {
+        assert_eq!(parse_message_link("42"), Ok(link(None, 42)));
+        assert_eq!(parse_message_link(" 42 "), Ok(link(None, 42)));
+        assert_eq!(parse_message_link("abc").unwrap_err(), "Not a message id: abc");
+        assert!(parse_message_link("").is_err());
+    }
+
+    #[test]
+    fn deep_links() {
+        assert_eq!(
+            parse_message_link("keybase://chat/keybase#general/123"),
+            Ok(link(Some("keybase#general"), 123))
+        );
+        assert_eq!(
+            parse_message_link("keybase://chat/keybase%23general/123/"),
+            Ok(link(Some("keybase#general"), 123))
+        );
+        assert_eq!(
+            parse_message_link("keybase://chat/alice,bob/7"),
+            Ok(link(Some("alice,bob"), 7))
+        );
+        assert_eq!(
+            parse_message_link("keybase://chat/alice,bob").unwrap_err(),
+            "Not a link to a message: keybase://chat/alice,bob"
+        );
+        assert_eq!(
+            parse_message_link("keybase://chat/alice,bob/latest").unwrap_err(),
+            "Not a message id: latest"
+        );
+        assert_eq!(
+            parse_message_link("keybase://chat/12").unwrap_err(),
+            "Not a link to a message: keybase://chat/12"
+        );
+    }
+
+    #[test]
+    fn resolves_conversations() {
+        let mut team = conversation!("test1");
+        team.channel.name = "keybase".to_string();
+        team.channel.topic_name = "general".to_string();
+        team.channel.members_type = MemberType::Team;
+        let mut random = team.clone();
+        random.id = "test2".to_string();
+        random.channel.topic_name = "random".to_string();
+        let mut users = conversation!("test3");
+        users.channel.name = "alice,bob".to_string();
+        let conversations: Vec<Conversation> =
+            vec![team.into(), random.into(), users.into()];
+
+        let resolve = |name| resolve_conversation(&conversations, name);
+        assert_eq!(resolve("keybase#general"), Ok("test1".to_string()));
+        assert_eq!(resolve("keybase"), Ok("test1".to_string()));
+        assert_eq!(resolve("Keybase#Random"), Ok("test2".to_string()));
+        assert_eq!(resolve("bob,alice"), Ok("test3".to_string()));
+        assert_eq!(resolve("alice").unwrap_err(), "No conversation named alice");
+        assert!(resolve("keybase#nope").is_err());
+    }
+}
@@ -0,0 +1,123 @@
+// # history.rs
+//
+// Shell-style history of sent messages for the message box: Up goes back through what you've sent,
+// Down comes forward again, and going past the newest one brings back what you were typing.
+
+// oldest entries are forgotten past this
+pub const MAX_HISTORY: usize = 100;
+
+#[derive(Default)]
+pub struct InputHistory {
+    // oldest first
+    entries: Vec<String>,
+    // entry on screen while going through the history, None when typing a new message
+    position: Option<usize>,
+    // what was in the box before going into the history
+    draft: String,
+}
+
+impl InputHistory {
+    pub fn push(&mut self, message: &str) {
+        self.position = None;
+        self.draft.clear();
+        // sending the same thing twice only needs one entry
+        if message.is_empty() || self.entries.last().map(String::as_str) == Some(message) {
+            return;
+        }
+        self.entries.push(message.to_string());
+        if self.entries.len() > MAX_HISTORY {
+            self.entries.remove(0);
+        }
+    }
+
+    // The entry before the one on screen, or None if there isn't one. `current` is what's in the
+    // box, which is kept as the draft when starting to go back.
+    pub fn older(&mut self, current: &str) -> Option<&str> {
+        let position = match self.position {
+            Some(0) => return None,
+            Some(position) => position - 1,
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len() - 1
+            }
+        };
+        self.position = Some(position);
+        Some(&self.entries[position])
+    }
+
+    // The entry after the one on screen, then the draft, then None once back to typing.
+    pub fn newer(&mut self) -> Option<&str> {
+        let position = self.position?;
+        if position + 1 < self.entries.len() {
+            self.position = Some(position + 1);
+            Some(&self.entries[position + 1])
+        } else {
+            self.position = None;
+            Some(&self.draft)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn history(entries: &[&str]) -> InputHistory {
+        let mut history = InputHistory::default();
+        for entry in entries {
+            history.push(entry);
+        }
+        history
+    }
+
+    #[test]
+    fn up_and_down() {
+        let mut history = history(&["one", "two", "three"]);
+
+        assert_eq!(history.older("draft"), Some("three"));
+        assert_eq!(history.older("three"), Some("two"));
+        assert_eq!(history.older("two"), Some("one"));
+        // stays on the oldest
+        assert_eq!(history.older("one"), None);
+
+        assert_eq!(history.newer(), Some("two"));
+        assert_eq!(history.newer(), Some("three"));
+        // past the newest is what was being typed
+        assert_eq!(history.newer(), Some("draft"));
+        assert_eq!(history.newer(), None);
+
+        // and round again
+        assert_eq!(history.older("draft"), Some("three"));
+    }
+
+    #[test]
+    fn empty() {
+        let mut history = InputHistory::default();
+        assert_eq!(history.older("draft"), None);
+        assert_eq!(history.newer(), None);
+    }
+
+    #[test]
+    fn sending_resets() {
+        let mut history = history(&["one", "one", ""]);
+        assert_eq!(history.older(""), Some("one"));
+        assert_eq!(history.older("one"), None);
+
+        history.push("two");
+        assert_eq!(history.older(""), Some("two"));
+    }
+
+    #[test]
+    fn capped() {
+        let mut history = InputHistory::default();
+        for i in 0..MAX_HISTORY + 10 {
+            history.push(&i.to_string());
+        }
+        let mut oldest = None;
+        while let Some(entry) = history.older("") {
+            oldest = Some(entry.to_string());
+        }
+        assert_eq!(oldest.as_deref(), Some("10"));
+    }
+}
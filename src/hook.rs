@@ -0,0 +1,185 @@
+// # hook.rs
+//
+// Runs a user command (`on_message_hook` in the config) for every incoming message, with the
+// message as JSON on its stdin. Handy for custom notifications or logging.
+
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::state::StateObserver;
+use crate::status::ConnectionStatus;
+use crate::types::{Conversation, Message};
+
+// how many hook processes can run at once, so a busy channel can't fork bomb us. Messages that
+// arrive while we're at the limit are skipped.
+const MAX_RUNNING: usize = 4;
+
+pub struct MessageHook {
+    command: String,
+    running: Arc<AtomicUsize>,
+}
+
+impl MessageHook {
+    pub fn new(command: &str) -> Self {
+        MessageHook {
+            command: command.to_string(),
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn run(&self, message: &Message) {
+        if self.running.fetch_add(1, Ordering::SeqCst) >= MAX_RUNNING {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            warn!("Too many on_message_hook commands running, skipping message {}", message.id);
+            return;
+        }
+
+        let input = match serde_json::to_vec(message) {
+            Ok(input) => input,
+            Err(e) => {
+                self.running.fetch_sub(1, Ordering::SeqCst);
+                error!("Failed to serialize message for on_message_hook: {}", e);
+                return;
+            }
+        };
+        let command = self.command.clone();
+        let running = self.running.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_hook(&command, input).await {
+                error!("on_message_hook `{}` failed: {}", command, e);
+            }
+            running.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+async fn run_hook(command: &str, input: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = command.split_whitespace();
+    let program = args.next().ok_or("empty command")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    {
+        // scoped so that the pipe is dropped
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(&input).await?;
+    }
+    let status = child.await?;
+    if !status.success() {
+        return Err(format!("exited with {}", status).into());
+    }
+    Ok(())
+}
+
+impl StateObserver for MessageHook {
+    fn on_conversation_change(&mut self, _: &Conversation) {}
+
+    fn on_conversations_added(&mut self, _: &[Conversation]) {}
+
+    fn on_conversations_appended(&mut self, _: &[Conversation], _: bool) {}
+
+    fn on_message(&mut self, message: &Message, _: &str, _: bool) {
+        // it runs for the real one when keybase sends it back
+        if !message.is_pending() {
+            self.run(message);
+        }
+    }
+
+    fn on_conversation_updated(&mut self, _: &Conversation) {}
+
+    fn on_username_changed(&mut self, _: &str) {}
+
+    fn on_typing(&mut self, _: &str, _: &str) {}
+
+    fn on_notice(&mut self, _: &str) {}
+
+    fn on_error(&mut self, _: String) {}
+
+    fn on_loading(&mut self, _: &str, _: bool) {}
+
+    fn on_send_failed(&mut self, _: &str) {}
+
+    fn on_channels(&mut self, _: &str, _: &[String]) {}
+
+    fn on_download(&mut self, _: &Path, _: &str) {}
+
+    fn on_status(&mut self, _: ConnectionStatus) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message;
+    use crate::types::{Channel, MemberType, MessageBody, MessageType};
+    use std::path::{Path, PathBuf};
+    use tokio::time::{delay_for, Duration};
+
+    // a hook that appends whatever it gets to a file, one invocation per line, and the directory
+    // it's all in
+    fn recording_hook(name: &str) -> (MessageHook, PathBuf, PathBuf) {
+        let dir = std::env::temp_dir()
+            .join(format!("keybase-chat-tui-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("invocations");
+        let _ = std::fs::remove_file(&log);
+        let script = dir.join("hook.sh");
+        std::fs::write(&script, format!("cat >> {0}\necho >> {0}\n", log.display())).unwrap();
+        (MessageHook::new(&format!("sh {}", script.display())), log, dir)
+    }
+
+    async fn invocations(log: &Path, expected: usize) -> Vec<String> {
+        for _ in 0..100 {
+            if let Ok(contents) = std::fs::read_to_string(log) {
+                let lines: Vec<String> = contents.lines().map(String::from).collect();
+                if lines.len() >= expected {
+                    return lines;
+                }
+            }
+            delay_for(Duration::from_millis(10)).await;
+        }
+        vec![]
+    }
+
+    #[tokio::test]
+    async fn runs_command_per_message() {
+        let (mut hook, log, dir) = recording_hook("runs");
+
+        // one at a time, so the two invocations can't interleave in the file
+        hook.on_message(&message!("test1", "hi", 1), "test1", true);
+        invocations(&log, 1).await;
+        hook.on_message(&message!("test1", "there", 2), "test1", false);
+        let lines = invocations(&log, 2).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(lines.len(), 2);
+        let first: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(first["id"], 1);
+        assert_eq!(first["conversation_id"], "test1");
+        assert_eq!(first["content"]["type"], "text");
+        assert_eq!(first["content"]["text"]["body"], "hi");
+        let second: serde_json::Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(second["id"], 2);
+    }
+
+    #[tokio::test]
+    async fn skips_messages_when_busy() {
+        let (mut hook, log, dir) = recording_hook("busy");
+        hook.running.store(MAX_RUNNING, Ordering::SeqCst);
+
+        hook.on_message(&message!("test1", "hi", 1), "test1", true);
+        delay_for(Duration::from_millis(50)).await;
+
+        let ran = log.exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!ran);
+        assert_eq!(hook.running.load(Ordering::SeqCst), MAX_RUNNING);
+    }
+}
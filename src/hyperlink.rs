@@ -0,0 +1,128 @@
+// # hyperlink.rs
+//
+// OSC 8 terminal hyperlinks (https://gist.github.com/egmontkob/eb114294efbcd5adb1944c9f3cb5feda),
+// which make URLs clickable in terminals that understand them.
+//
+// Cursive can't print them itself: the ncurses backend shows control characters as `^[`, and the
+// escape sequence would count towards the width of the line. So the chat area notes where the
+// URLs it drew ended up, and the UI writes them again straight to the terminal over the top,
+// this time as links (see `Ui::draw_hyperlinks`).
+
+const OSC8_START: &str = "\x1b]8;;";
+const OSC8_END: &str = "\x1b\\";
+
+// Characters that usually end a sentence rather than belong to the URL.
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', ')', ']', '\'', '"'];
+
+// Best guess at whether the terminal understands OSC 8, going by the environment variables that
+// the terminals known to support it set. `FORCE_HYPERLINK` overrides the guess either way.
+pub fn supports_hyperlinks() -> bool {
+    supports_hyperlinks_with(|name| std::env::var(name).ok())
+}
+
+fn supports_hyperlinks_with(env: impl Fn(&str) -> Option<String>) -> bool {
+    if let Some(force) = env("FORCE_HYPERLINK") {
+        return force != "0";
+    }
+    // tmux and screen don't pass them on
+    if env("TMUX").is_some() {
+        return false;
+    }
+    if env("KITTY_WINDOW_ID").is_some() || env("WT_SESSION").is_some() || env("DOMTERM").is_some() {
+        return true;
+    }
+    if let Some(version) = env("VTE_VERSION") {
+        // GNOME Terminal and friends, since VTE 0.50
+        return version.parse::<u32>().map(|v| v >= 5000).unwrap_or(false);
+    }
+    matches!(
+        env("TERM_PROGRAM").as_deref(),
+        Some("iTerm.app") | Some("WezTerm") | Some("vscode")
+    )
+}
+
+// Every http(s) URL in `text`, with where it starts (in bytes).
+pub fn find_urls(text: &str) -> Vec<(usize, &str)> {
+    let mut urls = vec![];
+    let mut offset = 0;
+    while let Some(start) = find_url_start(&text[offset..]) {
+        let rest = &text[offset + start..];
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let url = rest[..end].trim_end_matches(TRAILING_PUNCTUATION);
+        urls.push((offset + start, url));
+        // a bare `https://` still has to be stepped over
+        offset += start + url.len().max(1);
+    }
+    urls
+}
+
+pub fn hyperlink(url: &str, text: &str) -> String {
+    format!("{}{}{}{}{}{}", OSC8_START, url, OSC8_END, text, OSC8_START, OSC8_END)
+}
+
+// Write `text` as a link to `url` at column `x` of row `y` (from 0), leaving the cursor and colors
+// where they were for ncurses to carry on with.
+pub fn hyperlink_at(x: usize, y: usize, url: &str, text: &str) -> String {
+    format!("\x1b7\x1b[{};{}H{}\x1b8", y + 1, x + 1, hyperlink(url, text))
+}
+
+fn find_url_start(text: &str) -> Option<usize> {
+    match (text.find("https://"), text.find("http://")) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env_with(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn finds_urls() {
+        assert_eq!(
+            find_urls("see https://keybase.io/docs, or http://example.com."),
+            [(4, "https://keybase.io/docs"), (32, "http://example.com")]
+        );
+        assert!(find_urls("no links here").is_empty());
+        assert_eq!(find_urls("just https://"), [(5, "https://")]);
+    }
+
+    #[test]
+    fn wraps_links() {
+        assert_eq!(
+            hyperlink_at(4, 0, "https://keybase.io", "https://keybase.io"),
+            "\x1b7\x1b[1;5H\x1b]8;;https://keybase.io\x1b\\https://keybase.io\x1b]8;;\x1b\\\x1b8"
+        );
+    }
+
+    #[test]
+    fn detects_support() {
+        assert!(supports_hyperlinks_with(env_with(&[("TERM_PROGRAM", "WezTerm")])));
+        assert!(supports_hyperlinks_with(env_with(&[("VTE_VERSION", "6003")])));
+        assert!(!supports_hyperlinks_with(env_with(&[("VTE_VERSION", "4803")])));
+        assert!(!supports_hyperlinks_with(env_with(&[("TERM_PROGRAM", "Apple_Terminal")])));
+        assert!(!supports_hyperlinks_with(env_with(&[
+            ("TERM_PROGRAM", "WezTerm"),
+            ("TMUX", "/tmp/tmux-1000/default")
+        ])));
+        assert!(!supports_hyperlinks_with(env_with(&[])));
+    }
+
+    #[test]
+    fn force_overrides_detection() {
+        assert!(supports_hyperlinks_with(env_with(&[("FORCE_HYPERLINK", "1")])));
+        assert!(!supports_hyperlinks_with(env_with(&[
+            ("FORCE_HYPERLINK", "0"),
+            ("KITTY_WINDOW_ID", "1")
+        ])));
+    }
+}
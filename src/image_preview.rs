@@ -0,0 +1,155 @@
+// # image_preview.rs
+//
+// Built with the `image_preview` feature, an image saved with `/download` is shown in the
+// terminal, if the terminal can draw images: with kitty's graphics protocol, or as sixels
+// (converted by `img2sixel`, from libsixel). Anywhere else there's just the "Saved ..." notice,
+// same as without the feature.
+
+use std::path::Path;
+use std::process::Command;
+
+// how much room a preview gets, in terminal cells
+pub const PREVIEW_COLUMNS: u16 = 60;
+pub const PREVIEW_ROWS: u16 = 20;
+// sixels are sized in pixels, this is a typical cell width
+const CELL_WIDTH_PX: u32 = 10;
+
+// takes kitty's images off the screen again, sixels go when the screen's redrawn
+pub const CLEAR_SEQUENCE: &str = "\x1b_Ga=d,q=2\x1b\\";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Graphics {
+    Kitty,
+    Sixel,
+}
+
+// What the terminal can draw, going by its environment (`var` looks up an environment variable),
+// or None if it doesn't look like it can draw anything.
+pub fn detect_graphics<F: Fn(&str) -> Option<String>>(var: F) -> Option<Graphics> {
+    let term = var("TERM").unwrap_or_default();
+    // tmux and screen only pass the sequences on when they're wrapped up specially
+    if var("TMUX").is_some() || term.starts_with("screen") || term.starts_with("tmux") {
+        return None;
+    }
+    let program = var("TERM_PROGRAM").unwrap_or_default();
+    if var("KITTY_WINDOW_ID").is_some() || term.contains("kitty") || program == "ghostty" {
+        return Some(Graphics::Kitty);
+    }
+    let sixel_terms = ["foot", "mlterm", "yaft", "contour"];
+    if term.contains("sixel")
+        || sixel_terms.iter().any(|t| term.starts_with(t))
+        || program == "WezTerm"
+        || program == "iTerm.app"
+    {
+        return Some(Graphics::Sixel);
+    }
+    None
+}
+
+// The escape sequence that draws the image at `path` where the cursor is, or None if it isn't
+// an image the terminal can show.
+pub fn preview_sequence(graphics: Graphics, path: &Path, mime_type: &str) -> Option<Vec<u8>> {
+    match graphics {
+        // kitty reads the file itself, but only understands PNGs that way
+        Graphics::Kitty if mime_type == "image/png" => Some(kitty_sequence(path)),
+        Graphics::Sixel if mime_type.starts_with("image/") => sixel_sequence(path),
+        _ => None,
+    }
+}
+
+// a=T: show it now, t=f: the payload is the path of a file to read, q=2: no replies
+fn kitty_sequence(path: &Path) -> Vec<u8> {
+    format!(
+        "\x1b_Ga=T,f=100,t=f,q=2,c={},r={};{}\x1b\\",
+        PREVIEW_COLUMNS,
+        PREVIEW_ROWS,
+        base64(path.to_string_lossy().as_bytes())
+    )
+    .into_bytes()
+}
+
+// only the first frame of a gif (`-S`), an animation would keep writing over the UI
+fn sixel_sequence(path: &Path) -> Option<Vec<u8>> {
+    let width = (u32::from(PREVIEW_COLUMNS) * CELL_WIDTH_PX).to_string();
+    match Command::new("img2sixel").args(["-S", "-w", &width]).arg(path).output() {
+        Ok(output) if output.status.success() => Some(output.stdout),
+        Ok(output) => {
+            warn!("img2sixel couldn't convert {}: {}", path.display(), output.status);
+            None
+        }
+        Err(e) => {
+            warn!("Couldn't run img2sixel: {}", e);
+            None
+        }
+    }
+}
+
+// standard base64 with padding, which kitty wants the file path in
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn detect(vars: &[(&str, &str)]) -> Option<Graphics> {
+        let vars: HashMap<&str, &str> = vars.iter().cloned().collect();
+        detect_graphics(|name| vars.get(name).map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn detects_terminal_graphics() {
+        assert_eq!(detect(&[("TERM", "xterm-kitty")]), Some(Graphics::Kitty));
+        assert_eq!(
+            detect(&[("TERM", "xterm-256color"), ("KITTY_WINDOW_ID", "1")]),
+            Some(Graphics::Kitty)
+        );
+        assert_eq!(detect(&[("TERM", "foot")]), Some(Graphics::Sixel));
+        assert_eq!(detect(&[("TERM", "mlterm")]), Some(Graphics::Sixel));
+        assert_eq!(
+            detect(&[("TERM", "xterm-256color"), ("TERM_PROGRAM", "WezTerm")]),
+            Some(Graphics::Sixel)
+        );
+
+        // nothing that can draw images, or something in the way
+        assert_eq!(detect(&[("TERM", "xterm-256color")]), None);
+        assert_eq!(detect(&[]), None);
+        assert_eq!(detect(&[("TERM", "tmux-256color"), ("KITTY_WINDOW_ID", "1")]), None);
+        assert_eq!(detect(&[("TERM", "xterm-kitty"), ("TMUX", "/tmp/tmux-1000/default")]), None);
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(b"/tmp/cat.png"), "L3RtcC9jYXQucG5n");
+    }
+
+    #[test]
+    fn kitty_only_reads_pngs() {
+        let path = Path::new("/tmp/cat.png");
+        let sequence = preview_sequence(Graphics::Kitty, path, "image/png").unwrap();
+        assert_eq!(
+            String::from_utf8(sequence).unwrap(),
+            "\x1b_Ga=T,f=100,t=f,q=2,c=60,r=20;L3RtcC9jYXQucG5n\x1b\\"
+        );
+        assert_eq!(preview_sequence(Graphics::Kitty, path, "image/jpeg"), None);
+        assert_eq!(preview_sequence(Graphics::Sixel, path, "application/pdf"), None);
+    }
+}
@@ -0,0 +1,58 @@
+// # latency.rs
+//
+// Round trip times of keybase api calls, for spotting a slow keybase service. The executor records
+// every call and the status bar shows the latest one.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct Latency {
+    inner: Mutex<LatencyInner>,
+}
+
+#[derive(Default)]
+struct LatencyInner {
+    last: Option<Duration>,
+    total: Duration,
+    count: u32,
+}
+
+impl Latency {
+    pub fn record(&self, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last = Some(duration);
+        inner.total += duration;
+        inner.count += 1;
+    }
+
+    pub fn last(&self) -> Option<Duration> {
+        self.inner.lock().unwrap().last
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        let inner = self.inner.lock().unwrap();
+        if inner.count == 0 {
+            return None;
+        }
+        Some(inner.total / inner.count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn records_latency() {
+        let latency = Latency::default();
+        assert_eq!(latency.last(), None);
+        assert_eq!(latency.average(), None);
+
+        latency.record(Duration::from_millis(100));
+        latency.record(Duration::from_millis(300));
+
+        assert_eq!(latency.last(), Some(Duration::from_millis(300)));
+        assert_eq!(latency.average(), Some(Duration::from_millis(200)));
+    }
+}
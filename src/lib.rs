@@ -0,0 +1,86 @@
+// # lib.rs
+//
+// Everything behind the `keybase-chat-tui` binary, which is also usable on its own. The parts
+// meant for other crates are `client` (an async client for `keybase chat api`) and `types` (what
+// it sends and gets back).
+
+//! Talk to Keybase chat from Rust, through the same client `keybase-chat-tui` uses.
+//!
+//! [`Client`] runs every call through a [`KeybaseExecutor`], which is `keybase chat api` on the
+//! `PATH` by default ([`ClientExecutor`]). Anything else that answers the api's JSON can stand in
+//! for it, e.g. in tests:
+//!
+//! ```
+//! use std::error::Error;
+//!
+//! use async_trait::async_trait;
+//! use keybase_chat_tui::{Channel, Client, KeybaseClient, KeybaseExecutor, MemberType};
+//! use serde_json::{json, Value};
+//!
+//! struct MockExecutor;
+//!
+//! #[async_trait]
+//! impl KeybaseExecutor for MockExecutor {
+//!     async fn run_api_command(&self, command: Value) -> Result<Value, Box<dyn Error>> {
+//!         assert_eq!(command["method"], "send");
+//!         assert_eq!(command["params"]["options"]["message"]["body"], "hello");
+//!         Ok(json!({"result": {"message": "message sent", "id": 42}}))
+//!     }
+//!
+//!     async fn run_command(&self, _args: Vec<String>) -> Result<Value, Box<dyn Error>> {
+//!         Err("only the chat api is mocked".into())
+//!     }
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn Error>> {
+//! let client = Client::without_listener(MockExecutor);
+//! let channel = Channel {
+//!     name: "alice,bob".to_string(),
+//!     topic_name: "".to_string(),
+//!     members_type: MemberType::User,
+//! };
+//! let id = client.send_message(&channel, "hello").await?;
+//! assert_eq!(id, Some(42));
+//! # Ok(())
+//! # }
+//! ```
+
+#![feature(exact_size_is_empty)]
+#[macro_use]
+extern crate log;
+
+pub mod cache;
+pub mod client;
+mod commands;
+mod completion;
+pub mod config;
+mod connection;
+pub mod controller;
+pub mod error;
+pub mod export;
+mod fuzzy;
+mod goto;
+mod history;
+pub mod hook;
+mod hyperlink;
+mod image_preview;
+pub mod latency;
+mod mentions;
+pub mod notify;
+mod reactions;
+mod selection;
+mod sort;
+pub mod state;
+mod status;
+pub mod support;
+mod thread;
+pub mod types;
+pub mod ui;
+mod views;
+mod watchdog;
+#[macro_use]
+mod macros;
+
+pub use crate::client::{Client, ClientExecutor, KeybaseClient, KeybaseExecutor};
+pub use crate::types::{Channel, MemberType};
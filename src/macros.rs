@@ -1,12 +1,22 @@
 #[cfg(test)]
 #[macro_use]
-mod test {
+pub mod test {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    // ids for test messages that don't care what theirs is, so they still sort in the order
+    // they were made
+    pub fn next_message_id() -> u64 {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    }
+
     #[macro_export]
     macro_rules! conversation {
         ($id:expr) => {{
             KeybaseConversation {
                 id: $id.to_string(),
                 unread: false,
+                active_at: 0,
                 channel: Channel {
                     name: "channel".to_string(),
                     topic_name: "".to_string(),
@@ -19,12 +29,20 @@ mod test {
     #[macro_export]
     macro_rules! message {
         ($convo_id: expr, $text: expr) => {{
+            $crate::message!($convo_id, $text, $crate::macros::test::next_message_id())
+        }};
+        ($convo_id: expr, $text: expr, $id: expr) => {{
+            $crate::message!($convo_id, $text, $id, 0)
+        }};
+        ($convo_id: expr, $text: expr, $id: expr, $sent_at: expr) => {{
             use crate::types::Sender;
             Message {
+                id: $id,
                 conversation_id: $convo_id.to_string(),
                 content: MessageType::Text {
                     text: MessageBody {
                         body: $text.to_string(),
+                        reply_to: None,
                     },
                 },
                 channel: Channel {
@@ -36,6 +54,9 @@ mod test {
                     device_name: "My Device".to_string(),
                     username: "Some Guy".to_string(),
                 },
+                sent_at: $sent_at,
+                delivery: None,
+                is_ephemeral: false,
             }
         }};
     }
@@ -2,25 +2,24 @@
 //
 // Contains the cli and high-level orchestration of other components.
 
-#![feature(exact_size_is_empty)]
 #[macro_use]
 extern crate log;
 
-use tokio::time::{delay_for, Duration, Instant};
+use std::path::PathBuf;
+use std::sync::Arc;
 
-mod client;
-mod controller;
-mod state;
-mod types;
-mod ui;
-mod views;
-#[macro_use]
-mod macros;
+use tokio::time::{delay_for, Duration, Instant};
 
-use crate::client::{ClientExecutor, Client};
-use crate::controller::Controller;
-use crate::state::{ApplicationState, ApplicationStateInner};
-use crate::ui::UiBuilder;
+use keybase_chat_tui::client::{ClientExecutor, Client};
+use keybase_chat_tui::cache::cache_path;
+use keybase_chat_tui::config::Config;
+use keybase_chat_tui::controller::Controller;
+use keybase_chat_tui::hook::MessageHook;
+use keybase_chat_tui::latency::Latency;
+use keybase_chat_tui::notify::Notifier;
+use keybase_chat_tui::state::{ApplicationState, ApplicationStateInner};
+use keybase_chat_tui::support::SupportBundle;
+use keybase_chat_tui::ui::UiBuilder;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -35,19 +34,93 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("Starting...");
 
+    let mut config = Config::load();
+    let mut export_dir = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // write every conversation's history to files in a directory, instead of starting
+            // the UI
+            "export-all" => {
+                export_dir = Some(PathBuf::from(
+                    args.next().ok_or("export-all needs a directory")?,
+                ));
+            }
+            // record every api call to a file that can be attached to bug reports
+            "--support-bundle" => {
+                config.support_bundle = Some(PathBuf::from(
+                    args.next().ok_or("--support-bundle needs a file path")?,
+                ));
+            }
+            _ => return Err(format!("Unknown argument: {}", arg).into()),
+        }
+    }
+
+    let latency = Arc::new(Latency::default());
+    let executor = match &config.support_bundle {
+        Some(path) => ClientExecutor::with_support_bundle(SupportBundle::create(
+            path,
+            config.support_bundle_redact,
+            config.support_bundle_max_bytes,
+        )?),
+        None => ClientExecutor::default(),
+    }
+    .with_latency(latency.clone())
+    .with_keybase(config.keybase.clone());
+
+    if let Some(dir) = export_dir {
+        let client = Client::without_listener(executor);
+        return keybase_chat_tui::export::export_all(&client, &dir).await;
+    }
+
     // The UI object has all of the cursive (rust tui library) logic.
-    let (ui, ui_recv) = UiBuilder::new().build();
-    let mut state = ApplicationStateInner::default();
+    let (ui, ui_recv) = UiBuilder::new(&config).with_latency(latency.clone()).build();
+    let mut state = ApplicationStateInner::default().with_max_messages(config.messages_kept());
 
     state.register_observer(Box::new(ui.clone()));
-    let client = Client::<ClientExecutor>::default();
-    let mut controller = Controller::new(client, state, ui_recv);
-
-    controller.init().await?;
+    if let Some(command) = &config.on_message_hook {
+        state.register_observer(Box::new(MessageHook::new(command)));
+    }
+    let sounds = config.notification_sound.is_some()
+        || config.conversations.values().any(|c| c.notification_sound.is_some());
+    if sounds && !cfg!(feature = "sound") {
+        warn!("notification_sound is set, but sounds need the `sound` feature");
+    }
+    if config.notify_command.is_some() || (sounds && cfg!(feature = "sound")) {
+        let command = config.notify_command.as_deref().unwrap_or("");
+        let notifier = Notifier::new(command, config.notification_privacy).with_sounds(&config);
+        state.register_observer(Box::new(notifier));
+    }
+    let client = Client::without_listener(executor).with_keybase(config.keybase.clone());
+    client.start_listener()?;
+    // Quitting the UI stops the controller, and so does a SIGINT from outside, which would
+    // otherwise leave the terminal in a mess (Ctrl+C in the UI is just a key press).
+    let (mut shutdown, shutdown_recv) = tokio::sync::mpsc::channel(2);
+    let mut signal_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            signal_shutdown.send(()).await.ok();
+        }
+    });
+    let mut controller = Controller::new(client, state, ui_recv, shutdown_recv).with_config(config);
+    if let Some(path) = cache_path() {
+        controller = controller.with_cache(path);
+    }
 
-    tokio::select! {
-        _ = controller.process_events() => {}
-        _ = async {
+    // both run until the other stops them, so neither is dropped halfway through something
+    let (started, _) = tokio::join!(
+        async {
+            // the UI is already going, so anything cached is on screen while keybase answers
+            let started = controller.init().await;
+            if started.is_ok() {
+                if let Err(e) = controller.process_events().await {
+                    error!("{}", e);
+                }
+            }
+            ui.borrow_mut().quit();
+            started
+        },
+        async {
             let mut next_frame = Instant::now() + Duration::from_millis(16);
             loop {
                 let now = Instant::now();
@@ -60,7 +133,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 next_frame = Instant::now() + Duration::from_millis(16);
 
             }
-        } => { info!("Exiting."); }
+            info!("Exiting.");
+            shutdown.send(()).await.ok();
+        }
+    );
+    controller.shutdown().await;
+    if started.is_ok() {
+        controller.save_cache();
+    }
+    // stops the listener
+    drop(controller);
+    // the last of the UI, which puts the terminal back the way it was
+    drop(ui);
+    if let Some(average) = latency.average() {
+        info!("Average keybase api latency: {}ms", average.as_millis());
     }
-    Ok(())
+    started
 }
@@ -0,0 +1,242 @@
+// # mentions.rs
+//
+// `@team` and `#channel` mentions in message bodies, which the chat area draws as links to the
+// conversation they name. `#channel` means a channel in the same team as the message, and `@name`
+// only counts when we know of a team called that (otherwise it's just mentioning a user).
+//
+// Mentions of you (`@you`, or just `you`) are found separately, for highlighting.
+
+use std::ops::Range;
+
+use crate::types::{Channel, Conversation, MemberType, Message, MessageType};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mention {
+    // `@team`, meaning the team's general channel
+    Team(String),
+    // `#channel`, in the same team as the message
+    Channel(String),
+}
+
+// where following a mention goes
+#[derive(Clone, Debug, PartialEq)]
+pub enum MentionTarget {
+    // a conversation in the sidebar, by id
+    Conversation(String),
+    // a team channel we haven't got a conversation for, which has to be joined first
+    Channel(Channel),
+}
+
+pub fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+// Every `@name` and `#name` in `body`, with where they are (including the `@`/`#`). They have
+// to start a word, so `a@b.com` and `issue#12` don't count.
+pub fn find_mentions(body: &str) -> Vec<(Range<usize>, Mention)> {
+    let mut mentions = vec![];
+    let mut previous: Option<char> = None;
+    for (start, c) in body.char_indices() {
+        let starts_word = match previous {
+            Some(p) => !is_name_char(p) && p != '@' && p != '#',
+            None => true,
+        };
+        previous = Some(c);
+        if !starts_word || (c != '@' && c != '#') {
+            continue;
+        }
+        let rest = &body[start + 1..];
+        let len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        // a full stop at the end of a sentence isn't part of the name
+        let name = rest[..len].trim_end_matches('.');
+        if name.is_empty() {
+            continue;
+        }
+        let mention = if c == '@' {
+            Mention::Team(name.to_string())
+        } else {
+            Mention::Channel(name.to_string())
+        };
+        mentions.push((start..start + 1 + name.len(), mention));
+    }
+    mentions
+}
+
+// Where `username` is mentioned in `body`, with or without the `@`, ignoring case. It has to be
+// the whole word, so `alice` isn't mentioned in `alicen` or `alice@example.com`.
+pub fn find_user_mentions(body: &str, username: &str) -> Vec<Range<usize>> {
+    let mut found = vec![];
+    if username.is_empty() {
+        return found;
+    }
+    let lower = body.to_lowercase();
+    // lowercasing can change lengths outside of ascii, and then the positions don't line up
+    if lower.len() != body.len() {
+        return found;
+    }
+    let username = username.to_lowercase();
+    for (start, _) in lower.match_indices(&username) {
+        let end = start + username.len();
+        let before = lower[..start].chars().next_back();
+        // a full stop at the end of a sentence is fine, one in the middle of a name isn't
+        let after = lower[end..].trim_start_matches('.').chars().next();
+        if after.is_some_and(|c| is_name_char(c) || c == '@') || before.is_some_and(|c| is_name_char(c) || c == '#') {
+            continue;
+        }
+        let start = if before == Some('@') { start - 1 } else { start };
+        found.push(start..end);
+    }
+    found
+}
+
+// someone else's message that mentions you
+pub fn mentions_user(message: &Message, my_username: &str) -> bool {
+    match &message.content {
+        MessageType::Text { text } => {
+            message.sender.username != my_username
+                && !find_user_mentions(&text.body, my_username).is_empty()
+        }
+        _ => false,
+    }
+}
+
+fn find_channel<'a>(conversations: &'a [Conversation], team: &str, topic: &str) -> Option<&'a Conversation> {
+    conversations.iter().find(|c| {
+        let channel = &c.data.channel;
+        channel.members_type == MemberType::Team
+            && channel.name.eq_ignore_ascii_case(team)
+            && channel.topic_name.eq_ignore_ascii_case(topic)
+    })
+}
+
+// What a mention in a message sent to `channel` points at, or None if it isn't a link after all.
+pub fn resolve_mention(
+    mention: &Mention,
+    channel: &Channel,
+    conversations: &[Conversation],
+) -> Option<MentionTarget> {
+    let (team, topic) = match mention {
+        Mention::Team(team) => {
+            let known = conversations.iter().any(|c| {
+                c.data.channel.members_type == MemberType::Team
+                    && c.data.channel.name.eq_ignore_ascii_case(team)
+            });
+            if !known {
+                return None;
+            }
+            (team.as_str(), "general")
+        }
+        // outside of a team there's no telling which team the channel is in
+        Mention::Channel(_) if channel.members_type != MemberType::Team => return None,
+        Mention::Channel(topic) => (channel.name.as_str(), topic.as_str()),
+    };
+    Some(match find_channel(conversations, team, topic) {
+        Some(convo) => MentionTarget::Conversation(convo.id.clone()),
+        None => MentionTarget::Channel(Channel {
+            name: team.to_string(),
+            topic_name: topic.to_lowercase(),
+            members_type: MemberType::Team,
+        }),
+    })
+}
+
+// the mentions in a message that lead somewhere
+pub fn message_links(message: &Message, conversations: &[Conversation]) -> Vec<(Range<usize>, MentionTarget)> {
+    let body = match &message.content {
+        MessageType::Text { text } => &text.body,
+        _ => return vec![],
+    };
+    find_mentions(body)
+        .into_iter()
+        .filter_map(|(range, mention)| {
+            Some((range, resolve_mention(&mention, &message.channel, conversations)?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::conversation;
+    use crate::types::KeybaseConversation;
+
+    fn team_channel(id: &str, team: &str, topic: &str) -> Conversation {
+        let mut convo = conversation!(id);
+        convo.channel.name = team.to_string();
+        convo.channel.topic_name = topic.to_string();
+        convo.channel.members_type = MemberType::Team;
+        convo.into()
+    }
+
+    #[test]
+    fn finds_mentions() {
+        let body = "@keybase see #random, and #dev-ops. (@alice) a@b.com issue#12 # @";
+        let found: Vec<(&str, Mention)> = find_mentions(body)
+            .into_iter()
+            .map(|(range, mention)| (&body[range], mention))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("@keybase", Mention::Team("keybase".to_string())),
+                ("#random", Mention::Channel("random".to_string())),
+                ("#dev-ops", Mention::Channel("dev-ops".to_string())),
+                ("@alice", Mention::Team("alice".to_string())),
+            ]
+        );
+        assert!(find_mentions("plain text").is_empty());
+    }
+
+    #[test]
+    fn finds_user_mentions() {
+        fn found(body: &str) -> Vec<&str> {
+            find_user_mentions(body, "alice")
+                .into_iter()
+                .map(|range| &body[range])
+                .collect()
+        }
+        assert_eq!(found("@alice hi"), ["@alice"]);
+        assert_eq!(found("hi Alice, and @ALICE."), ["Alice", "@ALICE"]);
+        assert_eq!(found("alice"), ["alice"]);
+        assert!(found("alicen alice_b malice alice.b alice@example.com #alice").is_empty());
+        assert!(find_user_mentions("hi", "").is_empty());
+    }
+
+    #[test]
+    fn resolves_mentions() {
+        let conversations = vec![
+            team_channel("test1", "keybase", "general"),
+            team_channel("test2", "keybase", "random"),
+            team_channel("test3", "other", "dev"),
+        ];
+        let in_keybase = conversations[0].data.channel.clone();
+        let dm = conversation!("test4").channel;
+        let resolve = |mention: Mention, channel: &Channel| {
+            resolve_mention(&mention, channel, &conversations)
+        };
+        let conversation = |id: &str| Some(MentionTarget::Conversation(id.to_string()));
+
+        assert_eq!(resolve(Mention::Channel("random".to_string()), &in_keybase), conversation("test2"));
+        assert_eq!(resolve(Mention::Team("keybase".to_string()), &dm), conversation("test1"));
+        // not in the sidebar, so it has to be joined
+        assert_eq!(
+            resolve(Mention::Channel("Secret".to_string()), &in_keybase),
+            Some(MentionTarget::Channel(Channel {
+                name: "keybase".to_string(),
+                topic_name: "secret".to_string(),
+                members_type: MemberType::Team,
+            }))
+        );
+        assert_eq!(
+            resolve(Mention::Team("other".to_string()), &dm),
+            Some(MentionTarget::Channel(Channel {
+                name: "other".to_string(),
+                topic_name: "general".to_string(),
+                members_type: MemberType::Team,
+            }))
+        );
+        // a user, and a channel with no team to look in
+        assert_eq!(resolve(Mention::Team("alice".to_string()), &dm), None);
+        assert_eq!(resolve(Mention::Channel("random".to_string()), &dm), None);
+    }
+}
@@ -0,0 +1,363 @@
+// # notify.rs
+//
+// Desktop notifications for messages in conversations other than the one on screen, by running
+// `notify_command` from the config with a title and body, e.g. `notify-send <title> <body>`.
+// `notification_privacy` controls how much of the message ends up on screen.
+//
+// Built with the `sound` feature, a notification also plays a sound with `sound_command`: the
+// conversation's own `notification_sound` if it has one, otherwise the default one.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::config::Config;
+use crate::mentions::mentions_user;
+use crate::state::StateObserver;
+use crate::status::ConnectionStatus;
+use crate::types::{Conversation, Message, MessageType};
+
+// longer messages are cut short, notifications only have room for a few lines anyway
+pub const MAX_BODY_CHARS: usize = 120;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationPrivacy {
+    // who sent it, where, and what it says
+    #[default]
+    Full,
+    // only who sent it
+    SenderOnly,
+    // only that there's a new message
+    Generic,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+}
+
+// What a message says, for the notification body. Messages that don't show up as text (joins,
+// reactions and so on) don't get a notification at all.
+fn message_text(message: &Message) -> Option<String> {
+    match &message.content {
+        MessageType::Text { text } => Some(text.body.clone()),
+        MessageType::Attachment { attachment } => {
+            Some(format!("sent {}", attachment.object.filename))
+        }
+        MessageType::Unfurl { .. } => Some("shared a link".to_string()),
+        _ => None,
+    }
+}
+
+// The body on one line, and no more than `max` characters of it (including the `…`).
+fn truncate_body(text: &str, max: usize) -> String {
+    let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if text.chars().count() <= max {
+        return text;
+    }
+    let cut: String = text.chars().take(max.saturating_sub(1)).collect();
+    format!("{}…", cut.trim_end())
+}
+
+pub fn notification(
+    message: &Message,
+    conversation_name: &str,
+    privacy: NotificationPrivacy,
+    mention: bool,
+) -> Option<Notification> {
+    let text = truncate_body(&message_text(message)?, MAX_BODY_CHARS);
+    let sender = &message.sender.username;
+    let (title, body) = match privacy {
+        NotificationPrivacy::Full if mention => {
+            (format!("{} mentioned you in {}", sender, conversation_name), text)
+        }
+        NotificationPrivacy::Full => (format!("{} in {}", sender, conversation_name), text),
+        NotificationPrivacy::SenderOnly if mention => {
+            (format!("@{} mentioned you", sender), String::new())
+        }
+        NotificationPrivacy::SenderOnly => (format!("New message from @{}", sender), String::new()),
+        NotificationPrivacy::Generic => ("New message".to_string(), String::new()),
+    };
+    Some(Notification { title, body })
+}
+
+pub struct Notifier {
+    command: String,
+    privacy: NotificationPrivacy,
+    // conversation names by id, for the notification title
+    names: HashMap<String, String>,
+    my_username: Option<String>,
+    sound_command: String,
+    default_sound: Option<PathBuf>,
+    // sounds by conversation name
+    sounds: HashMap<String, PathBuf>,
+}
+
+impl Notifier {
+    pub fn new(command: &str, privacy: NotificationPrivacy) -> Self {
+        Notifier {
+            command: command.to_string(),
+            privacy,
+            names: HashMap::new(),
+            my_username: None,
+            sound_command: String::new(),
+            default_sound: None,
+            sounds: HashMap::new(),
+        }
+    }
+
+    pub fn with_sounds(mut self, config: &Config) -> Self {
+        self.sound_command = config.sound_command.clone();
+        self.default_sound = config.notification_sound.clone();
+        self.sounds = config
+            .conversations
+            .iter()
+            .filter_map(|(name, c)| Some((name.clone(), c.notification_sound.clone()?)))
+            .collect();
+        self
+    }
+
+    fn sound_for(&self, conversation_id: &str) -> Option<&Path> {
+        self.names
+            .get(conversation_id)
+            .and_then(|name| self.sounds.get(name))
+            .or(self.default_sound.as_ref())
+            .map(PathBuf::as_path)
+    }
+
+    fn notification_for(
+        &self,
+        message: &Message,
+        conversation_id: &str,
+        active: bool,
+    ) -> Option<Notification> {
+        let me = self.my_username.as_deref().unwrap_or("");
+        // you're already looking at it, or you sent it
+        if active || message.sender.username == me {
+            return None;
+        }
+        let name = self
+            .names
+            .get(conversation_id)
+            .map(String::as_str)
+            .unwrap_or(&message.channel.name);
+        let mention = mentions_user(message, me);
+        notification(message, name, self.privacy, mention)
+    }
+
+    fn send(&self, notification: Notification) {
+        let mut args = vec![notification.title];
+        if !notification.body.is_empty() {
+            args.push(notification.body);
+        }
+        run(self.command.clone(), args);
+    }
+
+    fn play(&self, sound: &Path) {
+        // a typo in the config shouldn't be silent
+        if !sound.exists() {
+            warn!("Notification sound {} doesn't exist", sound.display());
+            return;
+        }
+        run(
+            self.sound_command.clone(),
+            vec![sound.to_string_lossy().into_owned()],
+        );
+    }
+}
+
+// run `command` (a program and its own arguments) with `args` after them, in the background
+fn run(command: String, extra_args: Vec<String>) {
+    tokio::spawn(async move {
+        let mut args = command.split_whitespace();
+        let program = match args.next() {
+            Some(program) => program,
+            None => return,
+        };
+        let mut args: Vec<String> = args.map(String::from).collect();
+        args.extend(extra_args);
+        let status = Command::new(program)
+            .args(&args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await;
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => error!("`{}` exited with {}", command, status),
+            Err(e) => error!("`{}` failed: {}", command, e),
+        }
+    });
+}
+
+impl StateObserver for Notifier {
+    fn on_conversation_change(&mut self, _: &Conversation) {}
+
+    fn on_conversations_added(&mut self, data: &[Conversation]) {
+        for convo in data {
+            self.names.insert(convo.id.clone(), convo.get_name());
+        }
+    }
+
+    fn on_conversations_appended(&mut self, data: &[Conversation], _: bool) {
+        self.on_conversations_added(data);
+    }
+
+    fn on_message(&mut self, message: &Message, conversation_id: &str, active: bool) {
+        if let Some(notification) = self.notification_for(message, conversation_id, active) {
+            if cfg!(feature = "sound") {
+                if let Some(sound) = self.sound_for(conversation_id) {
+                    self.play(sound);
+                }
+            }
+            self.send(notification);
+        }
+    }
+
+    fn on_conversation_updated(&mut self, data: &Conversation) {
+        self.names.insert(data.id.clone(), data.get_name());
+    }
+
+    fn on_username_changed(&mut self, username: &str) {
+        self.my_username = Some(username.to_string());
+    }
+
+    fn on_typing(&mut self, _: &str, _: &str) {}
+
+    fn on_notice(&mut self, _: &str) {}
+
+    fn on_error(&mut self, _: String) {}
+
+    fn on_loading(&mut self, _: &str, _: bool) {}
+
+    fn on_send_failed(&mut self, _: &str) {}
+
+    fn on_channels(&mut self, _: &str, _: &[String]) {}
+
+    fn on_download(&mut self, _: &Path, _: &str) {}
+
+    fn on_status(&mut self, _: ConnectionStatus) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{conversation, message};
+    use crate::config::ConversationConfig;
+    use crate::types::{Channel, KeybaseConversation, MemberType, MessageBody};
+
+    fn payload(privacy: NotificationPrivacy, mention: bool) -> Notification {
+        let mut message = message!("test1", "lunch @me?", 1);
+        message.sender.username = "alice".to_string();
+        notification(&message, "team#general", privacy, mention).unwrap()
+    }
+
+    fn expected(title: &str, body: &str) -> Notification {
+        Notification {
+            title: title.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn full() {
+        assert_eq!(
+            payload(NotificationPrivacy::Full, false),
+            expected("alice in team#general", "lunch @me?")
+        );
+        assert_eq!(
+            payload(NotificationPrivacy::Full, true),
+            expected("alice mentioned you in team#general", "lunch @me?")
+        );
+    }
+
+    #[test]
+    fn sender_only() {
+        assert_eq!(
+            payload(NotificationPrivacy::SenderOnly, false),
+            expected("New message from @alice", "")
+        );
+        assert_eq!(
+            payload(NotificationPrivacy::SenderOnly, true),
+            expected("@alice mentioned you", "")
+        );
+    }
+
+    #[test]
+    fn generic() {
+        assert_eq!(payload(NotificationPrivacy::Generic, false), expected("New message", ""));
+        assert_eq!(payload(NotificationPrivacy::Generic, true), expected("New message", ""));
+    }
+
+    #[test]
+    fn long_messages_are_cut_short() {
+        assert_eq!(truncate_body("short", 10), "short");
+        assert_eq!(truncate_body("exactly 10", 10), "exactly 10");
+        assert_eq!(truncate_body("a bit too long", 10), "a bit too…");
+        // no space left before the …
+        assert_eq!(truncate_body("a bit long", 7), "a bit…");
+        assert_eq!(truncate_body("two\n\nlines  here", 20), "two lines here");
+        assert_eq!(truncate_body("ünïcödé ünïcödé", 8), "ünïcödé…");
+
+        let mut message = message!("test1", "x".repeat(500), 1);
+        message.sender.username = "alice".to_string();
+        let sent = notification(&message, "alice,me", NotificationPrivacy::Full, false).unwrap();
+        assert_eq!(sent.title, "alice in alice,me");
+        assert_eq!(sent.body.chars().count(), MAX_BODY_CHARS);
+        assert!(sent.body.ends_with('…'));
+    }
+
+    #[test]
+    fn only_for_other_peoples_messages_elsewhere() {
+        let mut notifier = Notifier::new("notify-send", NotificationPrivacy::Generic);
+        notifier.on_username_changed("me");
+        let mut message = message!("test1", "hi @ME", 1);
+        message.sender.username = "alice".to_string();
+
+        assert!(notifier.notification_for(&message, "test1", true).is_none());
+        assert!(notifier.notification_for(&message, "test1", false).is_some());
+        assert!(mentions_user(&message, "me"));
+
+        message.sender.username = "me".to_string();
+        assert!(notifier.notification_for(&message, "test1", false).is_none());
+    }
+
+    #[test]
+    fn sound_per_conversation() {
+        let mut config = Config {
+            notification_sound: Some(PathBuf::from("/sounds/default.oga")),
+            ..Config::default()
+        };
+        config.conversations.insert(
+            "boss,me".to_string(),
+            ConversationConfig {
+                notification_sound: Some(PathBuf::from("/sounds/boss.oga")),
+                ..ConversationConfig::default()
+            },
+        );
+        // configured, but without a sound of its own
+        config.conversations.insert("team#general".to_string(), ConversationConfig::default());
+        let mut notifier = Notifier::new("", NotificationPrivacy::Full).with_sounds(&config);
+
+        let mut boss = conversation!("test1");
+        boss.channel.name = "boss,me".to_string();
+        let mut team = conversation!("test2");
+        team.channel.name = "team".to_string();
+        team.channel.topic_name = "general".to_string();
+        team.channel.members_type = MemberType::Team;
+        notifier.on_conversations_added(&[boss.into(), team.into()]);
+
+        assert_eq!(notifier.sound_for("test1"), Some(Path::new("/sounds/boss.oga")));
+        assert_eq!(notifier.sound_for("test2"), Some(Path::new("/sounds/default.oga")));
+        assert_eq!(notifier.sound_for("unknown"), Some(Path::new("/sounds/default.oga")));
+
+        config.notification_sound = None;
+        let notifier = Notifier::new("", NotificationPrivacy::Full).with_sounds(&config);
+        assert_eq!(notifier.sound_for("test2"), None);
+    }
+}
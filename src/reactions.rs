@@ -0,0 +1,115 @@
+// # reactions.rs
+//
+// Reactions arrive as messages of their own, pointing at the message they react to. These helpers
+// gather them up per message so they can be drawn underneath it.
+
+use crate::types::{Message, MessageType};
+
+// Everyone who reacted to a message with the same emoji.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReactionSummary {
+    pub emoji: String,
+    // in the order they reacted
+    pub users: Vec<String>,
+}
+
+impl ReactionSummary {
+    // how the reaction is drawn under the message, e.g. `:+1: 3`
+    pub fn label(&self) -> String {
+        format!("{} {}", self.emoji, self.users.len())
+    }
+
+    // who reacted, e.g. `:+1: alice, bob, carol`
+    pub fn reactors(&self) -> String {
+        format!("{} {}", self.emoji, self.users.join(", "))
+    }
+}
+
+// The reactions to `message_id`, in the order each emoji was first used. `messages` is newest
+// first, like `Conversation::messages`.
+pub fn aggregate_reactions(messages: &[Message], message_id: u64) -> Vec<ReactionSummary> {
+    let mut summaries: Vec<ReactionSummary> = vec![];
+    for message in messages.iter().rev() {
+        let reaction = match &message.content {
+            MessageType::Reaction { reaction } if reaction.message_id == message_id => reaction,
+            _ => continue,
+        };
+        let username = &message.sender.username;
+        match summaries.iter_mut().find(|s| s.emoji == reaction.body) {
+            Some(summary) => {
+                if !summary.users.contains(username) {
+                    summary.users.push(username.clone());
+                }
+            }
+            None => summaries.push(ReactionSummary {
+                emoji: reaction.body.clone(),
+                users: vec![username.clone()],
+            }),
+        }
+    }
+    summaries
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message;
+    use crate::types::{Channel, MemberType, MessageBody, MessageReaction};
+
+    fn reaction(id: u64, target: u64, emoji: &str, username: &str) -> Message {
+        let mut message = message!("test1", "", id);
+        message.content = MessageType::Reaction {
+            reaction: MessageReaction {
+                message_id: target,
+                body: emoji.to_string(),
+            },
+        };
+        message.sender.username = username.to_string();
+        message
+    }
+
+    #[test]
+    fn aggregates_by_emoji() {
+        // newest first
+        let messages = vec![
+            reaction(6, 1, ":+1:", "carol"),
+            reaction(5, 2, ":+1:", "dave"),
+            reaction(4, 1, ":tada:", "alice"),
+            reaction(3, 1, ":+1:", "bob"),
+            reaction(2, 1, ":+1:", "alice"),
+            message!("test1", "hi", 1),
+        ];
+
+        let summaries = aggregate_reactions(&messages, 1);
+        assert_eq!(
+            summaries,
+            vec![
+                ReactionSummary {
+                    emoji: ":+1:".to_string(),
+                    users: vec!["alice".to_string(), "bob".to_string(), "carol".to_string()],
+                },
+                ReactionSummary {
+                    emoji: ":tada:".to_string(),
+                    users: vec!["alice".to_string()],
+                },
+            ]
+        );
+        assert!(aggregate_reactions(&messages, 7).is_empty());
+    }
+
+    #[test]
+    fn same_user_counts_once() {
+        let messages = vec![reaction(3, 1, ":+1:", "alice"), reaction(2, 1, ":+1:", "alice")];
+        assert_eq!(aggregate_reactions(&messages, 1)[0].users, ["alice"]);
+    }
+
+    #[test]
+    fn display_strings() {
+        let summary = ReactionSummary {
+            emoji: ":+1:".to_string(),
+            users: vec!["alice".to_string(), "bob".to_string(), "carol".to_string()],
+        };
+        assert_eq!(summary.label(), ":+1: 3");
+        assert_eq!(summary.reactors(), ":+1: alice, bob, carol");
+    }
+}
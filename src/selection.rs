@@ -0,0 +1,369 @@
+// # selection.rs
+//
+// Helpers for selecting messages in the chat area and turning a selection into text. Messages are
+// kept newest-first (see `Conversation`), so moving to an "older" message means a higher index.
+
+use crate::types::{Message, MessageType};
+
+// Only messages that show up in the chat area can be selected.
+pub fn is_selectable(message: &Message) -> bool {
+    matches!(
+        message.content,
+        MessageType::Text { .. } | MessageType::Unfurl { .. } | MessageType::Attachment { .. }
+    )
+}
+
+// Whether a message gets shown with the `/only` sender filter (if any) applied. Usernames are
+// compared without case, since Keybase lowercases them anyway.
+pub fn matches_sender(message: &Message, filter: Option<&str>) -> bool {
+    match filter {
+        Some(username) => message.sender.username.eq_ignore_ascii_case(username),
+        None => true,
+    }
+}
+
+// The text a message can be found by when searching.
+fn searchable_text(message: &Message) -> Option<&str> {
+    match &message.content {
+        MessageType::Text { text } => Some(&text.body),
+        MessageType::Attachment { attachment } => Some(&attachment.object.filename),
+        _ => None,
+    }
+}
+
+// The newest message containing `query` (ignoring case), or with `from` set, the next older one
+// after it so repeated searches step back through the matches. `messages` are newest first.
+pub fn find_match<'a>(
+    messages: impl IntoIterator<Item = &'a Message>,
+    query: &str,
+    from: Option<u64>,
+) -> Option<u64> {
+    if query.is_empty() {
+        return None;
+    }
+    let query = query.to_lowercase();
+    let mut messages = messages.into_iter();
+    if let Some(from) = from {
+        // skip up to and including the current match
+        messages.find(|m| m.id == from)?;
+    }
+    messages
+        .find(|m| matches!(searchable_text(m), Some(text) if text.to_lowercase().contains(&query)))
+        .map(|m| m.id)
+}
+
+// The message to select after moving one step older or newer from `selected`. With nothing
+// selected yet this starts from the newest message.
+pub fn next_selection(messages: &[Message], selected: Option<u64>, older: bool) -> Option<u64> {
+    let visible: Vec<u64> = messages
+        .iter()
+        .filter(|m| is_selectable(m))
+        .map(|m| m.id)
+        .collect();
+    let current = selected.and_then(|id| visible.iter().position(|v| *v == id));
+
+    let next = match (current, older) {
+        (None, _) => 0,
+        (Some(i), true) => (i + 1).min(visible.len().saturating_sub(1)),
+        (Some(i), false) => i.saturating_sub(1),
+    };
+    visible.get(next).copied()
+}
+
+// How many of the newest messages get a number for `/r<n>` and `/reply<n>`.
+pub const MAX_REFERENCES: usize = 9;
+
+// The messages that can be referred to by number, newest first: number 1 is the newest, up to
+// `MAX_REFERENCES`. Uses the same `/only` filter as the chat area so the numbers match what's on
+// screen.
+pub fn recent_references(messages: &[Message], filter: Option<&str>) -> Vec<u64> {
+    messages
+        .iter()
+        .filter(|m| is_selectable(m) && matches_sender(m, filter))
+        .take(MAX_REFERENCES)
+        .map(|m| m.id)
+        .collect()
+}
+
+// The id of the message numbered `reference`.
+pub fn resolve_reference(
+    messages: &[Message],
+    filter: Option<&str>,
+    reference: usize,
+) -> Result<u64, String> {
+    let recent = recent_references(messages, filter);
+    match reference.checked_sub(1).and_then(|i| recent.get(i)) {
+        Some(id) => Ok(*id),
+        None if recent.is_empty() => Err("No messages to refer to".to_string()),
+        None => Err(format!(
+            "No message numbered {} (the newest are numbered 1 to {})",
+            reference,
+            recent.len()
+        )),
+    }
+}
+
+// The message `/edit` changes: the selected one, or your newest if nothing is selected. Only your
+// own text messages can be edited.
+pub fn message_to_edit(messages: &[Message], selected: Option<u64>, me: &str) -> Result<u64, String> {
+    let mine = |m: &&Message| {
+        m.sender.username == me && matches!(m.content, MessageType::Text { .. })
+    };
+    match selected {
+        Some(id) => messages
+            .iter()
+            .find(|m| m.id == id)
+            .filter(mine)
+            .map(|m| m.id)
+            .ok_or_else(|| "You can only edit your own messages".to_string()),
+        None => messages
+            .iter()
+            .find(mine)
+            .map(|m| m.id)
+            .ok_or_else(|| "No message of yours to edit".to_string()),
+    }
+}
+
+// All selectable messages between `anchor` and `selected` (inclusive, in either direction),
+// oldest first. Without an anchor (or if it's no longer loaded) this is just the selected message.
+pub fn selected_range(
+    messages: &[Message],
+    anchor: Option<u64>,
+    selected: Option<u64>,
+) -> Vec<&Message> {
+    let position = |id: Option<u64>| id.and_then(|id| messages.iter().position(|m| m.id == id));
+
+    let end = match position(selected) {
+        Some(end) => end,
+        None => return vec![],
+    };
+    let start = position(anchor).unwrap_or(end);
+    let (newest, oldest) = (start.min(end), start.max(end));
+
+    messages[newest..=oldest]
+        .iter()
+        .rev()
+        .filter(|m| is_selectable(m))
+        .collect()
+}
+
+// `sender: body` for each message, one per line.
+pub fn format_messages(messages: &[&Message]) -> String {
+    messages
+        .iter()
+        .filter_map(|m| match &m.content {
+            MessageType::Text { text } => Some(format!("{}: {}", m.sender.username, text.body)),
+            MessageType::Attachment { attachment } => {
+                Some(format!("{}: {}", m.sender.username, attachment.object.filename))
+            }
+            _ => None,
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// The messages formatted as a markdown quote, ready to reply to.
+pub fn quote_messages(messages: &[&Message]) -> String {
+    format_messages(messages)
+        .lines()
+        .map(|line| format!("> {}\n", line))
+        .collect()
+}
+
+// The message drawn on `row` of the chat area, given how many rows each message takes up (oldest
+// first, like they're drawn). Rows below the last message don't belong to anything.
+pub fn message_at_row(rows: &[(u64, usize)], row: usize) -> Option<u64> {
+    let mut top = 0;
+    for (id, height) in rows {
+        if row < top + height {
+            return Some(*id);
+        }
+        top += height;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message;
+    use crate::types::{Channel, MemberType, MessageBody};
+
+    // newest first, like `Conversation::messages`
+    fn messages() -> Vec<Message> {
+        vec![
+            message!("test", "four", 4),
+            message!("test", "three", 3),
+            message!("test", "two", 2),
+            message!("test", "one", 1),
+        ]
+    }
+
+    fn ids(messages: &[&Message]) -> Vec<u64> {
+        messages.iter().map(|m| m.id).collect()
+    }
+
+    #[test]
+    fn move_selection() {
+        let messages = messages();
+        assert_eq!(next_selection(&messages, None, true), Some(4));
+        assert_eq!(next_selection(&messages, Some(4), true), Some(3));
+        assert_eq!(next_selection(&messages, Some(3), false), Some(4));
+        // stops at either end
+        assert_eq!(next_selection(&messages, Some(1), true), Some(1));
+        assert_eq!(next_selection(&messages, Some(4), false), Some(4));
+        assert_eq!(next_selection(&[], None, true), None);
+    }
+
+    #[test]
+    fn range_in_either_direction() {
+        let messages = messages();
+        assert_eq!(ids(&selected_range(&messages, Some(3), Some(1))), vec![1, 2, 3]);
+        assert_eq!(ids(&selected_range(&messages, Some(1), Some(3))), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn range_boundaries() {
+        let messages = messages();
+        // whole conversation
+        assert_eq!(
+            ids(&selected_range(&messages, Some(4), Some(1))),
+            vec![1, 2, 3, 4]
+        );
+        // anchor on the selection itself
+        assert_eq!(ids(&selected_range(&messages, Some(2), Some(2))), vec![2]);
+        // no anchor, or an anchor that isn't loaded any more
+        assert_eq!(ids(&selected_range(&messages, None, Some(2))), vec![2]);
+        assert_eq!(ids(&selected_range(&messages, Some(99), Some(2))), vec![2]);
+        // nothing selected
+        assert!(selected_range(&messages, Some(2), None).is_empty());
+    }
+
+    #[test]
+    fn quote_range() {
+        let messages = messages();
+        let range = selected_range(&messages, Some(3), Some(2));
+
+        assert_eq!(format_messages(&range), "Some Guy: two\nSome Guy: three");
+        assert_eq!(
+            quote_messages(&range),
+            "> Some Guy: two\n> Some Guy: three\n"
+        );
+    }
+
+    #[test]
+    fn quote_multiline_message() {
+        let message = message!("test", "first\nsecond", 1);
+        assert_eq!(
+            quote_messages(&[&message]),
+            "> Some Guy: first\n> second\n"
+        );
+    }
+
+    #[test]
+    fn row_to_message() {
+        // a one line message, a wrapped three line message, then something that isn't drawn
+        let rows = vec![(1, 1), (2, 3), (3, 0), (4, 1)];
+        assert_eq!(message_at_row(&rows, 0), Some(1));
+        assert_eq!(message_at_row(&rows, 1), Some(2));
+        assert_eq!(message_at_row(&rows, 3), Some(2));
+        assert_eq!(message_at_row(&rows, 4), Some(4));
+        // blank space under the messages
+        assert_eq!(message_at_row(&rows, 5), None);
+        assert_eq!(message_at_row(&[], 0), None);
+    }
+
+    #[test]
+    fn sender_filter() {
+        let mut message = message!("test", "hi", 1);
+        message.sender.username = "alice".to_string();
+
+        assert!(matches_sender(&message, None));
+        assert!(matches_sender(&message, Some("alice")));
+        assert!(matches_sender(&message, Some("Alice")));
+        assert!(!matches_sender(&message, Some("bob")));
+        assert!(!matches_sender(&message, Some("alic")));
+    }
+
+    #[test]
+    fn incremental_search() {
+        let messages = vec![
+            message!("test", "goodbye", 4),
+            message!("test", "Hello there", 3),
+            message!("test", "help", 2),
+            message!("test", "hello again", 1),
+        ];
+
+        // narrows down as more of the query is typed
+        assert_eq!(find_match(&messages, "h", None), Some(3));
+        assert_eq!(find_match(&messages, "hel", None), Some(3));
+        assert_eq!(find_match(&messages, "help", None), Some(2));
+        assert_eq!(find_match(&messages, "hello a", None), Some(1));
+        assert_eq!(find_match(&messages, "nope", None), None);
+        assert_eq!(find_match(&messages, "", None), None);
+    }
+
+    #[test]
+    fn search_next_match() {
+        let messages = messages();
+        assert_eq!(find_match(&messages, "o", None), Some(4));
+        assert_eq!(find_match(&messages, "o", Some(4)), Some(2));
+        assert_eq!(find_match(&messages, "o", Some(2)), Some(1));
+        assert_eq!(find_match(&messages, "o", Some(1)), None);
+    }
+
+    #[test]
+    fn references_resolve_newest_first() {
+        // newest first
+        let mut messages: Vec<Message> = (1..=12).rev().map(|id| message!("test1", "hi", id)).collect();
+        let mut join = message!("test1", "", 20);
+        join.content = MessageType::Join;
+        messages.insert(1, join);
+
+        assert_eq!(resolve_reference(&messages, None, 1), Ok(12));
+        // the join message doesn't get a number
+        assert_eq!(resolve_reference(&messages, None, 2), Ok(11));
+        assert_eq!(resolve_reference(&messages, None, 9), Ok(4));
+        assert_eq!(
+            resolve_reference(&messages, None, 10).unwrap_err(),
+            "No message numbered 10 (the newest are numbered 1 to 9)"
+        );
+        assert!(resolve_reference(&messages, None, 0).is_err());
+        assert_eq!(
+            resolve_reference(&[], None, 1).unwrap_err(),
+            "No messages to refer to"
+        );
+    }
+
+    #[test]
+    fn references_follow_the_sender_filter() {
+        let mut from_bob = message!("test1", "hey", 2);
+        from_bob.sender.username = "bob".to_string();
+        let messages = vec![from_bob, message!("test1", "hi", 1)];
+
+        assert_eq!(recent_references(&messages, None), [2, 1]);
+        assert_eq!(recent_references(&messages, Some("Some Guy")), [1]);
+        assert_eq!(resolve_reference(&messages, Some("bob"), 1), Ok(2));
+    }
+
+    #[test]
+    fn edits_own_messages() {
+        let mut from_bob = message!("test1", "hey", 3);
+        from_bob.sender.username = "bob".to_string();
+        let mut join = message!("test1", "", 4);
+        join.content = MessageType::Join;
+        let messages = vec![join, from_bob, message!("test1", "hi", 2), message!("test1", "yo", 1)];
+
+        assert_eq!(message_to_edit(&messages, None, "Some Guy"), Ok(2));
+        assert_eq!(message_to_edit(&messages, Some(1), "Some Guy"), Ok(1));
+        assert_eq!(
+            message_to_edit(&messages, Some(3), "Some Guy").unwrap_err(),
+            "You can only edit your own messages"
+        );
+        assert!(message_to_edit(&messages, Some(4), "Some Guy").is_err());
+        assert_eq!(
+            message_to_edit(&messages, None, "carol").unwrap_err(),
+            "No message of yours to edit"
+        );
+    }
+}
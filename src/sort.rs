@@ -0,0 +1,167 @@
+// # sort.rs
+//
+// Ordering of the conversation list. Every mode starts from activity order (most recent first,
+// with ties left in the order keybase listed them), and the sorts are stable.
+
+use std::cmp::Ordering;
+
+use serde::Deserialize;
+
+use crate::types::Conversation;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortMode {
+    // as keybase lists them
+    #[default]
+    Activity,
+    // by name, ignoring case
+    Alphabetical,
+    // conversations with unread messages on top, then by activity
+    UnreadFirst,
+    // in the order of `conversation_order` in the config, then by activity
+    Manual,
+}
+
+impl SortMode {
+    // the mode after this one, for cycling through them with a keybinding
+    pub fn next(self) -> Self {
+        match self {
+            SortMode::Activity => SortMode::Alphabetical,
+            SortMode::Alphabetical => SortMode::UnreadFirst,
+            SortMode::UnreadFirst => SortMode::Manual,
+            SortMode::Manual => SortMode::Activity,
+        }
+    }
+
+    // shown in the title of the conversation list
+    pub fn label(self) -> &'static str {
+        match self {
+            SortMode::Activity => "recent",
+            SortMode::Alphabetical => "a-z",
+            SortMode::UnreadFirst => "unread first",
+            SortMode::Manual => "manual",
+        }
+    }
+}
+
+pub fn most_recent_first(a: &Conversation, b: &Conversation) -> Ordering {
+    b.data.active_at.cmp(&a.data.active_at)
+}
+
+// Sort conversations for the sidebar. `order` is the manual order, by conversation name.
+pub fn sort_conversations<F: Fn(&Conversation) -> bool>(
+    conversations: &mut [Conversation],
+    mode: SortMode,
+    is_unread: F,
+    order: &[String],
+) {
+    conversations.sort_by(most_recent_first);
+    match mode {
+        SortMode::Activity => {}
+        SortMode::Alphabetical => conversations.sort_by_key(|c| c.get_name().to_lowercase()),
+        SortMode::UnreadFirst => conversations.sort_by_key(|c| !is_unread(c)),
+        SortMode::Manual => conversations.sort_by_key(|c| {
+            let name = c.get_name();
+            // anything not in the list goes after everything that is
+            order.iter().position(|n| *n == name).unwrap_or(order.len())
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::conversation;
+    use crate::types::{Channel, KeybaseConversation, MemberType};
+
+    // in activity order, most recent first
+    fn sample() -> Vec<Conversation> {
+        ["carol", "alice", "Dave", "bob"]
+            .iter()
+            .map(|name| {
+                let mut convo = conversation!(*name);
+                convo.channel.name = name.to_string();
+                convo.into()
+            })
+            .collect()
+    }
+
+    fn sorted(mode: SortMode, unread: &[&str], order: &[&str]) -> Vec<String> {
+        let mut conversations = sample();
+        let order: Vec<String> = order.iter().map(|n| n.to_string()).collect();
+        sort_conversations(
+            &mut conversations,
+            mode,
+            |c| unread.contains(&c.get_name().as_str()),
+            &order,
+        );
+        conversations.iter().map(|c| c.get_name()).collect()
+    }
+
+    #[test]
+    fn activity() {
+        assert_eq!(sorted(SortMode::Activity, &["bob"], &[]), ["carol", "alice", "Dave", "bob"]);
+    }
+
+    #[test]
+    fn most_recent() {
+        let mut conversations = sample();
+        conversations[3].data.active_at = 300;
+        conversations[1].data.active_at = 200;
+        assert_eq!(most_recent_first(&conversations[3], &conversations[1]), Ordering::Less);
+        assert_eq!(most_recent_first(&conversations[1], &conversations[3]), Ordering::Greater);
+        assert_eq!(most_recent_first(&conversations[0], &conversations[2]), Ordering::Equal);
+
+        sort_conversations(&mut conversations, SortMode::Activity, |_| false, &[]);
+        let names: Vec<String> = conversations.iter().map(|c| c.get_name()).collect();
+        assert_eq!(names, ["bob", "alice", "carol", "Dave"]);
+
+        // a new message in dave's moves it to the top
+        conversations[3].data.active_at = 400;
+        sort_conversations(&mut conversations, SortMode::Activity, |_| false, &[]);
+        assert_eq!(conversations[0].get_name(), "Dave");
+    }
+
+    #[test]
+    fn alphabetical() {
+        assert_eq!(sorted(SortMode::Alphabetical, &[], &[]), ["alice", "bob", "carol", "Dave"]);
+    }
+
+    #[test]
+    fn unread_first() {
+        assert_eq!(
+            sorted(SortMode::UnreadFirst, &["bob", "alice"], &[]),
+            ["alice", "bob", "carol", "Dave"]
+        );
+        assert_eq!(sorted(SortMode::UnreadFirst, &[], &[]), ["carol", "alice", "Dave", "bob"]);
+    }
+
+    #[test]
+    fn manual() {
+        assert_eq!(
+            sorted(SortMode::Manual, &[], &["bob", "carol"]),
+            ["bob", "carol", "alice", "Dave"]
+        );
+        // names that don't match a conversation are ignored
+        assert_eq!(
+            sorted(SortMode::Manual, &[], &["eve", "Dave"]),
+            ["Dave", "carol", "alice", "bob"]
+        );
+    }
+
+    #[test]
+    fn cycles_modes() {
+        let mut mode = SortMode::default();
+        let mut seen = vec![];
+        for _ in 0..4 {
+            seen.push(mode);
+            mode = mode.next();
+        }
+        assert_eq!(mode, SortMode::Activity);
+        assert_eq!(
+            seen,
+            [SortMode::Activity, SortMode::Alphabetical, SortMode::UnreadFirst, SortMode::Manual]
+        );
+    }
+}
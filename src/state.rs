@@ -23,11 +23,15 @@
 
 use std::collections::hash_map::Values;
 use std::collections::HashMap;
+use std::path::Path;
 
 #[cfg(test)]
 use mockall::*;
 
-use crate::types::{Conversation, Message};
+use crate::error::StateError;
+use crate::selection::is_selectable;
+use crate::status::ConnectionStatus;
+use crate::types::{Conversation, MemberType, Message, MessageType};
 
 type ConversationId = String;
 
@@ -38,7 +42,27 @@ type ConversationId = String;
 pub trait StateObserver {
     fn on_conversation_change(&mut self, data: &Conversation);
     fn on_conversations_added(&mut self, data: &[Conversation]);
+    // another page of conversations, to go after the ones there already
+    fn on_conversations_appended(&mut self, data: &[Conversation], more: bool);
     fn on_message(&mut self, data: &Message, conversation_id: &str, active: bool);
+    fn on_conversation_updated(&mut self, data: &Conversation);
+    fn on_username_changed(&mut self, username: &str);
+    // `username` is typing in the conversation right now
+    fn on_typing(&mut self, conversation_id: &str, username: &str);
+    // how something done in the background went (e.g. a download)
+    fn on_notice(&mut self, text: &str);
+    // something didn't work, e.g. a message that couldn't be sent
+    fn on_error(&mut self, message: String);
+    // messages for a conversation are being fetched (`loading`), or that's done, either way
+    fn on_loading(&mut self, conversation_id: &str, loading: bool);
+    // something you sent didn't go through, `body` being what was typed (why is a notice)
+    fn on_send_failed(&mut self, body: &str);
+    // the channels in `team` (topic names), to pick one from
+    fn on_channels(&mut self, team: &str, topic_names: &[String]);
+    // an attachment was saved to `path`
+    fn on_download(&mut self, path: &Path, mime_type: &str);
+    // how talking to keybase is going changed
+    fn on_status(&mut self, status: ConnectionStatus);
 }
 
 // This is the inner struct that lives inside the Arc<Mutex> which masquerades as the actual state.
@@ -52,6 +76,14 @@ pub struct ApplicationStateInner {
 
     // List of registered observers
     observers: Vec<Box<dyn StateObserver>>,
+
+    // username of whoever is logged in to keybase
+    my_username: Option<String>,
+
+    // how many messages each conversation keeps, the default if unset
+    max_messages: Option<usize>,
+
+    connection: ConnectionStatus,
 }
 
 pub struct Conversations<'a, I: Iterator<Item = &'a Conversation>> {
@@ -82,24 +114,99 @@ impl<'a, I: ExactSizeIterator + Iterator<Item = &'a Conversation>> ExactSizeIter
 
 pub trait ApplicationState {
     fn insert_conversation(&mut self, conversation: Conversation);
-    fn insert_message(&mut self, conversation_id: &str, message: Message);
-    fn set_current_conversation(&mut self, conversation_id: &str);
+    fn insert_message(&mut self, conversation_id: &str, message: Message) -> Result<(), StateError>;
+    fn set_current_conversation(&mut self, conversation_id: &str) -> Result<(), StateError>;
     fn get_current_conversation(&self) -> Option<&Conversation>;
     fn set_conversations(&mut self, conversations: Vec<Conversation>);
+    // like `set_conversations`, but anything not in `conversations` is gone
+    fn replace_conversations(&mut self, conversations: Vec<Conversation>);
+    // `more` is whether there are still conversations to load after these
+    fn append_conversations(&mut self, conversations: Vec<Conversation>, more: bool);
     fn get_conversations(&self) -> Conversations<Values<'_, String, Conversation>>;
     fn register_observer(&mut self, observer: Box<dyn StateObserver>);
     fn get_conversation(&self, conversation_id: &str) -> Option<&Conversation>;
     fn get_conversation_mut(&mut self, conversation_id: &str) -> Option<&mut Conversation>;
+    fn set_pinned(&mut self, conversation_id: &str, message_id: u64, pinned: bool);
+    fn remove_message(&mut self, conversation_id: &str, message_id: u64);
+    // a message we sent is now `message_id` (see `Conversation::confirm_pending`)
+    fn confirm_message(&mut self, conversation_id: &str, pending_id: u64, message_id: u64);
+    fn set_typing(&mut self, conversation_id: &str, username: &str);
+    // a page of older messages, with the cursor for the page after it
+    fn insert_history(&mut self, conversation_id: &str, messages: Vec<Message>, next: Option<String>);
+    fn set_my_username(&mut self, username: &str);
+    fn get_my_username(&self) -> Option<&str>;
+    fn set_topic_name(&mut self, conversation_id: &str, topic_name: &str);
+    fn set_hidden(&mut self, conversation_id: &str, hidden: bool);
+    fn set_muted(&mut self, conversation_id: &str, muted: bool);
+    fn set_member_count(&mut self, team: &str, count: Option<usize>);
+    fn set_participants(&mut self, team: &str, participants: &[String]);
+    fn notice(&mut self, text: &str);
+    fn set_loading(&mut self, conversation_id: &str, loading: bool);
+    fn error(&mut self, message: String);
+    // a message didn't go, so what was typed can be put back
+    fn send_failed(&mut self, body: &str);
+    fn show_channels(&mut self, team: &str, topic_names: &[String]);
+    fn downloaded(&mut self, path: &Path, mime_type: &str);
+    // only passed on when it's changed
+    fn set_connection_status(&mut self, status: ConnectionStatus);
+}
+
+impl ApplicationStateInner {
+    // change every channel of a team the same way
+    fn update_team<F: FnMut(&mut Conversation)>(&mut self, team: &str, mut f: F) {
+        let observers = &mut self.observers;
+        self.conversations
+            .values_mut()
+            .filter(|c| {
+                c.data.channel.members_type == MemberType::Team && c.data.channel.name == team
+            })
+            .for_each(|convo| {
+                f(convo);
+                let convo = &*convo;
+                observers
+                    .iter_mut()
+                    .for_each(|o| o.on_conversation_updated(convo));
+            });
+    }
+
+    pub fn with_max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = Some(max_messages);
+        self
+    }
+
+    // for conversations we've just heard about
+    fn set_up(&self, conversation: &mut Conversation) {
+        if let Some(username) = &self.my_username {
+            conversation.set_my_username(username);
+        }
+        if let Some(max_messages) = self.max_messages {
+            conversation.set_max_messages(max_messages);
+        }
+    }
 }
 
 impl ApplicationState for ApplicationStateInner {
-    fn insert_conversation(&mut self, conversation: Conversation) {
+    fn insert_conversation(&mut self, mut conversation: Conversation) {
+        self.set_up(&mut conversation);
         self.conversations
             .insert(conversation.id.clone(), conversation);
     }
 
-    // should return a result
-    fn insert_message(&mut self, conversation_id: &str, message: Message) {
+    fn insert_message(&mut self, conversation_id: &str, message: Message) -> Result<(), StateError> {
+        let convo = self
+            .conversations
+            .get(conversation_id)
+            .ok_or_else(|| StateError::UnknownConversation(conversation_id.to_string()))?;
+        // seen it already, so it isn't new to anyone
+        if convo.has_message(message.id) {
+            return Ok(());
+        }
+        if let MessageType::Delete { delete } = &message.content {
+            for message_id in &delete.message_ids {
+                self.remove_message(conversation_id, *message_id);
+            }
+            return Ok(());
+        }
         let is_active = {
             if let Some(convo) = self.get_current_conversation() {
                 convo.id == conversation_id
@@ -108,21 +215,57 @@ impl ApplicationState for ApplicationStateInner {
             }
         };
         if let Some(convo) = self.conversations.get_mut(conversation_id) {
-            self.observers
-                .iter_mut()
-                .for_each(|o| o.on_message(&message, conversation_id, is_active));
-            convo.insert_message(message);
+            // nobody hears about it until the conversation's opened
+            if is_active || !convo.muted {
+                self.observers
+                    .iter_mut()
+                    .for_each(|o| o.on_message(&message, conversation_id, is_active));
+            }
+            let from_me = self.my_username.as_deref() == Some(message.sender.username.as_str());
+            let unread = !is_active && !from_me && !convo.muted && is_selectable(&message);
+            // moves it up the sidebar, like keybase does
+            let bumped = is_selectable(&message) && message.sent_at > convo.data.active_at;
+            if bumped {
+                convo.data.active_at = message.sent_at;
+            }
+            // edits change the message they point at rather than showing up themselves
+            match &message.content {
+                MessageType::Edit { edit } => {
+                    convo.apply_edit(edit);
+                }
+                _ => convo.insert_message(message),
+            }
+            if unread {
+                convo.unread_count += 1;
+            }
+            if unread || bumped {
+                let convo = &*convo;
+                self.observers
+                    .iter_mut()
+                    .for_each(|o| o.on_conversation_updated(convo));
+            }
         }
+        Ok(())
     }
 
-    // should return a result
-    fn set_current_conversation(&mut self, conversation_id: &str) {
-        if let Some(convo) = self.conversations.get(conversation_id) {
-            self.current_conversation = Some(conversation_id.to_string());
+    fn set_current_conversation(&mut self, conversation_id: &str) -> Result<(), StateError> {
+        let convo = self
+            .conversations
+            .get_mut(conversation_id)
+            .ok_or_else(|| StateError::UnknownConversation(conversation_id.to_string()))?;
+        if convo.unread_count > 0 {
+            convo.unread_count = 0;
+            let convo = &*convo;
             self.observers
                 .iter_mut()
-                .for_each(|o| o.on_conversation_change(convo));
+                .for_each(|o| o.on_conversation_updated(convo));
         }
+        let convo = &*convo;
+        self.current_conversation = Some(conversation_id.to_string());
+        self.observers
+            .iter_mut()
+            .for_each(|o| o.on_conversation_change(convo));
+        Ok(())
     }
 
     fn get_current_conversation(&self) -> Option<&Conversation> {
@@ -134,7 +277,11 @@ impl ApplicationState for ApplicationStateInner {
         None
     }
 
-    fn set_conversations(&mut self, conversations: Vec<Conversation>) {
+    fn set_conversations(&mut self, mut conversations: Vec<Conversation>) {
+        for convo in conversations.iter_mut() {
+            self.set_up(convo);
+        }
+
         self.observers
             .iter_mut()
             .for_each(|o| o.on_conversations_added(conversations.as_slice()));
@@ -144,6 +291,27 @@ impl ApplicationState for ApplicationStateInner {
         }
     }
 
+    fn replace_conversations(&mut self, conversations: Vec<Conversation>) {
+        self.conversations.clear();
+        self.set_conversations(conversations);
+    }
+
+    fn append_conversations(&mut self, mut conversations: Vec<Conversation>, more: bool) {
+        for convo in conversations.iter_mut() {
+            self.set_up(convo);
+        }
+        // pages can overlap when the order changes between fetches
+        conversations.retain(|c| !self.conversations.contains_key(&c.id));
+
+        self.observers
+            .iter_mut()
+            .for_each(|o| o.on_conversations_appended(conversations.as_slice(), more));
+
+        for convo in conversations.into_iter() {
+            self.conversations.insert(convo.id.clone(), convo);
+        }
+    }
+
     fn get_conversations(&self) -> Conversations<Values<'_, String, Conversation>> {
         Conversations {
             inner: self.conversations.values(),
@@ -161,6 +329,177 @@ impl ApplicationState for ApplicationStateInner {
     fn get_conversation_mut(&mut self, conversation_id: &str) -> Option<&mut Conversation> {
         self.conversations.get_mut(conversation_id)
     }
+
+    fn set_pinned(&mut self, conversation_id: &str, message_id: u64, pinned: bool) {
+        if let Some(convo) = self.conversations.get_mut(conversation_id) {
+            if pinned {
+                convo.pinned.insert(message_id);
+            } else {
+                convo.pinned.remove(&message_id);
+            }
+            let convo = &*convo;
+            self.observers
+                .iter_mut()
+                .for_each(|o| o.on_conversation_updated(convo));
+        }
+    }
+
+    fn remove_message(&mut self, conversation_id: &str, message_id: u64) {
+        if let Some(convo) = self.conversations.get_mut(conversation_id) {
+            if !convo.remove_message(message_id) {
+                return;
+            }
+            convo.pinned.remove(&message_id);
+            let convo = &*convo;
+            self.observers
+                .iter_mut()
+                .for_each(|o| o.on_conversation_updated(convo));
+        }
+    }
+
+    fn confirm_message(&mut self, conversation_id: &str, pending_id: u64, message_id: u64) {
+        if let Some(convo) = self.conversations.get_mut(conversation_id) {
+            if !convo.confirm_pending(pending_id, message_id) {
+                return;
+            }
+            let convo = &*convo;
+            self.observers
+                .iter_mut()
+                .for_each(|o| o.on_conversation_updated(convo));
+        }
+    }
+
+    // not kept anywhere, it's only interesting for a few seconds
+    fn set_typing(&mut self, conversation_id: &str, username: &str) {
+        // keybase tells us about our own typing too
+        if self.my_username.as_deref() == Some(username)
+            || !self.conversations.contains_key(conversation_id)
+        {
+            return;
+        }
+        self.observers
+            .iter_mut()
+            .for_each(|o| o.on_typing(conversation_id, username));
+    }
+
+    fn insert_history(&mut self, conversation_id: &str, messages: Vec<Message>, next: Option<String>) {
+        if let Some(convo) = self.conversations.get_mut(conversation_id) {
+            convo.insert_history(messages, next);
+            let convo = &*convo;
+            self.observers
+                .iter_mut()
+                .for_each(|o| o.on_conversation_updated(convo));
+        }
+    }
+
+    fn set_my_username(&mut self, username: &str) {
+        self.my_username = Some(username.to_string());
+        for convo in self.conversations.values_mut() {
+            convo.set_my_username(username);
+        }
+        self.observers
+            .iter_mut()
+            .for_each(|o| o.on_username_changed(username));
+    }
+
+    fn get_my_username(&self) -> Option<&str> {
+        self.my_username.as_deref()
+    }
+
+    fn set_topic_name(&mut self, conversation_id: &str, topic_name: &str) {
+        if let Some(convo) = self.conversations.get_mut(conversation_id) {
+            convo.data.channel.topic_name = topic_name.to_string();
+            let convo = &*convo;
+            self.observers
+                .iter_mut()
+                .for_each(|o| o.on_conversation_updated(convo));
+        }
+    }
+
+    fn set_hidden(&mut self, conversation_id: &str, hidden: bool) {
+        if let Some(convo) = self.conversations.get_mut(conversation_id) {
+            if convo.hidden == hidden {
+                return;
+            }
+            convo.hidden = hidden;
+            let convo = &*convo;
+            self.observers
+                .iter_mut()
+                .for_each(|o| o.on_conversation_updated(convo));
+        }
+    }
+
+    fn set_muted(&mut self, conversation_id: &str, muted: bool) {
+        if let Some(convo) = self.conversations.get_mut(conversation_id) {
+            if convo.muted == muted {
+                return;
+            }
+            convo.muted = muted;
+            // what's unread so far doesn't count either
+            if muted {
+                convo.unread_count = 0;
+                convo.data.unread = false;
+            }
+            let convo = &*convo;
+            self.observers
+                .iter_mut()
+                .for_each(|o| o.on_conversation_updated(convo));
+        }
+    }
+
+    // every channel in the team gets the same count
+    fn set_member_count(&mut self, team: &str, count: Option<usize>) {
+        self.update_team(team, |convo| convo.member_count = count);
+    }
+
+    fn set_participants(&mut self, team: &str, participants: &[String]) {
+        self.update_team(team, |convo| convo.participants = participants.to_vec());
+    }
+
+    // nothing to keep, it's only passed on
+    fn notice(&mut self, text: &str) {
+        self.observers
+            .iter_mut()
+            .for_each(|o| o.on_notice(text));
+    }
+
+    fn set_loading(&mut self, conversation_id: &str, loading: bool) {
+        self.observers
+            .iter_mut()
+            .for_each(|o| o.on_loading(conversation_id, loading));
+    }
+
+    fn error(&mut self, message: String) {
+        self.observers
+            .iter_mut()
+            .for_each(|o| o.on_error(message.clone()));
+    }
+
+    fn send_failed(&mut self, body: &str) {
+        self.observers
+            .iter_mut()
+            .for_each(|o| o.on_send_failed(body));
+    }
+
+    fn show_channels(&mut self, team: &str, topic_names: &[String]) {
+        self.observers
+            .iter_mut()
+            .for_each(|o| o.on_channels(team, topic_names));
+    }
+
+    fn downloaded(&mut self, path: &Path, mime_type: &str) {
+        self.observers
+            .iter_mut()
+            .for_each(|o| o.on_download(path, mime_type));
+    }
+
+    fn set_connection_status(&mut self, status: ConnectionStatus) {
+        if self.connection == status {
+            return;
+        }
+        self.connection = status;
+        self.observers.iter_mut().for_each(|o| o.on_status(status));
+    }
 }
 
 #[cfg(test)]
@@ -205,14 +544,17 @@ mod test {
     fn current_conversation() {
         let mut state = ApplicationStateInner::default();
 
-        state.set_current_conversation("test");
+        assert_eq!(
+            state.set_current_conversation("test"),
+            Err(StateError::UnknownConversation("test".to_string()))
+        );
         assert!(state.get_current_conversation().is_none());
 
         let convo: Conversation = conversation!("test").into();
         let data_copy = convo.data.clone();
 
         state.insert_conversation(convo);
-        state.set_current_conversation("test");
+        state.set_current_conversation("test").unwrap();
         let current = state.get_current_conversation().unwrap();
 
         assert_eq!(current.id, "test");
@@ -237,7 +579,7 @@ mod test {
         let mut state = ApplicationStateInner::default();
 
         state.insert_conversation(conversation!("test").into());
-        state.insert_message("test", message!("test", "hey"));
+        state.insert_message("test", message!("test", "hey")).unwrap();
 
         let convo = state.get_conversation("test").unwrap();
 
@@ -247,7 +589,7 @@ mod test {
             panic!("Wrong message type");
         }
 
-        state.insert_message("test", message!("test", "there"));
+        state.insert_message("test", message!("test", "there")).unwrap();
         let convo = state.get_conversation("test").unwrap();
 
         // message should be prepended
@@ -258,6 +600,347 @@ mod test {
         }
     }
 
+    #[test]
+    fn insert_into_unknown_conversation() {
+        let mut state = ApplicationStateInner::default();
+        state.insert_conversation(conversation!("test").into());
+
+        assert_eq!(
+            state.insert_message("nope", message!("nope", "hi")),
+            Err(StateError::UnknownConversation("nope".to_string()))
+        );
+        assert!(state.insert_message("test", message!("test", "hi")).is_ok());
+    }
+
+    #[test]
+    fn edit_in_place() {
+        let mut state = ApplicationStateInner::default();
+        state.insert_conversation(conversation!("test").into());
+        state.insert_message("test", message!("test", "helo", 1)).unwrap();
+        state.insert_message("test", message!("test", "there", 2)).unwrap();
+
+        let mut edit = message!("test", "", 3);
+        edit.content = MessageType::Edit {
+            edit: MessageEdit {
+                message_id: 1,
+                body: "hello".to_string(),
+            },
+        };
+        state.insert_message("test", edit).unwrap();
+
+        let convo = state.get_conversation("test").unwrap();
+        let bodies: Vec<(u64, &str)> = convo
+            .messages
+            .iter()
+            .map(|m| match &m.content {
+                MessageType::Text { text } => (m.id, text.body.as_str()),
+                _ => panic!("Wrong message type"),
+            })
+            .collect();
+        // changed where it was, and the edit itself isn't kept
+        assert_eq!(bodies, [(2, "there"), (1, "hello")]);
+    }
+
+    #[test]
+    fn unread_counts() {
+        let mut state = ApplicationStateInner::default();
+        state.insert_conversation(conversation!("test1").into());
+        state.insert_conversation(conversation!("test2").into());
+        state.set_my_username("me");
+        state.set_current_conversation("test1").unwrap();
+
+        let count = |state: &ApplicationStateInner, id: &str| {
+            state.get_conversation(id).unwrap().unread_count
+        };
+        state.insert_message("test2", message!("test2", "one")).unwrap();
+        state.insert_message("test2", message!("test2", "two")).unwrap();
+        // the open conversation doesn't count
+        state.insert_message("test1", message!("test1", "seen")).unwrap();
+        // and neither do your own messages
+        let mut mine = message!("test2", "mine");
+        mine.sender.username = "me".to_string();
+        state.insert_message("test2", mine).unwrap();
+        // or edits to what's there
+        let mut edit = message!("test2", "");
+        edit.content = MessageType::Edit {
+            edit: MessageEdit { message_id: 1, body: "uno".to_string() },
+        };
+        state.insert_message("test2", edit).unwrap();
+        assert_eq!(count(&state, "test2"), 2);
+        assert_eq!(count(&state, "test1"), 0);
+
+        state.set_current_conversation("test2").unwrap();
+        state.insert_message("test1", message!("test1", "three")).unwrap();
+        state.insert_message("test1", message!("test1", "four")).unwrap();
+        assert_eq!(count(&state, "test2"), 0);
+        assert_eq!(count(&state, "test1"), 2);
+    }
+
+    #[test]
+    fn muted_conversations_stay_read() {
+        let mut state = ApplicationStateInner::default();
+        state.insert_conversation(conversation!("test1").into());
+        state.insert_conversation(conversation!("test2").into());
+        state.set_current_conversation("test1").unwrap();
+        state.insert_message("test2", message!("test2", "before")).unwrap();
+        state.set_muted("test2", true);
+        assert_eq!(state.get_conversation("test2").unwrap().unread_count, 0);
+
+        // only told about the message in the open conversation, and the one after unmuting
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_message()
+            .withf(|message: &Message, id: &str, _| {
+                id != "test2"
+                    || matches!(&message.content, MessageType::Text { text } if text.body == "after")
+            })
+            .times(2)
+            .return_const(());
+        obs.expect_on_conversation_updated().return_const(());
+        state.register_observer(Box::new(obs));
+
+        state.insert_message("test2", message!("test2", "one")).unwrap();
+        state.insert_message("test2", message!("test2", "two")).unwrap();
+        state.insert_message("test1", message!("test1", "seen")).unwrap();
+        assert_eq!(state.get_conversation("test2").unwrap().unread_count, 0);
+        assert_eq!(state.get_conversation("test2").unwrap().messages.len(), 3);
+
+        state.set_muted("test2", false);
+        state.insert_message("test2", message!("test2", "after")).unwrap();
+        assert_eq!(state.get_conversation("test2").unwrap().unread_count, 1);
+    }
+
+    #[test]
+    fn new_messages_bump_activity() {
+        let mut state = ApplicationStateInner::default();
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_conversation_change().return_const(());
+        obs.expect_on_message().return_const(());
+        obs.expect_on_conversation_updated()
+            .withf(|convo: &Conversation| convo.id == "test1" && convo.data.active_at == 200)
+            .times(1)
+            .return_const(());
+        state.register_observer(Box::new(obs));
+        let mut convo = conversation!("test1");
+        convo.active_at = 100;
+        state.insert_conversation(convo.into());
+        state.insert_conversation(conversation!("test2").into());
+        state.set_current_conversation("test1").unwrap();
+
+        state.insert_message("test1", message!("test1", "new", 2, 200)).unwrap();
+        // arriving late doesn't move it back
+        state.insert_message("test1", message!("test1", "old", 1, 50)).unwrap();
+        assert_eq!(state.get_conversation("test1").unwrap().data.active_at, 200);
+    }
+
+    #[test]
+    fn message_cap() {
+        let mut state = ApplicationStateInner::default().with_max_messages(3);
+        state.insert_conversation(conversation!("test1").into());
+        state.insert_history(
+            "test1",
+            vec![message!("test1", "two", 2), message!("test1", "one", 1)],
+            Some("older".to_string()),
+        );
+        let ids = |state: &ApplicationStateInner| -> Vec<u64> {
+            state.get_conversation("test1").unwrap().messages.iter().map(|m| m.id).collect()
+        };
+
+        state.insert_message("test1", message!("test1", "three", 3)).unwrap();
+        assert_eq!(ids(&state), [3, 2, 1]);
+        assert_eq!(state.get_conversation("test1").unwrap().history_next.as_deref(), Some("older"));
+
+        state.insert_message("test1", message!("test1", "four", 4)).unwrap();
+        state.insert_history("test1", vec![message!("test1", "five", 5)], Some("older".to_string()));
+        assert_eq!(ids(&state), [5, 4, 3]);
+        // the oldest ones are gone, so there's no carrying on from where they were
+        assert_eq!(state.get_conversation("test1").unwrap().history_next, None);
+    }
+
+    #[test]
+    fn duplicate_messages() {
+        let mut state = ApplicationStateInner::default();
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_conversation_change().return_const(());
+        // only told about it once
+        obs.expect_on_message().times(2).return_const(());
+        obs.expect_on_conversation_updated().return_const(());
+        state.register_observer(Box::new(obs));
+        state.insert_conversation(conversation!("test1").into());
+        state.set_current_conversation("test1").unwrap();
+
+        state.insert_message("test1", message!("test1", "hi", 2)).unwrap();
+        state.insert_message("test1", message!("test1", "hi again", 2)).unwrap();
+        state.insert_message("test1", message!("test1", "older", 1)).unwrap();
+        state.insert_history(
+            "test1",
+            vec![message!("test1", "older again", 1), message!("test1", "oldest", 0)],
+            None,
+        );
+
+        let bodies: Vec<String> = state
+            .get_conversation("test1")
+            .unwrap()
+            .messages
+            .iter()
+            .map(|m| match &m.content {
+                MessageType::Text { text } => text.body.clone(),
+                _ => String::new(),
+            })
+            .collect();
+        assert_eq!(bodies, ["hi", "older", "oldest"]);
+    }
+
+    #[test]
+    fn pending_messages_are_confirmed() {
+        let mut state = ApplicationStateInner::default();
+        state.insert_conversation(conversation!("test1").into());
+        state.set_current_conversation("test1").unwrap();
+        let pending = |convo: &Conversation, body: &str| {
+            let mut message = message!("test1", body, convo.next_pending_id());
+            message.delivery = Some(DeliveryState::Sending);
+            message
+        };
+
+        state.insert_message("test1", message!("test1", "earlier", 4)).unwrap();
+        for body in &["same", "same", "other"] {
+            let message = pending(state.get_conversation("test1").unwrap(), body);
+            state.insert_message("test1", message).unwrap();
+        }
+        let ids: Vec<u64> = state.get_conversation("test1").unwrap().messages.iter().map(|m| m.id).collect();
+        let first = FIRST_PENDING_ID;
+        assert_eq!(ids, [first + 2, first + 1, first, 4]);
+
+        // the first "same" comes back, and only replaces one of them
+        state.insert_message("test1", message!("test1", "same", 5)).unwrap();
+        let convo = state.get_conversation("test1").unwrap();
+        let shown: Vec<(u64, bool)> = convo.messages.iter().map(|m| (m.id, m.is_pending())).collect();
+        assert_eq!(shown, [(first + 2, true), (first + 1, true), (5, false), (4, false)]);
+        assert_eq!(convo.next_pending_id(), first + 3);
+
+        // someone else saying the same thing doesn't count
+        let mut echo = message!("test1", "other", 6);
+        echo.sender.username = "alice".to_string();
+        state.insert_message("test1", echo).unwrap();
+        assert_eq!(state.get_conversation("test1").unwrap().messages.len(), 5);
+
+        state.insert_message("test1", message!("test1", "same", 7)).unwrap();
+        state.insert_message("test1", message!("test1", "other", 8)).unwrap();
+        let convo = state.get_conversation("test1").unwrap();
+        assert!(convo.messages.iter().all(|m| !m.is_pending()));
+        assert_eq!(convo.messages.len(), 5);
+        assert_eq!(convo.next_pending_id(), first);
+    }
+
+    #[test]
+    fn delivery_states() {
+        let mut state = ApplicationStateInner::default();
+        state.insert_conversation(conversation!("test1").into());
+        let mut sending = |body: &str| {
+            let convo = state.get_conversation("test1").unwrap();
+            let mut message = message!("test1", body, convo.next_pending_id());
+            message.delivery = Some(DeliveryState::Sending);
+            state.insert_message("test1", message).unwrap();
+        };
+        sending("one");
+        sending("two");
+        let delivery = |state: &ApplicationStateInner, id: u64| {
+            let convo = state.get_conversation("test1").unwrap();
+            convo.messages.iter().find(|m| m.id == id).map(|m| m.delivery)
+        };
+        assert_eq!(delivery(&state, FIRST_PENDING_ID), Some(Some(DeliveryState::Sending)));
+
+        // keybase said which message it is now
+        state.confirm_message("test1", FIRST_PENDING_ID, 10);
+        assert_eq!(delivery(&state, FIRST_PENDING_ID), None);
+        assert_eq!(delivery(&state, 10), Some(Some(DeliveryState::Sent)));
+        // or the listener sent it back first
+        state.insert_message("test1", message!("test1", "two", 11)).unwrap();
+        assert_eq!(delivery(&state, 11), Some(Some(DeliveryState::Sent)));
+
+        // nothing pending with that id, or no such conversation
+        state.confirm_message("test1", FIRST_PENDING_ID + 5, 12);
+        state.confirm_message("unknown", FIRST_PENDING_ID, 12);
+        let ids: Vec<u64> = state.get_conversation("test1").unwrap().messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [11, 10]);
+        // someone else's message was never ours to deliver
+        state.insert_message("test1", message!("test1", "three", 13)).unwrap();
+        assert_eq!(delivery(&state, 13), Some(None));
+    }
+
+    #[test]
+    fn obs_typing() {
+        let mut state = ApplicationStateInner::default();
+
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_username_changed().return_const(());
+        obs.expect_on_typing()
+            .withf(|id: &str, username: &str| id == "test" && username == "alice")
+            .times(1)
+            .return_const(());
+
+        state.register_observer(Box::new(obs));
+        state.insert_conversation(conversation!("test").into());
+        state.set_my_username("me");
+        state.set_typing("test", "alice");
+        state.set_typing("test", "me");
+        state.set_typing("unknown", "alice");
+    }
+
+    #[test]
+    fn set_pinned() {
+        let mut state = ApplicationStateInner::default();
+
+        state.insert_conversation(conversation!("test").into());
+        state.set_pinned("test", 5, true);
+        assert!(state.get_conversation("test").unwrap().pinned.contains(&5));
+
+        state.set_pinned("test", 5, false);
+        assert!(state.get_conversation("test").unwrap().pinned.is_empty());
+    }
+
+    #[test]
+    fn self_conversation() {
+        let mut state = ApplicationStateInner::default();
+        state.set_my_username("me");
+
+        let mut notes = conversation!("notes");
+        notes.channel.name = "me".to_string();
+        let mut dm = conversation!("dm");
+        dm.channel.name = "me,you".to_string();
+        let mut team = conversation!("team");
+        team.channel.name = "me".to_string();
+        team.channel.members_type = MemberType::Team;
+
+        state.set_conversations(vec![notes.into(), dm.into(), team.into()]);
+
+        let notes = state.get_conversation("notes").unwrap();
+        assert!(notes.is_self);
+        assert_eq!(notes.get_name(), "Notes to self");
+        let dm = state.get_conversation("dm").unwrap();
+        assert!(!dm.is_self);
+        assert_eq!(dm.display_name(), "you");
+        assert!(!state.get_conversation("team").unwrap().is_self);
+    }
+
+    #[test]
+    fn self_conversation_before_username() {
+        let mut state = ApplicationStateInner::default();
+
+        let mut notes = conversation!("notes");
+        notes.channel.name = "me".to_string();
+        let mut dm = conversation!("dm");
+        dm.channel.name = "me,you".to_string();
+        state.insert_conversation(notes.into());
+        state.insert_conversation(dm.into());
+        assert!(!state.get_conversation("notes").unwrap().is_self);
+        assert_eq!(state.get_conversation("dm").unwrap().display_name(), "me,you");
+
+        state.set_my_username("me");
+        assert!(state.get_conversation("notes").unwrap().is_self);
+        assert_eq!(state.get_conversation("dm").unwrap().display_name(), "you");
+        assert_eq!(state.get_my_username(), Some("me"));
+    }
+
     // Observer Tests
 
     #[test]
@@ -276,7 +959,7 @@ mod test {
         state.register_observer(Box::new(obs));
 
         state.insert_conversation(test_convo);
-        state.set_current_conversation("test");
+        state.set_current_conversation("test").unwrap();
 
         assert_eq!(state.get_current_conversation().unwrap().id, "test");
     }
@@ -304,6 +987,41 @@ mod test {
         assert!(state.get_current_conversation().is_none())
     }
 
+    #[test]
+    fn replace_conversations() {
+        let mut state = ApplicationStateInner::default();
+        state.set_conversations(vec![conversation!("test1").into()]);
+        state.set_conversations(vec![conversation!("test2").into()]);
+        assert!(state.get_conversation("test1").is_some());
+
+        state.replace_conversations(vec![conversation!("test2").into()]);
+        assert!(state.get_conversation("test1").is_none());
+        assert!(state.get_conversation("test2").is_some());
+    }
+
+    #[test]
+    fn obs_append_conversations() {
+        let mut state = ApplicationStateInner::default();
+
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_conversations_appended()
+            .withf(|convos: &[Conversation], more: &bool| {
+                convos.len() == 1 && convos[0].id == "test2" && !*more
+            })
+            .times(1)
+            .return_const(());
+
+        state.register_observer(Box::new(obs));
+        state.insert_conversation(conversation!("test1").into());
+        // test1 is already there
+        state.append_conversations(
+            vec![conversation!("test1").into(), conversation!("test2").into()],
+            false,
+        );
+
+        assert_eq!(state.get_conversations().count(), 2);
+    }
+
     #[test]
     fn obs_send_message() {
         let mut state = ApplicationStateInner::default();
@@ -312,10 +1030,12 @@ mod test {
         let test_convo2: Conversation = conversation!("test2").into();
 
         let message = Message {
+            id: 1,
             conversation_id: "test1".to_string(),
             content: MessageType::Text {
                 text: MessageBody {
                     body: "My Message".to_string(),
+                    reply_to: None,
                 },
             },
             channel: Channel {
@@ -327,13 +1047,18 @@ mod test {
                 device_name: "My Device".to_string(),
                 username: "Some Guy".to_string(),
             },
+            sent_at: 0,
+            delivery: None,
+            is_ephemeral: false,
         };
 
         let message2 = Message {
+            id: 2,
             conversation_id: "test2".to_string(),
             content: MessageType::Text {
                 text: MessageBody {
                     body: "My Message 2".to_string(),
+                    reply_to: None,
                 },
             },
             channel: Channel {
@@ -345,6 +1070,9 @@ mod test {
                 device_name: "My Device".to_string(),
                 username: "Some Guy".to_string(),
             },
+            sent_at: 0,
+            delivery: None,
+            is_ephemeral: false,
         };
 
         let m1 = message.clone();
@@ -357,13 +1085,18 @@ mod test {
             })
             .times(1)
             .return_const(());
+        inactive_obs
+            .expect_on_conversation_updated()
+            .withf(|convo: &Conversation| convo.id == "test1" && convo.unread_count == 1)
+            .times(1)
+            .return_const(());
 
         state.insert_conversation(test_convo1);
         state.insert_conversation(test_convo2);
-        state.set_current_conversation("test2");
+        state.set_current_conversation("test2").unwrap();
 
         state.register_observer(Box::new(inactive_obs));
-        state.insert_message("test1", message);
+        state.insert_message("test1", message).unwrap();
 
         state.observers.clear();
 
@@ -378,6 +1111,123 @@ mod test {
             .return_const(());
 
         state.register_observer(Box::new(active_obs));
-        state.insert_message("test2", message2);
+        state.insert_message("test2", message2).unwrap();
+    }
+
+    #[test]
+    fn obs_set_pinned() {
+        let mut state = ApplicationStateInner::default();
+
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_conversation_updated()
+            .withf(|convo: &Conversation| convo.id == "test" && convo.pinned.contains(&5))
+            .times(1)
+            .return_const(());
+
+        state.register_observer(Box::new(obs));
+        state.insert_conversation(conversation!("test").into());
+        state.set_pinned("test", 5, true);
+    }
+
+    #[test]
+    fn obs_remove_message() {
+        let mut state = ApplicationStateInner::default();
+
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_message().times(2).return_const(());
+        obs.expect_on_conversation_updated()
+            .withf(|convo: &Conversation| convo.id == "test" && convo.messages.len() == 1)
+            .times(1)
+            .return_const(());
+
+        obs.expect_on_conversation_change().return_const(());
+
+        state.register_observer(Box::new(obs));
+        state.insert_conversation(conversation!("test").into());
+        state.set_current_conversation("test").unwrap();
+        state.insert_message("test", message!("test", "oops", 1)).unwrap();
+        state.insert_message("test", message!("test", "hi", 2)).unwrap();
+        state.get_conversation_mut("test").unwrap().pinned.insert(1);
+
+        let mut delete = message!("test", "", 3);
+        delete.content = MessageType::Delete {
+            delete: MessageDelete {
+                message_ids: vec![1],
+            },
+        };
+        state.insert_message("test", delete).unwrap();
+        // already gone, nothing to tell anyone
+        state.remove_message("test", 1);
+
+        let convo = state.get_conversation("test").unwrap();
+        let ids: Vec<u64> = convo.messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [2]);
+        assert!(convo.pinned.is_empty());
+    }
+
+    #[test]
+    fn obs_set_topic_name() {
+        let mut state = ApplicationStateInner::default();
+
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_conversation_updated()
+            .withf(|convo: &Conversation| convo.id == "test" && convo.data.channel.topic_name == "random")
+            .times(1)
+            .return_const(());
+
+        state.register_observer(Box::new(obs));
+        state.insert_conversation(conversation!("test").into());
+        state.set_topic_name("test", "random");
+
+        let convo = state.get_conversation("test").unwrap();
+        assert_eq!(convo.data.channel.topic_name, "random");
+    }
+
+    #[test]
+    fn hide_and_unhide() {
+        let mut state = ApplicationStateInner::default();
+
+        let mut obs = MockStateObserver::new();
+        let mut seq = Sequence::new();
+        obs.expect_on_conversation_updated()
+            .withf(|convo: &Conversation| convo.id == "test" && convo.hidden)
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+        obs.expect_on_conversation_updated()
+            .withf(|convo: &Conversation| convo.id == "test" && !convo.hidden)
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(());
+
+        state.register_observer(Box::new(obs));
+        state.insert_conversation(conversation!("test").into());
+        assert!(!state.get_conversation("test").unwrap().hidden);
+
+        state.set_hidden("test", true);
+        // already hidden, nothing changes
+        state.set_hidden("test", true);
+        assert!(state.get_conversation("test").unwrap().hidden);
+        assert!(state.get_conversation("test").is_some());
+
+        state.set_hidden("test", false);
+        assert!(!state.get_conversation("test").unwrap().hidden);
+
+        // unknown conversations are ignored
+        state.set_hidden("nope", true);
+    }
+
+    #[test]
+    fn obs_username_changed() {
+        let mut state = ApplicationStateInner::default();
+
+        let mut obs = MockStateObserver::new();
+        obs.expect_on_username_changed()
+            .withf(|username: &str| username == "me")
+            .times(1)
+            .return_const(());
+
+        state.register_observer(Box::new(obs));
+        state.set_my_username("me");
     }
 }
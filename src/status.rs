@@ -0,0 +1,196 @@
+// # status.rs
+//
+// The status bar under the chat, built from a template in the config, e.g.
+// `{connection} | {user} | {conversation} | {unread} unread | {latency} | {time}`.
+
+use std::time::Duration;
+
+use cursive::theme::{BaseColor, Color};
+use cursive::utils::markup::StyledString;
+
+pub const DEFAULT_TEMPLATE: &str =
+    "{connection} | {user} | {conversation} | {unread} unread | {latency} | {time}";
+
+const TOKENS: &[&str] = &["connection", "user", "conversation", "unread", "latency", "time"];
+
+// api calls slower than these show up yellow/red in the status bar
+const LATENCY_WARN: Duration = Duration::from_millis(300);
+const LATENCY_BAD: Duration = Duration::from_millis(1000);
+
+// How talking to keybase is going.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ConnectionStatus {
+    #[default]
+    Connected,
+    // the chat listener went away, and a new one is on its way
+    Reconnecting,
+    // keybase couldn't be reached for an api call
+    Error,
+}
+
+// Everything the status bar can show.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StatusInfo {
+    pub user: String,
+    pub conversation: String,
+    // conversations with unread messages
+    pub unread: usize,
+    // how long the last keybase api call took
+    pub latency: Option<Duration>,
+    pub time: String,
+    // who reacted with the focused reaction, shown instead of the template while there is one
+    pub reactors: Option<String>,
+    pub connection: ConnectionStatus,
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+    Text(&'a str),
+    Token(&'a str),
+}
+
+fn parse(template: &str) -> Result<Vec<Segment<'_>>, String> {
+    let mut segments = vec![];
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            segments.push(Segment::Text(&rest[..start]));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed `{{` in status bar template: {}", template))?;
+        segments.push(Segment::Token(&rest[start + 1..start + end]));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    Ok(segments)
+}
+
+// Check the template only uses tokens we know about, so typos show up when the config is loaded
+// rather than in the status bar.
+pub fn validate_template(template: &str) -> Result<(), String> {
+    for segment in parse(template)? {
+        if let Segment::Token(token) = segment {
+            if !TOKENS.contains(&token) {
+                return Err(format!("Unknown status bar token: {{{}}}", token));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn render_latency(latency: Option<Duration>) -> StyledString {
+    let latency = match latency {
+        Some(latency) => latency,
+        None => return StyledString::plain("↔ -"),
+    };
+    let color = if latency >= LATENCY_BAD {
+        BaseColor::Red
+    } else if latency >= LATENCY_WARN {
+        BaseColor::Yellow
+    } else {
+        BaseColor::Green
+    };
+    StyledString::styled(
+        format!("↔ {}ms", latency.as_millis()),
+        Color::Dark(color),
+    )
+}
+
+fn render_connection(connection: ConnectionStatus) -> StyledString {
+    let (text, color) = match connection {
+        ConnectionStatus::Connected => ("● connected", BaseColor::Green),
+        ConnectionStatus::Reconnecting => ("◌ reconnecting", BaseColor::Yellow),
+        ConnectionStatus::Error => ("✗ keybase unreachable", BaseColor::Red),
+    };
+    StyledString::styled(text, Color::Dark(color))
+}
+
+pub fn render_status(template: &str, info: &StatusInfo) -> StyledString {
+    let segments = match parse(template) {
+        Ok(segments) => segments,
+        // shouldn't happen, templates are validated when the config is loaded
+        Err(_) => return StyledString::plain(template),
+    };
+    let mut status = StyledString::new();
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => status.append_plain(text),
+            Segment::Token("connection") => status.append(render_connection(info.connection)),
+            Segment::Token("user") => status.append_plain(&info.user),
+            Segment::Token("conversation") => status.append_plain(&info.conversation),
+            Segment::Token("unread") => status.append_plain(info.unread.to_string()),
+            Segment::Token("latency") => status.append(render_latency(info.latency)),
+            Segment::Token("time") => status.append_plain(&info.time),
+            Segment::Token(token) => status.append_plain(format!("{{{}}}", token)),
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn info() -> StatusInfo {
+        StatusInfo {
+            user: "alice".to_string(),
+            conversation: "team#general".to_string(),
+            unread: 3,
+            latency: Some(Duration::from_millis(120)),
+            time: "12:34".to_string(),
+            reactors: None,
+            connection: ConnectionStatus::Connected,
+        }
+    }
+
+    #[test]
+    fn substitutes_tokens() {
+        assert_eq!(
+            render_status(DEFAULT_TEMPLATE, &info()).source(),
+            "● connected | alice | team#general | 3 unread | ↔ 120ms | 12:34"
+        );
+        assert_eq!(render_status("[{time}]{user}", &info()).source(), "[12:34]alice");
+        assert_eq!(render_status("no tokens", &info()).source(), "no tokens");
+        assert_eq!(render_status("", &info()).source(), "");
+    }
+
+    #[test]
+    fn colors_latency() {
+        let color = |millis| {
+            let latency = render_latency(Some(Duration::from_millis(millis)));
+            let color = latency.spans().next().unwrap().attr.color;
+            color
+        };
+        assert_eq!(color(120), Some(Color::Dark(BaseColor::Green).into()));
+        assert_eq!(color(500), Some(Color::Dark(BaseColor::Yellow).into()));
+        assert_eq!(color(2000), Some(Color::Dark(BaseColor::Red).into()));
+        assert_eq!(render_latency(None).source(), "↔ -");
+    }
+
+    #[test]
+    fn shows_connection() {
+        let mut info = info();
+        info.connection = ConnectionStatus::Reconnecting;
+        let status = render_status("{connection} {user}", &info);
+        assert_eq!(status.source(), "◌ reconnecting alice");
+        let color = status.spans().next().unwrap().attr.color;
+        assert_eq!(color, Some(Color::Dark(BaseColor::Yellow).into()));
+
+        info.connection = ConnectionStatus::Error;
+        assert_eq!(render_status("{connection}", &info).source(), "✗ keybase unreachable");
+    }
+
+    #[test]
+    fn validates_tokens() {
+        assert!(validate_template(DEFAULT_TEMPLATE).is_ok());
+        assert!(validate_template("").is_ok());
+        assert_eq!(
+            validate_template("{user} {battery}").unwrap_err(),
+            "Unknown status bar token: {battery}"
+        );
+        assert!(validate_template("{user").is_err());
+    }
+}
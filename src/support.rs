@@ -0,0 +1,188 @@
+// # support.rs
+//
+// An optional trace of every command sent to the Keybase API and the response that came back (or
+// the error, when none did), written as JSON lines into a single file that users can attach to bug reports. This is the
+// structured version of the debug logging in `client.rs`, and works in release builds too.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+// so a long session can't fill up the disk
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+const REDACTED: &str = "<redacted>";
+
+pub struct SupportBundle<W: Write = File> {
+    inner: Mutex<BundleWriter<W>>,
+    redact: bool,
+    max_bytes: u64,
+}
+
+struct BundleWriter<W: Write> {
+    writer: W,
+    written: u64,
+    // set once we hit the size cap, after which everything is dropped
+    full: bool,
+}
+
+impl SupportBundle<File> {
+    pub fn create(path: &Path, redact: bool, max_bytes: u64) -> io::Result<Self> {
+        Ok(SupportBundle::new(File::create(path)?, redact, max_bytes))
+    }
+}
+
+impl<W: Write> SupportBundle<W> {
+    pub fn new(writer: W, redact: bool, max_bytes: u64) -> Self {
+        SupportBundle {
+            inner: Mutex::new(BundleWriter {
+                writer,
+                written: 0,
+                full: false,
+            }),
+            redact,
+            max_bytes,
+        }
+    }
+
+    pub fn record_request(&self, command: &Value) {
+        self.record("request", command);
+    }
+
+    pub fn record_response(&self, response: &Value) {
+        self.record("response", response);
+    }
+
+    // a request that didn't get a response (or not one that could be read)
+    pub fn record_error(&self, error: &str) {
+        self.record("error", &Value::String(error.to_string()));
+    }
+
+    fn record(&self, kind: &str, value: &Value) {
+        let mut value = value.clone();
+        if self.redact {
+            redact_bodies(&mut value);
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let mut line = json!({
+            "kind": kind,
+            "timestamp_ms": timestamp,
+            "data": value
+        })
+        .to_string();
+        line.push('\n');
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.full {
+            return;
+        }
+        if inner.written + line.len() as u64 > self.max_bytes {
+            warn!(
+                "Support bundle reached {} bytes, not recording any more",
+                self.max_bytes
+            );
+            inner.full = true;
+            return;
+        }
+        match inner
+            .writer
+            .write_all(line.as_bytes())
+            .and_then(|_| inner.writer.flush())
+        {
+            Ok(()) => inner.written += line.len() as u64,
+            Err(e) => error!("Failed to write to support bundle: {}", e),
+        }
+    }
+}
+
+// message bodies live under a "body" key in both commands (`send`) and responses (`read`)
+fn redact_bodies(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                if key == "body" && v.is_string() {
+                    *v = Value::String(REDACTED.to_string());
+                } else {
+                    redact_bodies(v);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_bodies),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(bundle: SupportBundle<Vec<u8>>) -> Vec<Value> {
+        let written = bundle.inner.into_inner().unwrap().writer;
+        String::from_utf8(written)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn records_in_order() {
+        let bundle = SupportBundle::new(vec![], false, DEFAULT_MAX_BYTES);
+        bundle.record_request(&json!({"method": "list"}));
+        bundle.record_response(&json!({"result": {"conversations": []}}));
+        bundle.record_request(&json!({"method": "read"}));
+        bundle.record_response(&json!({"result": {"messages": []}}));
+        bundle.record_request(&json!({"method": "send"}));
+        bundle.record_error("keybase exited without answering");
+
+        let lines = lines(bundle);
+        let kinds: Vec<&str> = lines.iter().map(|l| l["kind"].as_str().unwrap()).collect();
+        assert_eq!(kinds, vec!["request", "response", "request", "response", "request", "error"]);
+        assert_eq!(lines[0]["data"], json!({"method": "list"}));
+        assert_eq!(lines[2]["data"], json!({"method": "read"}));
+        assert_eq!(lines[3]["data"], json!({"result": {"messages": []}}));
+        assert_eq!(lines[5]["data"], "keybase exited without answering");
+    }
+
+    #[test]
+    fn redacts_bodies() {
+        let bundle = SupportBundle::new(vec![], true, DEFAULT_MAX_BYTES);
+        bundle.record_request(&json!({
+            "method": "send",
+            "params": {"options": {"message": {"body": "secret"}}}
+        }));
+        bundle.record_response(&json!({
+            "result": {"messages": [{"msg": {"content": {"text": {"body": "secret"}}}}]}
+        }));
+
+        let lines = lines(bundle);
+        assert_eq!(
+            lines[0]["data"]["params"]["options"]["message"]["body"],
+            REDACTED
+        );
+        assert_eq!(
+            lines[1]["data"]["result"]["messages"][0]["msg"]["content"]["text"]["body"],
+            REDACTED
+        );
+        assert_eq!(lines[0]["data"]["method"], "send");
+    }
+
+    #[test]
+    fn stops_at_size_cap() {
+        let bundle = SupportBundle::new(vec![], false, 100);
+        bundle.record_request(&json!({"method": "list"}));
+        bundle.record_response(&json!({"result": {"conversations": ["a very long response"]}}));
+        bundle.record_request(&json!({"method": "list"}));
+
+        let lines = lines(bundle);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["kind"], "request");
+    }
+}
@@ -0,0 +1,118 @@
+// # thread.rs
+//
+// Orders messages as a shallow tree for the `layout.threaded` option: each reply goes straight
+// under the message it replies to, indented one level deeper. Replies nested deeper than
+// `MAX_DEPTH`, or to messages we don't have, are drawn flat where they were sent.
+
+use std::collections::HashMap;
+
+use crate::types::{Message, MessageType};
+
+pub const MAX_DEPTH: usize = 3;
+
+fn reply_to(message: &Message) -> Option<u64> {
+    match &message.content {
+        MessageType::Text { text } => text.reply_to,
+        _ => None,
+    }
+}
+
+// Takes messages oldest first and returns them in display order as (index into `messages`,
+// depth), with depth 0 for messages that aren't drawn as a reply.
+pub fn build_thread(messages: &[&Message]) -> Vec<(usize, usize)> {
+    let positions: HashMap<u64, usize> =
+        messages.iter().enumerate().map(|(i, m)| (m.id, i)).collect();
+
+    // work out where everything hangs first, so the tree can be walked in one go after
+    let mut depths = vec![0; messages.len()];
+    let mut children: Vec<Vec<usize>> = vec![vec![]; messages.len()];
+    let mut roots = vec![];
+    for (index, message) in messages.iter().enumerate() {
+        // only earlier messages can be parents, which also rules out cycles
+        let parent = reply_to(message)
+            .and_then(|id| positions.get(&id).copied())
+            .filter(|&parent| parent < index && depths[parent] < MAX_DEPTH);
+        match parent {
+            Some(parent) => {
+                depths[index] = depths[parent] + 1;
+                children[parent].push(index);
+            }
+            None => roots.push(index),
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(messages.len());
+    let mut stack: Vec<usize> = roots.into_iter().rev().collect();
+    while let Some(index) = stack.pop() {
+        ordered.push((index, depths[index]));
+        stack.extend(children[index].iter().rev());
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message;
+    use crate::types::{Channel, MemberType, MessageBody};
+
+    fn reply(id: u64, to: u64) -> Message {
+        let mut message = message!("test1", format!("reply {}", id), id);
+        if let MessageType::Text { text } = &mut message.content {
+            text.reply_to = Some(to);
+        }
+        message
+    }
+
+    fn thread(messages: &[Message]) -> Vec<(u64, usize)> {
+        let messages: Vec<&Message> = messages.iter().collect();
+        build_thread(&messages)
+            .into_iter()
+            .map(|(index, depth)| (messages[index].id, depth))
+            .collect()
+    }
+
+    #[test]
+    fn flat_without_replies() {
+        let messages = vec![message!("test1", "a", 1), message!("test1", "b", 2)];
+        assert_eq!(thread(&messages), [(1, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn replies_go_under_their_parent() {
+        let messages = vec![
+            message!("test1", "question", 1),
+            message!("test1", "unrelated", 2),
+            reply(3, 1),
+            reply(4, 3),
+            reply(5, 1),
+            reply(6, 2),
+        ];
+        assert_eq!(
+            thread(&messages),
+            [(1, 0), (3, 1), (4, 2), (5, 1), (2, 0), (6, 1)]
+        );
+    }
+
+    #[test]
+    fn deep_chains_fall_back_to_flat() {
+        let messages = vec![
+            message!("test1", "root", 1),
+            reply(2, 1),
+            reply(3, 2),
+            reply(4, 3),
+            reply(5, 4),
+            reply(6, 5),
+        ];
+        assert_eq!(
+            thread(&messages),
+            [(1, 0), (2, 1), (3, 2), (4, 3), (5, 0), (6, 1)]
+        );
+    }
+
+    #[test]
+    fn missing_parents_are_roots() {
+        let messages = vec![reply(2, 1), message!("test1", "b", 3)];
+        assert_eq!(thread(&messages), [(2, 0), (3, 0)]);
+    }
+}
@@ -5,7 +5,9 @@
 //
 // A lot of these were just trial and error while using the Keybase API and fixing serialization
 // errors.
+use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
@@ -13,28 +15,89 @@ use serde::{Deserialize, Serialize};
 #[serde(tag = "type")]
 pub enum ListenerEvent {
     #[serde(rename = "chat")]
-    ChatMessage(MessageWrapper),
+    ChatMessage(Box<MessageWrapper>),
+    // someone started typing in a conversation
+    #[serde(rename = "typing")]
+    Typing {
+        conversation_id: String,
+        username: String,
+    },
+    // not from keybase: the listener died and a new one has started. (Skipping it trips up
+    // serde next to `other`, so it gets a name keybase doesn't use instead.)
+    #[serde(rename = "listener_reconnected")]
+    ListenerReconnected,
+    // not from keybase either: the listener died, and a new one is about to be started
+    #[serde(rename = "listener_disconnected")]
+    ListenerDisconnected,
+    // any other kind of event, which we don't do anything with yet
+    #[serde(other)]
+    Unknown,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ApiResponseWrapper {
     pub result: ApiResponse,
 }
 
+// what the api sends back instead of a `result` when a command fails
 #[derive(Clone, Debug, Deserialize)]
+pub struct ApiErrorWrapper {
+    pub error: ApiError,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ApiError {
+    pub code: i32,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ApiResponse {
     ConversationList {
         conversations: Vec<KeybaseConversation>,
+        #[serde(default)]
+        pagination: Option<Pagination>,
     },
+    // kept as raw values so that one message we can't parse doesn't lose the whole batch
     MessageList {
-        messages: Vec<MessageWrapper>,
+        messages: Vec<serde_json::Value>,
+        #[serde(default)]
+        pagination: Option<Pagination>,
     },
+    // `message` is just "message sent", `id` is the new message's (not there for `nonblock` sends)
     MessageSent {
         message: String,
+        #[serde(default)]
+        id: Option<u64>,
     },
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Pagination {
+    // token for fetching the next page (older messages, or less active conversations)
+    pub next: String,
+    // this was the last page
+    pub last: bool,
+}
+
+// One page of a conversation's history, newest first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MessagePage {
+    pub messages: Vec<Message>,
+    // pass to `fetch_message_page` for the next (older) page, None if this was the oldest
+    pub next: Option<String>,
+}
+
+// Some of the conversation list, most recently active first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConversationPage {
+    pub conversations: Vec<KeybaseConversation>,
+    // pass to `fetch_conversation_page` for the next page, None if this was the last
+    pub next: Option<String>,
+}
+
 #[derive(Hash, PartialOrd, Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum MemberType {
     #[serde(rename = "impteamnative")]
@@ -51,51 +114,337 @@ pub struct Channel {
     pub members_type: MemberType,
 }
 
-#[derive(Hash, Eq, PartialOrd, PartialEq, Clone, Debug, Deserialize)]
+impl Channel {
+    // the implicit team with just yourself in it, which Keybase uses for notes to self
+    pub fn is_self_conversation(&self, my_username: &str) -> bool {
+        self.members_type == MemberType::User && self.name == my_username
+    }
+
+    // Everyone in a conversation between users, including you, e.g. `alice`, `bob` and `carol`
+    // for `alice,bob#carol` (people after the `#` can only read). Empty for teams, whose members
+    // aren't in the name.
+    pub fn participants(&self) -> Vec<String> {
+        if self.members_type != MemberType::User {
+            return vec![];
+        }
+        self.name
+            .split([',', '#'])
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    // The other people in a conversation between users, e.g. `bob` for `alice,bob` when you're
+    // alice. None for teams, and for the conversation with only yourself in it.
+    pub fn other_members(&self, my_username: &str) -> Option<String> {
+        if self.members_type != MemberType::User {
+            return None;
+        }
+        let others: Vec<&str> = self
+            .name
+            .split(',')
+            .filter(|name| !name.eq_ignore_ascii_case(my_username))
+            .collect();
+        if others.is_empty() {
+            return None;
+        }
+        Some(others.join(","))
+    }
+}
+
+#[derive(Hash, Eq, PartialOrd, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct KeybaseConversation {
     pub id: String,
     pub channel: Channel,
     pub unread: bool,
+    // when the last message was sent (unix time), which new messages move along
+    #[serde(default)]
+    pub active_at: i64,
 }
 
-#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct MessageBody {
     pub body: String,
+    // id of the message this one replies to
+    #[serde(default, rename = "replyTo", skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<u64>,
 }
 
-#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum MessageType {
     #[serde(rename = "join")]
     Join,
+    #[serde(rename = "leave")]
+    Leave,
     #[serde(rename = "attachment")]
-    Attachment {},
+    Attachment {
+        #[serde(default)]
+        attachment: MessageAttachment,
+    },
     #[serde(rename = "metadata")]
     Metadata {},
     #[serde(rename = "system")]
-    System {},
+    System {
+        #[serde(default)]
+        system: MessageSystem,
+    },
     #[serde(rename = "text")]
     Text { text: MessageBody },
     #[serde(rename = "unfurl")]
-    Unfurl {},
+    Unfurl {
+        #[serde(default)]
+        unfurl: MessageUnfurl,
+    },
     #[serde(rename = "reaction")]
-    Reaction {},
+    Reaction {
+        #[serde(default)]
+        reaction: MessageReaction,
+    },
+    #[serde(rename = "edit")]
+    Edit {
+        #[serde(default)]
+        edit: MessageEdit,
+    },
+    #[serde(rename = "delete")]
+    Delete {
+        #[serde(default)]
+        delete: MessageDelete,
+    },
 }
 
-#[derive(Clone, Debug, Deserialize)]
+// A reaction to another message. Keybase abbreviates the field names.
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MessageReaction {
+    // id of the message reacted to
+    #[serde(rename = "m")]
+    pub message_id: u64,
+    // the emoji, e.g. `:+1:`
+    #[serde(rename = "b")]
+    pub body: String,
+}
+
+// A new body for a message that was already sent.
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MessageEdit {
+    // id of the message edited
+    #[serde(rename = "messageID")]
+    pub message_id: u64,
+    pub body: String,
+}
+
+// Messages that were deleted (usually just the one).
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MessageDelete {
+    #[serde(rename = "messageIDs")]
+    pub message_ids: Vec<u64>,
+}
+
+// Things keybase says happened in a conversation, like someone being added to the team. Which one
+// it is depends on which of the fields is there, the rest we don't know how to describe.
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageSystem {
+    #[serde(rename = "systemType")]
+    pub system_type: u32,
+    #[serde(rename = "addedtoteam", skip_serializing_if = "Option::is_none")]
+    pub added_to_team: Option<SystemAddedToTeam>,
+    #[serde(rename = "bulkaddtoconv", skip_serializing_if = "Option::is_none")]
+    pub bulk_add_to_conv: Option<SystemBulkAddToConv>,
+    #[serde(rename = "createteam", skip_serializing_if = "Option::is_none")]
+    pub create_team: Option<SystemCreateTeam>,
+    #[serde(rename = "newchannel", skip_serializing_if = "Option::is_none")]
+    pub new_channel: Option<SystemNewChannel>,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SystemAddedToTeam {
+    pub team: String,
+    pub adder: String,
+    pub addee: String,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SystemBulkAddToConv {
+    pub usernames: Vec<String>,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SystemCreateTeam {
+    pub team: String,
+    pub creator: String,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SystemNewChannel {
+    pub creator: String,
+    #[serde(rename = "nameAtCreation")]
+    pub name_at_creation: String,
+}
+
+impl MessageSystem {
+    // e.g. `alice added bob to the team`
+    pub fn text(&self) -> String {
+        if let Some(added) = &self.added_to_team {
+            return format!("{} added {} to {}", added.adder, added.addee, added.team);
+        }
+        if let Some(bulk) = &self.bulk_add_to_conv {
+            return format!("{} added to the channel", bulk.usernames.join(", "));
+        }
+        if let Some(created) = &self.create_team {
+            return format!("{} created {}", created.creator, created.team);
+        }
+        if let Some(channel) = &self.new_channel {
+            return format!("{} created #{}", channel.creator, channel.name_at_creation);
+        }
+        format!("Keybase system message (type {})", self.system_type)
+    }
+}
+
+// Link previews (including giphy). Everything is optional, so shapes we don't know about still
+// deserialize and just render less nicely.
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageUnfurl {
+    pub unfurl: UnfurlResult,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UnfurlResult {
+    pub url: String,
+    pub unfurl: Unfurl,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Unfurl {
+    pub generic: Option<UnfurlGeneric>,
+    pub giphy: Option<UnfurlGiphy>,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UnfurlGeneric {
+    pub title: String,
+    pub url: String,
+    #[serde(rename = "siteName")]
+    pub site_name: String,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UnfurlGiphy {
+    #[serde(rename = "imageUrl")]
+    pub image_url: Option<String>,
+    #[serde(rename = "videoUrl")]
+    pub video_url: Option<String>,
+}
+
+impl UnfurlResult {
+    // giphy doesn't send a title, but the page url ends in one, e.g.
+    // `https://giphy.com/gifs/happy-dance-l0MYt5jPR6QX5pnqM` -> `happy dance`
+    pub fn giphy_title(&self) -> String {
+        let slug = self
+            .url
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or("");
+        let mut words: Vec<&str> = slug.split('-').collect();
+        // the last part is giphy's id
+        words.pop();
+        words.join(" ")
+    }
+}
+
+// Files sent to a conversation. We can't show them, but the metadata says what they are.
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageAttachment {
+    pub object: AttachmentObject,
+    pub uploaded: bool,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AttachmentObject {
+    pub filename: String,
+    pub title: String,
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+    pub size: u64,
+    pub metadata: AttachmentMetadata,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AttachmentMetadata {
+    pub image: Option<Dimensions>,
+    pub video: Option<Dimensions>,
+}
+
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Dimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AttachmentObject {
+    // only images and videos have dimensions
+    pub fn dimensions(&self) -> Option<&Dimensions> {
+        self.metadata.image.as_ref().or(self.metadata.video.as_ref())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MessageWrapper {
     pub msg: Message,
 }
 
-#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct Message {
+    pub id: u64,
     pub channel: Channel,
     pub content: MessageType,
     pub sender: Sender,
     pub conversation_id: String,
+    // unix timestamp (seconds) of when it was sent, 0 if we don't know
+    #[serde(default)]
+    pub sent_at: i64,
+    // how far along a message sent from here is, None for everything else
+    #[serde(skip)]
+    pub delivery: Option<DeliveryState>,
+    // an exploding message, gone once its lifetime is up (see `/explode`)
+    #[serde(default)]
+    pub is_ephemeral: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeliveryState {
+    // shown straight away, until keybase says it went (with its real id)
+    Sending,
+    Sent,
 }
 
-#[derive(PartialEq, Clone, Debug, Deserialize)]
+impl Message {
+    pub fn is_pending(&self) -> bool {
+        self.delivery == Some(DeliveryState::Sending)
+    }
+}
+
+// where ids for messages that haven't been sent yet start
+pub const FIRST_PENDING_ID: u64 = 1 << 63;
+
+// past this many, the oldest messages in a conversation are dropped
+pub const DEFAULT_MAX_MESSAGES: usize = 500;
+
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct Sender {
     pub username: String,
     pub device_name: String,
@@ -103,7 +452,42 @@ pub struct Sender {
 
 pub enum UiEvent {
     SendMessage(String),
+    // a message that explodes `lifetime_secs` after it's sent
+    SendExploding { body: String, lifetime_secs: u64 },
     SwitchConversation(String),
+    PinMessage(u64),
+    UnpinMessage(u64),
+    RenameChannel(String),
+    // hide the current conversation from the sidebar
+    HideConversation,
+    UnhideConversation(String),
+    // mute a conversation, or unmute it if it's muted
+    ToggleMute { conversation_id: String },
+    // (conversation id, message id) of the newest message seen
+    MarkRead(String, u64),
+    // (message id, emoji)
+    React(u64, String),
+    // (message id, text)
+    Reply(u64, String),
+    // (message id, new text) for one of your own messages
+    EditMessage(u64, String),
+    DeleteMessage(u64),
+    // fetch the next page of older messages for a conversation
+    LoadMore(String),
+    // (conversation id, message id) to open, loading older messages until it's there
+    GotoMessage(String, u64),
+    // join a team channel that isn't in the sidebar and switch to it
+    OpenChannel(Channel),
+    // fetch the next page of conversations (see `startup_conversation_limit`)
+    LoadMoreConversations,
+    // start a conversation with some users (`alice,bob`), or open a team channel (`team#channel`)
+    NewConversation { name: String, members_type: MemberType },
+    // save an attachment in the current conversation to `download_dir`
+    DownloadAttachment { message_id: u64 },
+    // fetch the channels in a team, to pick one to switch to
+    ListChannels(String),
+    // write the current conversation's messages to a file, one JSON message per line
+    ExportConversation { path: PathBuf },
 }
 
 #[derive(Clone, Debug)]
@@ -114,6 +498,26 @@ pub struct Conversation {
     pub fetched: bool,
     // messages we got from the API
     pub messages: Vec<Message>,
+    // ids of the messages pinned in this conversation
+    pub pinned: HashSet<u64>,
+    // whether this is the conversation with only yourself in it
+    pub is_self: bool,
+    // everyone but you, for conversations between users (set once we know who you are)
+    pub other_members: Option<String>,
+    // left out of the sidebar (see `/hide`)
+    pub hidden: bool,
+    // new messages don't make it unread or send notifications (see `/mute`)
+    pub muted: bool,
+    // how many people are in the team, for team channels (see `layout.member_counts`)
+    pub member_count: Option<usize>,
+    // everyone in it, including you. For teams this is empty until the conversation is opened.
+    pub participants: Vec<String>,
+    // pagination cursor for the next (older) page of messages, None once we've got them all
+    pub history_next: Option<String>,
+    // messages from other people since the conversation was last open
+    pub unread_count: u32,
+    // the most messages kept, see `max_messages` in the config
+    pub max_messages: usize,
 
     pub data: KeybaseConversation,
 }
@@ -134,28 +538,204 @@ impl Hash for Conversation {
 }
 
 impl Conversation {
-    // put the message at the beginning (messages are in time-descending order)
-    pub fn insert_message(&mut self, message: Message) {
-        self.messages.insert(0, message);
+    // Messages are kept newest first, by id. A new message almost always goes at the beginning,
+    // but one that arrives late still ends up where it belongs.
+    // A message we already have (e.g. from catching up after the listener reconnects) is left
+    // as it is.
+    pub fn insert_message(&mut self, mut message: Message) {
+        if self.has_message(message.id) {
+            return;
+        }
+        // the real thing takes the place of what was shown while it was being sent
+        if let Some(index) = self.pending_position(&message) {
+            self.messages.remove(index);
+            message.delivery = Some(DeliveryState::Sent);
+        }
+        let index = self
+            .messages
+            .iter()
+            .position(|m| m.id < message.id)
+            .unwrap_or(self.messages.len());
+        self.messages.insert(index, message);
+        self.drop_oldest();
+    }
+
+    // Past `max_messages` the oldest ones go. After that the pagination cursor would skip over
+    // what was dropped, so there's no loading older messages any more.
+    fn drop_oldest(&mut self) {
+        if self.messages.len() > self.max_messages {
+            self.messages.truncate(self.max_messages);
+            self.history_next = None;
+        }
+    }
+
+    // a lower limit drops what's over it straight away
+    pub fn set_max_messages(&mut self, max_messages: usize) {
+        self.max_messages = max_messages;
+        self.drop_oldest();
+    }
+
+    // a page of older messages, and where the page before it starts
+    pub fn insert_history(&mut self, messages: Vec<Message>, next: Option<String>) {
+        self.history_next = next;
+        self.insert_messages(messages);
+    }
+
+    // Start again from a fresh page of the newest messages, for when there may be a gap between it
+    // and what we had. Anything still being sent stays, and what was marked sent still is.
+    pub fn reset_history(&mut self, messages: Vec<Message>, next: Option<String>) {
+        let sent: Vec<u64> = self
+            .messages
+            .iter()
+            .filter(|m| m.delivery == Some(DeliveryState::Sent))
+            .map(|m| m.id)
+            .collect();
+        self.messages.retain(|m| m.is_pending());
+        self.insert_history(messages, next);
+        for message in self.messages.iter_mut().filter(|m| sent.contains(&m.id)) {
+            message.delivery = Some(DeliveryState::Sent);
+        }
+    }
+
+    pub fn has_message(&self, message_id: u64) -> bool {
+        self.messages.iter().any(|m| m.id == message_id)
     }
 
+    // Where the pending message that `message` is the real one of is. Same sender and text is
+    // close enough, and the oldest one goes first if the same thing was sent twice.
+    fn pending_position(&self, message: &Message) -> Option<usize> {
+        let body = match &message.content {
+            MessageType::Text { text } if !message.is_pending() => &text.body,
+            _ => return None,
+        };
+        self.messages.iter().rposition(|m| {
+            m.is_pending()
+                && m.sender.username == message.sender.username
+                && matches!(&m.content, MessageType::Text { text } if &text.body == body)
+        })
+    }
+
+    // whether `message` is the real one of a message still shown as pending
+    pub fn replaces_pending(&self, message: &Message) -> bool {
+        self.pending_position(message).is_some()
+    }
+
+    // The pending message `pending_id` went, and is message `message_id` now. Returns whether it
+    // was still pending, it's already gone if keybase sent the message back first.
+    pub fn confirm_pending(&mut self, pending_id: u64, message_id: u64) -> bool {
+        if self.has_message(message_id) {
+            return self.remove_message(pending_id);
+        }
+        let message = self.messages.iter_mut().find(|m| m.is_pending() && m.id == pending_id);
+        match message {
+            Some(message) => {
+                message.id = message_id;
+                message.delivery = Some(DeliveryState::Sent);
+            }
+            None => return false,
+        }
+        self.messages.sort_by_key(|m| std::cmp::Reverse(m.id));
+        true
+    }
+
+    // Pending messages get ids well above any real one, so they can't be mistaken for one and
+    // stay at the bottom of the chat until they've gone.
+    pub fn next_pending_id(&self) -> u64 {
+        self.messages
+            .iter()
+            .filter(|m| m.is_pending())
+            .map(|m| m.id + 1)
+            .max()
+            .unwrap_or(FIRST_PENDING_ID)
+    }
+
+    // Replace the text of a message we have, returning whether there was one to change.
+    pub fn apply_edit(&mut self, edit: &MessageEdit) -> bool {
+        let message = self.messages.iter_mut().find(|m| m.id == edit.message_id);
+        match message.map(|m| &mut m.content) {
+            Some(MessageType::Text { text }) => {
+                text.body = edit.body.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // returns whether we had the message
+    pub fn remove_message(&mut self, message_id: u64) -> bool {
+        let count = self.messages.len();
+        self.messages.retain(|m| m.id != message_id);
+        self.messages.len() != count
+    }
+
+    // merge a batch of messages (in any order) in with the ones we have
     pub fn insert_messages(&mut self, mut messages: Vec<Message>) {
-        // assume these are already in time-descending order, so we swap them and then append the
-        // older ones
-        std::mem::swap(&mut self.messages, &mut messages);
+        for message in &mut messages {
+            if let Some(index) = self.pending_position(message) {
+                self.messages.remove(index);
+                message.delivery = Some(DeliveryState::Sent);
+            }
+        }
         self.messages.extend(messages);
+        // the sort is stable, so the copy we already had is the one kept
+        self.messages.sort_by_key(|m| std::cmp::Reverse(m.id));
+        self.messages.dedup_by_key(|m| m.id);
+        self.drop_oldest();
     }
 
+    pub fn set_my_username(&mut self, my_username: &str) {
+        self.is_self = self.data.channel.is_self_conversation(my_username);
+        self.other_members = self.data.channel.other_members(my_username);
+    }
+
+    // Everyone we know is in it, the most recent senders first and then the rest of the
+    // participants.
+    pub fn recent_participants(&self) -> Vec<String> {
+        let mut names: Vec<String> = vec![];
+        let senders = self.messages.iter().map(|m| m.sender.username.as_str());
+        for name in senders.chain(self.participants.iter().map(String::as_str)) {
+            if !name.is_empty() && !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+        names
+    }
+
+    // The full name, e.g. `team#general` or `alice,bob`, which is what the config refers to
+    // conversations by.
     pub fn get_name(&self) -> String {
+        if self.is_self {
+            return "Notes to self".to_string();
+        }
         match self.data.channel.members_type {
             MemberType::Team => format!(
                 "{}#{}",
                 &self.data.channel.name, &self.data.channel.topic_name
             ),
-            // TODO: remove the username from the channel name for display
             MemberType::User => self.data.channel.name.to_string(),
         }
     }
+
+    // the name to show, which leaves you out of conversations between users
+    pub fn display_name(&self) -> String {
+        match &self.other_members {
+            Some(others) if !self.is_self => others.clone(),
+            _ => self.get_name(),
+        }
+    }
+
+    // The name with the team's member count after it, if there is one, and then the number of
+    // unread messages, e.g. `team#general (42) (3)`.
+    pub fn sidebar_name(&self, show_member_count: bool) -> String {
+        let name = match self.member_count {
+            Some(count) if show_member_count => format!("{} ({})", self.display_name(), count),
+            _ => self.display_name(),
+        };
+        match self.unread_count {
+            0 => name,
+            count => format!("{} ({})", name, count),
+        }
+    }
 }
 
 impl From<KeybaseConversation> for Conversation {
@@ -164,7 +744,535 @@ impl From<KeybaseConversation> for Conversation {
             id: kb.id.clone(),
             fetched: false,
             messages: vec![],
+            pinned: HashSet::new(),
+            is_self: false,
+            other_members: None,
+            hidden: false,
+            muted: false,
+            member_count: None,
+            participants: kb.channel.participants(),
+            history_next: None,
+            unread_count: 0,
+            max_messages: DEFAULT_MAX_MESSAGES,
             data: kb,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{conversation, message};
+    use serde_json::{from_value, json, Value};
+
+    #[test]
+    fn giphy_unfurl() {
+        let content: MessageType = from_value(json!({
+            "type": "unfurl",
+            "unfurl": {
+                "messageID": 12,
+                "unfurl": {
+                    "url": "https://giphy.com/gifs/happy-dance-l0MYt5jPR6QX5pnqM",
+                    "unfurl": {
+                        "unfurlType": 2,
+                        "giphy": {
+                            "imageUrl": "https://media.giphy.com/media/l0MYt5jPR6QX5pnqM/giphy.gif",
+                            "faviconUrl": "https://giphy.com/favicon.ico"
+                        }
+                    }
+                }
+            }
+        }))
+        .unwrap();
+
+        if let MessageType::Unfurl { unfurl } = content {
+            let giphy = unfurl.unfurl.unfurl.giphy.as_ref().unwrap();
+            assert_eq!(
+                giphy.image_url.as_deref(),
+                Some("https://media.giphy.com/media/l0MYt5jPR6QX5pnqM/giphy.gif")
+            );
+            assert_eq!(giphy.video_url, None);
+            assert_eq!(unfurl.unfurl.giphy_title(), "happy dance");
+        } else {
+            panic!("Wrong message type");
+        }
+    }
+
+    #[test]
+    fn unknown_unfurl() {
+        let content: MessageType = from_value(json!({
+            "type": "unfurl",
+            "unfurl": {"something": "new"}
+        }))
+        .unwrap();
+
+        assert_eq!(
+            content,
+            MessageType::Unfurl {
+                unfurl: MessageUnfurl::default()
+            }
+        );
+    }
+
+    #[test]
+    fn giphy_title_without_slug() {
+        let unfurl = UnfurlResult {
+            url: "https://giphy.com/gifs/l0MYt5jPR6QX5pnqM".to_string(),
+            unfurl: Unfurl::default(),
+        };
+        assert_eq!(unfurl.giphy_title(), "");
+    }
+
+    #[test]
+    fn image_attachment() {
+        let content: MessageType = from_value(json!({
+            "type": "attachment",
+            "attachment": {
+                "object": {
+                    "filename": "photo.jpg",
+                    "title": "",
+                    "mimeType": "image/jpeg",
+                    "size": 1_258_291,
+                    "metadata": {
+                        "assetType": 1,
+                        "image": {"width": 1920, "height": 1080, "isAudio": false}
+                    }
+                },
+                "uploaded": true
+            }
+        }))
+        .unwrap();
+
+        if let MessageType::Attachment { attachment } = content {
+            let object = &attachment.object;
+            assert_eq!(object.filename, "photo.jpg");
+            assert_eq!(object.mime_type, "image/jpeg");
+            assert_eq!(object.size, 1_258_291);
+            assert_eq!(
+                object.dimensions(),
+                Some(&Dimensions {
+                    width: 1920,
+                    height: 1080
+                })
+            );
+        } else {
+            panic!("Wrong message type");
+        }
+    }
+
+    #[test]
+    fn join_and_leave() {
+        let content: MessageType = from_value(json!({"type": "join", "join": {}})).unwrap();
+        assert_eq!(content, MessageType::Join);
+        let content: MessageType = from_value(json!({"type": "leave", "leave": {}})).unwrap();
+        assert_eq!(content, MessageType::Leave);
+    }
+
+    #[test]
+    fn messages_sorted_by_id() {
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_message(message!("test1", "five", 5));
+        convo.insert_messages(vec![message!("test1", "two", 2), message!("test1", "six", 6)]);
+        // arrived late
+        convo.insert_message(message!("test1", "three", 3));
+        convo.insert_message(message!("test1", "seven", 7));
+        convo.insert_messages(vec![message!("test1", "one", 1)]);
+
+        let ids: Vec<u64> = convo.messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [7, 6, 5, 3, 2, 1]);
+    }
+
+    #[test]
+    fn confirm_pending() {
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_message(message!("test1", "earlier", 4));
+        for body in &["first", "second"] {
+            let mut message = message!("test1", *body, convo.next_pending_id());
+            message.delivery = Some(DeliveryState::Sending);
+            convo.insert_message(message);
+        }
+        let first = FIRST_PENDING_ID;
+
+        assert!(convo.confirm_pending(first, 5));
+        let shown: Vec<(u64, bool)> = convo.messages.iter().map(|m| (m.id, m.is_pending())).collect();
+        assert_eq!(shown, [(first + 1, true), (5, false), (4, false)]);
+        // keybase sending it back doesn't add it again
+        convo.insert_message(message!("test1", "first", 5));
+        assert_eq!(convo.messages.len(), 3);
+
+        // sent back before we heard it went
+        convo.insert_message(message!("test1", "second", 6));
+        assert!(!convo.confirm_pending(first + 1, 6));
+        let ids: Vec<u64> = convo.messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [6, 5, 4]);
+    }
+
+    #[test]
+    fn reset_history() {
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.max_messages = 3;
+        let mut sent = message!("test1", "sent", 5);
+        sent.delivery = Some(DeliveryState::Sent);
+        let mut pending = message!("test1", "pending", FIRST_PENDING_ID);
+        pending.delivery = Some(DeliveryState::Sending);
+        convo.insert_messages(vec![message!("test1", "old", 1), sent, pending]);
+
+        let page = (2..=5).rev().map(|i| message!("test1", "new", i)).collect();
+        convo.reset_history(page, Some("older".to_string()));
+
+        // the pending one and the newest of the page, which goes past the cap
+        let shown: Vec<(u64, Option<DeliveryState>)> =
+            convo.messages.iter().map(|m| (m.id, m.delivery)).collect();
+        assert_eq!(
+            shown,
+            [
+                (FIRST_PENDING_ID, Some(DeliveryState::Sending)),
+                (5, Some(DeliveryState::Sent)),
+                (4, None)
+            ]
+        );
+        assert_eq!(convo.history_next, None);
+    }
+
+    #[test]
+    fn sidebar_name() {
+        let mut convo: Conversation = KeybaseConversation {
+            id: "test1".to_string(),
+            unread: false,
+            active_at: 0,
+            channel: Channel {
+                name: "keybase".to_string(),
+                topic_name: "general".to_string(),
+                members_type: MemberType::Team,
+            },
+        }
+        .into();
+
+        // not fetched (or the fetch failed)
+        assert_eq!(convo.sidebar_name(true), "keybase#general");
+        convo.member_count = Some(42);
+        assert_eq!(convo.sidebar_name(true), "keybase#general (42)");
+        assert_eq!(convo.sidebar_name(false), "keybase#general");
+        convo.unread_count = 3;
+        assert_eq!(convo.sidebar_name(true), "keybase#general (42) (3)");
+        assert_eq!(convo.sidebar_name(false), "keybase#general (3)");
+    }
+
+    #[test]
+    fn recent_participants() {
+        let mut convo = conversation!("test1");
+        convo.channel.name = "alice,bob,me".to_string();
+        let mut convo: Conversation = convo.into();
+        let mut from_bob = message!("test1", "hi", 2);
+        from_bob.sender.username = "bob".to_string();
+        let mut from_carol = message!("test1", "hello", 1);
+        from_carol.sender.username = "carol".to_string();
+        convo.insert_messages(vec![from_bob, from_carol]);
+        assert_eq!(convo.recent_participants(), ["bob", "carol", "alice", "me"]);
+    }
+
+    #[test]
+    fn participants_from_channel_name() {
+        let channel = |name: &str| Channel {
+            name: name.to_string(),
+            topic_name: String::new(),
+            members_type: MemberType::User,
+        };
+        assert_eq!(channel("alice,bob").participants(), ["alice", "bob"]);
+        // notes to self
+        assert_eq!(channel("alice").participants(), ["alice"]);
+        assert_eq!(channel("alice,bob#carol").participants(), ["alice", "bob", "carol"]);
+        assert_eq!(channel("alice,bob@twitter").participants(), ["alice", "bob@twitter"]);
+
+        let team = Channel {
+            members_type: MemberType::Team,
+            ..channel("keybase")
+        };
+        assert!(team.participants().is_empty());
+
+        let convo: Conversation = KeybaseConversation {
+            channel: channel("alice,me"),
+            ..conversation!("test1")
+        }
+        .into();
+        assert_eq!(convo.participants, ["alice", "me"]);
+    }
+
+    #[test]
+    fn other_members() {
+        let channel = |name: &str| Channel {
+            name: name.to_string(),
+            topic_name: String::new(),
+            members_type: MemberType::User,
+        };
+        assert_eq!(channel("alice,bob").other_members("alice"), Some("bob".to_string()));
+        assert_eq!(channel("alice,bob").other_members("bob"), Some("alice".to_string()));
+        assert_eq!(
+            channel("alice,bob,carol").other_members("Bob"),
+            Some("alice,carol".to_string())
+        );
+        // not in it at all
+        assert_eq!(channel("alice,bob").other_members("dave"), Some("alice,bob".to_string()));
+        assert_eq!(channel("alice").other_members("alice"), None);
+
+        let mut team = channel("alice");
+        team.members_type = MemberType::Team;
+        assert_eq!(team.other_members("bob"), None);
+
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.data.channel = channel("alice,bob,carol");
+        convo.set_my_username("alice");
+        assert_eq!(convo.display_name(), "bob,carol");
+        // the config still uses the full name
+        assert_eq!(convo.get_name(), "alice,bob,carol");
+    }
+
+    #[test]
+    fn system_messages() {
+        let text = |value: Value| match from_value::<MessageType>(value).unwrap() {
+            MessageType::System { system } => system.text(),
+            other => panic!("Expected a system message, got {:?}", other),
+        };
+
+        assert_eq!(
+            text(json!({
+                "type": "system",
+                "system": {
+                    "systemType": 0,
+                    "addedtoteam": {"team": "keybase", "adder": "alice", "addee": "bob", "role": 2}
+                }
+            })),
+            "alice added bob to keybase"
+        );
+        assert_eq!(
+            text(json!({
+                "type": "system",
+                "system": {
+                    "systemType": 9,
+                    "newchannel": {"creator": "alice", "nameAtCreation": "random", "convID": "abc"}
+                }
+            })),
+            "alice created #random"
+        );
+        assert_eq!(
+            text(json!({
+                "type": "system",
+                "system": {"systemType": 7, "bulkaddtoconv": {"usernames": ["bob", "carol"]}}
+            })),
+            "bob, carol added to the channel"
+        );
+        assert_eq!(
+            text(json!({
+                "type": "system",
+                "system": {"systemType": 4, "gitpush": {"team": "keybase", "pusher": "alice"}}
+            })),
+            "Keybase system message (type 4)"
+        );
+        // no details at all
+        assert_eq!(text(json!({"type": "system"})), "Keybase system message (type 0)");
+    }
+
+    #[test]
+    fn reaction() {
+        let content: MessageType = from_value(json!({
+            "type": "reaction",
+            "reaction": {"m": 42, "b": ":+1:"}
+        }))
+        .unwrap();
+
+        assert_eq!(
+            content,
+            MessageType::Reaction {
+                reaction: MessageReaction {
+                    message_id: 42,
+                    body: ":+1:".to_string()
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn reaction_event() {
+        let event: ListenerEvent = from_value(json!({
+            "type": "chat",
+            "source": "remote",
+            "msg": {
+                "id": 43,
+                "conversation_id": "test1",
+                "channel": {"name": "alice,bob", "members_type": "impteamnative"},
+                "sender": {"username": "alice", "device_name": "phone"},
+                "sent_at": 1600000000,
+                "content": {"type": "reaction", "reaction": {"m": 42, "b": ":tada:"}}
+            }
+        }))
+        .unwrap();
+
+        let msg = match event {
+            ListenerEvent::ChatMessage(wrapper) => wrapper.msg,
+            _ => panic!("Expected a chat message"),
+        };
+        assert_eq!(msg.id, 43);
+        assert_eq!(msg.sender.username, "alice");
+        assert_eq!(
+            msg.content,
+            MessageType::Reaction {
+                reaction: MessageReaction {
+                    message_id: 42,
+                    body: ":tada:".to_string()
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn typing_event() {
+        let event: ListenerEvent = from_value(json!({
+            "type": "typing",
+            "conversation_id": "test1",
+            "username": "alice"
+        }))
+        .unwrap();
+
+        match event {
+            ListenerEvent::Typing { conversation_id, username } => {
+                assert_eq!(conversation_id, "test1");
+                assert_eq!(username, "alice");
+            }
+            _ => panic!("Expected a typing event"),
+        }
+    }
+
+    #[test]
+    fn unknown_event() {
+        let event: ListenerEvent = from_value(json!({
+            "type": "wallet",
+            "notification": {"amount": "1 XLM"}
+        }))
+        .unwrap();
+
+        assert!(matches!(event, ListenerEvent::Unknown));
+    }
+
+    #[test]
+    fn edit() {
+        let content: MessageType = from_value(json!({
+            "type": "edit",
+            "edit": {"messageID": 42, "body": "fixed typo"}
+        }))
+        .unwrap();
+
+        assert_eq!(
+            content,
+            MessageType::Edit {
+                edit: MessageEdit {
+                    message_id: 42,
+                    body: "fixed typo".to_string()
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn delete() {
+        let content: MessageType = from_value(json!({
+            "type": "delete",
+            "delete": {"messageIDs": [42, 43]}
+        }))
+        .unwrap();
+
+        assert_eq!(
+            content,
+            MessageType::Delete {
+                delete: MessageDelete {
+                    message_ids: vec![42, 43]
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn file_attachment() {
+        let content: MessageType = from_value(json!({
+            "type": "attachment",
+            "attachment": {
+                "object": {
+                    "filename": "notes.txt",
+                    "mimeType": "text/plain",
+                    "size": 512,
+                    "metadata": {}
+                },
+                "uploaded": true
+            }
+        }))
+        .unwrap();
+
+        if let MessageType::Attachment { attachment } = content {
+            assert_eq!(attachment.object.filename, "notes.txt");
+            assert_eq!(attachment.object.dimensions(), None);
+        } else {
+            panic!("Wrong message type");
+        }
+    }
+
+    // everything we parse from keybase can be written back out (e.g. by `export-all`) and read
+    // in again, with the same `type` tags and field names
+    #[test]
+    fn round_trip() {
+        let contents = vec![
+            json!({"type": "join"}),
+            json!({"type": "leave"}),
+            json!({"type": "metadata"}),
+            json!({"type": "text", "text": {"body": "hi", "replyTo": 3}}),
+            json!({
+                "type": "attachment",
+                "attachment": {
+                    "object": {"filename": "cat.png", "mimeType": "image/png", "size": 5}
+                }
+            }),
+            json!({
+                "type": "system",
+                "system": {
+                    "systemType": 9,
+                    "newchannel": {"creator": "alice", "nameAtCreation": "random"}
+                }
+            }),
+            json!({
+                "type": "unfurl",
+                "unfurl": {"unfurl": {"url": "https://example.com", "unfurl": {"unfurlType": 0}}}
+            }),
+            json!({"type": "reaction", "reaction": {"m": 42, "b": ":+1:"}}),
+            json!({"type": "edit", "edit": {"messageID": 42, "body": "fixed"}}),
+            json!({"type": "delete", "delete": {"messageIDs": [42, 43]}}),
+        ];
+        for value in contents {
+            let content: MessageType = from_value(value.clone()).unwrap();
+            let serialized = serde_json::to_value(&content).unwrap();
+            assert_eq!(serialized["type"], value["type"]);
+            assert_eq!(from_value::<MessageType>(serialized).unwrap(), content);
+        }
+
+        let wrapper: MessageWrapper = from_value(json!({
+            "msg": {
+                "id": 43,
+                "conversation_id": "test1",
+                "channel": {"name": "alice,bob", "members_type": "impteamnative"},
+                "sender": {"username": "alice", "device_name": "phone"},
+                "sent_at": 1600000000,
+                "content": {"type": "reaction", "reaction": {"m": 42, "b": ":tada:"}}
+            }
+        }))
+        .unwrap();
+        let serialized = serde_json::to_value(&wrapper).unwrap();
+        assert_eq!(serialized["msg"]["content"]["reaction"], json!({"m": 42, "b": ":tada:"}));
+        assert_eq!(from_value::<MessageWrapper>(serialized.clone()).unwrap(), wrapper);
+
+        let response = ApiResponseWrapper {
+            result: ApiResponse::MessageList {
+                messages: vec![serialized],
+                pagination: Some(Pagination { next: "abc".to_string(), last: false }),
+            },
+        };
+        let serialized = serde_json::to_value(&response).unwrap();
+        assert_eq!(from_value::<ApiResponseWrapper>(serialized).unwrap(), response);
+    }
+}
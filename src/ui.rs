@@ -3,59 +3,167 @@
 // Contains the main UI struct and all the views that don't exist in their own module.
 
 use std::cell::RefCell;
-use std::path::PathBuf;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use chrono::{Local, TimeZone};
+use cursive::theme::{self, BaseColor, Color, ColorStyle, Effect, Style, Theme};
+use cursive::utils::markup::StyledString;
 use cursive::{event::*, view::*, views::*, Cursive, CursiveExt};
 use dirs::config_dir;
 use log::debug;
+use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 
+use crate::commands::{parse_input, Command};
+use crate::completion::{mention_at, rank_candidates, Completion, MAX_CANDIDATES};
+use crate::config::{Config, LayoutConfig};
+use crate::fuzzy::rank;
+use crate::goto::{resolve_conversation, MessageLink};
+use crate::history::InputHistory;
+use crate::hyperlink::{hyperlink_at, supports_hyperlinks};
+use crate::image_preview::{detect_graphics, preview_sequence, CLEAR_SEQUENCE};
+use crate::latency::Latency;
+use crate::mentions::{
+    find_mentions, find_user_mentions, mentions_user, message_links, resolve_mention,
+    MentionTarget,
+};
+use crate::selection::{
+    find_match, format_messages, is_selectable, matches_sender, message_at_row, message_to_edit,
+    next_selection, quote_messages, recent_references, resolve_reference, selected_range,
+};
+use crate::reactions::aggregate_reactions;
 use crate::state::StateObserver;
-use crate::types::{Conversation, Message, MessageType, UiEvent};
+use crate::sort::{sort_conversations, SortMode};
+use crate::status::{render_status, ConnectionStatus, StatusInfo};
+use crate::thread::build_thread;
+use crate::types::{
+    AttachmentObject, Channel, Conversation, DeliveryState, MemberType, Message, MessageType,
+    UiEvent, UnfurlResult,
+};
+use crate::views::chat::{wrap, ChatView};
 use crate::views::conversation::{ConversationName, ConversationView};
+use crate::views::layout::{Gutter, PercentWidth};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+// wait for a pause in typing this long before searching, so fast typing doesn't re-render the
+// chat on every keystroke
+const SEARCH_DEBOUNCE: Duration = Duration::from_millis(150);
+// wait for messages to stop arriving for this long before marking the conversation read
+const READ_DEBOUNCE: Duration = Duration::from_secs(1);
+// how long "alice is typing…" stays up without hearing from them again
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+// the spinner in the chat while a conversation's messages load, one frame at a time
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+const SPINNER_FRAME: Duration = Duration::from_millis(100);
+
 pub struct UiBuilder {
     cursive: Cursive,
+    config: Config,
+    latency: Arc<Latency>,
+}
+
+// The theme in `path`, or cursive's default one if there isn't one there or it doesn't parse (a
+// typo in the theme shouldn't stop the app starting). Only a theme that can't be read is an error.
+fn load_theme(path: &Path) -> io::Result<Theme> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Theme::default()),
+        Err(e) => return Err(e),
+    };
+    Ok(theme::load_toml(&contents).unwrap_or_else(|e| {
+        warn!("Using the default theme, {} doesn't parse: {:?}", path.display(), e);
+        Theme::default()
+    }))
 }
 
 impl UiBuilder {
-    pub fn new() -> Self {
+    pub fn new(config: &Config) -> Self {
         let mut siv = Cursive::default();
 
         // load a theme from `$HOME/.config/keybase-chat-tui/theme.toml` (on linux)
         if let Some(dir) = config_dir() {
             let theme_path = PathBuf::new().join(dir).join("keybase-chat-tui/theme.toml");
-            if theme_path.exists() {
-                siv.load_toml(theme_path.to_str().unwrap())
-                    .expect("Failed to load theme");
-            }
+            siv.set_theme(load_theme(&theme_path).expect("Failed to read theme"));
         }
 
-        siv.add_layer(
-            Dialog::around(
-                LinearLayout::horizontal()
-                    .child(conversation_list())
-                    .child(chat_area()),
-            )
-            .title(format!("keybase-chat-tui ({})", VERSION)),
-        );
+        let mut layout = LinearLayout::vertical().child(main_layout(&config.layout));
+        if !config.status_bar.is_empty() {
+            layout.add_child(TextView::new("").with_id("status_bar"));
+        }
+        siv.add_layer(Dialog::around(layout).title(format!("keybase-chat-tui ({})", VERSION)));
 
         // focus the edit view (where you type) on the initial render
         siv.focus_id("edit").unwrap();
 
-        UiBuilder { cursive: siv }
+        // select a message (or a range of them, with shift) in the chat area and act on it
+        siv.add_global_callback(Event::Alt(Key::Up), |s| select_message(s, true, false));
+        siv.add_global_callback(Event::Alt(Key::Down), |s| select_message(s, false, false));
+        siv.add_global_callback(Event::Shift(Key::Up), |s| select_message(s, true, true));
+        siv.add_global_callback(Event::Shift(Key::Down), |s| select_message(s, false, true));
+        siv.add_global_callback(Event::AltChar('p'), |s| pin_selected_message(s, true));
+        siv.add_global_callback(Event::AltChar('u'), |s| pin_selected_message(s, false));
+        siv.add_global_callback(Event::AltChar('q'), quote_selected_messages);
+        siv.add_global_callback(Event::AltChar('a'), reply_to_selected_message);
+        siv.add_global_callback(Event::AltChar('c'), copy_selected_messages);
+        siv.add_global_callback(Event::AltChar('r'), focus_next_reaction);
+        siv.add_global_callback(Event::AltChar('e'), react_to_selected_message);
+        siv.add_global_callback(Event::AltChar('l'), focus_next_link);
+
+        siv.add_global_callback(Event::AltChar('d'), toggle_compact);
+        siv.add_global_callback(Event::AltChar('n'), toggle_references);
+        siv.add_global_callback(Event::AltChar('z'), toggle_paused);
+        siv.add_global_callback(Event::AltChar('s'), cycle_sort_mode);
+        siv.add_global_callback(Event::CtrlChar('f'), open_search);
+        siv.add_global_callback(Event::AltChar('o'), open_new_conversation);
+        siv.add_global_callback(Event::CtrlChar('k'), open_quick_switcher);
+        // cursive already quits on Ctrl+C
+        siv.add_global_callback(Event::CtrlChar('q'), |s| s.quit());
+
+        // step through the conversation list, from anywhere but the message box for j/k
+        siv.add_global_callback('j', |s| switch_to_adjacent(s, true));
+        siv.add_global_callback('k', |s| switch_to_adjacent(s, false));
+        siv.add_global_callback(Event::CtrlChar('n'), |s| switch_to_adjacent(s, true));
+        siv.add_global_callback(Event::CtrlChar('p'), |s| switch_to_adjacent(s, false));
+        siv.add_global_callback(Event::AltChar('j'), switch_to_next_unread);
+
+        UiBuilder {
+            cursive: siv,
+            config: config.clone(),
+            latency: Arc::default(),
+        }
+    }
+
+    // where the status bar reads api call latency from
+    pub fn with_latency(mut self, latency: Arc<Latency>) -> Self {
+        self.latency = latency;
+        self
     }
 
     pub fn build(mut self) -> (Rc<RefCell<Ui>>, Receiver<UiEvent>) {
         let (ui_send, ui_recv) = mpsc::channel(32);
         let executor = UiExecutor {
             sender: ui_send,
+            state: UiState {
+                compact: self.config.layout.compact,
+                sort_mode: self.config.sort_mode,
+                config: self.config,
+                latency: self.latency,
+                hyperlinks: supports_hyperlinks(),
+                ..UiState::default()
+            },
         };
 
         self.cursive.set_user_data(executor);
+        update_sidebar_title(&mut self.cursive);
 
         (
             Rc::new(RefCell::new(Ui {
@@ -72,193 +180,3930 @@ pub struct Ui {
 }
 
 impl Ui {
+    // the next `step` returns false
+    pub fn quit(&mut self) {
+        self.cursive.quit();
+    }
+
     // render one 'frame'
     pub fn step(&mut self) -> bool {
         if !self.cursive.is_running() {
             return false;
         }
 
-        self.cursive.step();
+        self.update_status();
+        self.run_pending_search();
+        self.run_pending_read();
+        self.check_new_below();
+        self.expire_typing();
+        self.animate_loading();
+        if self.cursive.step() {
+            // moving the focus around the sidebar clears its highlight
+            mark_unread_changed(&mut self.cursive);
+        }
+        self.draw_pending_preview();
+        self.draw_hyperlinks();
 
         true
     }
 
-    fn render_conversation_list(&mut self, data: &[Conversation]) {
-        self.cursive
-            .call_on_id("conversation_list", |view: &mut ListView| {
-                view.clear();
-                for convo in data.iter() {
-                    debug!("Adding child: {}", &convo.get_name());
-                    view.add_child("", conversation_view(convo.clone()))
+    // re-render the status bar, if anything in it changed
+    fn update_status(&mut self) {
+        // only the conversations in the sidebar can be highlighted
+        let shown = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| {
+                let state = &mut executor.state;
+                if std::mem::replace(&mut state.unread_changed, false) {
+                    Some(state.sidebar_order.clone())
+                } else {
+                    None
                 }
-            });
+            })
+            .flatten();
+        let unread = match shown {
+            Some(ids) => ids
+                .iter()
+                .filter(|id| {
+                    self.cursive
+                        .call_on_id(id, |view: &mut ConversationView| view.unread)
+                        .unwrap_or(false)
+                })
+                .count(),
+            None => self
+                .cursive
+                .with_user_data(|executor: &mut UiExecutor| executor.state.status.unread)
+                .unwrap_or(0),
+        };
+        let time = Local::now().format("%H:%M").to_string();
+
+        let status = self.cursive.with_user_data(|executor: &mut UiExecutor| {
+            let state = &mut executor.state;
+            let latency = state.latency.last();
+            let reactors = focused_reactors(state);
+            if state.status.unread == unread
+                && state.status.time == time
+                && state.status.latency == latency
+                && state.status.reactors == reactors
+                && !state.status_changed
+            {
+                return None;
+            }
+            state.status.unread = unread;
+            state.status.latency = latency;
+            state.status.time = time;
+            state.status.reactors = reactors;
+            state.status_changed = false;
+            Some(match &state.status.reactors {
+                Some(reactors) => StyledString::plain(reactors),
+                None => render_status(&state.config.status_bar, &state.status),
+            })
+        });
+
+        if let Some(Some(status)) = status {
+            self.cursive
+                .call_on_id("status_bar", |view: &mut TextView| view.set_content(status));
+        }
+    }
+
+    // search for whatever was typed in the search box, once typing stops for a moment
+    fn run_pending_search(&mut self) {
+        let now = Instant::now();
+        let query = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| {
+                let state = &mut executor.state;
+                match &state.pending_search {
+                    Some(pending) if pending.is_due(now) => {
+                        state.pending_search.take().map(|p| p.query)
+                    }
+                    _ => None,
+                }
+            })
+            .flatten();
+
+        if let Some(query) = query {
+            search(&mut self.cursive, &query, false);
+        }
+    }
+
+    fn animate_loading(&mut self) {
+        let since = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| executor.state.loading_since)
+            .flatten();
+        if let Some(since) = since {
+            let text = loading_text(since.elapsed());
+            self.cursive
+                .call_on_id("chat_container", |view: &mut ChatView| view.set_content(vec![(text, 0)]));
+        }
+    }
+
+    // Once scrolled back down to the newest messages, the new ones below have been seen, and the
+    // chat sticks to the bottom again as more come in.
+    fn check_new_below(&mut self) {
+        let new_below = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| executor.state.new_below)
+            .unwrap_or(0);
+        if new_below == 0 {
+            return;
+        }
+        let at_bottom = self
+            .cursive
+            .call_on_id("chat_scroll", |view: &mut ChatScrollView| view.is_at_bottom())
+            .unwrap_or(false);
+        if at_bottom {
+            self.cursive
+                .with_user_data(|executor: &mut UiExecutor| executor.state.new_below = 0);
+            restore_scroll_position(&mut self.cursive, None);
+            update_chat_title(&mut self.cursive);
+        }
+    }
+
+    fn expire_typing(&mut self) {
+        let now = Instant::now();
+        let expired = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| match &executor.state.typing {
+                Some(typing) => typing.is_expired(now),
+                None => false,
+            })
+            .unwrap_or(false);
+        if expired {
+            set_typing(&mut self.cursive, None);
+        }
+    }
+
+    // tell keybase we've seen the newest message, once they stop coming in for a moment
+    fn run_pending_read(&mut self) {
+        let now = Instant::now();
+        let read = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| {
+                let state = &mut executor.state;
+                match &state.pending_read {
+                    Some(pending) if pending.is_due(now) => state.pending_read.take(),
+                    _ => None,
+                }
+            })
+            .flatten();
+
+        if let Some(read) = read {
+            send_event(
+                &mut self.cursive,
+                UiEvent::MarkRead(read.conversation_id, read.message_id),
+            );
+        }
+    }
+
+    // Images are drawn straight to the terminal, on top of the preview layer once cursive has put
+    // it on screen. Row 2 is just under its title.
+    fn draw_pending_preview(&mut self) {
+        let preview = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| executor.state.pending_preview.take())
+            .flatten();
+        if let Some(preview) = preview {
+            let mut stdout = io::stdout();
+            let drawn = stdout
+                .write_all(b"\x1b[2;1H")
+                .and_then(|_| stdout.write_all(&preview))
+                .and_then(|_| stdout.flush());
+            if let Err(e) = drawn {
+                error!("Failed to draw image preview: {}", e);
+            }
+        }
+    }
+
+    // URLs in the chat are written again straight to the terminal as links, over the top of what
+    // cursive drew, whenever it draws the chat. Not while there's a dialog on top of it though,
+    // they'd go over that.
+    fn draw_hyperlinks(&mut self) {
+        let links = self
+            .cursive
+            .call_on_id("chat_container", |view: &mut ChatView| view.take_drawn_links())
+            .flatten();
+        let enabled = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| executor.state.hyperlinks)
+            .unwrap_or(false);
+        let links = match links {
+            Some(links) if enabled && self.cursive.screen().len() == 1 && !links.is_empty() => links,
+            _ => return,
+        };
+        let sequence: String = links
+            .iter()
+            .map(|link| hyperlink_at(link.x, link.y, &link.url, &link.text))
+            .collect();
+        let mut stdout = io::stdout();
+        let drawn = stdout
+            .write_all(sequence.as_bytes())
+            .and_then(|_| stdout.flush());
+        if let Err(e) = drawn {
+            error!("Failed to draw links: {}", e);
+        }
+    }
+
+    fn set_status<F: FnOnce(&mut StatusInfo)>(&mut self, f: F) {
+        self.cursive.with_user_data(|executor: &mut UiExecutor| {
+            f(&mut executor.state.status);
+            executor.state.status_changed = true;
+        });
+    }
+
+    fn render_conversation_list(&mut self, data: &[Conversation]) {
+        self.cursive.with_user_data(|executor: &mut UiExecutor| {
+            executor.state.sidebar = data.to_vec();
+        });
+        redraw_sidebar(&mut self.cursive);
         self.cursive.refresh();
     }
 
     fn render_conversation(&mut self, data: &Conversation) {
+        // every way of switching conversations ends up here, so this is where the old one's
+        // place is kept
+        save_scroll_position(&mut self.cursive, &data.id);
+        self.cursive.with_user_data(|executor: &mut UiExecutor| {
+            executor.state.conversation = Some(data.clone());
+            // the conversation from the application state already has them
+            executor.state.paused_messages.clear();
+            executor.state.selected_message = None;
+            executor.state.selection_anchor = None;
+            executor.state.replying_to = None;
+            executor.state.sender_filter = None;
+            executor.state.loading_history = None;
+            executor.state.unread_changed = true;
+        });
+        set_typing(&mut self.cursive, None);
+        redraw_chat(&mut self.cursive);
+        let place = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| executor.state.scroll_memory.take(&data.id))
+            .flatten();
+        // what came in while it was away is below where it was left
+        let new_below = place.map_or(0, |place| {
+            data.messages
+                .iter()
+                .filter(|m| is_new_message(m) && place.newest.is_none_or(|newest| m.id > newest))
+                .count()
+        });
         self.cursive
-            .call_on_id("chat_container", |view: &mut TextView| {
-                view.set_content("");
-                for msg in data.messages.iter().rev() {
-                    render_message(view, msg);
-                }
-            });
-        self.cursive
-            .call_on_id("chat_panel", |view: &mut Panel<LinearLayout>| {
-                view.set_title(data.get_name());
-            });
+            .with_user_data(|executor: &mut UiExecutor| executor.state.new_below = new_below);
+        restore_scroll_position(&mut self.cursive, place.map(|place| place.row));
+        update_chat_title(&mut self.cursive);
         self.cursive.refresh();
     }
 
     fn new_message(&mut self, message: &Message) {
-        self.cursive
-            .call_on_id("chat_container", |view: &mut TextView| {
-                render_message(view, message);
-            });
+        // changes a message that's already on screen
+        if let MessageType::Edit { edit } = &message.content {
+            let edited = self
+                .cursive
+                .with_user_data(|executor: &mut UiExecutor| match &mut executor.state.conversation {
+                    Some(convo) => convo.apply_edit(edit),
+                    None => false,
+                })
+                .unwrap_or(false);
+            if edited {
+                redraw_chat(&mut self.cursive);
+                self.cursive.refresh();
+            }
+            return;
+        }
+
+        // hold on to it until updates are resumed
+        let buffered = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| {
+                let state = &mut executor.state;
+                if state.paused && state.conversation.is_some() {
+                    state.paused_messages.push(message.clone());
+                    return true;
+                }
+                false
+            })
+            .unwrap_or(false);
+        if buffered {
+            update_chat_title(&mut self.cursive);
+            self.cursive.refresh();
+            return;
+        }
+
+        // reactions are drawn under the message they react to, and threaded replies under the
+        // message they reply to, which are already on screen. Message numbers all shift by one.
+        let (threaded, show_references) = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| {
+                let state = &executor.state;
+                (state.config.layout.threaded, state.show_references)
+            })
+            .unwrap_or_default();
+        let is_reply = matches!(&message.content, MessageType::Text { text } if text.reply_to.is_some());
+        // and a message we sent takes the place of the pending one already shown
+        let replaces_pending = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| {
+                matches!(&executor.state.conversation, Some(c) if c.replaces_pending(message))
+            })
+            .unwrap_or(false);
+        if matches!(message.content, MessageType::Reaction { .. })
+            || (threaded && is_reply)
+            || show_references
+            || replaces_pending
+        {
+            let is_shown = self
+                .cursive
+                .with_user_data(|executor: &mut UiExecutor| match &mut executor.state.conversation {
+                    Some(convo) => {
+                        convo.insert_message(message.clone());
+                        true
+                    }
+                    None => false,
+                })
+                .unwrap_or(false);
+            if is_shown {
+                redraw_chat(&mut self.cursive);
+                self.cursive.refresh();
+            }
+            return;
+        }
+        let text = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| {
+                let state = &mut executor.state;
+                let filter = state.sender_filter.as_deref();
+                let mut text = None;
+                if let Some(convo) = &mut state.conversation {
+                    if matches_sender(message, filter) {
+                        let previous = convo.messages.iter().find(|m| matches_sender(m, filter));
+                        let show_sender = should_show_sender(state.compact, previous, message);
+                        let links = message_links(message, &state.sidebar);
+                        let rendered = render_message(
+                            message,
+                            false,
+                            false,
+                            show_sender,
+                            &links,
+                            None,
+                            &state.status.user,
+                        );
+                        let indent = body_column(message, &rendered);
+                        text = Some((rendered, indent));
+                    }
+                    convo.insert_message(message.clone());
+                }
+                text
+            })
+            .flatten();
+        if let Some((text, indent)) = text {
+            self.cursive
+                .call_on_id("chat_container", |view: &mut ChatView| view.append(text, indent));
+        }
         self.cursive.refresh();
     }
 
-    fn unread_message(&mut self, conversation_id: &str) {
-        self.cursive
+    fn update_conversation(&mut self, data: &Conversation) {
+        let is_current = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| match &mut executor.state.conversation {
+                Some(convo) if convo.id == data.id => {
+                    *convo = data.clone();
+                    true
+                }
+                _ => false,
+            })
+            .unwrap_or(false);
+
+        let order_changed = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| {
+                match executor.state.sidebar.iter_mut().find(|c| c.id == data.id) {
+                    Some(convo) => {
+                        // a new message can move it up the list too
+                        let changed = convo.hidden != data.hidden
+                            || convo.muted != data.muted
+                            || convo.data.active_at != data.data.active_at;
+                        *convo = data.clone();
+                        changed
+                    }
+                    None => false,
+                }
+            })
+            .unwrap_or(false);
+
+        if order_changed {
+            redraw_sidebar(&mut self.cursive);
+        } else {
+            // the name might have changed (e.g. a renamed channel)
+            self.cursive
+                .call_on_id(&data.id, |view: &mut ConversationView| {
+                    view.set_conversation(data.clone())
+                });
+        }
+
+        if is_current {
+            redraw_chat(&mut self.cursive);
+            let oldest = self
+                .cursive
+                .with_user_data(|executor: &mut UiExecutor| executor.state.loading_history.take())
+                .flatten();
+            if let Some(message_id) = oldest {
+                scroll_to_message(&mut self.cursive, message_id);
+            }
+            update_chat_title(&mut self.cursive);
+            let name = data.display_name();
+            self.set_status(|status| status.conversation = name);
+        }
+        self.cursive.refresh();
+    }
+
+    fn unread_message(&mut self, conversation_id: &str, mentioned: bool) {
+        let was_unread = self
+            .cursive
             .call_on_id(conversation_id, |view: &mut ConversationView| {
-                view.unread = true;
-            });
+                view.mentioned |= mentioned;
+                std::mem::replace(&mut view.unread, true)
+            })
+            .unwrap_or(true);
+        if !was_unread {
+            mark_unread_changed(&mut self.cursive);
+        }
+        let sort_mode = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| executor.state.sort_mode);
+        // move it up to the other unread conversations
+        if !was_unread && sort_mode == Some(SortMode::UnreadFirst) {
+            redraw_sidebar(&mut self.cursive);
+        }
         self.cursive.refresh();
     }
 }
 
-// TODO: move this into a new view that inherits from TextView so we can color the username.
-fn render_message(view: &mut TextView, message: &Message) {
-    match &message.content {
-        MessageType::Text { text } => {
-            view.append(&format!("{}: {}\n", message.sender.username, text.body));
+fn render_message(
+    message: &Message,
+    pinned: bool,
+    selected: bool,
+    show_sender: bool,
+    links: &[(Range<usize>, MentionTarget)],
+    focused_link: Option<usize>,
+    my_username: &str,
+) -> StyledString {
+    let text = match &message.content {
+        MessageType::Text { text } if show_sender => {
+            format!("{}: {}\n", message.sender.username, text.body)
         }
-        MessageType::Unfurl {} => {
-            view.append(&format!(
-                "{} sent an Unfurl and I don't know how to render it\n",
-                message.sender.username
-            ));
+        // line the text up with the message above
+        MessageType::Text { text } => format!(
+            "{}{}\n",
+            " ".repeat(message.sender.username.chars().count() + 2),
+            text.body
+        ),
+        MessageType::Unfurl { unfurl } => render_unfurl(&message.sender.username, &unfurl.unfurl),
+        MessageType::Attachment { attachment } => {
+            render_attachment(&message.sender.username, &attachment.object)
+        }
+        // only drawn on its own when the message it reacts to isn't loaded
+        MessageType::Reaction { reaction } => format!(
+            "{} reacted {} to message {}\n",
+            message.sender.username, reaction.body, reaction.message_id
+        ),
+        // set apart from what people said, there's no sender to show
+        MessageType::System { system } => {
+            return StyledString::styled(
+                format!("  — {} —\n", system.text()),
+                ColorStyle::secondary(),
+            )
+        }
+        _ => return StyledString::new(),
+    };
+    let username = &message.sender.username;
+    // lined up text (see above) doesn't start with the name
+    let has_name = text.starts_with(username.as_str());
+    let text_len = text.len();
+    let text = match sent_time(message) {
+        Some(time) => format!("{} {}", time, text),
+        None => text,
+    };
+    let text = if pinned { format!("📌 {}", text) } else { text };
+    let text = if message.is_ephemeral { format!("💣 {}", text) } else { text };
+    // one of yours that keybase has got
+    let text = if message.delivery == Some(DeliveryState::Sent) {
+        format!("✓ {}", text)
+    } else {
+        text
+    };
+    // greyed out until it's actually gone
+    if message.is_pending() {
+        return StyledString::styled(text, ColorStyle::secondary());
+    }
+
+    let style = if selected {
+        Style::from(ColorStyle::highlight())
+    } else {
+        Style::none()
+    };
+    // links are ranges of the body, which comes last (before the newline)
+    let body_start = match &message.content {
+        MessageType::Text { text: body } => text.len() - 1 - body.body.len(),
+        _ => 0,
+    };
+    let mut styled = StyledString::new();
+    let mut end = 0;
+    // the name comes right before the message, after the time and pin. Selected messages keep
+    // the highlight colors.
+    if has_name && !selected {
+        let name_start = text.len() - text_len;
+        styled.append_plain(&text[..name_start]);
+        styled.append_styled(username, username_color(username));
+        end = name_start + username.len();
+    }
+    // parts of the body drawn differently: links, and mentions of you that aren't links
+    let mut spans: Vec<(Range<usize>, Style)> = links
+        .iter()
+        .enumerate()
+        .map(|(index, (range, _))| {
+            let link_style = if focused_link == Some(index) {
+                Style::from(ColorStyle::highlight())
+            } else {
+                style
+            };
+            (range.clone(), link_style.combine(Effect::Underline))
+        })
+        .collect();
+    if let MessageType::Text { text: body } = &message.content {
+        for range in find_user_mentions(&body.body, my_username) {
+            if !spans.iter().any(|(link, _)| link.start < range.end && range.start < link.end) {
+                spans.push((range, style.combine(mention_style(selected))));
+            }
         }
-        _ => {}
     }
+    spans.sort_by_key(|(range, _)| range.start);
+    for (range, span_style) in spans {
+        let start = body_start + range.start;
+        styled.append_styled(&text[end..start], style);
+        styled.append_styled(&text[start..body_start + range.end], span_style);
+        end = body_start + range.end;
+    }
+    styled.append_styled(&text[end..], style);
+    styled
 }
 
-impl StateObserver for Ui {
-    fn on_conversation_change(&mut self, data: &Conversation) {
-        self.render_conversation(data);
-        self.cursive.focus_id("edit").unwrap();
+// mentions of you stand out, but selected messages keep the highlight colors
+fn mention_style(selected: bool) -> Style {
+    if selected {
+        Style::from(Effect::Bold)
+    } else {
+        Style::from(ColorStyle::title_primary()).combine(Effect::Bold)
     }
+}
 
-    fn on_conversations_added(&mut self, conversations: &[Conversation]) {
-        self.render_conversation_list(conversations);
+// picked for being readable on the default (white) background
+const USERNAME_COLORS: [Color; 10] = [
+    Color::Dark(BaseColor::Red),
+    Color::Dark(BaseColor::Green),
+    Color::Dark(BaseColor::Blue),
+    Color::Dark(BaseColor::Magenta),
+    Color::Dark(BaseColor::Cyan),
+    Color::Light(BaseColor::Red),
+    Color::Light(BaseColor::Green),
+    Color::Light(BaseColor::Blue),
+    Color::Light(BaseColor::Magenta),
+    Color::Light(BaseColor::Cyan),
+];
+
+// the same user always gets the same color
+fn username_color(username: &str) -> Color {
+    let mut hasher = DefaultHasher::new();
+    username.hash(&mut hasher);
+    USERNAME_COLORS[(hasher.finish() % USERNAME_COLORS.len() as u64) as usize]
+}
+
+// `HH:MM` in local time, if we know when it was sent
+fn sent_time(message: &Message) -> Option<String> {
+    if message.sent_at == 0 {
+        return None;
+    }
+    let time = Local.timestamp_opt(message.sent_at, 0).single()?;
+    Some(time.format("%H:%M").to_string())
+}
+
+fn render_unfurl(username: &str, unfurl: &UnfurlResult) -> String {
+    if let Some(giphy) = &unfurl.unfurl.giphy {
+        let title = unfurl.giphy_title();
+        let title = if title.is_empty() { "GIF" } else { &title };
+        let url = giphy.image_url.as_ref().unwrap_or(&unfurl.url);
+        format!("{} sent a GIF: {}\n  {}\n", username, title, url)
+    } else if let Some(generic) = &unfurl.unfurl.generic {
+        format!("{} shared a link: {} ({})\n", username, generic.title, generic.url)
+    } else {
+        format!(
+            "{} sent an Unfurl and I don't know how to render it\n",
+            username
+        )
     }
+}
 
-    fn on_message(&mut self, message: &Message, conversation_id: &str, active: bool) {
-        if active {
-            // write the message in the chat box
-            self.new_message(&message);
+fn render_attachment(username: &str, object: &AttachmentObject) -> String {
+    let mut details = vec![];
+    if !object.mime_type.is_empty() {
+        details.push(object.mime_type.clone());
+    }
+    details.push(format_size(object.size));
+    if let Some(dimensions) = object.dimensions() {
+        details.push(format!("{}x{}", dimensions.width, dimensions.height));
+    }
+    format!("{} sent {} ({})\n", username, object.filename, details.join(", "))
+}
+
+// e.g. 1258291 -> `1.2 MB`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+// in compact mode, only the first of several text messages in a row from someone gets their name
+fn should_show_sender(compact: bool, previous: Option<&Message>, message: &Message) -> bool {
+    let continues = matches!(
+        previous,
+        Some(p) if p.sender.username == message.sender.username
+            && matches!(p.content, MessageType::Text { .. })
+    );
+    !(compact && continues)
+}
+
+// where the body of a text message starts in `rendered`, which its wrapped rows line up under
+fn body_column(message: &Message, rendered: &StyledString) -> usize {
+    match &message.content {
+        MessageType::Text { text } => {
+            // the body comes last, before the newline
+            let source = rendered.source();
+            let start = source.len().saturating_sub(text.body.len() + 1);
+            StyledString::plain(&source[..start]).width()
+        }
+        _ => 0,
+    }
+}
+
+// every message in the chat area with how it's drawn and how far its wrapped rows are indented,
+// oldest first
+fn render_chat_messages(state: &UiState) -> Vec<(u64, StyledString, usize)> {
+    let mut rendered = vec![];
+    if let Some(convo) = &state.conversation {
+        let selected: Vec<u64> =
+            selected_range(&convo.messages, state.selection_anchor, state.selected_message)
+                .iter()
+                .map(|m| m.id)
+                .collect();
+        let filter = state.sender_filter.as_deref();
+        let focused = focused_reaction(state);
+        let focused_link = focused_link(state);
+        let loaded: HashSet<u64> = convo.messages.iter().map(|m| m.id).collect();
+        let visible: Vec<&Message> = convo
+            .messages
+            .iter()
+            .rev()
+            .filter(|m| matches_sender(m, filter))
+            // drawn with the message they react to instead, if we have it
+            .filter(|m| match &m.content {
+                MessageType::Reaction { reaction } => !loaded.contains(&reaction.message_id),
+                _ => true,
+            })
+            .collect();
+        let order = if state.config.layout.threaded {
+            build_thread(&visible)
         } else {
-            // highlight the conversation with unread messages
-            self.unread_message(conversation_id);
+            (0..visible.len()).map(|index| (index, 0)).collect()
+        };
+        let references = if state.show_references {
+            recent_references(&convo.messages, filter)
+        } else {
+            vec![]
+        };
+        let mut previous: Option<&Message> = None;
+        for (index, depth) in order {
+            let msg = visible[index];
+            let mut text = StyledString::new();
+            if let Some(number) = references.iter().position(|id| *id == msg.id) {
+                text.append_plain(format!("[{}] ", number + 1));
+            }
+            if depth > 0 {
+                text.append_plain(format!("{}↳ ", "  ".repeat(depth)));
+            }
+            let focused_link = match focused_link {
+                Some((id, index)) if id == msg.id => Some(index),
+                _ => None,
+            };
+            text.append(render_message(
+                msg,
+                convo.pinned.contains(&msg.id),
+                selected.contains(&msg.id),
+                should_show_sender(state.compact, previous, msg),
+                &message_links(msg, &state.sidebar),
+                focused_link,
+                &state.status.user,
+            ));
+            let focused = match focused {
+                Some((id, index)) if id == msg.id => Some(index),
+                _ => None,
+            };
+            let indent = body_column(msg, &text);
+            text.append(render_reactions(&convo.messages, msg.id, focused));
+            rendered.push((msg.id, text, indent));
+            previous = Some(msg);
         }
     }
+    rendered
 }
 
-impl StateObserver for Rc<RefCell<Ui>> {
-    fn on_conversation_change(&mut self, data: &Conversation) {
-        self.borrow_mut().on_conversation_change(data)
+// the reactions to a message as a line of `:+1: 2  :tada: 1` under it, with the focused one (if
+// any) highlighted
+fn render_reactions(messages: &[Message], message_id: u64, focused: Option<usize>) -> StyledString {
+    let mut line = StyledString::new();
+    let summaries = aggregate_reactions(messages, message_id);
+    if summaries.is_empty() {
+        return line;
+    }
+    line.append_plain("  ");
+    for (index, summary) in summaries.iter().enumerate() {
+        if index > 0 {
+            line.append_plain("  ");
+        }
+        if focused == Some(index) {
+            line.append_styled(summary.label(), ColorStyle::highlight());
+        } else {
+            line.append_plain(summary.label());
+        }
     }
+    line.append_plain("\n");
+    line
+}
 
-    fn on_conversations_added(&mut self, conversations: &[Conversation]) {
-        self.borrow_mut().on_conversations_added(conversations)
+// the focused reaction as (message id, index into its reactions), if it's still on the selected
+// message
+fn focused_reaction(state: &UiState) -> Option<(u64, usize)> {
+    match state.focused_reaction {
+        Some((id, index)) if state.selected_message == Some(id) => Some((id, index)),
+        _ => None,
     }
+}
 
-    fn on_message(&mut self, message: &Message, conversation_id: &str, active: bool) {
-        self.borrow_mut()
-            .on_message(message, conversation_id, active)
+// who reacted with the focused reaction, for the status bar
+fn focused_reactors(state: &UiState) -> Option<String> {
+    let (id, index) = focused_reaction(state)?;
+    let convo = state.conversation.as_ref()?;
+    aggregate_reactions(&convo.messages, id)
+        .get(index)
+        .map(|summary| summary.reactors())
+}
+
+// step through the reactions on the selected message, and then back to none
+fn focus_next_reaction(s: &mut Cursive) {
+    s.with_user_data(|executor: &mut UiExecutor| {
+        let state = &mut executor.state;
+        let (id, count) = match (&state.conversation, state.selected_message) {
+            (Some(convo), Some(id)) => (id, aggregate_reactions(&convo.messages, id).len()),
+            _ => return,
+        };
+        let next = match focused_reaction(state) {
+            Some((_, index)) => index + 1,
+            None => 0,
+        };
+        state.focused_reaction = if next < count { Some((id, next)) } else { None };
+    });
+    redraw_chat(s);
+}
+
+// the focused `@team`/`#channel` link as (message id, index into its links), if it's still on the
+// selected message
+fn focused_link(state: &UiState) -> Option<(u64, usize)> {
+    match state.focused_link {
+        Some((id, index)) if state.selected_message == Some(id) => Some((id, index)),
+        _ => None,
     }
 }
 
-#[derive(Clone)]
-struct UiExecutor {
-    sender: Sender<UiEvent>,
+// step through the links in the selected message, and then back to none
+fn focus_next_link(s: &mut Cursive) {
+    s.with_user_data(|executor: &mut UiExecutor| {
+        let state = &mut executor.state;
+        let count = match (&state.conversation, state.selected_message) {
+            (Some(convo), Some(id)) => match convo.messages.iter().find(|m| m.id == id) {
+                Some(message) => message_links(message, &state.sidebar).len(),
+                None => return,
+            },
+            _ => return,
+        };
+        let id = state.selected_message.unwrap();
+        let next = match focused_link(state) {
+            Some((_, index)) => index + 1,
+            None => 0,
+        };
+        state.focused_link = if next < count { Some((id, next)) } else { None };
+    });
+    redraw_chat(s);
 }
 
-// helper to create the view of available conversations on the left. Should probably go to its own
-// module.
-fn conversation_view(convo: Conversation) -> impl View {
-    let id = convo.id.clone();
-    let view = ConversationView::new(convo).with_id(id);
-    OnEventView::new(view)
-        // handle left clicking on a conversation name
-        .on_event_inner(
-            EventTrigger::mouse(),
-            handle_switch
-        )
-        // handle pressing enter when a conversation name has focus
-        .on_event_inner(
-            cursive::event::Key::Enter,
-            handle_switch
-        )
+// where the focused link goes, if there is one
+fn focused_link_target(state: &UiState) -> Option<MentionTarget> {
+    let (id, index) = focused_link(state)?;
+    let message = state.conversation.as_ref()?.messages.iter().find(|m| m.id == id)?;
+    message_links(message, &state.sidebar)
+        .into_iter()
+        .nth(index)
+        .map(|(_, target)| target)
 }
 
-fn handle_switch(v: &mut IdView<ConversationView>, e: &Event) -> Option<EventResult> {
-                if let Event::Mouse {
-                    event: MouseEvent::Release(MouseButton::Left),
-                    ..
-                } = *e
-                {
-                    let convo = v.conversation_id();
+// the link drawn at `column` of `row` in a chat area `width` columns wide, if any
+fn link_at(state: &UiState, width: usize, row: usize, column: usize) -> Option<MentionTarget> {
+    let convo = state.conversation.as_ref()?;
+    let mut top = 0;
+    for (id, text, indent) in render_chat_messages(state) {
+        let lines = wrap(&text, width, indent);
+        if row >= top + lines.len() {
+            top += lines.len();
+            continue;
+        }
+        let mut x = 0;
+        let span = lines[row - top].spans().find(|span| {
+            x += span.width;
+            column < x
+        })?;
+        let clicked = span.content;
+        let message = convo.messages.iter().find(|m| m.id == id)?;
+        // links are drawn as spans of their own, so the whole span is the mention
+        return match find_mentions(clicked).as_slice() {
+            [(range, mention)] if range.len() == clicked.len() => {
+                resolve_mention(mention, &message.channel, &state.sidebar)
+            }
+            _ => None,
+        };
+    }
+    None
+}
 
-                    Some(EventResult::with_cb(move |s| {
-                        s.with_user_data(|executor: &mut UiExecutor| {
-                            let mut exec = executor.clone();
-                            let c = convo.clone();
-                            tokio::spawn(async move {
-                                exec.sender.send(UiEvent::SwitchConversation(c)).await.ok();
-                            });
-                        });
-                    }))
-                } else {
-                    None
-                }
+fn follow_link(s: &mut Cursive, target: MentionTarget) {
+    match target {
+        MentionTarget::Conversation(conversation_id) => {
+            send_event(s, UiEvent::SwitchConversation(conversation_id));
+        }
+        MentionTarget::Channel(channel) => send_event(s, UiEvent::OpenChannel(channel)),
+    }
 }
 
-fn send_chat_message(s: &mut Cursive, msg: &str) {
-    if msg.is_empty() {
-        return;
+// how many rows each message takes up in a chat area `width` columns wide, once wrapped
+fn message_rows(state: &UiState, width: usize) -> Vec<(u64, usize)> {
+    render_chat_messages(state)
+        .into_iter()
+        .map(|(id, text, indent)| (id, wrap(&text, width, indent).len()))
+        .collect()
+}
+
+// re-render the whole chat area from the conversation stored in the UI state
+fn redraw_chat(s: &mut Cursive) {
+    let content = s
+        .with_user_data(|executor: &mut UiExecutor| {
+            render_chat_messages(&executor.state)
+                .into_iter()
+                .map(|(_, text, indent)| (text, indent))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    s.call_on_id("chat_container", |view: &mut ChatView| {
+        view.set_content(content);
+    });
+}
+
+// the conversation name, plus a reminder when some of it is filtered out
+fn chat_title(state: &UiState) -> String {
+    let name = match &state.conversation {
+        Some(convo) => convo.display_name(),
+        None => return String::new(),
+    };
+    let name = match &state.sender_filter {
+        Some(username) => format!("{} (only @{})", name, username),
+        None => name,
+    };
+    if state.paused {
+        format!("{} ⏸ updates paused ({} buffered)", name, state.paused_messages.len())
+    } else if state.new_below > 0 {
+        format!("{} ↓ {} new", name, state.new_below)
+    } else {
+        name
     }
+}
+
+// counts towards the "N new" in the chat title, unlike reactions, edits and so on
+fn is_new_message(message: &Message) -> bool {
+    matches!(message.content, MessageType::Text { .. } | MessageType::Attachment { .. })
+        && !message.is_pending()
+}
 
-    s.call_on_id("edit", |view: &mut EditView| view.set_content(""));
+// stop new messages from moving the chat around while reading, or show the ones that came in
+// since pausing
+fn toggle_paused(s: &mut Cursive) {
     s.with_user_data(|executor: &mut UiExecutor| {
-        let mut exec = executor.clone();
-        let c = msg.to_owned();
-        tokio::spawn(async move {
-            exec.sender.send(UiEvent::SendMessage(c)).await.ok();
+        let state = &mut executor.state;
+        state.paused = !state.paused;
+        if !state.paused {
+            let buffered = std::mem::take(&mut state.paused_messages);
+            if let Some(convo) = &mut state.conversation {
+                // in the order they arrived
+                for message in buffered {
+                    convo.insert_message(message);
+                }
+            }
+        }
+    });
+    redraw_chat(s);
+    update_chat_title(s);
+}
+
+fn update_chat_title(s: &mut Cursive) {
+    let title = s
+        .with_user_data(|executor: &mut UiExecutor| chat_title(&executor.state))
+        .unwrap_or_default();
+    s.call_on_id("chat_panel", |view: &mut Panel<LinearLayout>| view.set_title(title));
+}
+
+fn set_sender_filter(s: &mut Cursive, username: Option<String>) {
+    s.with_user_data(|executor: &mut UiExecutor| {
+        let state = &mut executor.state;
+        state.sender_filter = username;
+        state.selected_message = None;
+        state.selection_anchor = None;
+    });
+    redraw_chat(s);
+    update_chat_title(s);
+}
+
+// find-as-you-type in the current conversation: the chat jumps to the newest matching message as
+// you type, and enter steps back to older matches
+fn open_search(s: &mut Cursive) {
+    let edit = EditView::new()
+        .on_edit(|s, query, _| {
+            let query = query.to_string();
+            s.with_user_data(|executor: &mut UiExecutor| {
+                executor.state.pending_search = Some(PendingSearch {
+                    query,
+                    edited_at: Instant::now(),
+                });
+            });
+        })
+        .on_submit(|s, query| search(s, query, true));
+    let dialog = OnEventView::new(Dialog::around(edit.fixed_width(30)).title("Search"))
+        .on_event(Key::Esc, |s| {
+            s.pop_layer();
         });
+    s.add_layer(dialog);
+}
+
+// select (and scroll to) the newest message matching `query`, or the next older one with `next`
+fn search(s: &mut Cursive, query: &str, next: bool) {
+    let found = s
+        .with_user_data(|executor: &mut UiExecutor| {
+            let state = &mut executor.state;
+            let convo = state.conversation.as_ref()?;
+            let filter = state.sender_filter.as_deref();
+            let visible = convo.messages.iter().filter(|m| matches_sender(m, filter));
+            let from = if next { state.selected_message } else { None };
+            // start from the newest again after the oldest match
+            let found = find_match(visible.clone(), query, from)
+                .or_else(|| find_match(visible, query, None));
+            state.selected_message = found;
+            state.selection_anchor = None;
+            found
+        })
+        .flatten();
+
+    redraw_chat(s);
+    if let Some(message_id) = found {
+        scroll_to_message(s, message_id);
+    }
+}
+
+fn scroll_to_message(s: &mut Cursive, message_id: u64) {
+    let width = s
+        .call_on_id("chat_scroll", |view: &mut ChatScrollView| view.content_viewport().width())
+        .unwrap_or(0);
+    let row = s
+        .with_user_data(|executor: &mut UiExecutor| {
+            message_rows(&executor.state, width)
+                .iter()
+                .take_while(|(id, _)| *id != message_id)
+                .map(|(_, height)| height)
+                .sum::<usize>()
+        })
+        .unwrap_or(0);
+    restore_scroll_position(s, Some(row));
+}
+
+fn cycle_sort_mode(s: &mut Cursive) {
+    s.with_user_data(|executor: &mut UiExecutor| {
+        executor.state.sort_mode = executor.state.sort_mode.next();
     });
+    update_sidebar_title(s);
+    redraw_sidebar(s);
 }
 
-fn conversation_list() -> ViewBox {
-    let convo_list =
-        Panel::new(ListView::new().with_id("conversation_list")).title("Conversations");
-    ViewBox::new(
-        BoxView::new(SizeConstraint::Free, SizeConstraint::Full, convo_list).as_boxed_view(),
-    )
+fn update_sidebar_title(s: &mut Cursive) {
+    let mode = s
+        .with_user_data(|executor: &mut UiExecutor| executor.state.sort_mode)
+        .unwrap_or_default();
+    let title = match mode {
+        SortMode::Activity => "Conversations".to_string(),
+        mode => format!("Conversations ({})", mode.label()),
+    };
+    s.call_on_id("conversation_panel", |view: &mut Panel<IdView<ListView>>| {
+        view.set_title(title)
+    });
 }
 
-fn chat_area() -> ViewBox {
-    let mut text = TextView::new("").with_id("chat_container").scrollable();
-    text.set_scroll_strategy(cursive::view::ScrollStrategy::StickToBottom);
+fn toggle_references(s: &mut Cursive) {
+    s.with_user_data(|executor: &mut UiExecutor| {
+        executor.state.show_references = !executor.state.show_references;
+    });
+    redraw_chat(s);
+}
 
-    let chat_layout = LinearLayout::vertical()
-        .child(BoxView::new(
-            SizeConstraint::Full,
-            SizeConstraint::Full,
-            text,
-        ))
-        .child(EditView::new().on_submit(send_chat_message).with_id("edit"));
-    let chat = Panel::new(chat_layout).with_id("chat_panel");
+fn toggle_compact(s: &mut Cursive) {
+    s.with_user_data(|executor: &mut UiExecutor| {
+        executor.state.compact = !executor.state.compact;
+    });
+    redraw_chat(s);
+}
 
-    ViewBox::new(BoxView::new(SizeConstraint::Full, SizeConstraint::Full, chat).as_boxed_view())
+// move the message selection one message up (older) or down (newer). When `extend` is set the
+// previously selected message stays selected too, growing a range.
+fn select_message(s: &mut Cursive, older: bool, extend: bool) {
+    s.with_user_data(|executor: &mut UiExecutor| {
+        let state = &mut executor.state;
+        if let Some(convo) = &state.conversation {
+            if !extend {
+                state.selection_anchor = None;
+            } else if state.selection_anchor.is_none() {
+                state.selection_anchor = state.selected_message;
+            }
+            state.selected_message =
+                next_selection(&convo.messages, state.selected_message, older);
+        }
+    });
+    redraw_chat(s);
+}
+
+// the selected message(s) formatted with `format`, or None if nothing is selected
+fn selected_text(s: &mut Cursive, format: fn(&[&Message]) -> String) -> Option<String> {
+    s.with_user_data(|executor: &mut UiExecutor| {
+        let state = &executor.state;
+        state.conversation.as_ref().and_then(|convo| {
+            let range =
+                selected_range(&convo.messages, state.selection_anchor, state.selected_message);
+            if range.is_empty() {
+                None
+            } else {
+                Some(format(&range))
+            }
+        })
+    })
+    .flatten()
+}
+
+// put the selected message(s) into the edit box as a quote
+fn quote_selected_messages(s: &mut Cursive) {
+    if let Some(quote) = selected_text(s, quote_messages) {
+        s.call_on_id("edit", |view: &mut TextArea| {
+            let content = format!("{}{}", quote, view.get_content());
+            set_composer_content(view, content);
+        });
+        s.focus_id("edit").unwrap();
+    }
+}
+
+// Start a reply to the selected message, with a quote of it in the edit box. Sending it sends
+// everything typed after the quote as a reply; deleting the quote makes it a normal message again.
+fn reply_to_selected_message(s: &mut Cursive) {
+    let reply = s
+        .with_user_data(|executor: &mut UiExecutor| {
+            let state = &mut executor.state;
+            let message_id = state.selected_message?;
+            let message = state.conversation.as_ref()?.messages.iter().find(|m| m.id == message_id)?;
+            let preview = quote_messages(&[message]);
+            state.replying_to = Some((message_id, preview.clone()));
+            Some(preview)
+        })
+        .flatten();
+    if let Some(preview) = reply {
+        s.call_on_id("edit", |view: &mut TextArea| set_composer_content(view, preview));
+        s.focus_id("edit").unwrap();
+    }
+}
+
+// The message id and body of the reply being sent, if `msg` is one. The body is empty if nothing
+// was typed under the quote yet, and then it's still a reply once something is.
+fn take_reply(s: &mut Cursive, msg: &str) -> Option<(u64, String)> {
+    s.with_user_data(|executor: &mut UiExecutor| {
+        let (message_id, preview) = executor.state.replying_to.clone()?;
+        let body = match msg.strip_prefix(preview.as_str()) {
+            Some(body) if body.trim().is_empty() => return Some((message_id, String::new())),
+            Some(body) => body,
+            // the quote was taken out, so it's an ordinary message
+            None => {
+                executor.state.replying_to = None;
+                return None;
+            }
+        };
+        executor.state.replying_to = None;
+        // sent as typed, indentation and trailing spaces can matter (e.g. in code blocks)
+        Some((message_id, body.to_string()))
+    })
+    .flatten()
+}
+
+// pipe the selected message(s) into the configured clipboard command
+fn copy_selected_messages(s: &mut Cursive) {
+    let text = selected_text(s, format_messages);
+    let command = s
+        .with_user_data(|executor: &mut UiExecutor| executor.state.config.copy_command.clone())
+        .flatten();
+
+    match (text, command) {
+        (Some(text), Some(command)) => {
+            tokio::spawn(async move {
+                if let Err(e) = run_copy_command(&command, text).await {
+                    error!("Failed to copy messages with `{}`: {}", command, e);
+                }
+            });
+        }
+        (Some(_), None) => warn!("Can't copy messages without `copy_command` in the config"),
+        _ => {}
+    }
+}
+
+async fn run_copy_command(command: &str, text: String) -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = command.split_whitespace();
+    let program = args.next().ok_or("empty command")?;
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .spawn()?;
+    {
+        // scoped so that the pipe is dropped
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(text.as_bytes()).await?;
+    }
+    child.await?;
+    Ok(())
+}
+
+fn pin_selected_message(s: &mut Cursive, pin: bool) {
+    let selected = s
+        .with_user_data(|executor: &mut UiExecutor| executor.state.selected_message)
+        .flatten();
+
+    if let Some(message_id) = selected {
+        if pin {
+            send_event(s, UiEvent::PinMessage(message_id));
+        } else {
+            send_event(s, UiEvent::UnpinMessage(message_id));
+        }
+    }
+}
+
+// ask for an emoji to react to the selected message with
+fn react_to_selected_message(s: &mut Cursive) {
+    let selected = s
+        .with_user_data(|executor: &mut UiExecutor| executor.state.selected_message)
+        .flatten();
+    let message_id = match selected {
+        Some(message_id) => message_id,
+        None => return,
+    };
+
+    let edit = EditView::new().on_submit(move |s, reaction| {
+        s.pop_layer();
+        send_reaction(s, message_id, reaction);
+    });
+    let dialog = OnEventView::new(Dialog::around(edit.fixed_width(20)).title("React with"))
+        .on_event(Key::Esc, |s| {
+            s.pop_layer();
+        });
+    s.add_layer(dialog);
+}
+
+// `+1` and `:+1:` both work
+fn reaction_name(reaction: &str) -> String {
+    if reaction.starts_with(':') {
+        reaction.to_string()
+    } else {
+        format!(":{}:", reaction)
+    }
+}
+
+fn send_reaction(s: &mut Cursive, message_id: u64, reaction: &str) {
+    let reaction = reaction.trim();
+    if reaction.is_empty() {
+        return;
+    }
+    send_event(s, UiEvent::React(message_id, reaction_name(reaction)));
+}
+
+// ask who to start a conversation with, or which team channel to open
+fn open_new_conversation(s: &mut Cursive) {
+    let layout = LinearLayout::vertical()
+        .child(TextView::new("Usernames (alice,bob) or team#channel"))
+        .child(EditView::new().on_submit(submit_new_conversation).fixed_width(40))
+        .child(TextView::new("").with_id("new_conversation_error"));
+    let dialog = OnEventView::new(Dialog::around(layout).title("New conversation"))
+        .on_event(Key::Esc, |s| {
+            s.pop_layer();
+        });
+    s.add_layer(dialog);
+}
+
+// closes the dialog, unless there's something wrong with what was typed
+fn submit_new_conversation(s: &mut Cursive, input: &str) {
+    match parse_new_conversation(input) {
+        Ok((name, members_type)) => {
+            s.pop_layer();
+            send_event(s, UiEvent::NewConversation { name, members_type });
+        }
+        Err(e) => {
+            let error = StyledString::styled(e, Color::Dark(BaseColor::Red));
+            s.call_on_id("new_conversation_error", |view: &mut TextView| view.set_content(error));
+        }
+    }
+}
+
+// Ctrl+k: type part of a conversation's name, and Enter switches to the best match (or the one
+// picked with Up/Down)
+fn open_quick_switcher(s: &mut Cursive) {
+    let edit = EditView::new()
+        .on_edit(|s, query, _| update_quick_switcher(s, query))
+        .on_submit(|s, _| submit_quick_switcher(s));
+    let results = SelectView::<String>::new().with_id("switcher_results");
+    let layout = LinearLayout::vertical()
+        .child(edit.fixed_width(40))
+        .child(results.scrollable().max_height(10));
+    // the arrows move through the results while typing
+    let dialog = OnEventView::new(Dialog::around(layout).title("Switch to"))
+        .on_pre_event(Key::Up, |s| move_quick_switcher(s, true))
+        .on_pre_event(Key::Down, |s| move_quick_switcher(s, false))
+        .on_event(Key::Esc, |s| {
+            s.pop_layer();
+        });
+    s.add_layer(dialog);
+    update_quick_switcher(s, "");
+}
+
+fn update_quick_switcher(s: &mut Cursive, query: &str) {
+    let conversations: Vec<(String, String)> = s
+        .with_user_data(|executor: &mut UiExecutor| {
+            executor
+                .state
+                .sidebar
+                .iter()
+                .filter(|c| !c.hidden)
+                .map(|c| (c.get_name(), c.id.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+    let ranked = rank(query, conversations.iter().map(|(name, _)| name.as_str()));
+    s.call_on_id("switcher_results", |view: &mut SelectView<String>| {
+        view.clear();
+        for index in ranked {
+            let (name, id) = &conversations[index];
+            view.add_item(name.as_str(), id.clone());
+        }
+    });
+}
+
+fn submit_quick_switcher(s: &mut Cursive) {
+    let picked = s
+        .call_on_id("switcher_results", |view: &mut SelectView<String>| view.selection())
+        .flatten();
+    if let Some(conversation_id) = picked {
+        s.pop_layer();
+        send_event(s, UiEvent::SwitchConversation(conversation_id.to_string()));
+    }
+}
+
+fn move_quick_switcher(s: &mut Cursive, up: bool) {
+    let callback = s.call_on_id("switcher_results", |view: &mut SelectView<String>| {
+        if up {
+            view.select_up(1)
+        } else {
+            view.select_down(1)
+        }
+    });
+    if let Some(callback) = callback {
+        callback(s);
+    }
+}
+
+// keybase usernames are letters, numbers and underscores, team names can have dots too
+fn is_valid_name(name: &str, team: bool) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || (team && (c == '.' || c == '-')))
+}
+
+// `alice`, `@alice, bob` or `team#channel` (a team on its own means its general channel), as the
+// name to ask the controller for
+fn parse_new_conversation(input: &str) -> Result<(String, MemberType), String> {
+    let input = input.trim().to_lowercase();
+    if input.is_empty() {
+        return Err("Enter some usernames or a team#channel".to_string());
+    }
+    if input.contains('#') {
+        return match input.split_once('#') {
+            Some((team, topic)) if is_valid_name(team, true) && is_valid_name(topic, true) => {
+                Ok((input.to_string(), MemberType::Team))
+            }
+            _ => Err(format!("Not a team channel: {}", input)),
+        };
+    }
+    let mut users = vec![];
+    for user in input.split(',') {
+        let user = user.trim().trim_start_matches('@');
+        if !is_valid_name(user, false) {
+            return Err(format!("Not a username: {}", user));
+        }
+        users.push(user);
+    }
+    Ok((users.join(","), MemberType::User))
+}
+
+impl StateObserver for Ui {
+    fn on_conversation_change(&mut self, data: &Conversation) {
+        let name = data.display_name();
+        self.set_status(|status| status.conversation = name);
+        self.render_conversation(data);
+        finish_goto(&mut self.cursive, data);
+        self.cursive.focus_id("edit").unwrap();
+    }
+
+    fn on_conversations_added(&mut self, conversations: &[Conversation]) {
+        self.render_conversation_list(conversations);
+    }
+
+    fn on_conversations_appended(&mut self, conversations: &[Conversation], more: bool) {
+        self.cursive.with_user_data(|executor: &mut UiExecutor| {
+            executor.state.sidebar.extend_from_slice(conversations);
+            executor.state.more_conversations = more;
+        });
+        redraw_sidebar(&mut self.cursive);
+        self.cursive.refresh();
+    }
+
+    fn on_message(&mut self, message: &Message, conversation_id: &str, active: bool) {
+        // checked before the message goes in, which pushes the content down a row
+        let at_bottom = self
+            .cursive
+            .call_on_id("chat_scroll", |view: &mut ChatScrollView| view.is_at_bottom())
+            .unwrap_or(false);
+        if active {
+            let was_typing = self
+                .cursive
+                .with_user_data(|executor: &mut UiExecutor| {
+                    matches!(&executor.state.typing, Some(t) if t.username == message.sender.username)
+                })
+                .unwrap_or(false);
+            if was_typing {
+                set_typing(&mut self.cursive, None);
+            }
+            // write the message in the chat box
+            self.new_message(&message);
+            let paused = self
+                .cursive
+                .with_user_data(|executor: &mut UiExecutor| executor.state.paused)
+                .unwrap_or(false);
+            if at_bottom {
+                // follow it down, even if the chat was scrolled somewhere before
+                restore_scroll_position(&mut self.cursive, None);
+            } else if !paused && is_new_message(message) {
+                self.cursive
+                    .with_user_data(|executor: &mut UiExecutor| executor.state.new_below += 1);
+                update_chat_title(&mut self.cursive);
+            }
+        } else if is_selectable(message) {
+            // highlight the conversation with unread messages, more so if they're about you (but
+            // not for edits, reactions and so on to what's there already)
+            let mentioned = self
+                .cursive
+                .with_user_data(|executor: &mut UiExecutor| {
+                    mentions_user(message, &executor.state.status.user)
+                })
+                .unwrap_or(false);
+            self.unread_message(conversation_id, mentioned);
+        }
+        self.cursive.with_user_data(|executor: &mut UiExecutor| {
+            let state = &mut executor.state;
+            // paused messages haven't been seen yet
+            let at_bottom = at_bottom && !state.paused;
+            if should_mark_read(active, at_bottom, &message.sender.username, &state.status.user) {
+                state.pending_read = Some(PendingRead {
+                    conversation_id: conversation_id.to_string(),
+                    message_id: message.id,
+                    received_at: Instant::now(),
+                });
+            }
+        });
+    }
+
+    fn on_conversation_updated(&mut self, data: &Conversation) {
+        self.update_conversation(data);
+    }
+
+    fn on_username_changed(&mut self, username: &str) {
+        let username = username.to_string();
+        self.set_status(|status| status.user = username);
+    }
+
+    fn on_typing(&mut self, conversation_id: &str, username: &str) {
+        let on_screen = self
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| {
+                matches!(&executor.state.conversation, Some(c) if c.id == conversation_id)
+            })
+            .unwrap_or(false);
+        if on_screen {
+            let typing = Typing {
+                username: username.to_string(),
+                started_at: Instant::now(),
+            };
+            set_typing(&mut self.cursive, Some(typing));
+            self.cursive.refresh();
+        }
+    }
+
+    fn on_notice(&mut self, text: &str) {
+        set_notice(&mut self.cursive, text);
+        self.cursive.refresh();
+    }
+
+    fn on_error(&mut self, message: String) {
+        set_input_error(&mut self.cursive, Some(message));
+        self.cursive.refresh();
+    }
+
+    fn on_loading(&mut self, conversation_id: &str, loading: bool) {
+        let since = if loading { Some(Instant::now()) } else { None };
+        self.cursive.with_user_data(|executor: &mut UiExecutor| {
+            let state = &mut executor.state;
+            state.loading_since = since;
+            // any older messages asked for aren't coming, so scrolling up can ask again
+            if !loading && state.conversation.as_ref().is_some_and(|c| c.id == conversation_id) {
+                state.loading_history = None;
+            }
+        });
+        if loading {
+            self.animate_loading();
+        } else {
+            // put back whatever was there, in case the conversation doesn't change after all
+            redraw_chat(&mut self.cursive);
+        }
+        self.cursive.refresh();
+    }
+
+    fn on_send_failed(&mut self, body: &str) {
+        restore_draft(&mut self.cursive, body);
+        self.cursive.refresh();
+    }
+
+    fn on_channels(&mut self, team: &str, topic_names: &[String]) {
+        show_channel_picker(&mut self.cursive, team, topic_names);
+        self.cursive.refresh();
+    }
+
+    fn on_download(&mut self, path: &Path, mime_type: &str) {
+        if !cfg!(feature = "image_preview") {
+            return;
+        }
+        let preview = detect_graphics(|name| std::env::var(name).ok())
+            .and_then(|graphics| preview_sequence(graphics, path, mime_type));
+        // otherwise the notice saying where it went is all there is
+        if let Some(preview) = preview {
+            show_image_preview(&mut self.cursive, path, preview);
+            self.cursive.refresh();
+        }
+    }
+
+    fn on_status(&mut self, status: ConnectionStatus) {
+        self.set_status(|s| s.connection = status);
+    }
+}
+
+impl StateObserver for Rc<RefCell<Ui>> {
+    fn on_conversation_change(&mut self, data: &Conversation) {
+        self.borrow_mut().on_conversation_change(data)
+    }
+
+    fn on_conversations_added(&mut self, conversations: &[Conversation]) {
+        self.borrow_mut().on_conversations_added(conversations)
+    }
+
+    fn on_conversations_appended(&mut self, conversations: &[Conversation], more: bool) {
+        self.borrow_mut().on_conversations_appended(conversations, more)
+    }
+
+    fn on_message(&mut self, message: &Message, conversation_id: &str, active: bool) {
+        self.borrow_mut()
+            .on_message(message, conversation_id, active)
+    }
+
+    fn on_conversation_updated(&mut self, data: &Conversation) {
+        self.borrow_mut().on_conversation_updated(data)
+    }
+
+    fn on_username_changed(&mut self, username: &str) {
+        self.borrow_mut().on_username_changed(username)
+    }
+
+    fn on_typing(&mut self, conversation_id: &str, username: &str) {
+        self.borrow_mut().on_typing(conversation_id, username)
+    }
+
+    fn on_notice(&mut self, text: &str) {
+        self.borrow_mut().on_notice(text)
+    }
+
+    fn on_error(&mut self, message: String) {
+        self.borrow_mut().on_error(message)
+    }
+
+    fn on_loading(&mut self, conversation_id: &str, loading: bool) {
+        self.borrow_mut().on_loading(conversation_id, loading)
+    }
+
+    fn on_send_failed(&mut self, body: &str) {
+        self.borrow_mut().on_send_failed(body)
+    }
+
+    fn on_channels(&mut self, team: &str, topic_names: &[String]) {
+        self.borrow_mut().on_channels(team, topic_names)
+    }
+
+    fn on_download(&mut self, path: &Path, mime_type: &str) {
+        self.borrow_mut().on_download(path, mime_type)
+    }
+
+    fn on_status(&mut self, status: ConnectionStatus) {
+        self.borrow_mut().on_status(status)
+    }
+}
+
+struct UiExecutor {
+    sender: Sender<UiEvent>,
+    state: UiState,
+}
+
+// State that belongs to the UI rather than the application, kept in the cursive user data so
+// that callbacks can get at it.
+#[derive(Default)]
+struct UiState {
+    config: Config,
+    // copy of the conversation shown in the chat area
+    conversation: Option<Conversation>,
+    // message selected in the chat area, which message actions (like pinning) apply to
+    selected_message: Option<u64>,
+    // other end of a range of selected messages (for quoting/copying several at once)
+    selection_anchor: Option<u64>,
+    // messages sent from the message box, for Up and Down
+    history: InputHistory,
+    // names for the `@name` being typed, listed above the message box
+    completion: Option<Completion>,
+    // message being replied to (Alt+a), and the quote of it put in the edit box
+    replying_to: Option<(u64, String)>,
+    // reaction picked out with Alt+r, as (message id, index into its reactions). Only counts
+    // while that message is selected.
+    focused_reaction: Option<(u64, usize)>,
+    // same again for `@team`/`#channel` links with Alt+l, which Enter follows
+    focused_link: Option<(u64, usize)>,
+    // starts out as `layout.compact`, but can be toggled while running
+    compact: bool,
+    // number the newest messages for `/r<n>` and `/reply<n>` (toggled with Alt+n)
+    show_references: bool,
+    // starts out as `sort_mode`, but can be cycled through while running
+    sort_mode: SortMode,
+    // search text that hasn't been searched for yet
+    pending_search: Option<PendingSearch>,
+    // new messages aren't drawn while paused (Alt+z), they wait in `paused_messages`
+    paused: bool,
+    paused_messages: Vec<Message>,
+    // messages seen that keybase doesn't know we've read yet
+    pending_read: Option<PendingRead>,
+    // escape sequence for an image to draw once its preview layer is on screen
+    pending_preview: Option<Vec<u8>>,
+    // the terminal understands OSC 8, so URLs in the chat can be made clickable
+    hyperlinks: bool,
+    // someone typing in the conversation on screen
+    typing: Option<Typing>,
+    // when messages for the conversation being opened started loading
+    loading_since: Option<Instant>,
+    // only show messages from this user in the chat area (see `/only`)
+    sender_filter: Option<String>,
+    // (conversation id, message id) that `/goto` is waiting for the controller to open
+    pending_goto: Option<(String, u64)>,
+    // oldest message on screen when older ones were asked for, to keep our place once they arrive
+    loading_history: Option<u64>,
+    // every conversation in the sidebar (including hidden ones), in order
+    sidebar: Vec<Conversation>,
+    // ids of the conversations actually listed, top to bottom, for stepping through with j/k
+    sidebar_order: Vec<String>,
+    // only list conversations with this in their name (typed in above the list)
+    sidebar_filter: String,
+    // there are conversations left to load (see `startup_conversation_limit`)
+    more_conversations: bool,
+    status: StatusInfo,
+    // shared with the client executor, which records how long each api call takes
+    latency: Arc<Latency>,
+    // the status bar needs re-rendering
+    status_changed: bool,
+    // the sidebar's highlights might have changed, so the unread count needs counting again
+    unread_changed: bool,
+    scroll_memory: ScrollMemory,
+    // messages that came in below what's on screen while scrolled up, shown in the chat title
+    new_below: usize,
+}
+
+// Whether a new message means the conversation can be marked read: it has to be the one on screen,
+// scrolled all the way down (not reading history further up), and from someone else, since
+// sending already marks a conversation read.
+fn should_mark_read(active: bool, at_bottom: bool, sender: &str, me: &str) -> bool {
+    active && at_bottom && sender != me
+}
+
+// The newest message seen in the active conversation. Replaced by each new message, so a burst of
+// them gets marked read in one go.
+struct PendingRead {
+    conversation_id: String,
+    message_id: u64,
+    received_at: Instant,
+}
+
+impl PendingRead {
+    fn is_due(&self, now: Instant) -> bool {
+        now.duration_since(self.received_at) >= READ_DEBOUNCE
+    }
+}
+
+struct Typing {
+    username: String,
+    started_at: Instant,
+}
+
+impl Typing {
+    fn is_expired(&self, now: Instant) -> bool {
+        now.duration_since(self.started_at) >= TYPING_TIMEOUT
+    }
+}
+
+// `Loading… |`, with the spinner going round
+fn loading_text(elapsed: Duration) -> StyledString {
+    let frame = (elapsed.as_millis() / SPINNER_FRAME.as_millis()) as usize % SPINNER.len();
+    StyledString::styled(format!("Loading… {}", SPINNER[frame]), ColorStyle::secondary())
+}
+
+// show (or with None, clear) the line under the chat saying who's typing
+fn set_typing(s: &mut Cursive, typing: Option<Typing>) {
+    let text = match &typing {
+        Some(typing) => StyledString::styled(
+            format!("{} is typing…", typing.username),
+            ColorStyle::secondary(),
+        ),
+        None => StyledString::new(),
+    };
+    s.with_user_data(|executor: &mut UiExecutor| executor.state.typing = typing);
+    s.call_on_id("typing_indicator", |view: &mut TextView| view.set_content(text));
+}
+
+struct PendingSearch {
+    query: String,
+    edited_at: Instant,
+}
+
+impl PendingSearch {
+    fn is_due(&self, now: Instant) -> bool {
+        now.duration_since(self.edited_at) >= SEARCH_DEBOUNCE
+    }
+}
+
+// where a conversation was left: the top row on screen, and the newest message in it then
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ScrollPlace {
+    row: usize,
+    newest: Option<u64>,
+}
+
+// Where each conversation we switched away from was scrolled to, so switching back puts you where
+// you were. Conversations that were scrolled all the way down aren't kept, they just stick to the
+// bottom again like a fresh conversation.
+#[derive(Default)]
+struct ScrollMemory {
+    places: HashMap<String, ScrollPlace>,
+}
+
+impl ScrollMemory {
+    fn save(&mut self, conversation_id: &str, place: ScrollPlace, at_bottom: bool) {
+        if at_bottom {
+            self.places.remove(conversation_id);
+        } else {
+            self.places.insert(conversation_id.to_string(), place);
+        }
+    }
+
+    // only restored once, after that the scroll view keeps track of it
+    fn take(&mut self, conversation_id: &str) -> Option<ScrollPlace> {
+        self.places.remove(conversation_id)
+    }
+}
+
+type ChatScrollView = ScrollView<IdView<ChatView>>;
+
+// remember where the conversation on screen is scrolled to, unless it's `next_id` (which is only
+// being drawn again)
+fn save_scroll_position(s: &mut Cursive, next_id: &str) {
+    let position = s.call_on_id("chat_scroll", |view: &mut ChatScrollView| {
+        (view.content_viewport().top(), view.is_at_bottom())
+    });
+    if let Some((row, at_bottom)) = position {
+        s.with_user_data(|executor: &mut UiExecutor| {
+            let state = &mut executor.state;
+            if let Some(convo) = state.conversation.as_ref().filter(|c| c.id != next_id) {
+                let newest = convo.messages.iter().find(|m| !m.is_pending()).map(|m| m.id);
+                state.scroll_memory.save(&convo.id, ScrollPlace { row, newest }, at_bottom);
+            }
+        });
+    }
+}
+
+fn restore_scroll_position(s: &mut Cursive, row: Option<usize>) {
+    match row {
+        Some(row) => {
+            s.call_on_id("chat_scroll", |view: &mut ChatScrollView| {
+                view.set_scroll_strategy(ScrollStrategy::KeepRow);
+            });
+            // the new content needs to be laid out before we can scroll through it
+            let size = s.screen_size();
+            s.screen_mut().layout(size);
+            s.call_on_id("chat_scroll", |view: &mut ChatScrollView| {
+                view.set_offset((0, row));
+            });
+        }
+        None => {
+            s.call_on_id("chat_scroll", |view: &mut ChatScrollView| {
+                view.set_scroll_strategy(ScrollStrategy::StickToBottom);
+            });
+        }
+    }
+}
+
+fn mark_unread_changed(s: &mut Cursive) {
+    s.with_user_data(|executor: &mut UiExecutor| executor.state.unread_changed = true);
+}
+
+// re-render the conversation list from the UI state, leaving out hidden conversations
+fn redraw_sidebar(s: &mut Cursive) {
+    let (mut conversations, sort_mode, order, member_counts, more, filter, current) = s
+        .with_user_data(|executor: &mut UiExecutor| {
+            let state = &executor.state;
+            (
+                state.sidebar.clone(),
+                state.sort_mode,
+                state.config.conversation_order.clone(),
+                state.config.layout.member_counts,
+                state.more_conversations,
+                state.sidebar_filter.clone(),
+                state.conversation.as_ref().map(|c| c.id.clone()),
+            )
+        })
+        .unwrap_or_default();
+
+    // the views are thrown away, so hang on to which ones were highlighted
+    let unread: Vec<String> = conversations
+        .iter()
+        .filter(|c| {
+            s.call_on_id(&c.id, |view: &mut ConversationView| view.unread)
+                .unwrap_or(false)
+        })
+        .map(|c| c.id.clone())
+        .collect();
+    let mentioned: Vec<String> = conversations
+        .iter()
+        .filter(|c| {
+            s.call_on_id(&c.id, |view: &mut ConversationView| view.mentioned)
+                .unwrap_or(false)
+        })
+        .map(|c| c.id.clone())
+        .collect();
+
+    // conversations without a view yet (e.g. on the first render) go by what keybase says
+    let sort_unread: Vec<String> = conversations
+        .iter()
+        .filter(|c| {
+            !c.muted
+                && s.call_on_id(&c.id, |view: &mut ConversationView| view.unread)
+                    .unwrap_or(c.data.unread)
+        })
+        .map(|c| c.id.clone())
+        .collect();
+    sort_conversations(
+        &mut conversations,
+        sort_mode,
+        |c| sort_unread.contains(&c.id),
+        &order,
+    );
+
+    // the open conversation stays in the list whatever the filter is
+    let shown: Vec<&Conversation> = conversations
+        .iter()
+        .filter(|c| !c.hidden && (matches_filter(c, &filter) || current.as_ref() == Some(&c.id)))
+        .collect();
+    let order = shown.iter().map(|c| c.id.clone()).collect();
+    s.with_user_data(|executor: &mut UiExecutor| {
+        executor.state.sidebar_order = order;
+        executor.state.unread_changed = true;
+    });
+
+    s.call_on_id("conversation_list", |view: &mut ListView| {
+        view.clear();
+        for convo in shown {
+            debug!("Adding child: {}", &convo.get_name());
+            let mut child = ConversationView::new(convo.clone());
+            // muting it clears what was unread
+            child.unread = !convo.muted && unread.contains(&convo.id);
+            child.mentioned = !convo.muted && mentioned.contains(&convo.id);
+            child.show_member_count = member_counts;
+            view.add_child("", conversation_view(child))
+        }
+        if more {
+            view.add_child(
+                "",
+                Button::new_raw("Load more...", |s| {
+                    send_event(s, UiEvent::LoadMoreConversations)
+                }),
+            );
+        }
+    });
+}
+
+// The next (or previous) of `len` conversations after `current`, going round at the ends. With
+// none open it's the first (or last) one.
+fn step_index(current: Option<usize>, len: usize, forward: bool) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+    Some(match (current, forward) {
+        (Some(index), true) => (index + 1) % len,
+        (Some(index), false) => (index + len - 1) % len,
+        (None, true) => 0,
+        (None, false) => len - 1,
+    })
+}
+
+fn switch_to_adjacent(s: &mut Cursive, forward: bool) {
+    let next = s
+        .with_user_data(|executor: &mut UiExecutor| {
+            let state = &executor.state;
+            let current = state.conversation.as_ref().and_then(|convo| {
+                state.sidebar_order.iter().position(|id| *id == convo.id)
+            });
+            let index = step_index(current, state.sidebar_order.len(), forward)?;
+            Some(state.sidebar_order[index].clone())
+        })
+        .flatten();
+    if let Some(conversation_id) = next {
+        send_event(s, UiEvent::SwitchConversation(conversation_id));
+    }
+}
+
+// The first unread conversation after `current`, going round the end of the list. None if the
+// only unread one (if any) is `current`.
+fn next_unread(unread: &[bool], current: Option<usize>) -> Option<usize> {
+    let len = unread.len();
+    let start = current.map_or(0, |index| index + 1);
+    (start..start + len)
+        .map(|index| index % len)
+        .take_while(|index| Some(*index) != current)
+        .find(|index| unread[*index])
+}
+
+fn switch_to_next_unread(s: &mut Cursive) {
+    let (order, current) = s
+        .with_user_data(|executor: &mut UiExecutor| {
+            let state = &executor.state;
+            let current = state.conversation.as_ref().and_then(|convo| {
+                state.sidebar_order.iter().position(|id| *id == convo.id)
+            });
+            let order: Vec<(String, bool)> = state
+                .sidebar_order
+                .iter()
+                .filter_map(|id| state.sidebar.iter().find(|c| c.id == *id))
+                .map(|c| (c.id.clone(), !c.muted && (c.data.unread || c.unread_count > 0)))
+                .collect();
+            (order, current)
+        })
+        .unwrap_or_default();
+    // highlighted in the list if there's a view for it, otherwise it's what keybase says
+    let unread: Vec<bool> = order
+        .iter()
+        .map(|(id, unread)| {
+            s.call_on_id(id, |view: &mut ConversationView| view.unread)
+                .unwrap_or(*unread)
+        })
+        .collect();
+    if let Some(index) = next_unread(&unread, current) {
+        send_event(s, UiEvent::SwitchConversation(order[index].0.clone()));
+    }
+}
+
+// case-insensitive, anywhere in the name
+fn matches_filter(conversation: &Conversation, filter: &str) -> bool {
+    conversation
+        .display_name()
+        .to_lowercase()
+        .contains(&filter.trim().to_lowercase())
+}
+
+fn filter_conversations(s: &mut Cursive, filter: &str, _: usize) {
+    let filter = filter.to_string();
+    s.with_user_data(|executor: &mut UiExecutor| executor.state.sidebar_filter = filter);
+    redraw_sidebar(s);
+}
+
+fn show_unhide_picker(s: &mut Cursive) {
+    let hidden: Vec<(String, String)> = s
+        .with_user_data(|executor: &mut UiExecutor| {
+            executor
+                .state
+                .sidebar
+                .iter()
+                .filter(|c| c.hidden)
+                .map(|c| (c.display_name(), c.id.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if hidden.is_empty() {
+        s.add_layer(Dialog::info("No hidden conversations"));
+        return;
+    }
+
+    let picker = SelectView::new()
+        .with_all(hidden)
+        .on_submit(|s, conversation_id: &String| {
+            s.pop_layer();
+            send_event(s, UiEvent::UnhideConversation(conversation_id.clone()));
+        });
+    s.add_layer(Dialog::around(picker).title("Unhide").dismiss_button("Cancel"));
+}
+
+// `c` on a team in the conversation list, the picker shows up once the channels are fetched
+fn list_team_channels(s: &mut Cursive, conversation_id: &str) {
+    let team = s
+        .with_user_data(|executor: &mut UiExecutor| {
+            executor
+                .state
+                .sidebar
+                .iter()
+                .find(|c| c.id == conversation_id)
+                .filter(|c| c.data.channel.members_type == MemberType::Team)
+                .map(|c| c.data.channel.name.clone())
+        })
+        .flatten();
+    match team {
+        Some(team) => send_event(s, UiEvent::ListChannels(team)),
+        None => set_input_error(s, Some("Only teams have channels".to_string())),
+    }
+}
+
+fn show_channel_picker(s: &mut Cursive, team: &str, topic_names: &[String]) {
+    if topic_names.is_empty() {
+        s.add_layer(Dialog::info(format!("No channels in {}", team)));
+        return;
+    }
+
+    let team = team.to_string();
+    let picker = SelectView::new()
+        .with_all(topic_names.iter().map(|topic| (format!("#{}", topic), topic.clone())))
+        .on_submit(move |s, topic_name: &String| {
+            s.pop_layer();
+            open_team_channel(s, &team, topic_name);
+        });
+    s.add_layer(Dialog::around(picker.scrollable()).title("Channels").dismiss_button("Cancel"));
+}
+
+// A screen of its own for an image, so where it's drawn is known. It's drawn after cursive has
+// drawn this (see `draw_pending_preview`).
+fn show_image_preview(s: &mut Cursive, path: &Path, preview: Vec<u8>) {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let title = TextView::new(format!("{} (Esc to close)", name));
+    let layer = OnEventView::new(LinearLayout::vertical().child(title).child(DummyView.full_screen()))
+        .on_event(Key::Esc, |s| {
+            s.pop_layer();
+            let mut stdout = io::stdout();
+            stdout.write_all(CLEAR_SEQUENCE.as_bytes()).ok();
+            stdout.flush().ok();
+            // sixels stay until everything's drawn over them
+            s.clear();
+        });
+    s.add_fullscreen_layer(layer);
+    s.with_user_data(|executor: &mut UiExecutor| executor.state.pending_preview = Some(preview));
+}
+
+// switches to the channel, joining it first if it isn't in the sidebar
+fn open_team_channel(s: &mut Cursive, team: &str, topic_name: &str) {
+    let channel = Channel {
+        name: team.to_string(),
+        topic_name: topic_name.to_string(),
+        members_type: MemberType::Team,
+    };
+    send_event(s, UiEvent::OpenChannel(channel));
+}
+
+fn confirm_delete(s: &mut Cursive) {
+    let selected = s
+        .with_user_data(|executor: &mut UiExecutor| executor.state.selected_message)
+        .flatten();
+    let message_id = match selected {
+        Some(message_id) => message_id,
+        None => {
+            s.add_layer(Dialog::info("Select a message to delete first"));
+            return;
+        }
+    };
+
+    let dialog = Dialog::text("Delete the selected message for everyone?")
+        .button("Delete", move |s| {
+            s.pop_layer();
+            send_event(s, UiEvent::DeleteMessage(message_id));
+        })
+        .dismiss_button("Cancel");
+    s.add_layer(dialog);
+}
+
+// hand an event to the controller from inside a cursive callback
+fn send_event(s: &mut Cursive, event: UiEvent) {
+    s.with_user_data(|executor: &mut UiExecutor| {
+        let mut sender = executor.sender.clone();
+        tokio::spawn(async move {
+            sender.send(event).await.ok();
+        });
+    });
+}
+
+// helper to create the view of available conversations on the left. Should probably go to its own
+// module.
+fn conversation_view(view: ConversationView) -> impl View {
+    let id = view.conversation_id();
+    let view = view.with_id(id);
+    OnEventView::new(view)
+        // handle left clicking on a conversation name
+        .on_event_inner(
+            EventTrigger::mouse(),
+            handle_switch
+        )
+        // handle pressing enter when a conversation name has focus
+        .on_event_inner(
+            cursive::event::Key::Enter,
+            handle_switch
+        )
+        // pick another channel in the same team
+        .on_event_inner('c', |v, _| {
+            let convo = v.conversation_id();
+            Some(EventResult::with_cb(move |s| list_team_channels(s, &convo)))
+        })
+}
+
+fn handle_switch(v: &mut IdView<ConversationView>, e: &Event) -> Option<EventResult> {
+    let switch = matches!(
+        *e,
+        Event::Mouse {
+            event: MouseEvent::Release(MouseButton::Left),
+            ..
+        } | Event::Key(Key::Enter)
+    );
+    if !switch {
+        return None;
+    }
+
+    let convo = v.conversation_id();
+    Some(EventResult::with_cb(move |s| {
+        send_event(s, UiEvent::SwitchConversation(convo.clone()));
+    }))
+}
+
+fn handle_chat_click(v: &mut IdView<ChatScrollView>, e: &Event) -> Option<EventResult> {
+    let position = match *e {
+        Event::Mouse {
+            offset,
+            position,
+            event: MouseEvent::Release(MouseButton::Left),
+        } => position.checked_sub(offset)?,
+        _ => return None,
+    };
+
+    let viewport = v.get_mut().content_viewport();
+    // clicked on the scrollbar
+    if position.x >= viewport.width() {
+        return None;
+    }
+    let row = viewport.top() + position.y;
+    let width = viewport.width();
+
+    let column = position.x;
+    Some(EventResult::with_cb(move |s| {
+        let link = s
+            .with_user_data(|executor: &mut UiExecutor| link_at(&executor.state, width, row, column))
+            .flatten();
+        if let Some(target) = link {
+            follow_link(s, target);
+            return;
+        }
+        s.with_user_data(|executor: &mut UiExecutor| {
+            let state = &mut executor.state;
+            let rows = message_rows(state, width);
+            state.selection_anchor = None;
+            // clicking blank space clears the selection
+            state.selected_message = message_at_row(&rows, row);
+        });
+        redraw_chat(s);
+    }))
+}
+
+// show (or with None, clear) what was wrong with the last thing typed, just above the message box
+fn set_input_error(s: &mut Cursive, error: Option<String>) {
+    let text = match error {
+        Some(error) => StyledString::styled(error, Color::Dark(BaseColor::Red)),
+        None => StyledString::new(),
+    };
+    s.call_on_id("input_error", |view: &mut TextView| view.set_content(text));
+}
+
+// the same line is used for how things done in the background went, until the next thing typed
+fn set_notice(s: &mut Cursive, text: &str) {
+    let text = text.to_string();
+    s.call_on_id("input_error", |view: &mut TextView| view.set_content(text));
+}
+
+fn send_chat_message(s: &mut Cursive, msg: &str) {
+    if msg.is_empty() {
+        // Enter on an empty line follows the focused link, if there is one
+        let link = s
+            .with_user_data(|executor: &mut UiExecutor| focused_link_target(&executor.state))
+            .flatten();
+        if let Some(target) = link {
+            follow_link(s, target);
+        }
+        return;
+    }
+
+    if let Some((message_id, body)) = take_reply(s, msg) {
+        if !body.is_empty() {
+            clear_composer(s, msg);
+            send_event(s, UiEvent::Reply(message_id, body));
+        }
+        return;
+    }
+
+    match parse_input(msg).and_then(|command| resolve_references(s, command)) {
+        Ok(command) => {
+            clear_composer(s, msg);
+            set_input_error(s, None);
+            match command {
+                Command::Event(event) => send_event(s, event),
+                Command::PickHiddenConversation => show_unhide_picker(s),
+                Command::FilterSender(username) => set_sender_filter(s, username),
+                Command::Goto(link) => goto_message(s, link),
+                Command::Delete => confirm_delete(s),
+                Command::Quit => s.quit(),
+                Command::React(..)
+                | Command::Reply(..)
+                | Command::Edit(..)
+                | Command::Switch(..)
+                | Command::ReactToSelected(..)
+                | Command::ToggleMute
+                | Command::DownloadSelected => unreachable!("resolved above"),
+            }
+        }
+        // leave the input alone so it can be fixed up
+        Err(e) => set_input_error(s, Some(e)),
+    }
+}
+
+// The controller opens the conversation (loading older messages if it has to), and once it's on
+// screen `finish_goto` scrolls to the message.
+fn goto_message(s: &mut Cursive, link: MessageLink) {
+    let target = s
+        .with_user_data(|executor: &mut UiExecutor| {
+            let state = &mut executor.state;
+            let conversation_id = match &link.conversation {
+                Some(name) => resolve_conversation(&state.sidebar, name)?,
+                None => match &state.conversation {
+                    Some(convo) => convo.id.clone(),
+                    None => return Err("No conversation selected".to_string()),
+                },
+            };
+            state.pending_goto = Some((conversation_id.clone(), link.message_id));
+            Ok(conversation_id)
+        })
+        .unwrap_or_else(|| Err("No conversation selected".to_string()));
+
+    match target {
+        Ok(conversation_id) => {
+            send_event(s, UiEvent::GotoMessage(conversation_id, link.message_id))
+        }
+        Err(e) => s.add_layer(Dialog::info(e)),
+    }
+}
+
+// select and scroll to the message `/goto` asked for, if this is the conversation it's in
+fn finish_goto(s: &mut Cursive, data: &Conversation) {
+    let message_id = match s
+        .with_user_data(|executor: &mut UiExecutor| executor.state.pending_goto.take())
+        .flatten()
+    {
+        Some((conversation_id, message_id)) if conversation_id == data.id => message_id,
+        _ => return,
+    };
+
+    if !data.messages.iter().any(|m| m.id == message_id) {
+        s.add_layer(Dialog::info(format!(
+            "Couldn't find message {} in {}",
+            message_id,
+            data.display_name()
+        )));
+        return;
+    }
+    s.with_user_data(|executor: &mut UiExecutor| {
+        executor.state.selected_message = Some(message_id);
+    });
+    redraw_chat(s);
+    scroll_to_message(s, message_id);
+}
+
+// swap the message numbers in `/r3` and `/reply3` for the ids of the messages they refer to
+fn resolve_references(s: &mut Cursive, command: Command) -> Result<Command, String> {
+    let mut resolve = |reference| {
+        s.with_user_data(|executor: &mut UiExecutor| {
+            let state = &executor.state;
+            match &state.conversation {
+                Some(convo) => {
+                    resolve_reference(&convo.messages, state.sender_filter.as_deref(), reference)
+                }
+                None => Err("No conversation selected".to_string()),
+            }
+        })
+        .unwrap_or_else(|| Err("No conversation selected".to_string()))
+    };
+    match command {
+        Command::React(reference, reaction) => {
+            Ok(Command::Event(UiEvent::React(resolve(reference)?, reaction)))
+        }
+        Command::Reply(reference, msg) => Ok(Command::Event(UiEvent::Reply(resolve(reference)?, msg))),
+        Command::Edit(msg) => {
+            let message_id = s
+                .with_user_data(|executor: &mut UiExecutor| {
+                    let state = &executor.state;
+                    match &state.conversation {
+                        Some(convo) => {
+                            message_to_edit(&convo.messages, state.selected_message, &state.status.user)
+                        }
+                        None => Err("No conversation selected".to_string()),
+                    }
+                })
+                .unwrap_or_else(|| Err("No conversation selected".to_string()))?;
+            Ok(Command::Event(UiEvent::EditMessage(message_id, msg)))
+        }
+        Command::Switch(name) => {
+            let conversation_id = s
+                .with_user_data(|executor: &mut UiExecutor| {
+                    resolve_conversation(&executor.state.sidebar, &name)
+                })
+                .unwrap_or_else(|| Err(format!("No conversation named {}", name)))?;
+            Ok(Command::Event(UiEvent::SwitchConversation(conversation_id)))
+        }
+        Command::ReactToSelected(reaction) => {
+            let message_id = s
+                .with_user_data(|executor: &mut UiExecutor| executor.state.selected_message)
+                .flatten()
+                .ok_or_else(|| "No message selected".to_string())?;
+            Ok(Command::Event(UiEvent::React(message_id, reaction_name(&reaction))))
+        }
+        Command::ToggleMute => {
+            let conversation_id = s
+                .with_user_data(|executor: &mut UiExecutor| {
+                    executor.state.conversation.as_ref().map(|c| c.id.clone())
+                })
+                .flatten()
+                .ok_or_else(|| "No conversation selected".to_string())?;
+            Ok(Command::Event(UiEvent::ToggleMute { conversation_id }))
+        }
+        Command::DownloadSelected => {
+            let message_id = s
+                .with_user_data(|executor: &mut UiExecutor| {
+                    let state = &executor.state;
+                    let convo = state.conversation.as_ref()?;
+                    convo
+                        .messages
+                        .iter()
+                        .find(|m| Some(m.id) == state.selected_message)
+                        .filter(|m| matches!(m.content, MessageType::Attachment { .. }))
+                        .map(|m| m.id)
+                })
+                .flatten()
+                .ok_or_else(|| "Select an attachment to download first".to_string())?;
+            Ok(Command::Event(UiEvent::DownloadAttachment { message_id }))
+        }
+        command => Ok(command),
+    }
+}
+
+fn scrolls_up(e: &Event) -> bool {
+    matches!(
+        e,
+        Event::Key(Key::Up)
+            | Event::Key(Key::PageUp)
+            | Event::Key(Key::Home)
+            | Event::Mouse { event: MouseEvent::WheelUp, .. }
+    )
+}
+
+// scrolling up past the oldest message loads the ones before it
+fn handle_chat_scroll(v: &mut IdView<ChatScrollView>, e: &Event) -> Option<EventResult> {
+    let mut view = v.get_mut();
+    let result = view.on_event(e.clone());
+    if view.content_viewport().top() > 0 {
+        return Some(result);
+    }
+    Some(result.and(EventResult::with_cb(load_older_messages)))
+}
+
+fn load_older_messages(s: &mut Cursive) {
+    let conversation_id = s
+        .with_user_data(|executor: &mut UiExecutor| {
+            let state = &mut executor.state;
+            let convo = state.conversation.as_ref()?;
+            // already on its way, or there's nothing older
+            if state.loading_history.is_some() || convo.history_next.is_none() {
+                return None;
+            }
+            state.loading_history = Some(convo.messages.last()?.id);
+            Some(convo.id.clone())
+        })
+        .flatten();
+    if let Some(conversation_id) = conversation_id {
+        send_event(s, UiEvent::LoadMore(conversation_id));
+    }
+}
+
+fn main_layout(config: &LayoutConfig) -> LinearLayout {
+    LinearLayout::horizontal()
+        .child(conversation_list(config))
+        .child(Gutter::new(config.gutter, config.separator))
+        .child(chat_area())
+}
+
+fn conversation_list(config: &LayoutConfig) -> ViewBox {
+    let filter = EditView::new()
+        .on_edit(filter_conversations)
+        .with_id("convo_filter");
+    let convo_list = LinearLayout::vertical()
+        .child(filter)
+        .child(ListView::new().with_id("conversation_list"));
+    let convo_list = Panel::new(convo_list)
+        .title("Conversations")
+        .with_id("conversation_panel");
+    match config.sidebar_percent {
+        Some(percent) => ViewBox::new(PercentWidth::new(convo_list, percent).as_boxed_view()),
+        None => ViewBox::new(
+            BoxView::new(SizeConstraint::Free, SizeConstraint::Full, convo_list).as_boxed_view(),
+        ),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ComposerKey {
+    Send,
+    Newline,
+}
+
+// Enter sends what's in the message box. Terminals mostly can't tell Shift+Enter apart from
+// Enter, so Alt+Enter starts a new line too.
+fn composer_key(event: &Event) -> Option<ComposerKey> {
+    match event {
+        Event::Key(Key::Enter) => Some(ComposerKey::Send),
+        Event::Shift(Key::Enter) | Event::Alt(Key::Enter) => Some(ComposerKey::Newline),
+        _ => None,
+    }
+}
+
+// replace what's in the message box, leaving the cursor at the end to carry on typing
+fn set_composer_content(view: &mut TextArea, content: String) {
+    let end = content.len();
+    view.set_content(content);
+    view.set_cursor(end);
+}
+
+// keys that change what's in the message box or move the cursor, after which the names listed
+// for an `@name` need updating
+fn edits_composer(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Char(_)
+            | Event::Key(Key::Backspace)
+            | Event::Key(Key::Del)
+            | Event::Key(Key::Left)
+            | Event::Key(Key::Right)
+            | Event::Key(Key::Home)
+            | Event::Key(Key::End)
+    )
+}
+
+// Where messages are typed, which can span several lines. Up on the first line and Down on the
+// last go through the messages sent before. While names are listed for an `@name`, Up and Down
+// pick one instead and Tab or Enter puts it in.
+fn composer() -> OnEventView<IdView<TextArea>> {
+    OnEventView::new(TextArea::new().with_id("edit"))
+        .on_pre_event_inner(
+            EventTrigger::from_fn(|e| composer_key(e).is_some()),
+            |view, event| match composer_key(event)? {
+                ComposerKey::Send => {
+                    let msg = view.get_mut().get_content().to_string();
+                    Some(EventResult::with_cb(move |s| {
+                        if !complete_mention(s) {
+                            send_chat_message(s, &msg);
+                        }
+                    }))
+                }
+                ComposerKey::Newline => Some(
+                    view.get_mut()
+                        .on_event(Event::Key(Key::Enter))
+                        .and(EventResult::with_cb(update_completion)),
+                ),
+            },
+        )
+        .on_pre_event_inner(EventTrigger::from_fn(edits_composer), |view, event| {
+            let result = view.get_mut().on_event(event.clone());
+            Some(result.and(EventResult::with_cb(update_completion)))
+        })
+        .on_pre_event(Key::Tab, |s| {
+            complete_mention(s);
+        })
+        .on_pre_event(Key::Esc, |s| {
+            take_completion(s);
+            draw_completion(s);
+        })
+        .on_pre_event(Key::Up, |s| composer_up_down(s, true))
+        .on_pre_event(Key::Down, |s| composer_up_down(s, false))
+}
+
+fn composer_up_down(s: &mut Cursive, up: bool) {
+    if let Some(mut completion) = take_completion(s) {
+        completion.select(up);
+        s.with_user_data(|executor: &mut UiExecutor| executor.state.completion = Some(completion));
+        draw_completion(s);
+        return;
+    }
+    let key = if up { Key::Up } else { Key::Down };
+    match s.call_on_id("edit", |view: &mut TextArea| view.on_event(Event::Key(key))) {
+        Some(EventResult::Ignored) => recall_history(s, up),
+        Some(result) => result.process(s),
+        None => {}
+    }
+}
+
+// list the people the `@name` just before the cursor could be, or nobody if there isn't one
+fn update_completion(s: &mut Cursive) {
+    let (content, cursor) = s
+        .call_on_id("edit", |view: &mut TextArea| (view.get_content().to_string(), view.cursor()))
+        .unwrap_or_default();
+    s.with_user_data(|executor: &mut UiExecutor| {
+        let state = &mut executor.state;
+        state.completion = mention_at(&content, cursor).and_then(|(start, prefix)| {
+            let mut names = state.conversation.as_ref()?.recent_participants();
+            names.retain(|name| *name != state.status.user);
+            let candidates: Vec<String> = rank_candidates(&names, prefix)
+                .into_iter()
+                .take(MAX_CANDIDATES)
+                .map(String::from)
+                .collect();
+            if candidates.is_empty() {
+                return None;
+            }
+            Some(Completion {
+                start,
+                end: cursor,
+                candidates,
+                selected: 0,
+            })
+        });
+    });
+    draw_completion(s);
+}
+
+// The names listed, taken out of the UI state. None if there aren't any, or they're not for what's
+// in the message box any more (it was changed some other way, like quoting a message).
+fn take_completion(s: &mut Cursive) -> Option<Completion> {
+    let completion = s
+        .with_user_data(|executor: &mut UiExecutor| executor.state.completion.take())
+        .flatten()?;
+    let (content, cursor) = s
+        .call_on_id("edit", |view: &mut TextArea| (view.get_content().to_string(), view.cursor()))?;
+    let still_typing = cursor == completion.end
+        && matches!(mention_at(&content, cursor), Some((start, _)) if start == completion.start);
+    if still_typing {
+        Some(completion)
+    } else {
+        draw_completion(s);
+        None
+    }
+}
+
+// put the picked name in the message box, if there are names listed
+fn complete_mention(s: &mut Cursive) -> bool {
+    let completion = match take_completion(s) {
+        Some(completion) => completion,
+        None => return false,
+    };
+    s.call_on_id("edit", |view: &mut TextArea| {
+        let (content, cursor) = completion.apply(view.get_content());
+        view.set_content(content);
+        view.set_cursor(cursor);
+    });
+    draw_completion(s);
+    true
+}
+
+fn render_completion(completion: Option<&Completion>) -> StyledString {
+    let mut text = StyledString::new();
+    if let Some(completion) = completion {
+        for (index, name) in completion.candidates.iter().enumerate() {
+            let line = format!("@{}\n", name);
+            if index == completion.selected {
+                text.append_styled(line, ColorStyle::highlight());
+            } else {
+                text.append_plain(line);
+            }
+        }
+    }
+    text
+}
+
+fn draw_completion(s: &mut Cursive) {
+    let text = s
+        .with_user_data(|executor: &mut UiExecutor| render_completion(executor.state.completion.as_ref()))
+        .unwrap_or_default();
+    s.call_on_id("mention_list", |view: &mut TextView| view.set_content(text));
+}
+
+fn recall_history(s: &mut Cursive, older: bool) {
+    let current = s
+        .call_on_id("edit", |view: &mut TextArea| view.get_content().to_string())
+        .unwrap_or_default();
+    let entry = s
+        .with_user_data(|executor: &mut UiExecutor| {
+            let history = &mut executor.state.history;
+            let entry = if older { history.older(&current) } else { history.newer() };
+            entry.map(String::from)
+        })
+        .flatten();
+    if let Some(entry) = entry {
+        s.call_on_id("edit", |view: &mut TextArea| set_composer_content(view, entry));
+    }
+}
+
+// clear the message box once `msg` has gone, keeping it for Up to bring back
+fn clear_composer(s: &mut Cursive, msg: &str) {
+    s.call_on_id("edit", |view: &mut TextArea| view.set_content(""));
+    s.with_user_data(|executor: &mut UiExecutor| executor.state.history.push(msg));
+}
+
+// put back a message that failed to go, unless something new is being typed
+fn restore_draft(s: &mut Cursive, body: &str) {
+    let body = body.to_string();
+    s.call_on_id("edit", |view: &mut TextArea| {
+        if view.get_content().is_empty() {
+            set_composer_content(view, body);
+        }
+    });
+}
+
+fn chat_area() -> ViewBox {
+    let mut text = ChatView::default().with_id("chat_container").scrollable();
+    text.set_scroll_strategy(ScrollStrategy::StickToBottom);
+
+    // handle left clicking on a message, and scrolling up into history
+    let text = OnEventView::new(text.with_id("chat_scroll"))
+        .on_event_inner(EventTrigger::mouse(), handle_chat_click)
+        .on_pre_event_inner(EventTrigger::from_fn(scrolls_up), handle_chat_scroll);
+
+    let chat_layout = LinearLayout::vertical()
+        .child(BoxView::new(
+            SizeConstraint::Full,
+            SizeConstraint::Full,
+            text,
+        ))
+        .child(TextView::new("").with_id("typing_indicator"))
+        .child(TextView::new("").with_id("input_error"))
+        .child(TextView::new("").with_id("mention_list"))
+        .child(composer());
+    let chat = Panel::new(chat_layout).with_id("chat_panel");
+
+    ViewBox::new(BoxView::new(SizeConstraint::Full, SizeConstraint::Full, chat).as_boxed_view())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::goto::parse_message_link;
+    use crate::{conversation, message};
+    use crate::types::{
+        AttachmentMetadata, MessageBody, Dimensions, KeybaseConversation, MessageEdit,
+        MessageReaction, MessageSystem, SystemNewChannel,
+    };
+
+    // the whole chat area as one string
+    fn render_chat(state: &UiState) -> StyledString {
+        let mut content = StyledString::new();
+        for (_, rendered, _) in render_chat_messages(state) {
+            content.append(rendered);
+        }
+        content
+    }
+
+    #[test]
+    fn theme_fallback() {
+        let dir = std::env::temp_dir().join(format!("keybase-chat-tui-theme-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("theme.toml");
+
+        // no theme, or a broken one, is the default
+        let _ = std::fs::remove_file(&path);
+        assert!(load_theme(&path).unwrap().shadow);
+        std::fs::write(&path, "shadow = [").unwrap();
+        assert!(load_theme(&path).unwrap().shadow);
+
+        std::fs::write(&path, "shadow = false").unwrap();
+        assert!(!load_theme(&path).unwrap().shadow);
+
+        // there but unreadable
+        assert!(load_theme(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn dummy_cursive() -> (Cursive, Receiver<UiEvent>) {
+        let (sender, receiver) = mpsc::channel(32);
+        let mut siv = Cursive::dummy();
+        siv.set_user_data(UiExecutor {
+            sender,
+            state: UiState::default(),
+        });
+        (siv, receiver)
+    }
+
+    fn conversation_name(id: &str) -> IdView<ConversationView> {
+        let convo: Conversation = conversation!(id).into();
+        ConversationView::new(convo).with_id(id)
+    }
+
+    #[tokio::test]
+    async fn enter_switches_conversation() {
+        let (mut siv, mut receiver) = dummy_cursive();
+        let mut view = conversation_name("test1");
+
+        handle_switch(&mut view, &Event::Key(Key::Enter))
+            .unwrap()
+            .process(&mut siv);
+
+        match receiver.recv().await {
+            Some(UiEvent::SwitchConversation(id)) => assert_eq!(id, "test1"),
+            _ => panic!("Expected a SwitchConversation event"),
+        }
+    }
+
+    #[tokio::test]
+    async fn click_switches_conversation() {
+        let (mut siv, mut receiver) = dummy_cursive();
+        let mut view = conversation_name("test1");
+        let click = Event::Mouse {
+            offset: (0, 0).into(),
+            position: (0, 0).into(),
+            event: MouseEvent::Release(MouseButton::Left),
+        };
+
+        handle_switch(&mut view, &click).unwrap().process(&mut siv);
+
+        match receiver.recv().await {
+            Some(UiEvent::SwitchConversation(id)) => assert_eq!(id, "test1"),
+            _ => panic!("Expected a SwitchConversation event"),
+        }
+    }
+
+    #[tokio::test]
+    async fn react_with_prompted_emoji() {
+        let (mut siv, mut receiver) = dummy_cursive();
+
+        send_reaction(&mut siv, 3, "  ");
+        send_reaction(&mut siv, 3, "+1");
+        send_reaction(&mut siv, 4, ":tada:");
+
+        match receiver.recv().await {
+            Some(UiEvent::React(3, reaction)) => assert_eq!(reaction, ":+1:"),
+            _ => panic!("Expected a React event"),
+        }
+        match receiver.recv().await {
+            Some(UiEvent::React(4, reaction)) => assert_eq!(reaction, ":tada:"),
+            _ => panic!("Expected a React event"),
+        }
+    }
+
+    #[test]
+    fn composer_keys() {
+        assert_eq!(composer_key(&Event::Key(Key::Enter)), Some(ComposerKey::Send));
+        assert_eq!(composer_key(&Event::Shift(Key::Enter)), Some(ComposerKey::Newline));
+        assert_eq!(composer_key(&Event::Alt(Key::Enter)), Some(ComposerKey::Newline));
+        assert_eq!(composer_key(&Event::Char('a')), None);
+        assert_eq!(composer_key(&Event::CtrlChar('f')), None);
+    }
+
+    #[test]
+    fn completes_mentions() {
+        let (mut siv, _) = dummy_cursive();
+        siv.add_layer(
+            LinearLayout::vertical()
+                .child(TextView::new("").with_id("mention_list"))
+                .child(composer()),
+        );
+        siv.focus_id("edit").unwrap();
+        let mut convo = conversation!("test1");
+        convo.channel.name = "alicia,me,malice".to_string();
+        let convo: Conversation = convo.into();
+        siv.with_user_data(|executor: &mut UiExecutor| {
+            executor.state.conversation = Some(convo);
+            executor.state.status.user = "me".to_string();
+        });
+        let listed = |siv: &mut Cursive| {
+            siv.call_on_id("mention_list", |view: &mut TextView| {
+                view.get_content().source().to_string()
+            })
+            .unwrap()
+        };
+        let content = |siv: &mut Cursive| {
+            siv.call_on_id("edit", |view: &mut TextArea| view.get_content().to_string())
+                .unwrap()
+        };
+
+        for c in "hi @ali".chars() {
+            siv.on_event(Event::Char(c));
+        }
+        assert_eq!(listed(&mut siv), "@alicia\n@malice\n");
+        siv.on_event(Event::Key(Key::Down));
+        siv.on_event(Event::Key(Key::Tab));
+        assert_eq!(content(&mut siv), "hi @malice ");
+        assert_eq!(listed(&mut siv), "");
+
+        // Enter completes rather than sending, and you're not in the list
+        siv.on_event(Event::Char('@'));
+        assert_eq!(listed(&mut siv), "@alicia\n@malice\n");
+        siv.on_event(Event::Key(Key::Enter));
+        assert_eq!(content(&mut siv), "hi @malice @alicia ");
+
+        // nobody by that name
+        for c in "@bob".chars() {
+            siv.on_event(Event::Char(c));
+        }
+        assert_eq!(listed(&mut siv), "");
+    }
+
+    #[tokio::test]
+    async fn multi_line_messages() {
+        let (mut siv, mut receiver) = dummy_cursive();
+        siv.add_layer(composer());
+        for event in [
+            Event::Char('h'),
+            Event::Char('i'),
+            Event::Alt(Key::Enter),
+            Event::Char('!'),
+            Event::Key(Key::Enter),
+        ] {
+            siv.on_event(event);
+        }
+
+        match receiver.recv().await {
+            Some(UiEvent::SendMessage(body)) => assert_eq!(body, "hi\n!"),
+            _ => panic!("Expected a SendMessage event"),
+        }
+        let content = siv
+            .call_on_id("edit", |view: &mut TextArea| view.get_content().to_string())
+            .unwrap();
+        assert_eq!(content, "");
+
+        // and it comes back with Up
+        siv.on_event(Event::Key(Key::Up));
+        let content = siv
+            .call_on_id("edit", |view: &mut TextArea| view.get_content().to_string())
+            .unwrap();
+        assert_eq!(content, "hi\n!");
+        // the dummy backend is too narrow for the cursor to be on the last line
+        recall_history(&mut siv, false);
+        let content = siv
+            .call_on_id("edit", |view: &mut TextArea| view.get_content().to_string())
+            .unwrap();
+        assert_eq!(content, "");
+    }
+
+    #[tokio::test]
+    async fn failed_sends_come_back() {
+        let (mut siv, mut receiver) = dummy_cursive();
+        siv.add_layer(TextView::new("").with_id("input_error"));
+        siv.add_layer(composer());
+        send_chat_message(&mut siv, "hello");
+        assert!(receiver.recv().await.is_some());
+        send_chat_message(&mut siv, "again");
+        assert!(receiver.recv().await.is_some());
+        let mut ui = Ui { cursive: siv };
+        let content = |ui: &mut Ui| {
+            ui.cursive
+                .call_on_id("edit", |view: &mut TextArea| view.get_content().to_string())
+                .unwrap()
+        };
+
+        // the one that failed, not just the last one sent
+        ui.on_send_failed("hello");
+        assert_eq!(content(&mut ui), "hello");
+
+        // something new being typed is left alone
+        ui.cursive
+            .call_on_id("edit", |view: &mut TextArea| view.set_content("next"));
+        ui.on_send_failed("again");
+        assert_eq!(content(&mut ui), "next");
+    }
+
+    #[tokio::test]
+    async fn team_channel_picker() {
+        let (mut siv, mut receiver) = dummy_cursive();
+        siv.add_layer(TextView::new("").with_id("input_error"));
+        let mut team = conversation!("test2");
+        team.channel.name = "keybase".to_string();
+        team.channel.topic_name = "general".to_string();
+        team.channel.members_type = MemberType::Team;
+        siv.with_user_data(|executor: &mut UiExecutor| {
+            executor.state.sidebar = vec![conversation!("test1").into(), team.into()];
+        });
+
+        list_team_channels(&mut siv, "test1");
+        let error = siv
+            .call_on_id("input_error", |view: &mut TextView| view.get_content().source().to_string())
+            .unwrap();
+        assert_eq!(error, "Only teams have channels");
+
+        list_team_channels(&mut siv, "test2");
+        match receiver.recv().await {
+            Some(UiEvent::ListChannels(team)) => assert_eq!(team, "keybase"),
+            _ => panic!("Expected a ListChannels event"),
+        }
+
+        open_team_channel(&mut siv, "keybase", "random");
+        match receiver.recv().await {
+            Some(UiEvent::OpenChannel(channel)) => {
+                assert_eq!(channel.name, "keybase");
+                assert_eq!(channel.topic_name, "random");
+                assert_eq!(channel.members_type, MemberType::Team);
+            }
+            _ => panic!("Expected an OpenChannel event"),
+        }
+    }
+
+    #[tokio::test]
+    async fn commands_resolved_by_the_ui() {
+        let (mut siv, mut receiver) = dummy_cursive();
+        siv.add_layer(TextView::new("").with_id("input_error"));
+        let mut team = conversation!("test2");
+        team.channel.name = "keybase".to_string();
+        team.channel.topic_name = "general".to_string();
+        team.channel.members_type = MemberType::Team;
+        siv.with_user_data(|executor: &mut UiExecutor| {
+            executor.state.sidebar = vec![conversation!("test1").into(), team.into()];
+            executor.state.selected_message = Some(4);
+        });
+        let input_error = |siv: &mut Cursive| {
+            siv.call_on_id("input_error", |view: &mut TextView| {
+                view.get_content().source().to_string()
+            })
+            .unwrap()
+        };
+
+        send_chat_message(&mut siv, "/switch nope");
+        assert_eq!(input_error(&mut siv), "No conversation named nope");
+        send_chat_message(&mut siv, "/frobnicate");
+        assert_eq!(input_error(&mut siv), "Unknown command: /frobnicate");
+
+        send_chat_message(&mut siv, "/switch keybase#general");
+        match receiver.recv().await {
+            Some(UiEvent::SwitchConversation(id)) => assert_eq!(id, "test2"),
+            _ => panic!("Expected a SwitchConversation event"),
+        }
+        assert_eq!(input_error(&mut siv), "");
+
+        send_chat_message(&mut siv, "/react tada");
+        match receiver.recv().await {
+            Some(UiEvent::React(4, reaction)) => assert_eq!(reaction, ":tada:"),
+            _ => panic!("Expected a React event"),
+        }
+
+        // only attachments can be downloaded
+        let mut file = message!("test1", "", 5);
+        file.content = MessageType::Attachment { attachment: Default::default() };
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_messages(vec![message!("test1", "hi", 4), file]);
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.conversation = Some(convo));
+        send_chat_message(&mut siv, "/download");
+        assert_eq!(input_error(&mut siv), "Select an attachment to download first");
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.selected_message = Some(5));
+        send_chat_message(&mut siv, "/download");
+        match receiver.recv().await {
+            Some(UiEvent::DownloadAttachment { message_id }) => assert_eq!(message_id, 5),
+            _ => panic!("Expected a DownloadAttachment event"),
+        }
+    }
+
+    #[tokio::test]
+    async fn reply_to_selected() {
+        let (mut siv, mut receiver) = dummy_cursive();
+        siv.add_layer(TextArea::new().with_id("edit"));
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_messages(vec![message!("test1", "lunch?", 2), message!("test1", "hi", 1)]);
+        siv.with_user_data(|executor: &mut UiExecutor| {
+            executor.state.conversation = Some(convo);
+            executor.state.selected_message = Some(2);
+        });
+
+        reply_to_selected_message(&mut siv);
+        let content = siv
+            .call_on_id("edit", |view: &mut TextArea| view.get_content().to_string())
+            .unwrap();
+        assert_eq!(content.as_str(), "> Some Guy: lunch?\n");
+
+        // nothing to send yet, but still replying
+        send_chat_message(&mut siv, &format!("{}  ", content));
+        assert!(receiver.try_recv().is_err());
+        send_chat_message(&mut siv, &format!("{}sure", content));
+        match receiver.recv().await {
+            Some(UiEvent::Reply(2, body)) => assert_eq!(body, "sure"),
+            _ => panic!("Expected a Reply event"),
+        }
+        reply_to_selected_message(&mut siv);
+        send_chat_message(&mut siv, &format!("{}```\n  indented  \n```", content));
+        match receiver.recv().await {
+            Some(UiEvent::Reply(2, body)) => assert_eq!(body, "```\n  indented  \n```"),
+            _ => panic!("Expected a Reply event"),
+        }
+        // only the once
+        send_chat_message(&mut siv, "sure");
+        match receiver.recv().await {
+            Some(UiEvent::SendMessage(body)) => assert_eq!(body, "sure"),
+            _ => panic!("Expected a SendMessage event"),
+        }
+    }
+
+    #[tokio::test]
+    async fn scrolling_up_loads_history_once() {
+        let (mut siv, mut receiver) = dummy_cursive();
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_messages(vec![message!("test1", "two", 2), message!("test1", "one", 1)]);
+        convo.history_next = Some("older".to_string());
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.conversation = Some(convo));
+
+        load_older_messages(&mut siv);
+        // still waiting for the first page
+        load_older_messages(&mut siv);
+
+        match receiver.recv().await {
+            Some(UiEvent::LoadMore(id)) => assert_eq!(id, "test1"),
+            _ => panic!("Expected a LoadMore event"),
+        }
+        let loading = siv
+            .with_user_data(|executor: &mut UiExecutor| executor.state.loading_history)
+            .flatten();
+        assert_eq!(loading, Some(1));
+        assert!(receiver.try_recv().is_err());
+
+        // they couldn't be fetched
+        let mut ui = Ui { cursive: siv };
+        ui.on_loading("test1", false);
+        load_older_messages(&mut ui.cursive);
+        match receiver.recv().await {
+            Some(UiEvent::LoadMore(id)) => assert_eq!(id, "test1"),
+            _ => panic!("Expected a LoadMore event"),
+        }
+    }
+
+    fn attachment(metadata: AttachmentMetadata) -> AttachmentObject {
+        AttachmentObject {
+            filename: "photo.jpg".to_string(),
+            title: "".to_string(),
+            mime_type: "image/jpeg".to_string(),
+            size: 1_258_291,
+            metadata,
+        }
+    }
+
+    #[test]
+    fn render_attachment_with_dimensions() {
+        let object = attachment(AttachmentMetadata {
+            image: Some(Dimensions {
+                width: 1920,
+                height: 1080,
+            }),
+            video: None,
+        });
+        assert_eq!(
+            render_attachment("alice", &object),
+            "alice sent photo.jpg (image/jpeg, 1.2 MB, 1920x1080)\n"
+        );
+    }
+
+    #[test]
+    fn render_attachment_without_dimensions() {
+        let object = attachment(AttachmentMetadata::default());
+        assert_eq!(
+            render_attachment("alice", &object),
+            "alice sent photo.jpg (image/jpeg, 1.2 MB)\n"
+        );
+    }
+
+    #[test]
+    fn human_readable_sizes() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KB");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.0 GB");
+    }
+
+    #[test]
+    fn render_system_message() {
+        let mut message = message!("test1", "", 1);
+        message.content = MessageType::System {
+            system: MessageSystem {
+                new_channel: Some(SystemNewChannel {
+                    creator: "alice".to_string(),
+                    name_at_creation: "random".to_string(),
+                }),
+                ..MessageSystem::default()
+            },
+        };
+        let rendered = render_message(&message, false, false, true, &[], None, "");
+        assert_eq!(rendered.source(), "  — alice created #random —\n");
+    }
+
+    #[test]
+    fn render_pending_message() {
+        let mut message = message!("test1", "on its way", 1);
+        message.delivery = Some(DeliveryState::Sending);
+        let rendered = render_message(&message, false, false, true, &[], None, "");
+        assert_eq!(rendered.source(), "Some Guy: on its way\n");
+        // all of it greyed out, the name too
+        assert!(rendered.spans().all(|span| span.attr.color == Some(ColorStyle::secondary())));
+    }
+
+    #[test]
+    fn render_sent_message() {
+        let mut message = message!("test1", "made it", 1);
+        message.delivery = Some(DeliveryState::Sent);
+        let rendered = render_message(&message, false, false, true, &[], None, "");
+        assert_eq!(rendered.source(), "✓ Some Guy: made it\n");
+    }
+
+    #[test]
+    fn render_exploding_message() {
+        let mut message = message!("test1", "shh", 1);
+        message.is_ephemeral = true;
+        let rendered = render_message(&message, false, false, true, &[], None, "");
+        assert_eq!(rendered.source(), "💣 Some Guy: shh\n");
+        assert_eq!(body_column(&message, &rendered), 13);
+    }
+
+    #[test]
+    fn toggle_compact_rendering() {
+        let (mut siv, _) = dummy_cursive();
+        let mut convo: Conversation = conversation!("test1").into();
+        // newest first
+        convo.insert_messages(vec![
+            message!("test1", "three", 3),
+            message!("test1", "two", 2),
+            message!("test1", "one", 1),
+        ]);
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.conversation = Some(convo));
+
+        let render = |siv: &mut Cursive| {
+            siv.with_user_data(|executor: &mut UiExecutor| render_chat(&executor.state).source().to_string())
+                .unwrap()
+        };
+
+        assert_eq!(render(&mut siv), "Some Guy: one\nSome Guy: two\nSome Guy: three\n");
+        toggle_compact(&mut siv);
+        assert_eq!(render(&mut siv), "Some Guy: one\n          two\n          three\n");
+        toggle_compact(&mut siv);
+        assert_eq!(render(&mut siv), "Some Guy: one\nSome Guy: two\nSome Guy: three\n");
+    }
+
+    #[test]
+    fn scroll_memory_across_switches() {
+        let mut memory = ScrollMemory::default();
+
+        // scrolled up in test1, then switched to test2 which was at the bottom
+        let place = ScrollPlace { row: 12, newest: Some(3) };
+        memory.save("test1", place, false);
+        memory.save("test2", ScrollPlace { row: 40, newest: None }, true);
+
+        assert_eq!(memory.take("test2"), None);
+        assert_eq!(memory.take("test1"), Some(place));
+        // restored already, so the scroll view is in charge again
+        assert_eq!(memory.take("test1"), None);
+
+        // scrolling back down forgets the old position
+        memory.save("test1", place, false);
+        memory.save("test1", ScrollPlace { row: 30, newest: Some(3) }, true);
+        assert_eq!(memory.take("test1"), None);
+    }
+
+    #[test]
+    fn switching_back_restores_scroll_position() {
+        let (mut siv, _) = dummy_cursive();
+        siv.add_layer(chat_area());
+        let mut ui = Ui { cursive: siv };
+        let mut test1: Conversation = conversation!("test1").into();
+        test1.insert_messages((1..=50).rev().map(|i| message!("test1", "hi", i)).collect());
+        let offset = |ui: &mut Ui| {
+            ui.cursive
+                .call_on_id("chat_scroll", |view: &mut ChatScrollView| view.content_viewport().top())
+                .unwrap()
+        };
+
+        ui.render_conversation(&test1);
+        // the dummy backend's screen is too small to scroll in
+        ui.cursive.screen_mut().layout((40, 10).into());
+        ui.cursive
+            .call_on_id("chat_scroll", |view: &mut ChatScrollView| view.set_offset((0, 10)));
+        assert_eq!(offset(&mut ui), 10);
+
+        // any way of switching away goes through here
+        ui.render_conversation(&conversation!("test2").into());
+        let place = ui
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| {
+                executor.state.scroll_memory.places.get("test1").copied()
+            })
+            .unwrap();
+        assert_eq!(place, Some(ScrollPlace { row: 10, newest: Some(50) }));
+
+        test1.insert_messages(vec![message!("test1", "new", 52), message!("test1", "new", 51)]);
+        ui.render_conversation(&test1);
+        let title = ui
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| {
+                (executor.state.new_below, chat_title(&executor.state))
+            })
+            .unwrap();
+        assert_eq!(title, (2, "channel ↓ 2 new".to_string()));
+
+        // scrolled all the way down, they've been seen
+        ui.cursive.screen_mut().layout((40, 10).into());
+        ui.cursive.call_on_id("chat_scroll", |view: &mut ChatScrollView| view.scroll_to_bottom());
+        ui.check_new_below();
+        let new_below = ui
+            .cursive
+            .with_user_data(|executor: &mut UiExecutor| executor.state.new_below)
+            .unwrap();
+        assert_eq!(new_below, 0);
+    }
+
+    #[test]
+    fn wrapped_message_rows() {
+        let mut state = UiState::default();
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_messages(vec![
+            message!("test1", "a message long enough to wrap", 2),
+            message!("test1", "short", 1),
+        ]);
+        state.conversation = Some(convo);
+
+        // `Some Guy: short` fits in 30 columns, the second one needs two rows
+        assert_eq!(message_rows(&state, 30), vec![(1, 1), (2, 2)]);
+        // wrapped under the body, which leaves 10 columns for it
+        assert_eq!(message_rows(&state, 20), vec![(1, 1), (2, 4)]);
+        assert_eq!(message_rows(&state, 80), vec![(1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn click_selects_message() {
+        let (mut siv, _) = dummy_cursive();
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_messages(vec![message!("test1", "two", 2), message!("test1", "one", 1)]);
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.conversation = Some(convo));
+
+        let mut view = ChatView::default().with_id("chat_container").scrollable().with_id("chat_scroll");
+        view.layout((40, 10).into());
+        let click = |y| Event::Mouse {
+            offset: (0, 0).into(),
+            position: (0, y).into(),
+            event: MouseEvent::Release(MouseButton::Left),
+        };
+        let selected = |siv: &mut Cursive| {
+            siv.with_user_data(|executor: &mut UiExecutor| executor.state.selected_message)
+                .unwrap()
+        };
+
+        handle_chat_click(&mut view, &click(1)).unwrap().process(&mut siv);
+        assert_eq!(selected(&mut siv), Some(2));
+        handle_chat_click(&mut view, &click(0)).unwrap().process(&mut siv);
+        assert_eq!(selected(&mut siv), Some(1));
+        // blank space below the messages
+        handle_chat_click(&mut view, &click(5)).unwrap().process(&mut siv);
+        assert_eq!(selected(&mut siv), None);
+        // only left clicks
+        assert!(handle_chat_click(&mut view, &Event::Key(Key::Enter)).is_none());
+    }
+
+    #[test]
+    fn sender_filter() {
+        let mut state = UiState::default();
+        let mut convo: Conversation = conversation!("test1").into();
+        let mut from_alice = message!("test1", "hi", 2);
+        from_alice.sender.username = "alice".to_string();
+        convo.insert_messages(vec![from_alice, message!("test1", "hello", 1)]);
+        state.conversation = Some(convo);
+
+        assert_eq!(render_chat(&state).source(), "Some Guy: hello\nalice: hi\n");
+        assert_eq!(chat_title(&state), "channel");
+
+        state.sender_filter = Some("alice".to_string());
+        assert_eq!(render_chat(&state).source(), "alice: hi\n");
+        assert_eq!(chat_title(&state), "channel (only @alice)");
+    }
+
+    #[test]
+    fn search_debounce() {
+        let edited_at = Instant::now();
+        let pending = PendingSearch {
+            query: "hel".to_string(),
+            edited_at,
+        };
+        assert!(!pending.is_due(edited_at));
+        assert!(!pending.is_due(edited_at + Duration::from_millis(50)));
+        assert!(pending.is_due(edited_at + SEARCH_DEBOUNCE));
+    }
+
+    #[test]
+    fn search_selects_match() {
+        let (mut siv, _) = dummy_cursive();
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_messages(vec![
+            message!("test1", "help", 3),
+            message!("test1", "hello", 2),
+            message!("test1", "hi", 1),
+        ]);
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.conversation = Some(convo));
+        let selected = |siv: &mut Cursive| {
+            siv.with_user_data(|executor: &mut UiExecutor| executor.state.selected_message)
+                .unwrap()
+        };
+
+        search(&mut siv, "h", false);
+        assert_eq!(selected(&mut siv), Some(3));
+        search(&mut siv, "hell", false);
+        assert_eq!(selected(&mut siv), Some(2));
+        // enter steps to older matches, then wraps around
+        search(&mut siv, "h", true);
+        assert_eq!(selected(&mut siv), Some(1));
+        search(&mut siv, "h", true);
+        assert_eq!(selected(&mut siv), Some(3));
+    }
+
+    #[tokio::test]
+    async fn goto_resolves_and_scrolls() {
+        let (mut siv, mut receiver) = dummy_cursive();
+        let mut team = conversation!("test2");
+        team.channel.name = "keybase".to_string();
+        team.channel.topic_name = "general".to_string();
+        team.channel.members_type = MemberType::Team;
+        let mut convo: Conversation = team.into();
+        siv.with_user_data(|executor: &mut UiExecutor| {
+            executor.state.conversation = Some(conversation!("test1").into());
+            executor.state.sidebar = vec![conversation!("test1").into(), convo.clone()];
+        });
+
+        goto_message(&mut siv, parse_message_link("keybase://chat/keybase#general/2").unwrap());
+        match receiver.recv().await {
+            Some(UiEvent::GotoMessage(id, 2)) => assert_eq!(id, "test2"),
+            _ => panic!("Expected a GotoMessage event"),
+        }
+        convo.insert_messages(vec![message!("test2", "b", 2), message!("test2", "a", 1)]);
+        finish_goto(&mut siv, &convo);
+        let selected = siv
+            .with_user_data(|executor: &mut UiExecutor| executor.state.selected_message)
+            .unwrap();
+        assert_eq!(selected, Some(2));
+
+        // not in the history the controller could load
+        goto_message(&mut siv, parse_message_link("keybase://chat/keybase/9").unwrap());
+        receiver.recv().await;
+        let layers = siv.screen().len();
+        finish_goto(&mut siv, &convo);
+        assert_eq!(siv.screen().len(), layers + 1);
+
+        // unknown conversations never get as far as the controller
+        goto_message(&mut siv, parse_message_link("keybase://chat/nope/1").unwrap());
+        assert_eq!(siv.screen().len(), layers + 2);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn follow_channel_links() {
+        let (mut siv, mut receiver) = dummy_cursive();
+        siv.add_layer(chat_area());
+        let team = |id: &str, topic: &str| {
+            let mut convo = conversation!(id);
+            convo.channel.name = "keybase".to_string();
+            convo.channel.topic_name = topic.to_string();
+            convo.channel.members_type = MemberType::Team;
+            convo
+        };
+        let mut general: Conversation = team("test1", "general").into();
+        let mut message = message!("test1", "see #random and #new", 1);
+        message.channel = general.data.channel.clone();
+        general.insert_messages(vec![message]);
+        siv.with_user_data(|executor: &mut UiExecutor| {
+            executor.state.sidebar = vec![general.clone(), team("test2", "random").into()];
+            executor.state.conversation = Some(general);
+            executor.state.selected_message = Some(1);
+        });
+
+        // underlined in the chat
+        let rendered = siv
+            .with_user_data(|executor: &mut UiExecutor| render_chat_messages(&executor.state))
+            .unwrap();
+        let underlined: Vec<&str> = rendered[0]
+            .1
+            .spans()
+            .filter(|span| span.attr.effects.contains(Effect::Underline))
+            .map(|span| span.content)
+            .collect();
+        assert_eq!(underlined, ["#random", "#new"]);
+
+        // clicked on (`Some Guy: see #random`)
+        let link = siv
+            .with_user_data(|executor: &mut UiExecutor| link_at(&executor.state, 80, 0, 16))
+            .unwrap();
+        assert_eq!(link, Some(MentionTarget::Conversation("test2".to_string())));
+        let link = siv
+            .with_user_data(|executor: &mut UiExecutor| link_at(&executor.state, 80, 0, 12))
+            .unwrap();
+        assert_eq!(link, None);
+
+        // focused with Alt+l and followed with Enter
+        focus_next_link(&mut siv);
+        send_chat_message(&mut siv, "");
+        match receiver.recv().await {
+            Some(UiEvent::SwitchConversation(id)) => assert_eq!(id, "test2"),
+            _ => panic!("Expected a SwitchConversation event"),
+        }
+        focus_next_link(&mut siv);
+        send_chat_message(&mut siv, "");
+        match receiver.recv().await {
+            Some(UiEvent::OpenChannel(channel)) => assert_eq!(channel.topic_name, "new"),
+            _ => panic!("Expected an OpenChannel event"),
+        }
+        // and back to none
+        focus_next_link(&mut siv);
+        send_chat_message(&mut siv, "");
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn username_colors() {
+        let names = ["alice", "bob", "carol", "dave", "eve", "mallory", "trent", "zed"];
+        for name in &names {
+            let color = username_color(name);
+            assert_eq!(color, username_color(name));
+            // the chat area's background
+            assert_ne!(color, Color::Dark(BaseColor::White));
+            assert_ne!(color, Color::Light(BaseColor::White));
+        }
+        // not everyone gets the same one
+        let first = username_color(names[0]);
+        assert!(names.iter().any(|name| username_color(name) != first));
+    }
+
+    #[test]
+    fn render_sent_time() {
+        let render = |message: &Message| {
+            render_message(message, false, false, true, &[], None, "").source().to_string()
+        };
+        assert_eq!(render(&message!("test1", "hi", 1)), "Some Guy: hi\n");
+
+        let sent_at = 1_600_000_000;
+        let time = Local.timestamp(sent_at, 0).format("%H:%M").to_string();
+        assert_eq!(
+            render(&message!("test1", "hi", 1, sent_at)),
+            format!("{} Some Guy: hi\n", time)
+        );
+    }
+
+    #[test]
+    fn typing_expires() {
+        let started_at = Instant::now();
+        let typing = Typing {
+            username: "alice".to_string(),
+            started_at,
+        };
+        assert!(!typing.is_expired(started_at + Duration::from_secs(1)));
+        assert!(typing.is_expired(started_at + TYPING_TIMEOUT));
+    }
+
+    #[test]
+    fn conversation_filter() {
+        let mut team = conversation!("test1");
+        team.channel.name = "Keybase".to_string();
+        team.channel.topic_name = "general".to_string();
+        team.channel.members_type = MemberType::Team;
+        let team: Conversation = team.into();
+
+        assert!(matches_filter(&team, ""));
+        assert!(matches_filter(&team, "keyb"));
+        assert!(matches_filter(&team, "BASE#GEN"));
+        assert!(matches_filter(&team, " general "));
+        assert!(!matches_filter(&team, "random"));
+    }
+
+    #[test]
+    fn cycle_sort_mode_reorders_sidebar() {
+        let (mut siv, _) = dummy_cursive();
+        siv.add_layer(main_layout(&LayoutConfig::default()));
+        let sidebar: Vec<Conversation> = ["bob", "alice"]
+            .iter()
+            .map(|name| {
+                let mut convo = conversation!(*name);
+                convo.channel.name = name.to_string();
+                convo.into()
+            })
+            .collect();
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.sidebar = sidebar);
+        redraw_sidebar(&mut siv);
+
+        let names = |siv: &mut Cursive| {
+            siv.call_on_id("conversation_list", |view: &mut ListView| {
+                view.children()
+                    .iter()
+                    .filter_map(|child| match child {
+                        ListChild::Row(_, view) => view
+                            .as_any()
+                            .downcast_ref::<OnEventView<IdView<ConversationView>>>()
+                            .map(|v| ConversationName::name(v.get_inner())),
+                        ListChild::Delimiter => None,
+                    })
+                    .collect::<Vec<String>>()
+            })
+            .unwrap()
+        };
+
+        assert_eq!(names(&mut siv), ["bob", "alice"]);
+        cycle_sort_mode(&mut siv);
+        assert_eq!(names(&mut siv), ["alice", "bob"]);
+    }
+
+    #[test]
+    fn reactions_render_and_focus() {
+        let (mut siv, _) = dummy_cursive();
+        let reaction = |id: u64, emoji: &str, username: &str| {
+            let mut message = message!("test1", "", id);
+            message.content = MessageType::Reaction {
+                reaction: MessageReaction {
+                    message_id: 1,
+                    body: emoji.to_string(),
+                },
+            };
+            message.sender.username = username.to_string();
+            message
+        };
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_messages(vec![
+            reaction(4, ":tada:", "carol"),
+            reaction(3, ":+1:", "bob"),
+            reaction(2, ":+1:", "alice"),
+            message!("test1", "hi", 1),
+        ]);
+        siv.with_user_data(|executor: &mut UiExecutor| {
+            executor.state.conversation = Some(convo);
+            executor.state.selected_message = Some(1);
+        });
+
+        let rendered = siv
+            .with_user_data(|executor: &mut UiExecutor| render_chat(&executor.state))
+            .unwrap();
+        assert_eq!(rendered.source(), "Some Guy: hi\n  :+1: 2  :tada: 1\n");
+
+        let reactors = |siv: &mut Cursive| {
+            siv.with_user_data(|executor: &mut UiExecutor| focused_reactors(&executor.state))
+                .unwrap()
+        };
+        assert_eq!(reactors(&mut siv), None);
+        focus_next_reaction(&mut siv);
+        assert_eq!(reactors(&mut siv).as_deref(), Some(":+1: alice, bob"));
+        focus_next_reaction(&mut siv);
+        assert_eq!(reactors(&mut siv).as_deref(), Some(":tada: carol"));
+        focus_next_reaction(&mut siv);
+        assert_eq!(reactors(&mut siv), None);
+
+        // moving the selection away drops the focus
+        focus_next_reaction(&mut siv);
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.selected_message = None);
+        assert_eq!(reactors(&mut siv), None);
+    }
+
+    #[test]
+    fn reactions_to_unloaded_messages() {
+        let (mut siv, _) = dummy_cursive();
+        let mut reaction = message!("test1", "", 3);
+        reaction.content = MessageType::Reaction {
+            reaction: MessageReaction {
+                message_id: 1,
+                body: ":+1:".to_string(),
+            },
+        };
+        reaction.sender.username = "alice".to_string();
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_messages(vec![reaction, message!("test1", "hi", 2)]);
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.conversation = Some(convo));
+
+        let rendered = siv
+            .with_user_data(|executor: &mut UiExecutor| render_chat(&executor.state))
+            .unwrap();
+        assert_eq!(rendered.source(), "Some Guy: hi\nalice reacted :+1: to message 1\n");
+    }
+
+    #[test]
+    fn mark_read_decision() {
+        assert!(should_mark_read(true, true, "alice", "me"));
+        // scrolled up reading history
+        assert!(!should_mark_read(true, false, "alice", "me"));
+        // some other conversation
+        assert!(!should_mark_read(false, true, "alice", "me"));
+        assert!(!should_mark_read(false, false, "alice", "me"));
+        // our own message
+        assert!(!should_mark_read(true, true, "me", "me"));
+    }
+
+    #[test]
+    fn mark_read_debounce() {
+        let received_at = Instant::now();
+        let pending = PendingRead {
+            conversation_id: "test1".to_string(),
+            message_id: 1,
+            received_at,
+        };
+        assert!(!pending.is_due(received_at));
+        assert!(pending.is_due(received_at + READ_DEBOUNCE));
+    }
+
+    #[test]
+    fn threaded_rendering() {
+        let (mut siv, _) = dummy_cursive();
+        let mut reply = message!("test1", "yes", 3);
+        if let MessageType::Text { text } = &mut reply.content {
+            text.reply_to = Some(1);
+        }
+        let mut convo: Conversation = conversation!("test1").into();
+        // newest first
+        convo.insert_messages(vec![
+            reply,
+            message!("test1", "other", 2),
+            message!("test1", "question", 1),
+        ]);
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.conversation = Some(convo));
+
+        let render = |siv: &mut Cursive, threaded: bool| {
+            siv.with_user_data(|executor: &mut UiExecutor| {
+                executor.state.config.layout.threaded = threaded;
+                render_chat(&executor.state).source().to_string()
+            })
+            .unwrap()
+        };
+
+        assert_eq!(
+            render(&mut siv, false),
+            "Some Guy: question\nSome Guy: other\nSome Guy: yes\n"
+        );
+        assert_eq!(
+            render(&mut siv, true),
+            "Some Guy: question\n  ↳ Some Guy: yes\nSome Guy: other\n"
+        );
+    }
+
+    #[test]
+    fn message_references() {
+        let (mut siv, _) = dummy_cursive();
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_messages(vec![message!("test1", "two", 2), message!("test1", "one", 1)]);
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.conversation = Some(convo));
+
+        let render = |siv: &mut Cursive| {
+            siv.with_user_data(|executor: &mut UiExecutor| render_chat(&executor.state).source().to_string())
+                .unwrap()
+        };
+        assert_eq!(render(&mut siv), "Some Guy: one\nSome Guy: two\n");
+        toggle_references(&mut siv);
+        assert_eq!(render(&mut siv), "[2] Some Guy: one\n[1] Some Guy: two\n");
+
+        match resolve_references(&mut siv, Command::React(2, ":+1:".to_string())) {
+            Ok(Command::Event(UiEvent::React(1, reaction))) => assert_eq!(reaction, ":+1:"),
+            _ => panic!("Expected a React event"),
+        }
+        assert!(resolve_references(&mut siv, Command::Reply(3, "hi".to_string())).is_err());
+    }
+
+    #[test]
+    fn paused_messages_flush_in_order() {
+        let (mut siv, _) = dummy_cursive();
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_messages(vec![message!("test1", "one", 1)]);
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.conversation = Some(convo));
+        let mut ui = Ui { cursive: siv };
+        let render = |ui: &mut Ui| {
+            ui.cursive
+                .with_user_data(|executor: &mut UiExecutor| render_chat(&executor.state).source().to_string())
+                .unwrap()
+        };
+        let title = |ui: &mut Ui| {
+            ui.cursive
+                .with_user_data(|executor: &mut UiExecutor| chat_title(&executor.state))
+                .unwrap()
+        };
+
+        toggle_paused(&mut ui.cursive);
+        ui.new_message(&message!("test1", "two", 2));
+        ui.new_message(&message!("test1", "three", 3));
+        assert_eq!(render(&mut ui), "Some Guy: one\n");
+        assert_eq!(title(&mut ui), "channel ⏸ updates paused (2 buffered)");
+
+        toggle_paused(&mut ui.cursive);
+        assert_eq!(render(&mut ui), "Some Guy: one\nSome Guy: two\nSome Guy: three\n");
+        assert_eq!(title(&mut ui), "channel");
+    }
+
+    #[test]
+    fn mentions_of_me() {
+        let (mut siv, _) = dummy_cursive();
+        siv.add_layer(main_layout(&LayoutConfig::default()));
+        let convos: Vec<Conversation> =
+            vec![conversation!("test1").into(), conversation!("test2").into()];
+        siv.with_user_data(|executor: &mut UiExecutor| {
+            executor.state.status.user = "me".to_string();
+            executor.state.sidebar = convos;
+        });
+        redraw_sidebar(&mut siv);
+        let mut ui = Ui { cursive: siv };
+
+        let message = message!("test1", "hi @me and meg", 1);
+        let rendered = render_message(&message, false, false, true, &[], None, "me");
+        let bold: Vec<&str> = rendered
+            .spans()
+            .filter(|span| span.attr.effects.contains(Effect::Bold))
+            .map(|span| span.content)
+            .collect();
+        assert_eq!(bold, ["@me"]);
+
+        let flags = |ui: &mut Ui, id: &str| {
+            ui.cursive
+                .call_on_id(id, |view: &mut ConversationView| (view.unread, view.mentioned))
+                .unwrap()
+        };
+        ui.on_message(&message!("test1", "hello", 1), "test1", false);
+        ui.on_message(&message!("test2", "hello me", 2), "test2", false);
+        assert_eq!(flags(&mut ui, "test1"), (true, false));
+        assert_eq!(flags(&mut ui, "test2"), (true, true));
+    }
+
+    #[test]
+    fn edits_stay_read() {
+        let (mut siv, _) = dummy_cursive();
+        siv.add_layer(main_layout(&LayoutConfig::default()));
+        let convos: Vec<Conversation> = vec![conversation!("test1").into()];
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.sidebar = convos);
+        redraw_sidebar(&mut siv);
+        let mut ui = Ui { cursive: siv };
+
+        let mut edit = message!("test1", "", 2);
+        edit.content = MessageType::Edit {
+            edit: MessageEdit { message_id: 1, body: "fixed a typo".to_string() },
+        };
+        ui.on_message(&edit, "test1", false);
+
+        let unread = ui
+            .cursive
+            .call_on_id("test1", |view: &mut ConversationView| view.unread)
+            .unwrap();
+        assert!(!unread);
+    }
+
+    #[test]
+    fn unread_count_only_recounted_on_changes() {
+        let (mut siv, _) = dummy_cursive();
+        siv.add_layer(main_layout(&LayoutConfig::default()));
+        let convos: Vec<Conversation> =
+            vec![conversation!("test1").into(), conversation!("test2").into()];
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.sidebar = convos);
+        redraw_sidebar(&mut siv);
+        let mut ui = Ui { cursive: siv };
+        let unread = |ui: &mut Ui| {
+            ui.update_status();
+            ui.cursive
+                .with_user_data(|executor: &mut UiExecutor| executor.state.status.unread)
+                .unwrap()
+        };
+
+        ui.on_message(&message!("test2", "hello", 1), "test2", false);
+        assert_eq!(unread(&mut ui), 1);
+
+        // nothing told it the highlight went, so it isn't counted again
+        ui.cursive.call_on_id("test2", |view: &mut ConversationView| view.unread = false);
+        assert_eq!(unread(&mut ui), 1);
+        mark_unread_changed(&mut ui.cursive);
+        assert_eq!(unread(&mut ui), 0);
+    }
+
+    #[test]
+    fn new_conversation_names() {
+        let user = |name: &str| Ok((name.to_string(), MemberType::User));
+        let team = |name: &str| Ok((name.to_string(), MemberType::Team));
+        assert_eq!(parse_new_conversation(" Alice "), user("alice"));
+        assert_eq!(parse_new_conversation("@alice, bob_2"), user("alice,bob_2"));
+        assert_eq!(parse_new_conversation("keybase.dev#Random"), team("keybase.dev#random"));
+        assert_eq!(parse_new_conversation("keybase"), user("keybase"));
+
+        assert!(parse_new_conversation("").is_err());
+        assert_eq!(parse_new_conversation("alice,,bob").unwrap_err(), "Not a username: ");
+        assert_eq!(parse_new_conversation("al ice").unwrap_err(), "Not a username: al ice");
+        assert_eq!(parse_new_conversation("team#").unwrap_err(), "Not a team channel: team#");
+        assert!(parse_new_conversation("#general").is_err());
+        assert!(parse_new_conversation("a#b#c").is_err());
+    }
+
+    #[tokio::test]
+    async fn new_conversation_dialog() {
+        let (mut siv, mut receiver) = dummy_cursive();
+        open_new_conversation(&mut siv);
+        let error = |siv: &mut Cursive| {
+            siv.call_on_id("new_conversation_error", |view: &mut TextView| {
+                view.get_content().source().to_string()
+            })
+        };
+
+        submit_new_conversation(&mut siv, "bad name");
+        assert_eq!(error(&mut siv).unwrap(), "Not a username: bad name");
+        assert!(receiver.try_recv().is_err());
+
+        submit_new_conversation(&mut siv, "bob");
+        // closed
+        assert!(error(&mut siv).is_none());
+        match receiver.recv().await {
+            Some(UiEvent::NewConversation { name, members_type }) => {
+                assert_eq!(name, "bob");
+                assert_eq!(members_type, MemberType::User);
+            }
+            _ => panic!("Expected a NewConversation event"),
+        }
+    }
+
+    #[tokio::test]
+    async fn quick_switcher() {
+        let (mut siv, mut receiver) = dummy_cursive();
+        let mut general = conversation!("test1");
+        general.channel = Channel {
+            name: "keybase".to_string(),
+            topic_name: "general".to_string(),
+            members_type: MemberType::Team,
+        };
+        let mut dm = conversation!("test2");
+        dm.channel.name = "alice,bob".to_string();
+        let mut generic = conversation!("test3");
+        generic.channel.name = "me,generic_user".to_string();
+        siv.with_user_data(|executor: &mut UiExecutor| {
+            executor.state.sidebar = vec![general.into(), dm.into(), generic.into()];
+        });
+        let results = |siv: &mut Cursive| {
+            siv.call_on_id("switcher_results", |view: &mut SelectView<String>| {
+                view.iter().map(|(name, _)| name.to_string()).collect::<Vec<String>>()
+            })
+            .unwrap()
+        };
+
+        open_quick_switcher(&mut siv);
+        assert_eq!(results(&mut siv).len(), 3);
+        update_quick_switcher(&mut siv, "GEN");
+        assert_eq!(results(&mut siv), ["me,generic_user", "keybase#general"]);
+        update_quick_switcher(&mut siv, "kbgen");
+        assert_eq!(results(&mut siv), ["keybase#general"]);
+
+        submit_quick_switcher(&mut siv);
+        match receiver.recv().await {
+            Some(UiEvent::SwitchConversation(id)) => assert_eq!(id, "test1"),
+            _ => panic!("Expected a SwitchConversation event"),
+        }
+        assert!(siv.call_on_id("switcher_results", |_: &mut SelectView<String>| ()).is_none());
+    }
+
+    #[test]
+    fn step_through_conversations() {
+        assert_eq!(step_index(Some(0), 3, true), Some(1));
+        assert_eq!(step_index(Some(2), 3, true), Some(0));
+        assert_eq!(step_index(Some(1), 3, false), Some(0));
+        assert_eq!(step_index(Some(0), 3, false), Some(2));
+        assert_eq!(step_index(None, 3, true), Some(0));
+        assert_eq!(step_index(None, 3, false), Some(2));
+        assert_eq!(step_index(Some(0), 1, true), Some(0));
+        assert_eq!(step_index(None, 0, true), None);
+    }
+
+    #[test]
+    fn finds_next_unread() {
+        let unread = [false, true, false, true];
+        assert_eq!(next_unread(&unread, Some(1)), Some(3));
+        // round the end
+        assert_eq!(next_unread(&unread, Some(3)), Some(1));
+        assert_eq!(next_unread(&unread, None), Some(1));
+        assert_eq!(next_unread(&[false, true], Some(1)), None);
+        assert_eq!(next_unread(&[false, false], Some(0)), None);
+        assert_eq!(next_unread(&[], None), None);
+    }
+
+    #[tokio::test]
+    async fn switch_to_next_conversation() {
+        let (mut siv, mut receiver) = dummy_cursive();
+        siv.add_layer(main_layout(&LayoutConfig::default()));
+        let mut hidden: Conversation = conversation!("test2").into();
+        hidden.hidden = true;
+        let sidebar = vec![conversation!("test1").into(), hidden, conversation!("test3").into()];
+        siv.with_user_data(|executor: &mut UiExecutor| {
+            executor.state.conversation = Some(sidebar[0].clone());
+            executor.state.sidebar = sidebar;
+        });
+        redraw_sidebar(&mut siv);
+
+        // hidden ones are skipped
+        switch_to_adjacent(&mut siv, true);
+        match receiver.recv().await {
+            Some(UiEvent::SwitchConversation(id)) => assert_eq!(id, "test3"),
+            _ => panic!("Expected a SwitchConversation event"),
+        }
+        switch_to_adjacent(&mut siv, false);
+        match receiver.recv().await {
+            Some(UiEvent::SwitchConversation(id)) => assert_eq!(id, "test3"),
+            _ => panic!("Expected a SwitchConversation event"),
+        }
+    }
+
+    #[test]
+    fn loading_spinner() {
+        assert_eq!(loading_text(Duration::from_millis(0)).source(), "Loading… |");
+        assert_eq!(loading_text(Duration::from_millis(150)).source(), "Loading… /");
+        assert_eq!(loading_text(Duration::from_millis(450)).source(), "Loading… |");
+
+        let (mut siv, _) = dummy_cursive();
+        siv.add_layer(chat_area());
+        let mut convo: Conversation = conversation!("test1").into();
+        convo.insert_messages(vec![message!("test1", "hi", 1)]);
+        siv.with_user_data(|executor: &mut UiExecutor| executor.state.conversation = Some(convo));
+        let mut ui = Ui { cursive: siv };
+        let chat = |ui: &mut Ui| {
+            ui.cursive
+                .call_on_id("chat_container", |view: &mut ChatView| view.content())
+                .unwrap()
+        };
+
+        ui.on_loading("test2", true);
+        assert!(chat(&mut ui).starts_with("Loading…"));
+        // the fetch failed, so it's back to what was there
+        ui.on_loading("test2", false);
+        assert_eq!(chat(&mut ui), "Some Guy: hi\n");
+    }
+
+    #[test]
+    fn other_events_dont_switch() {
+        let mut view = conversation_name("test1");
+        assert!(handle_switch(&mut view, &Event::Key(Key::Tab)).is_none());
+        assert!(handle_switch(&mut view, &Event::Char('a')).is_none());
+    }
 }
@@ -0,0 +1,270 @@
+use std::cell::RefCell;
+
+use cursive::utils::lines::spans::{LinesIterator, Row};
+use cursive::utils::markup::StyledString;
+use cursive::view::View;
+use cursive::{Printer, Vec2};
+
+use crate::hyperlink::find_urls;
+
+// wrapped text needs at least this much room next to the indent, otherwise it isn't indented
+const MIN_BODY_WIDTH: usize = 10;
+
+// a URL in the chat: where the part of it on row `y` starts, what that part says, and where the
+// whole thing goes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Link {
+    pub x: usize,
+    pub y: usize,
+    pub text: String,
+    pub url: String,
+}
+
+// The chat area: each message wrapped to the width it's given, with the rows after its first one
+// starting `indent` columns in, so long messages line up under their body instead of under the
+// username. Wrapped again whenever the width changes.
+#[derive(Default)]
+pub struct ChatView {
+    // each message with its indent
+    messages: Vec<(StyledString, usize)>,
+    // every message's rows, wrapped for `width`
+    rows: Vec<StyledString>,
+    // the URLs in `rows`
+    links: Vec<Link>,
+    width: Option<usize>,
+    // where the URLs went on screen the last time it was drawn, for `Ui::draw_hyperlinks`
+    drawn_links: RefCell<Option<Vec<Link>>>,
+}
+
+impl ChatView {
+    pub fn set_content(&mut self, messages: Vec<(StyledString, usize)>) {
+        self.messages = messages;
+        self.width = None;
+    }
+
+    pub fn append(&mut self, text: StyledString, indent: usize) {
+        if let Some(width) = self.width {
+            let rows = wrap(&text, width, indent);
+            self.links.extend(message_links(&rows, self.rows.len()));
+            self.rows.extend(rows);
+        }
+        self.messages.push((text, indent));
+    }
+
+    // the URLs drawn since this was last asked, in screen coordinates
+    pub fn take_drawn_links(&mut self) -> Option<Vec<Link>> {
+        self.drawn_links.get_mut().take()
+    }
+
+    #[cfg(test)]
+    pub fn content(&self) -> String {
+        self.messages.iter().map(|(text, _)| text.source()).collect()
+    }
+
+    fn compute_rows(&mut self, width: usize) {
+        if self.width == Some(width) {
+            return;
+        }
+        self.rows.clear();
+        self.links.clear();
+        for (text, indent) in &self.messages {
+            let rows = wrap(text, width, *indent);
+            self.links.extend(message_links(&rows, self.rows.len()));
+            self.rows.extend(rows);
+        }
+        self.width = Some(width);
+    }
+}
+
+fn row_text(text: &StyledString, row: &Row) -> StyledString {
+    let mut line = StyledString::new();
+    for span in row.resolve(text) {
+        line.append_styled(span.content, *span.attr);
+    }
+    line
+}
+
+// what's left of `text` from byte `start` on
+fn tail(text: &StyledString, start: usize) -> StyledString {
+    let mut rest = StyledString::new();
+    let mut end = 0;
+    for span in text.spans() {
+        end += span.content.len();
+        if end > start {
+            let skip = span.content.len().saturating_sub(end - start);
+            rest.append_styled(&span.content[skip..], *span.attr);
+        }
+    }
+    rest
+}
+
+// `text` broken into rows no wider than `width`, with every row after the first starting `indent`
+// columns in
+pub fn wrap(text: &StyledString, width: usize, indent: usize) -> Vec<StyledString> {
+    let mut lines = LinesIterator::new(text, width);
+    let first = match lines.next() {
+        Some(row) => row,
+        None => return vec![],
+    };
+    let mut rows = vec![row_text(text, &first)];
+    let end = match first.overall_indices(text.spans_raw()) {
+        Some((_, end)) if indent > 0 && indent + MIN_BODY_WIDTH <= width => end,
+        _ => {
+            rows.extend(lines.map(|row| row_text(text, &row)));
+            return rows;
+        }
+    };
+    // the rest, without the space or newline the first row ended at
+    let mut rest = tail(text, end);
+    if rest.source().starts_with([' ', '\n']) {
+        rest = tail(&rest, 1);
+    }
+    let padding = " ".repeat(indent);
+    for row in LinesIterator::new(&rest, width - indent) {
+        let mut line = StyledString::plain(padding.as_str());
+        line.append(row_text(&rest, &row));
+        rows.push(line);
+    }
+    rows
+}
+
+// The URLs in one message's `rows`, the first of them being row `y`. One too long for its row
+// carries on at the start of the next, and each part links to the whole thing.
+fn message_links(rows: &[StyledString], y: usize) -> Vec<Link> {
+    let mut links = vec![];
+    for (i, row) in rows.iter().enumerate() {
+        let text = row.source();
+        for (start, url) in find_urls(text) {
+            let x = StyledString::plain(&text[..start]).width();
+            let mut parts = vec![(x, i, url)];
+            let mut whole = url.to_string();
+            // it was broken off at the end of the row, rather than at a space
+            let mut broken = start + url.len() == text.len();
+            for (j, next) in rows.iter().enumerate().skip(i + 1) {
+                if !broken {
+                    break;
+                }
+                let next = next.source();
+                let rest = next.trim_start_matches(' ');
+                let part = &rest[..rest.find(char::is_whitespace).unwrap_or(rest.len())];
+                whole.push_str(part);
+                parts.push((next.len() - rest.len(), j, part));
+                broken = part.len() == rest.len();
+            }
+            links.extend(parts.into_iter().filter(|(_, _, part)| !part.is_empty()).map(
+                |(x, j, part)| Link {
+                    x,
+                    y: y + j,
+                    text: part.to_string(),
+                    url: whole.clone(),
+                },
+            ));
+        }
+    }
+    links
+}
+
+impl View for ChatView {
+    fn draw(&self, printer: &Printer) {
+        for (y, row) in self.rows.iter().enumerate() {
+            printer.print_styled((0, y), row.into());
+        }
+        // only the ones scrolled into view, that fit across
+        let top = printer.content_offset.y;
+        let links = self
+            .links
+            .iter()
+            .filter(|link| link.y >= top && link.y < top + printer.output_size.y)
+            .filter(|link| link.x + StyledString::plain(link.text.as_str()).width() <= printer.output_size.x)
+            .map(|link| Link {
+                x: printer.offset.x + link.x,
+                y: printer.offset.y + link.y - top,
+                ..link.clone()
+            })
+            .collect();
+        *self.drawn_links.borrow_mut() = Some(links);
+    }
+
+    fn required_size(&mut self, req: Vec2) -> Vec2 {
+        self.compute_rows(req.x);
+        Vec2::new(req.x, self.rows.len())
+    }
+
+    fn layout(&mut self, size: Vec2) {
+        self.compute_rows(size.x);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn plain_rows(text: &str, width: usize, indent: usize) -> Vec<String> {
+        wrap(&StyledString::plain(text), width, indent)
+            .iter()
+            // rows keep the space they were broken at
+            .map(|row| row.source().trim_end().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn hanging_indent() {
+        let text = "12:00 alice: the quick brown fox jumps\n";
+        assert_eq!(
+            plain_rows(text, 24, 13),
+            [
+                "12:00 alice: the quick",
+                "             brown fox",
+                "             jumps",
+            ]
+        );
+        // fits on one row
+        assert_eq!(plain_rows(text, 80, 13), ["12:00 alice: the quick brown fox jumps"]);
+        // lines in the message line up too
+        assert_eq!(plain_rows("alice: one\ntwo\n", 40, 7), ["alice: one", "       two"]);
+        // too narrow to spare the room
+        assert_eq!(
+            plain_rows(text, 20, 13),
+            ["12:00 alice: the", "quick brown fox", "jumps"]
+        );
+        assert!(plain_rows("", 20, 0).is_empty());
+    }
+
+    #[test]
+    fn finds_links() {
+        let mut view = ChatView::default();
+        view.set_content(vec![
+            (StyledString::plain("alice: docs at https://keybase.io/docs\n"), 7),
+            (StyledString::plain("bob: https://example.com/a/very/long/path ok\n"), 5),
+        ]);
+        view.layout(Vec2::new(40, 10));
+        let link = |x, y, text: &str, url: &str| Link {
+            x,
+            y,
+            text: text.to_string(),
+            url: url.to_string(),
+        };
+        assert_eq!(
+            view.links,
+            [
+                link(15, 0, "https://keybase.io/docs", "https://keybase.io/docs"),
+                // too long for the row, so it's broken in two
+                link(5, 1, "https://example.com/a/very/long/", "https://example.com/a/very/long/path"),
+                link(5, 2, "path", "https://example.com/a/very/long/path"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rewraps_on_resize() {
+        let mut view = ChatView::default();
+        view.set_content(vec![(StyledString::plain("alice: the quick brown fox\n"), 7)]);
+        assert_eq!(view.required_size(Vec2::new(40, 10)), Vec2::new(40, 1));
+
+        view.layout(Vec2::new(20, 10));
+        assert_eq!(view.rows.len(), 2);
+        view.append(StyledString::plain("bob: hi\n"), 5);
+        assert_eq!(view.rows.len(), 3);
+        assert_eq!(view.content(), "alice: the quick brown fox\nbob: hi\n");
+    }
+}
@@ -1,6 +1,6 @@
 use cursive::align::Align;
 use cursive::direction::Direction;
-use cursive::theme::ColorStyle;
+use cursive::theme::{ColorStyle, Effect};
 use cursive::view::{View, ViewWrapper};
 use cursive::{Printer, Vec2};
 
@@ -16,6 +16,10 @@ pub trait ConversationName: View {
 pub struct ConversationView {
     conversation: Conversation,
     pub unread: bool,
+    // an unread message mentions you, which stands out more than just being unread
+    pub mentioned: bool,
+    // draw a team's member count after its name
+    pub show_member_count: bool,
 }
 
 impl ConversationView {
@@ -23,13 +27,32 @@ impl ConversationView {
         ConversationView {
             conversation: convo,
             unread: false,
+            mentioned: false,
+            show_member_count: false,
         }
     }
+
+    pub fn set_conversation(&mut self, convo: Conversation) {
+        self.conversation = convo;
+    }
+
+    // the name as drawn, cut short if it's too long but always keeping the counts after it
+    fn label(&self) -> String {
+        let name = self.name();
+        let full = self.conversation.sidebar_name(self.show_member_count);
+        if full.len() <= MAX_NAME_LENGTH {
+            return full;
+        }
+        let count = &full[name.len()..];
+        let keep = MAX_NAME_LENGTH.saturating_sub(count.len() + 4);
+        let cut = (0..=keep).rev().find(|&i| name.is_char_boundary(i)).unwrap_or(0);
+        format!("{}...{}", &name[..cut], count)
+    }
 }
 
 impl ConversationName for ConversationView {
     fn name(&self) -> String {
-        self.conversation.get_name()
+        self.conversation.display_name()
     }
 
     fn conversation_id(&self) -> String {
@@ -52,10 +75,20 @@ where
 
 impl View for ConversationView {
     fn draw(&self, printer: &Printer) {
-        let name = self.name();
+        let label = self.label();
         let offset = Align::top_left().v.get_offset(1, printer.size.y);
         let printer = &printer.offset((0, offset));
 
+        if self.conversation.muted && !printer.focused {
+            return printer.with_color(ColorStyle::secondary(), |printer| {
+                printer.print((0, 0), &label)
+            });
+        }
+        if self.mentioned && !printer.focused {
+            return printer.with_color(ColorStyle::title_primary(), |printer| {
+                printer.with_effect(Effect::Bold, |printer| printer.print((0, 0), &label))
+            });
+        }
         let style = if self.unread && !printer.focused {
             ColorStyle::highlight_inactive()
         } else if printer.focused {
@@ -63,22 +96,16 @@ impl View for ConversationView {
         } else {
             ColorStyle::primary()
         };
-        printer.with_color(style, |printer| {
-            if name.len() > MAX_NAME_LENGTH {
-                printer.print((0, 0), &name[0..MAX_NAME_LENGTH - 4]);
-                printer.print((MAX_NAME_LENGTH - 4, 0), "...");
-            } else {
-                printer.print((0, 0), &name);
-            }
-        })
+        printer.with_color(style, |printer| printer.print((0, 0), &label))
     }
 
     fn take_focus(&mut self, _: Direction) -> bool {
         self.unread = false;
+        self.mentioned = false;
         true
     }
 
     fn required_size(&mut self, _: Vec2) -> Vec2 {
-        Vec2::new((self.name().len() + 1).min(MAX_NAME_LENGTH), 1)
+        Vec2::new((self.label().len() + 1).min(MAX_NAME_LENGTH), 1)
     }
 }
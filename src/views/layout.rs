@@ -0,0 +1,98 @@
+use cursive::view::{View, ViewWrapper};
+use cursive::{Printer, Vec2};
+
+// keep both sides usable no matter what the config says or how small the terminal gets
+const MIN_SIDEBAR_WIDTH: usize = 10;
+const MIN_CHAT_WIDTH: usize = 20;
+
+// Sizes the wrapped view to a percentage of the available width (and all of the height), which
+// is recomputed whenever the terminal is resized.
+pub struct PercentWidth<V> {
+    view: V,
+    percent: usize,
+}
+
+impl<V> PercentWidth<V> {
+    pub fn new(view: V, percent: usize) -> Self {
+        PercentWidth { view, percent }
+    }
+}
+
+// The width a sidebar taking up `percent` of `available` columns should get.
+pub fn sidebar_width(available: usize, percent: usize) -> usize {
+    let width = available * percent / 100;
+    width
+        .min(available.saturating_sub(MIN_CHAT_WIDTH))
+        .max(MIN_SIDEBAR_WIDTH.min(available))
+}
+
+impl<V: View> ViewWrapper for PercentWidth<V> {
+    cursive::wrap_impl!(self.view: V);
+
+    fn wrap_required_size(&mut self, req: Vec2) -> Vec2 {
+        let width = sidebar_width(req.x, self.percent);
+        self.view.required_size(Vec2::new(width, req.y));
+        Vec2::new(width, req.y)
+    }
+}
+
+// Empty space between the conversation list and the chat area, optionally with a line down the
+// middle of it.
+pub struct Gutter {
+    width: usize,
+    line: bool,
+}
+
+impl Gutter {
+    pub fn new(width: usize, line: bool) -> Self {
+        Gutter { width, line }
+    }
+}
+
+impl View for Gutter {
+    fn draw(&self, printer: &Printer) {
+        if self.line && self.width > 0 {
+            printer.print_vline((self.width / 2, 0), printer.size.y, "│");
+        }
+    }
+
+    fn required_size(&mut self, req: Vec2) -> Vec2 {
+        Vec2::new(self.width, req.y)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cursive::views::{LinearLayout, TextView};
+
+    #[test]
+    fn sidebar_percentage() {
+        assert_eq!(sidebar_width(100, 25), 25);
+        assert_eq!(sidebar_width(200, 30), 60);
+    }
+
+    #[test]
+    fn sidebar_clamped_to_terminal() {
+        // too narrow a sidebar
+        assert_eq!(sidebar_width(100, 1), MIN_SIDEBAR_WIDTH);
+        // leaves no room for the chat
+        assert_eq!(sidebar_width(100, 95), 100 - MIN_CHAT_WIDTH);
+        // tiny terminal
+        assert_eq!(sidebar_width(5, 25), 5);
+    }
+
+    #[test]
+    fn layout_with_custom_proportions() {
+        let mut layout = LinearLayout::horizontal()
+            .child(PercentWidth::new(TextView::new("conversations"), 40))
+            .child(Gutter::new(3, true))
+            .child(TextView::new("chat"));
+
+        let size = layout.required_size(Vec2::new(100, 30));
+        layout.layout(Vec2::new(100, 30));
+
+        assert_eq!(size.y, 30);
+        assert!(size.x >= 43);
+    }
+}
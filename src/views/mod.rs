@@ -1 +1,3 @@
+pub mod chat;
 pub mod conversation;
+pub mod layout;
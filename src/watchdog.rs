@@ -0,0 +1,111 @@
+// # watchdog.rs
+//
+// Decides when the `keybase chat api-listen` process has gone stale and should be restarted. The
+// process doesn't crash when the machine sleeps, it just silently stops delivering events, so we
+// look for two symptoms:
+//
+// * the heartbeat timer firing much later than it should have, which means the machine was
+//   asleep in between
+// * a message we sent never coming back through the listener
+
+use std::time::{Duration, SystemTime};
+
+pub struct ListenerWatchdog {
+    interval: Duration,
+    // wall clock time of the last heartbeat, which keeps moving while the machine sleeps (unlike
+    // `Instant`)
+    last_tick: SystemTime,
+    // when we sent a message that the listener hasn't echoed back yet
+    awaiting_echo_since: Option<SystemTime>,
+}
+
+impl ListenerWatchdog {
+    pub fn new(interval: Duration, now: SystemTime) -> Self {
+        ListenerWatchdog {
+            interval,
+            last_tick: now,
+            awaiting_echo_since: None,
+        }
+    }
+
+    // we sent a message, so the listener should see it shortly
+    pub fn on_sent(&mut self, now: SystemTime) {
+        if self.awaiting_echo_since.is_none() {
+            self.awaiting_echo_since = Some(now);
+        }
+    }
+
+    // the listener delivered something, so it's still alive
+    pub fn on_event(&mut self) {
+        self.awaiting_echo_since = None;
+    }
+
+    // Called on every heartbeat, returns whether the listener should be restarted.
+    pub fn on_tick(&mut self, now: SystemTime) -> bool {
+        let since_last_tick = now.duration_since(self.last_tick).unwrap_or_default();
+        let woke_up = since_last_tick > self.interval * 2;
+        let missed_echo = self
+            .awaiting_echo_since
+            .map(|sent| now.duration_since(sent).unwrap_or_default() > self.interval)
+            .unwrap_or(false);
+
+        self.last_tick = now;
+        if woke_up || missed_echo {
+            self.awaiting_echo_since = None;
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    fn start() -> (ListenerWatchdog, SystemTime) {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        (ListenerWatchdog::new(INTERVAL, now), now)
+    }
+
+    #[test]
+    fn regular_ticks() {
+        let (mut watchdog, now) = start();
+        assert!(!watchdog.on_tick(now + INTERVAL));
+        assert!(!watchdog.on_tick(now + INTERVAL * 2));
+        assert!(!watchdog.on_tick(now + INTERVAL * 3));
+    }
+
+    #[test]
+    fn restart_after_sleep() {
+        let (mut watchdog, now) = start();
+        assert!(watchdog.on_tick(now + Duration::from_secs(60 * 60)));
+        // back to normal afterwards
+        assert!(!watchdog.on_tick(now + Duration::from_secs(60 * 61)));
+    }
+
+    #[test]
+    fn restart_when_sent_message_not_echoed() {
+        let (mut watchdog, now) = start();
+        watchdog.on_sent(now + Duration::from_secs(30));
+        assert!(!watchdog.on_tick(now + INTERVAL));
+        assert!(watchdog.on_tick(now + INTERVAL * 2));
+        assert!(!watchdog.on_tick(now + INTERVAL * 3));
+    }
+
+    #[test]
+    fn echo_clears_sent_message() {
+        let (mut watchdog, now) = start();
+        watchdog.on_sent(now + Duration::from_secs(30));
+        watchdog.on_event();
+        assert!(!watchdog.on_tick(now + INTERVAL));
+        assert!(!watchdog.on_tick(now + INTERVAL * 2));
+    }
+
+    #[test]
+    fn clock_going_backwards() {
+        let (mut watchdog, now) = start();
+        assert!(!watchdog.on_tick(now - INTERVAL));
+    }
+}